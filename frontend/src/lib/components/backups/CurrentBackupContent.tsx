@@ -49,8 +49,8 @@ export default function CurrentBackupContent(
                         <div className="flex flex-col">
                             <label className="text-small text-gray-600">Compression</label>
                             <p className="text-medium text-gray-800 font-medium">
-                                {data.incremental.stage.compressionLevel}
-                                {data.incremental.stage.compressionLevel !== "None" &&
+                                {data.incremental.stage.compression.algorithm} {data.incremental.stage.compression.level}
+                                {data.incremental.stage.compression.algorithm !== "None" &&
                                     `, ${(data.incremental.stage.bytesWritten / data.incremental.stage.bytesRead * 100).toFixed(2)}%`}
                             </p>
                         </div>
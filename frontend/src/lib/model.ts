@@ -1,4 +1,4 @@
-import {CompressionLevel} from "@/lib/queries/spec";
+import {Compression} from "@/lib/queries/spec";
 import {REnum} from "@/lib/types";
 
 export type CurrentBackupJob = {
@@ -22,7 +22,7 @@ export type CurrentIncrementalBackupUploading = {
     bytesRead: number,
     bytesWritten: number,
     bytesWrittenPerSecond: number,
-    compressionLevel: CompressionLevel;
+    compression: Compression;
     encrypted: boolean,
     finishing: boolean,
 }
@@ -39,7 +39,7 @@ export type HistoryBackupJob = {
             localSnapshot: string,
             bytesRead: number,
             bytesWritten: number,
-            compressionLevel: CompressionLevel,
+            compression: Compression,
             encrypted: boolean
         }
     }>
@@ -54,7 +54,7 @@ type Result = REnum<{
         localSnapshot: string,
         bytesRead: number,
         bytesWritten: number,
-        compressionLevel: CompressionLevel,
+        compression: Compression,
         encrypted: boolean
     }
 }>
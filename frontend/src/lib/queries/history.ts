@@ -31,7 +31,7 @@ function convertHistoryBackupJob(entry: JobHistoryAPI['entries'][0]): HistoryBac
             localSnapshot: entry.IncrementalBackup.state.Success.local_snapshot,
             bytesRead: entry.IncrementalBackup.state.Success.bytes_read,
             bytesWritten: entry.IncrementalBackup.state.Success.bytes_written,
-            compressionLevel: entry.IncrementalBackup.state.Success.compression_level,
+            compression: entry.IncrementalBackup.state.Success.compression,
             encrypted: entry.IncrementalBackup.state.Success.encrypted
         }
     }
@@ -31,7 +31,7 @@ export function convertCurrentBackupJob(data: components["schemas"]["JobStates"]
                 bytesRead: uploading.bytes_read,
                 bytesWritten: uploading.bytes_written,
                 bytesWrittenPerSecond: 0,
-                compressionLevel: uploading.compression_level,
+                compression: uploading.compression,
                 encrypted: uploading.encrypted,
                 finishing: uploading.finishing
             }
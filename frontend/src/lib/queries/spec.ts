@@ -1,6 +1,11 @@
 import {ExclusiveEnum} from "@/lib/types";
 
-export type CompressionLevel = "None" | "Fast" | "Balanced" | "Best";
+export type CompressionAlgorithm = "Zstd" | "Lz4" | "Gzip" | "Xz" | "None";
+
+export type Compression = {
+    algorithm: CompressionAlgorithm,
+    level: number,
+};
 
 export type CurrentJobsAPI = {
     restore: never,
@@ -24,7 +29,7 @@ type CurrentIncrementalBackupJobStage = {
         local_snapshot: string,
         bytes_read: number,
         bytes_written: number,
-        compression_level: CompressionLevel;
+        compression: Compression;
         encrypted: boolean,
         finishing: boolean,
     },
@@ -48,7 +53,7 @@ type JobResult = ExclusiveEnum<{
                 local_snapshot: string,
                 bytes_read: number,
                 bytes_written: number,
-                compression_level: CompressionLevel,
+                compression: Compression,
                 encrypted: boolean
             }
         }>
@@ -0,0 +1,234 @@
+use crate::objects::{
+    AdaptiveCompression, BackupSource, Compression, CompressionAlgorithm, CompressionLevel,
+    EncryptionLevel,
+};
+use crate::services::data_tunnel::{DataTunnel, EncodingDataTunnel};
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Benches the sample of `generated_sample` instead of a file, the default.
+pub const GENERATED_SAMPLE: &str = "generated";
+const GENERATED_SAMPLE_BYTES: usize = 64 * 1024 * 1024;
+const GENERATED_BLOCK_BYTES: usize = 64 * 1024;
+
+const WORDS: &[&str] = &[
+    "backup", "snapshot", "subvolume", "chunk", "stream", "data", "dance", "the", "of", "to",
+    "remote", "local", "history", "parent", "zstd", "receive", "send", "file", "folder", "key",
+];
+
+/// Every setting is run this often, the median run is reported.
+const ROUNDS: usize = 3;
+
+#[derive(Debug)]
+pub struct BenchResult {
+    pub compression: Compression,
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+    pub duration: Duration,
+}
+
+impl BenchResult {
+    pub fn throughput_mb_per_s(&self) -> f64 {
+        self.read_bytes as f64 / 1024.0 / 1024.0 / self.duration.as_secs_f64()
+    }
+
+    /// Compressed size in percent of the input.
+    pub fn ratio(&self) -> f64 {
+        self.written_bytes as f64 / self.read_bytes as f64 * 100.0
+    }
+}
+
+pub fn default_settings() -> Vec<Compression> {
+    let workers = std::thread::available_parallelism()
+        .map(|threads| threads.get() as u32)
+        .unwrap_or(1);
+
+    vec![
+        CompressionAlgorithm::None.into(),
+        CompressionAlgorithm::Lz4.into(),
+        Compression::new(CompressionAlgorithm::Gzip, 1),
+        CompressionAlgorithm::Gzip.into(),
+        CompressionLevel::Fast.into(),
+        CompressionLevel::Balanced.into(),
+        CompressionLevel::Best.into(),
+        Compression {
+            workers,
+            ..CompressionLevel::Best.into()
+        },
+        Compression {
+            workers,
+            window_log: Some(27),
+            long_distance_matching: true,
+            ..CompressionLevel::Best.into()
+        },
+        Compression {
+            workers,
+            adaptive: Some(AdaptiveCompression::default()),
+            ..CompressionLevel::Best.into()
+        },
+        CompressionAlgorithm::Xz.into(),
+    ]
+}
+
+/// Stands in for a `btrfs send` stream of a home folder: blocks of text, zeroed extents and
+/// random data like already compressed files, mixed by a fixed seed so every run is the same.
+pub fn generated_sample(len: usize) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(0xDA7A_DA9C);
+    let mut sample = Vec::with_capacity(len);
+    while sample.len() < len {
+        let block_len = GENERATED_BLOCK_BYTES.min(len - sample.len());
+        let start = sample.len();
+        match rng.random_range(0..4) {
+            0 | 1 => {
+                while sample.len() < start + block_len {
+                    sample.extend_from_slice(WORDS[rng.random_range(0..WORDS.len())].as_bytes());
+                    sample.push(b' ');
+                }
+                sample.truncate(start + block_len);
+            }
+            2 => sample.resize(start + block_len, 0),
+            _ => {
+                sample.resize(start + block_len, 0);
+                rng.fill_bytes(&mut sample[start..]);
+            }
+        }
+    }
+    sample
+}
+
+/// Loads the file at `sample` into memory, or generates the sample for `GENERATED_SAMPLE`.
+pub fn read_sample(sample: &str) -> std::io::Result<Vec<u8>> {
+    match sample {
+        GENERATED_SAMPLE => Ok(generated_sample(GENERATED_SAMPLE_BYTES)),
+        path => std::fs::read(Path::new(path)),
+    }
+}
+
+/// The sample is held in memory so the disk does not skew the numbers.
+pub fn run_bench(sample: &[u8], settings: &[Compression]) -> std::io::Result<Vec<BenchResult>> {
+    let sample: Arc<[u8]> = sample.into();
+
+    settings
+        .iter()
+        .map(|compression| {
+            let mut runs = Vec::with_capacity(ROUNDS);
+            for _ in 0..ROUNDS {
+                runs.push(run_setting(&sample, *compression)?);
+            }
+            runs.sort_by_key(|run| run.duration);
+            Ok(runs.swap_remove(ROUNDS / 2))
+        })
+        .collect()
+}
+
+fn run_setting(sample: &Arc<[u8]>, compression: Compression) -> std::io::Result<BenchResult> {
+    let tunnel = EncodingDataTunnel {
+        compression,
+        encryption_level: EncryptionLevel::None,
+        source: BackupSource::BtrfsStream,
+        parent: None,
+        compression_mix: Default::default(),
+    };
+    let transfer = tunnel.tracked_transfer(Cursor::new(Arc::clone(sample)), std::io::sink());
+
+    let start_time = Instant::now();
+    transfer.run()?;
+    let duration = start_time.elapsed();
+
+    Ok(BenchResult {
+        compression,
+        read_bytes: transfer.reader_bytes_count(),
+        written_bytes: transfer.writer_bytes_count(),
+        duration,
+    })
+}
+
+/// `data-dance bench [sample] [setting...]`, the sample is a file or `generated`, the default.
+/// Settings are given as JSON like `'{"algorithm": "Zstd", "level": 19, "workers": 8}'` and
+/// replace the default ones.
+pub fn bench_command(args: &[String]) -> i32 {
+    let sample = args.first().map(String::as_str).unwrap_or(GENERATED_SAMPLE);
+
+    let mut settings = Vec::new();
+    for arg in args.iter().skip(1) {
+        let compression = match serde_json::from_str::<Compression>(arg) {
+            Ok(compression) => compression,
+            Err(err) => {
+                eprintln!("Invalid compression setting '{arg}': {err}");
+                return 2;
+            }
+        };
+        if let Err(err) = compression.validate() {
+            eprintln!("Invalid compression setting '{arg}': {err}");
+            return 2;
+        }
+        settings.push(compression);
+    }
+    if settings.is_empty() {
+        settings = default_settings();
+    }
+
+    let results = match read_sample(sample).and_then(|data| run_bench(&data, &settings)) {
+        Ok(results) => results,
+        Err(err) => {
+            eprintln!("Benchmark on {sample} failed: {err}");
+            return 1;
+        }
+    };
+
+    println!("Sample: {sample}");
+    for result in results {
+        println!(
+            "{:>10.2} MB/s {:>8.2}% {}",
+            result.throughput_mb_per_s(),
+            result.ratio(),
+            serde_json::to_string(&result.compression).unwrap()
+        );
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_reports_every_setting() {
+        let sample = std::env::temp_dir()
+            .join(format!("data-dance-bench-{}.bin", rand::random::<u64>()));
+        std::fs::write(&sample, b"btrfs send stream stand-in, ".repeat(10_000)).unwrap();
+        let settings = [
+            CompressionAlgorithm::None.into(),
+            Compression {
+                workers: 2,
+                ..CompressionLevel::Fast.into()
+            },
+        ];
+
+        let data = read_sample(sample.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&sample).unwrap();
+        let results = run_bench(&data, &settings).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1].compression, settings[1]);
+        for result in &results {
+            assert_eq!(result.read_bytes, 280_000);
+        }
+        assert!(results[0].ratio() > 100.0);
+        assert!(results[1].ratio() < 10.0);
+    }
+
+    #[test]
+    fn generated_sample_is_deterministic_and_partly_compressible() {
+        let sample = generated_sample(4 * 1024 * 1024);
+
+        assert_eq!(sample.len(), 4 * 1024 * 1024);
+        assert_eq!(sample, generated_sample(4 * 1024 * 1024));
+        let results = run_bench(&sample, &[CompressionLevel::Fast.into()]).unwrap();
+        assert!((10.0..90.0).contains(&results[0].ratio()), "{}", results[0].ratio());
+    }
+}
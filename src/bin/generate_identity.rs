@@ -0,0 +1,10 @@
+#![allow(warnings)]
+
+fn main() {
+    // The recipient goes into `remote_storage.recipients`, the identity is kept offline
+    let (recipient, identity) = data_dance::services::encryption::recipients::generate_identity()
+        .expect("failed to generate an X25519 key pair");
+
+    println!("recipient: {recipient}");
+    println!("identity:  {identity}");
+}
@@ -1,5 +1,4 @@
-//#[cfg(test)]
-//mod compare_compression;
+pub mod bench;
 
 #[cfg(test)]
 mod create_sample_config;
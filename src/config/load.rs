@@ -1,4 +1,7 @@
-use crate::config::DataDanceConfiguration;
+use crate::config::{
+    DataDanceConfiguration, RECIPIENTS_KEY_ID, RemoteDestination, UNENCRYPTED_KEY_ID,
+};
+use crate::services::encryption::recipients::parse_recipient;
 use crate::objects::job_result::IncrementalBackupResultState::Error;
 use std::env::VarError;
 use std::fs;
@@ -48,11 +51,169 @@ fn read_config(file_path: impl AsRef<Path>) -> Result<DataDanceConfiguration, Co
     let config_content = fs::read_to_string(file_path)?;
 
     // Parse the config content from TOML into the Config struct
-    let config = toml::from_str(&config_content)?;
+    let config: DataDanceConfiguration = toml::from_str(&config_content)?;
+
+    if config.remote_storage.encryption.is_some() && !config.remote_storage.recipients.is_empty() {
+        return Err(ConfigLoadError::InvalidConfig {
+            details: "set either an encryption password or recipients, not both".to_string(),
+        });
+    }
+    for recipient in &config.remote_storage.recipients {
+        parse_recipient(recipient).map_err(|err| ConfigLoadError::InvalidConfig {
+            details: format!("invalid recipient '{recipient}': {err}"),
+        })?;
+    }
+    config
+        .remote_storage
+        .compression
+        .validate()
+        .map_err(|details| ConfigLoadError::InvalidConfig { details })?;
+    validate_destination(&config.remote_storage.dest)?;
+    for replica in &config.remote_storage.replicas {
+        validate_destination(&replica.dest)?;
+    }
+    if let Some(migration) = &config.migration {
+        validate_destination(&migration.source)?;
+    }
+    for (index, replica) in config.remote_storage.replicas.iter().enumerate() {
+        if config.remote_storage.replicas[..index]
+            .iter()
+            .any(|other| other.name == replica.name)
+        {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: format!("the replica name '{}' is used more than once", replica.name),
+            });
+        }
+    }
+    if let Some(full_backup) = &config.full_backup
+        && !full_backup
+            .remote_root
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+    {
+        return Err(ConfigLoadError::InvalidConfig {
+            details: "the remote root of full data backups must be a relative path without '..'"
+                .to_string(),
+        });
+    }
+    if let Some(repository) = &config.remote_storage.repository {
+        if !(256..=4 * 1024 * 1024).contains(&repository.average_chunk_size) {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "the average chunk size must be between 256 B and 4 MiB".to_string(),
+            });
+        }
+        // Collecting garbage reads the chunk indexes, which needs a password
+        if !config.remote_storage.recipients.is_empty() {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "the chunk repository cannot be used with recipients".to_string(),
+            });
+        }
+        if !config.remote_storage.replicas.is_empty() {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "the chunk repository cannot be used with replicas".to_string(),
+            });
+        }
+    }
+    if let Some(resumable_upload) = &config.remote_storage.resumable_upload {
+        if !(1024 * 1024..=256 * 1024 * 1024).contains(&resumable_upload.segment_size) {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "the segment size must be between 1 MiB and 256 MiB".to_string(),
+            });
+        }
+        // Segments are stored like repository chunks
+        if !config.remote_storage.recipients.is_empty() {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "resumable uploads cannot be used with recipients".to_string(),
+            });
+        }
+        if !config.remote_storage.replicas.is_empty() {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "resumable uploads cannot be used with replicas".to_string(),
+            });
+        }
+        // Chunked backups keep the key they were made with, rekeying skips them
+        if !config.remote_storage.keyring.is_empty() {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: "resumable uploads cannot be used with a keyring, their backups \
+                    cannot be rekeyed"
+                    .to_string(),
+            });
+        }
+    }
+    if config.remote_storage.encryption_key_id.as_deref() == Some(RECIPIENTS_KEY_ID) {
+        return Err(ConfigLoadError::InvalidConfig {
+            details: format!("the key id '{RECIPIENTS_KEY_ID}' is reserved for recipients"),
+        });
+    }
+    if config.remote_storage.encryption_key_id.as_deref() == Some(UNENCRYPTED_KEY_ID) {
+        return Err(ConfigLoadError::InvalidConfig {
+            details: format!(
+                "the key id '{UNENCRYPTED_KEY_ID}' is reserved for unencrypted backups"
+            ),
+        });
+    }
+    let current_key_id = config.remote_storage.current_key_id();
+    for (index, key) in config.remote_storage.keyring.iter().enumerate() {
+        let duplicate = config.remote_storage.keyring[..index]
+            .iter()
+            .any(|other| other.id == key.id);
+        if key.id == RECIPIENTS_KEY_ID {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: format!("the key id '{RECIPIENTS_KEY_ID}' is reserved for recipients"),
+            });
+        }
+        if key.id == UNENCRYPTED_KEY_ID {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: format!(
+                "the key id '{UNENCRYPTED_KEY_ID}' is reserved for unencrypted backups"
+            ),
+            });
+        }
+        if duplicate || current_key_id == key.id {
+            return Err(ConfigLoadError::InvalidConfig {
+                details: format!("the key id '{}' is used more than once", key.id),
+            });
+        }
+    }
+
+    if let Some(schedule) = &config.schedule {
+        schedule
+            .rule
+            .validate()
+            .map_err(|details| ConfigLoadError::InvalidConfig { details })?;
+    }
+    if let Some(schedule) = config
+        .restore_drill
+        .as_ref()
+        .and_then(|restore_drill| restore_drill.schedule.as_ref())
+    {
+        schedule
+            .rule
+            .validate()
+            .map_err(|details| ConfigLoadError::InvalidConfig { details })?;
+    }
 
     Ok(config)
 }
 
+/// Rejects URLs the destination services would fail to parse when a job is set up.
+fn validate_destination(dest: &RemoteDestination) -> Result<(), ConfigLoadError> {
+    match dest {
+        RemoteDestination::S3 { endpoint, .. } => {
+            url::Url::parse(endpoint).map_err(|err| ConfigLoadError::InvalidConfig {
+                details: format!("invalid s3 endpoint '{endpoint}': {err}"),
+            })?;
+        }
+        RemoteDestination::WebDav { url, .. } => {
+            url::Url::parse(url).map_err(|err| ConfigLoadError::InvalidConfig {
+                details: format!("invalid webdav url '{url}': {err}"),
+            })?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 #[derive(Error, Debug)]
 pub enum ConfigLoadError {
     #[error("file was not found at: {path:?}")]
@@ -1,4 +1,4 @@
-use crate::objects::{CompressionLevel, SensitiveString};
+use crate::objects::{Compression, EncryptionLevel, SensitiveString};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -10,6 +10,15 @@ pub struct DataDanceConfiguration {
 
     pub local_storage: LocalStorageConfig,
     pub remote_storage: RemoteStorageConfig,
+
+    #[serde(default)]
+    pub full_backup: Option<FullBackupConfig>,
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    pub migration: Option<MigrationConfig>,
+    #[serde(default)]
+    pub restore_drill: Option<RestoreDrillConfig>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -21,6 +30,9 @@ pub struct WebConfig {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LocalStorageConfig {
     pub source: LocalSource,
+    /// Holds the job history and state. A restore downloads every link of a chain with recorded
+    /// checksums here to verify it before receiving it, one at a time, so it needs room for the
+    /// largest of them.
     pub jobs_folder: PathBuf,
 }
 
@@ -30,18 +42,327 @@ pub enum LocalSource {
         snapshots_folder: PathBuf,
         source_folder: PathBuf,
         send_compressed_data: bool,
+        /// Folder restored snapshots are received into.
+        #[serde(default)]
+        restore_folder: Option<PathBuf>,
+        #[serde(default)]
+        snapshot_retention: SnapshotRetention,
     },
     Fake {
         backup_byte_size: usize,
     },
 }
 
+impl LocalSource {
+    /// The same source, receiving restores into `restore_folder` instead.
+    pub fn with_restore_folder(self, restore_folder: PathBuf) -> Self {
+        match self {
+            LocalSource::Btrfs {
+                snapshots_folder,
+                source_folder,
+                send_compressed_data,
+                snapshot_retention,
+                ..
+            } => LocalSource::Btrfs {
+                snapshots_folder,
+                source_folder,
+                send_compressed_data,
+                restore_folder: Some(restore_folder),
+                snapshot_retention,
+            },
+            LocalSource::Fake { backup_byte_size } => LocalSource::Fake { backup_byte_size },
+        }
+    }
+}
+
+/// Which local snapshots survive a backup. Only folders starting with `snapshot_` were created
+/// by data-dance, everything else in the snapshots folder is left alone.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotRetention {
+    /// Newest backed up snapshots to keep. The latest one is always kept as the next parent.
+    pub keep_last: usize,
+    /// Also keep backed up snapshots younger than this.
+    pub keep_within_hours: Option<u64>,
+    /// Snapshots renamed to `<name>@<tag>` are kept when `<tag>` is listed here.
+    pub keep_tags: Vec<String>,
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            keep_last: 2,
+            keep_within_hours: None,
+            keep_tags: Vec::new(),
+        }
+    }
+}
+
+/// Automatically submitted incremental backups.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    pub rule: ScheduleRule,
+    /// Upper bound of a random delay added to every planned run.
+    #[serde(default)]
+    pub jitter_seconds: u64,
+    /// Run once right away if a planned run was missed while the server was down.
+    #[serde(default)]
+    pub catch_up: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ScheduleRule {
+    /// Cron expression with 5 (minute precision) or 6 (second precision) fields, evaluated in UTC.
+    Cron { expression: String },
+    Interval { seconds: u64 },
+}
+
+/// Backups the migrate job copies into `remote_storage.dest`, like when switching providers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MigrationConfig {
+    pub source: RemoteDestination,
+    #[serde(default)]
+    pub mode: MigrationMode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MigrationMode {
+    /// Files are copied byte for byte and keep their compression and key.
+    #[default]
+    Copy,
+    /// Files are decrypted with the keyring and written with the current compression and
+    /// encryption, like new backups are.
+    Reencode,
+}
+
+/// Test restores that replay a backup chain into a scratch folder and compare the result with
+/// the snapshot manifest recorded by the backup.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestoreDrillConfig {
+    /// Every drill receives into a new folder below this one, which is deleted afterwards.
+    /// It has to be on btrfs.
+    pub scratch_folder: PathBuf,
+    #[serde(default)]
+    pub pick: DrillPick,
+    /// Automatically submitted drills, checked like the backup schedule.
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+}
+
+/// Which of the backups with a recorded manifest a drill restores.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrillPick {
+    #[default]
+    Latest,
+    Random,
+}
+
+/// File-level backups that do not depend on btrfs. Their archives are uploaded to the replicas
+/// as well.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FullBackupConfig {
+    pub filesystem_root: PathBuf,
+    /// Folder inside the remote destination the archives are uploaded to.
+    #[serde(default)]
+    pub remote_root: PathBuf,
+    /// Archives outside of the policy are pruned after every full data backup, apart from the
+    /// snapshot backups. Without it no archive expires.
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RemoteStorageConfig {
+    /// Where backups are restored and rekeyed from, a backup fails without it.
     pub dest: RemoteDestination,
+    /// Further destinations every backup is uploaded to at the same time. Each keeps its own
+    /// backup history.
+    #[serde(default)]
+    pub replicas: Vec<ReplicaConfig>,
 
     pub encryption: Option<SensitiveString>,
-    pub compression: CompressionLevel,
+    /// Recorded with every backup encrypted with `encryption`, so the password can be found in
+    /// the `keyring` once it is rotated. Defaults to `DEFAULT_KEY_ID`.
+    #[serde(default)]
+    pub encryption_key_id: Option<String>,
+    /// Previous passwords, only used to decrypt backups recorded with their id.
+    #[serde(default)]
+    pub keyring: Vec<KeyringEntry>,
+    /// X25519 public keys (`x25519:<base64>`) backups are encrypted to instead of a password.
+    /// Restoring then needs the identity of one of them.
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// A preset like `"Best"` (zstd) or an explicit `{ algorithm = "Xz", level = 6 }`.
+    pub compression: Compression,
+
+    /// Snapshot backups outside of the policy are pruned after every backup. Without it nothing
+    /// expires. Archives of full data backups have a retention of their own.
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    /// When to start a new chain with a full backup instead of another incremental.
+    #[serde(default)]
+    pub consolidation: ConsolidationPolicy,
+    /// Stores snapshot backups as deduplicated chunks instead of one file each. Backups made
+    /// before keep their single file. Chunked backups keep the key they were made with when
+    /// the key is rotated and cannot be migrated.
+    #[serde(default)]
+    pub repository: Option<RepositoryConfig>,
+    /// Uploads incremental backups in segments, so a failed upload is retried or resumed by the
+    /// next run instead of starting over. The segments are chunks, so the backups are chunked
+    /// like with the `repository`, even without it: they cannot be replicated and keep their
+    /// key, neither `replicas` nor a `keyring` are accepted with it.
+    #[serde(default)]
+    pub resumable_upload: Option<ResumableUploadConfig>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplicaConfig {
+    /// Names the replica in job results.
+    pub name: String,
+    pub dest: RemoteDestination,
+    #[serde(default)]
+    pub policy: ReplicationPolicy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicationPolicy {
+    /// The backup fails when the replica cannot be written.
+    #[default]
+    Required,
+    /// Failures are reported in the job result, the backup still succeeds. A replica missing
+    /// the parent of an incremental backup is skipped until the next full backup.
+    BestEffort,
+}
+
+/// The stream of a backup is split at content-defined points, chunks already on the remote are
+/// not uploaded again. Chunks are removed once no backup in the history references them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RepositoryConfig {
+    /// Chunks are cut between a quarter and four times this many bytes, from 256 B to 4 MiB.
+    pub average_chunk_size: u32,
+}
+
+impl Default for RepositoryConfig {
+    fn default() -> Self {
+        Self {
+            average_chunk_size: 1024 * 1024,
+        }
+    }
+}
+
+/// The segments are stored like the chunks of the repository, with the repository they are its
+/// chunks. The confirmed segments are recorded in `upload_journal.json` of the jobs folder, a
+/// resumed upload does not send them again as long as the snapshot streams the same data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResumableUploadConfig {
+    /// Bytes of the stream per segment without the repository, from 1 MiB to 256 MiB.
+    pub segment_size: u64,
+    /// How often a failed upload is resumed within the same run.
+    pub retries: u32,
+    /// Waited before the first retry, doubled before every further one.
+    pub retry_delay_seconds: u64,
+}
+
+impl Default for ResumableUploadConfig {
+    fn default() -> Self {
+        Self {
+            segment_size: 64 * 1024 * 1024,
+            retries: 3,
+            retry_delay_seconds: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyringEntry {
+    pub id: String,
+    pub password: SensitiveString,
+}
+
+/// Key id of passwords without an explicit id, including the ones of backups made before key
+/// ids were recorded.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Key id of backups encrypted to the `recipients`, they are decrypted with an identity.
+pub const RECIPIENTS_KEY_ID: &str = "recipients";
+
+/// Key id of backups made without encryption.
+pub const UNENCRYPTED_KEY_ID: &str = "none";
+
+/// Limits on backup chains. Without any limit every backup after the first is incremental.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsolidationPolicy {
+    /// Incrementals on top of a single full backup.
+    pub max_incrementals: Option<u32>,
+    /// Age of the full backup a chain starts with.
+    pub max_age_days: Option<u64>,
+    /// Combined size of the incrementals relative to their full backup, e.g. `0.5`.
+    pub max_incremental_size_ratio: Option<f64>,
+}
+
+impl RemoteStorageConfig {
+    /// Id recorded with new backups, `UNENCRYPTED_KEY_ID` unless they are encrypted.
+    pub fn current_key_id(&self) -> String {
+        match &self.encryption {
+            _ if !self.recipients.is_empty() => RECIPIENTS_KEY_ID.to_string(),
+            Some(_) => self
+                .encryption_key_id
+                .clone()
+                .unwrap_or_else(|| DEFAULT_KEY_ID.to_string()),
+            None => UNENCRYPTED_KEY_ID.to_string(),
+        }
+    }
+
+    /// Whether a backup recorded with `key_id` is encrypted like new backups are.
+    pub fn is_current_key(&self, key_id: Option<&str>) -> bool {
+        // Backups made before key ids were recorded are taken to use the default key
+        key_id.unwrap_or(DEFAULT_KEY_ID) == self.current_key_id()
+    }
+
+    /// Password of the key `key_id`, either the current one or one from the keyring.
+    pub fn password_for(&self, key_id: Option<&str>) -> Option<&SensitiveString> {
+        let key_id = key_id.unwrap_or(DEFAULT_KEY_ID);
+        if self.current_key_id() == key_id {
+            return self.encryption.as_ref();
+        }
+        self.keyring
+            .iter()
+            .find(|key| key.id == key_id)
+            .map(|key| &key.password)
+    }
+
+    pub fn encryption_level(&self) -> EncryptionLevel {
+        if self.recipients.is_empty() {
+            self.encryption.clone().into()
+        } else {
+            EncryptionLevel::Recipients {
+                recipients: self.recipients.clone(),
+                identities: Vec::new(),
+            }
+        }
+    }
+}
+
+/// Grandfather-father-son retention. Every count keeps the newest backup of that many
+/// distinct periods (UTC), backups that retained incrementals build on are always kept.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Newest backups to keep regardless of their age. The latest backup is never pruned.
+    #[serde(default)]
+    pub keep_last: u32,
+    #[serde(default)]
+    pub hourly: u32,
+    #[serde(default)]
+    pub daily: u32,
+    #[serde(default)]
+    pub weekly: u32,
+    #[serde(default)]
+    pub monthly: u32,
+    #[serde(default)]
+    pub yearly: u32,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -52,6 +373,37 @@ pub enum RemoteDestination {
         port: Option<u16>,
         folder: PathBuf,
     },
+    /// Talks SFTP in process over one session per job.
+    Sftp {
+        username: String,
+        hostname: String,
+        port: Option<u16>,
+        folder: PathBuf,
+        /// Private key to log in with, the ssh agent is asked when unset.
+        identity_file: Option<PathBuf>,
+        identity_passphrase: Option<SensitiveString>,
+        /// The host key must be listed here, defaults to `~/.ssh/known_hosts`.
+        known_hosts: Option<PathBuf>,
+    },
+    /// Any S3 compatible object storage.
+    S3 {
+        bucket: String,
+        /// Prepended to every key, like `backups/host1/`.
+        #[serde(default)]
+        prefix: String,
+        region: String,
+        /// Like `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000`.
+        endpoint: String,
+        access_key_id: String,
+        secret_access_key: SensitiveString,
+    },
+    /// A WebDAV folder, like a Nextcloud share or a storage box.
+    WebDav {
+        /// The folder holding the backups, like `https://u1234.your-storagebox.de/backups/`.
+        url: String,
+        username: String,
+        password: SensitiveString,
+    },
     Local {
         folder: PathBuf,
     },
@@ -1,10 +1,13 @@
 use crate::config::DataDanceConfiguration;
 use crate::jobs::JobExecutor;
+use crate::scheduler::Scheduler;
 use std::net::SocketAddr;
 
 pub struct DataDanceContext {
     pub config: DataDanceConfiguration,
     pub executor: JobExecutor,
+    pub scheduler: Scheduler,
+    pub restore_drill_scheduler: Scheduler,
 }
 
 impl DataDanceContext {
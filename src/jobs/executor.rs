@@ -49,8 +49,10 @@ impl JobExecutor {
         let job = match job {
             JobVariant::Backup(backup_job) => {
                 let mut current_backup_guard = self.current_backup.lock().unwrap();
-                if current_backup_guard.is_some() {
-                    return Err(ExecutorError::JobAlreadyRunning);
+                if let Some(running) = current_backup_guard.as_deref() {
+                    return Err(ExecutorError::JobAlreadyRunning {
+                        running: running.name(),
+                    });
                 }
                 let job = Arc::new(backup_job);
                 current_backup_guard.deref_mut().replace(Arc::clone(&job));
@@ -58,8 +60,10 @@ impl JobExecutor {
             }
             JobVariant::Restoration(restoration_job) => {
                 let mut current_restoration_guard = self.current_restoration.lock().unwrap();
-                if current_restoration_guard.is_some() {
-                    return Err(ExecutorError::JobAlreadyRunning);
+                if let Some(running) = current_restoration_guard.as_deref() {
+                    return Err(ExecutorError::JobAlreadyRunning {
+                        running: running.name(),
+                    });
                 }
                 let job = Arc::new(restoration_job);
                 current_restoration_guard
@@ -87,12 +91,12 @@ impl JobExecutor {
             let persist_history_result: io::Result<()> = try {
                 let mut history: JobHistory = {
                     let handle = File::open(history_path.clone())?;
-                    serde_json::from_reader(BufReader::new(handle))?
+                    serde_json::from_reader(BufReader::new(handle)).map_err(io::Error::from)?
                 };
                 history.entries.push(result);
                 {
                     let handle = File::create(history_path.clone())?;
-                    serde_json::to_writer(BufWriter::new(handle), &history)?;
+                    serde_json::to_writer(BufWriter::new(handle), &history).map_err(io::Error::from)?;
                 }
             };
             if let Err(err) = persist_history_result {
@@ -118,14 +122,30 @@ impl JobExecutor {
         let current_restoration = self.current_restoration.lock().unwrap();
 
         JobStates {
-            restore: None,
+            restore: current_restoration.as_deref().map(|job| match job {
+                RestorationJobVariant::DataRestoration(restore_job) => restore_job.stats(),
+            }),
             backup: current_backup
                 .as_deref()
                 .map(|job| match job {
-                    BackupJobVariant::FullDataBackup() => None,
+                    BackupJobVariant::FullDataBackup(full_job) => {
+                        Some(BackupJobState::Full(full_job.stats()))
+                    }
                     BackupJobVariant::IncrementalDataBackup(incremental_job) => {
                         Some(BackupJobState::Incremental(incremental_job.stats()))
                     }
+                    BackupJobVariant::Rekey(rekey_job) => {
+                        Some(BackupJobState::Rekey(rekey_job.stats()))
+                    }
+                    BackupJobVariant::Migrate(migrate_job) => {
+                        Some(BackupJobState::Migrate(migrate_job.stats()))
+                    }
+                    BackupJobVariant::Verify(verify_job) => {
+                        Some(BackupJobState::Verify(verify_job.stats()))
+                    }
+                    BackupJobVariant::RestoreDrill(drill_job) => {
+                        Some(BackupJobState::RestoreDrill(drill_job.stats()))
+                    }
                 })
                 .flatten(),
         }
@@ -157,16 +177,22 @@ impl JobVariantReference {
     pub fn run(&self) -> JobResult {
         match self {
             JobVariantReference::Backup(job) => match job.deref() {
-                BackupJobVariant::FullDataBackup() => {
-                    todo!()
+                BackupJobVariant::FullDataBackup(full_job) => {
+                    JobResult::FullDataBackup(full_job.run())
                 }
                 BackupJobVariant::IncrementalDataBackup(incremental_job) => {
                     JobResult::IncrementalBackup(incremental_job.run())
                 }
+                BackupJobVariant::Rekey(rekey_job) => JobResult::Rekey(rekey_job.run()),
+                BackupJobVariant::Migrate(migrate_job) => JobResult::Migrate(migrate_job.run()),
+                BackupJobVariant::Verify(verify_job) => JobResult::Verify(verify_job.run()),
+                BackupJobVariant::RestoreDrill(drill_job) => {
+                    JobResult::RestoreDrill(drill_job.run())
+                }
             },
-            JobVariantReference::Restoration(job) => match job {
-                &_ => {
-                    todo!()
+            JobVariantReference::Restoration(job) => match job.deref() {
+                RestorationJobVariant::DataRestoration(restore_job) => {
+                    JobResult::Restore(restore_job.run())
                 }
             },
         }
@@ -175,6 +201,6 @@ impl JobVariantReference {
 
 #[derive(Clone, Copy, Debug, Error)]
 pub enum ExecutorError {
-    #[error("Job already running")]
-    JobAlreadyRunning,
+    #[error("Job already running: {running}")]
+    JobAlreadyRunning { running: &'static str },
 }
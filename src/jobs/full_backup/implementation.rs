@@ -0,0 +1,105 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::full_backup::state::FullDataBackupJobState;
+use crate::jobs::full_backup::{FullDataBackupJob, FullDataBackupJobOptions};
+use crate::jobs::Job;
+use crate::objects;
+use crate::objects::job_state::{FetchingMetadataState, FullDataBackupStage, FullDataBackupUploadState};
+use crate::objects::EncryptionLevel;
+use crate::services::data_dest::{dest_service_from_config, dest_service_from_destination};
+use std::ops::Deref;
+
+impl Job for FullDataBackupJob {
+    type CompletionStats = objects::job_result::FullDataBackupResult;
+    type RunningStats = objects::job_state::FullDataBackupState;
+
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let dest_service = dest_service_from_config(&config)?;
+        let full_backup_config = config.full_backup.clone();
+
+        let options = FullDataBackupJobOptions {
+            filesystem_root: full_backup_config
+                .as_ref()
+                .map(|full_backup| full_backup.filesystem_root.clone()),
+            remote_root: full_backup_config
+                .as_ref()
+                .map(|full_backup| full_backup.remote_root.clone())
+                .unwrap_or_default(),
+            compression: config.remote_storage.compression,
+            encryption: config.remote_storage.encryption_level(),
+            key_id: Some(config.remote_storage.current_key_id()),
+            retention: full_backup_config.and_then(|full_backup| full_backup.retention),
+        };
+
+        let replicas = config.remote_storage.replicas.clone();
+        replicas.into_iter().try_fold(
+            FullDataBackupJob::new(options, dest_service),
+            |job, replica| {
+                let service = dest_service_from_destination(replica.dest)?;
+                Ok(job.with_replica(replica.name, replica.policy, service))
+            },
+        )
+    }
+
+    fn run(&self) -> Self::CompletionStats {
+        let started_at = chrono::Utc::now();
+        self.set_internal_state(FullDataBackupJobState::Started { started_at });
+
+        let result = self.run_impl();
+
+        let finished_at = chrono::Utc::now();
+
+        objects::job_result::FullDataBackupResult {
+            started_at,
+            finished_at,
+            state: match result {
+                Ok(result) => objects::job_result::FullDataBackupResultState::Success(result),
+                Err(err) => objects::job_result::FullDataBackupResultState::Error(err.to_string()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Self::RunningStats {
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            FullDataBackupJobState::Initial => objects::job_state::FullDataBackupState {
+                started_at: chrono::Utc::now(),
+                stage: FetchingMetadataState.into(),
+            },
+            FullDataBackupJobState::Started { started_at } => {
+                objects::job_state::FullDataBackupState {
+                    started_at: *started_at,
+                    stage: FetchingMetadataState.into(),
+                }
+            }
+            FullDataBackupJobState::Uploading {
+                started_at,
+                uploading_state,
+            } => objects::job_state::FullDataBackupState {
+                started_at: *started_at,
+                stage: FullDataBackupStage::Uploading(FullDataBackupUploadState {
+                    timestamp: chrono::Utc::now(),
+                    filesystem_root: self
+                        .options
+                        .filesystem_root
+                        .as_ref()
+                        .map(|root| root.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    remote_filename: uploading_state
+                        .remote_path_relative
+                        .to_string_lossy()
+                        .to_string(),
+                    bytes_read: uploading_state.read_bytes.value(),
+                    bytes_written: uploading_state.written_bytes.value(),
+                    compression: self.encoding_data_tunnel.compression,
+                    encrypted: match &self.encoding_data_tunnel.encryption_level {
+                        EncryptionLevel::None => false,
+                        EncryptionLevel::Symmetrical { .. } => true,
+                        EncryptionLevel::Recipients { .. } => true,
+                    },
+                    finishing: uploading_state.finishing,
+                }),
+            },
+        }
+    }
+}
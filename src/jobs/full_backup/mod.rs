@@ -1,13 +1,96 @@
-use crate::objects::{CompressionLevel, EncryptionLevel};
+mod implementation;
+mod run;
+mod state;
+#[cfg(test)]
+mod tests;
+
+use crate::config::{ReplicationPolicy, RetentionPolicy};
+use crate::jobs::full_backup::run::FullDataBackupRunError;
+use crate::jobs::full_backup::state::FullDataBackupJobState;
+use crate::jobs::replica::Replica;
+use crate::objects::{BackupSource, Compression, EncryptionLevel};
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::EncodingDataTunnel;
+use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
+use std::sync::Mutex;
 
 pub struct FullDataBackupJobOptions {
-    pub filesystem_root: PathBuf,
+    /// `None` if no full backup is configured, the job then fails right away.
+    pub filesystem_root: Option<PathBuf>,
+    /// Folder inside the remote destination the archives are uploaded to.
     pub remote_root: PathBuf,
-    pub compression: CompressionLevel,
+    pub compression: Compression,
     pub encryption: EncryptionLevel,
+    pub key_id: Option<String>,
+    /// Applies to the archives only.
+    pub retention: Option<RetentionPolicy>,
 }
 
 pub struct FullDataBackupJob {
     options: FullDataBackupJobOptions,
+    encoding_data_tunnel: EncodingDataTunnel,
+
+    remote_service: Mutex<Box<dyn DestService + Send>>,
+    /// Receive the same archive as `remote_service`, see `ReplicaConfig`.
+    replicas: Vec<Replica>,
+
+    state: Mutex<FullDataBackupJobState>,
+}
+
+impl FullDataBackupJob {
+    pub fn new(
+        options: FullDataBackupJobOptions,
+        remote_service: Box<dyn DestService + Send>,
+    ) -> Self {
+        let data_tunnel = EncodingDataTunnel {
+            compression: options.compression,
+            encryption_level: options.encryption.clone(),
+            source: BackupSource::Tar,
+            parent: None,
+            compression_mix: Default::default(),
+        };
+
+        Self {
+            options,
+            encoding_data_tunnel: data_tunnel,
+
+            remote_service: Mutex::new(remote_service),
+            replicas: Vec::new(),
+
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn with_replica(
+        mut self,
+        name: impl Into<String>,
+        policy: ReplicationPolicy,
+        service: Box<dyn DestService + Send>,
+    ) -> Self {
+        self.replicas.push(Replica::new(name, policy, service));
+        self
+    }
+
+    pub fn set_internal_state(&self, new_state: FullDataBackupJobState) {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            let state = state_lock.deref_mut();
+            *state = new_state
+        }
+    }
+
+    pub fn update_internal_state(
+        &self,
+        map_state: impl Fn(
+            &FullDataBackupJobState,
+        ) -> Result<FullDataBackupJobState, FullDataBackupRunError>,
+    ) -> Result<(), FullDataBackupRunError> {
+        let mut state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        let new_state = map_state(state)?;
+        drop(state_lock);
+        self.set_internal_state(new_state);
+        Ok(())
+    }
 }
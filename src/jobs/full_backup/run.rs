@@ -0,0 +1,254 @@
+use crate::jobs::full_backup::state::{FullDataBackupJobState, FullDataBackupJobUploadState};
+use crate::jobs::full_backup::FullDataBackupJob;
+use crate::jobs::replica::{ReplicaError, ReplicaUpload};
+use crate::objects::job_result::FullDataBackupUploadResult;
+use crate::objects::{BackupEntry, BackupHistory, BackupSource, BackupType, EncryptionLevel, Path};
+use crate::services::archive::TarArchiveReader;
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::DataTunnel;
+use std::ops::Deref;
+use std::path::PathBuf;
+use thiserror::Error;
+
+impl FullDataBackupJob {
+    pub fn run_impl(&self) -> Result<FullDataBackupUploadResult, FullDataBackupRunError> {
+        let Some(filesystem_root) = &self.options.filesystem_root else {
+            return Err(FullDataBackupRunError::NotConfigured);
+        };
+        let mut history = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock.backup_history().map_err(|err| {
+                FullDataBackupRunError::IoError {
+                    stage: FullDataBackupRunStage::FetchingMetadata,
+                    source: err,
+                }
+            })?
+        };
+
+        let mut replicas =
+            ReplicaUpload::start(&self.replicas, FullDataBackupRunStage::FetchingMetadata)?;
+
+        let archive_reader = TarArchiveReader::new(filesystem_root.clone())
+            .map_err(|err| FullDataBackupRunError::IoError {
+                stage: FullDataBackupRunStage::Archiving,
+                source: err,
+            })?;
+
+        let now = chrono::Utc::now();
+        let archive_name = format!("files_{}", now.format("%Y-%m-%d-%H-%M-%S"));
+        let dest_filename = self
+            .options
+            .remote_root
+            .join(PathBuf::from(&archive_name).with_added_extension("bin"));
+
+        let dest_writer = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock
+                .get_backup_writer(dest_filename.clone())
+                .map_err(|err| FullDataBackupRunError::IoError {
+                    stage: FullDataBackupRunStage::Uploading,
+                    source: err,
+                })?
+        };
+        let (dest_writer, upload_failures) =
+            replicas.fan_out(dest_writer, &dest_filename, FullDataBackupRunStage::Uploading)?;
+
+        let transfer = self
+            .encoding_data_tunnel
+            .clone()
+            .tracked_transfer(archive_reader, dest_writer);
+
+        self.update_internal_state(|old_state| {
+            let started_at = match old_state {
+                FullDataBackupJobState::Started { started_at } => started_at,
+                FullDataBackupJobState::Uploading { started_at, .. } => started_at,
+                _ => Err(FullDataBackupRunError::ConcurrentStateManipulation {
+                    message: "Cannot be initial state when upload starts".to_string(),
+                })?,
+            };
+
+            Ok(FullDataBackupJobState::Uploading {
+                started_at: *started_at,
+                uploading_state: FullDataBackupJobUploadState {
+                    remote_path_relative: dest_filename.clone(),
+                    read_bytes: transfer.reader_bytes_counter(),
+                    written_bytes: transfer.writer_bytes_counter(),
+                    finishing: false,
+                },
+            })
+        })?;
+
+        let mut uploaded = transfer.run();
+        let upload_error = replicas.writers_failed(
+            upload_failures.take(),
+            &dest_filename,
+            FullDataBackupRunStage::Uploading,
+        )?;
+        if let Some(err) = upload_error
+            && uploaded.is_ok()
+        {
+            uploaded = Err(err);
+        }
+        let uploaded = uploaded.and_then(|()| {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock
+                .verify_backup_file_size(dest_filename.clone(), transfer.writer_bytes_count())
+        });
+        if let Err(err) = uploaded {
+            // Some destinations complete whatever was written when the writer is dropped. The
+            // archives are not at the top of the destinations, so no orphan cleanup finds them.
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            let _ = remote_service_lock.remove_backup_file(dest_filename.clone());
+            replicas.remove_uploads(&dest_filename);
+            return Err(FullDataBackupRunError::IoError {
+                stage: FullDataBackupRunStage::Uploading,
+                source: err,
+            });
+        }
+        replicas.verify_sizes(
+            &dest_filename,
+            transfer.writer_bytes_count(),
+            FullDataBackupRunStage::Uploading,
+        )?;
+
+        self.update_internal_state(|old_state| match old_state {
+            FullDataBackupJobState::Uploading {
+                started_at,
+                uploading_state: old_upload_state,
+            } => Ok(FullDataBackupJobState::Uploading {
+                started_at: *started_at,
+                uploading_state: FullDataBackupJobUploadState {
+                    finishing: true,
+                    ..old_upload_state.clone()
+                },
+            }),
+            _ => Err(FullDataBackupRunError::ConcurrentStateManipulation {
+                message: "Cannot switch states when upload finished".to_string(),
+            }),
+        })?;
+
+        let new_backup_id = now.timestamp() as u32;
+        let new_backup_entry = BackupEntry {
+            id: new_backup_id,
+            parent: None,
+            timestamp: now.timestamp_millis() as u64,
+            remote_filename: Path::from(dest_filename.clone()),
+            local_snapshot: Path::from(filesystem_root.clone()),
+            backup_type: BackupType::Full,
+            size: Some(transfer.writer_bytes_counter().value()),
+            key_id: self.options.key_id.clone(),
+            compression: Some(self.encoding_data_tunnel.compression),
+            source_size: Some(transfer.reader_bytes_count()),
+            source_hash: Some(transfer.reader_hash()),
+            // Every replica was sent the same bytes
+            stored_hash: Some(transfer.writer_hash()),
+            manifest: None,
+            chunk_count: None,
+            source: Some(BackupSource::Tar),
+            rekey_pending: false,
+        };
+        history.entries.push(new_backup_entry.clone());
+        self.store_history(&**self.remote_service.lock().unwrap(), history)
+            .map_err(|(stage, err)| FullDataBackupRunError::IoError { stage, source: err })?;
+
+        replicas.store_histories(&new_backup_entry, |service, replica_history| {
+            self.store_history(service, replica_history)
+        })?;
+
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            FullDataBackupJobState::Uploading {
+                uploading_state, ..
+            } => Ok(FullDataBackupUploadResult {
+                id: new_backup_id,
+                filesystem_root: filesystem_root.to_string_lossy().to_string(),
+                remote_filename: dest_filename.to_string_lossy().to_string(),
+                bytes_read: uploading_state.read_bytes.value(),
+                bytes_written: uploading_state.written_bytes.value(),
+                compression: self.encoding_data_tunnel.compression,
+                encrypted: match self.encoding_data_tunnel.encryption_level {
+                    EncryptionLevel::None => false,
+                    EncryptionLevel::Symmetrical { .. } => true,
+                    EncryptionLevel::Recipients { .. } => true,
+                },
+                replicas: replicas.results(),
+            }),
+            _ => Err(FullDataBackupRunError::ConcurrentStateManipulation {
+                message: "Initial state".to_string(),
+            }),
+        }
+    }
+
+    /// Records the new archive in the history of a destination and applies the retention of
+    /// the archives to it.
+    fn store_history(
+        &self,
+        service: &dyn DestService,
+        mut history: BackupHistory,
+    ) -> Result<(), (FullDataBackupRunStage, std::io::Error)> {
+        service
+            .set_backup_history(history.clone())
+            .map_err(|err| (FullDataBackupRunStage::StoringMetadata, err))?;
+
+        let expired = match &self.options.retention {
+            Some(retention) => history.prune_archives(retention),
+            None => Vec::new(),
+        };
+        if !expired.is_empty() {
+            service
+                .set_backup_history(history.clone())
+                .map_err(|err| (FullDataBackupRunStage::PruningHistory, err))?;
+            // Only files at the top of the destination are cleared as orphans
+            for entry in expired {
+                let _ = service.remove_backup_file(entry.remote_filename.to_path_buf());
+            }
+        }
+
+        service
+            .clear_orphaned_backups(&history)
+            .map_err(|err| (FullDataBackupRunStage::ClearingOrphanedBackups, err))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum FullDataBackupRunError {
+    #[error("no full backup is configured")]
+    NotConfigured,
+    #[error("IO error during full backup stage {stage:?}")]
+    IoError {
+        stage: FullDataBackupRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("IO error on replica '{name}' during full backup stage {stage:?}: {source}")]
+    ReplicaIoError {
+        name: String,
+        stage: FullDataBackupRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
+    ConcurrentStateManipulation { message: String },
+}
+
+impl From<ReplicaError<FullDataBackupRunStage>> for FullDataBackupRunError {
+    fn from(err: ReplicaError<FullDataBackupRunStage>) -> Self {
+        FullDataBackupRunError::ReplicaIoError {
+            name: err.name,
+            stage: err.stage,
+            source: err.source,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FullDataBackupRunStage {
+    FetchingMetadata,
+    Archiving,
+    Uploading,
+    StoringMetadata,
+    PruningHistory,
+    ClearingOrphanedBackups,
+}
@@ -0,0 +1,27 @@
+use crate::services::tracking::BytesCounter;
+use std::path::PathBuf;
+
+pub(crate) enum FullDataBackupJobState {
+    Initial,
+    Started {
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Uploading {
+        started_at: chrono::DateTime<chrono::Utc>,
+        uploading_state: FullDataBackupJobUploadState,
+    },
+}
+
+#[derive(Clone)]
+pub struct FullDataBackupJobUploadState {
+    pub remote_path_relative: PathBuf,
+    pub read_bytes: BytesCounter,
+    pub written_bytes: BytesCounter,
+    pub finishing: bool,
+}
+
+impl Default for FullDataBackupJobState {
+    fn default() -> Self {
+        Self::Initial
+    }
+}
@@ -0,0 +1,224 @@
+use crate::config::{ReplicationPolicy, RetentionPolicy};
+use crate::jobs::full_backup::{FullDataBackupJob, FullDataBackupJobOptions};
+use crate::jobs::Job;
+use crate::objects::job_result::FullDataBackupResultState;
+use crate::objects::{
+    BackupEntry, BackupHistory, BackupSource, BackupType, Compression, CompressionAlgorithm,
+    CompressionLevel, EncryptionLevel,
+};
+use crate::services::channels::ChannelWriter;
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::{DataTunnel, DecodingDataTunnel};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+fn make_filesystem_root() -> PathBuf {
+    let root = std::env::temp_dir().join(format!("data-dance-full-{}", rand::random::<u64>()));
+    fs::create_dir_all(root.join("documents")).unwrap();
+    fs::write(root.join("notes.txt"), b"some notes").unwrap();
+    fs::write(root.join("documents/report.txt"), b"quarterly report").unwrap();
+    root
+}
+
+#[test]
+fn full_backup_uploads_tar_archive() {
+    let root = make_filesystem_root();
+    let password = "123456";
+
+    let fake_dest = FakeDestService::new(BackupHistory::default());
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let job = FullDataBackupJob::new(
+        FullDataBackupJobOptions {
+            filesystem_root: Some(root.clone()),
+            remote_root: PathBuf::new(),
+            compression: Compression::new(CompressionAlgorithm::Xz, 3),
+            encryption: EncryptionLevel::Symmetrical {
+                password: password.into(),
+            },
+            key_id: None,
+            retention: None,
+        },
+        Box::new(fake_dest),
+    );
+    let result = job.run();
+    fs::remove_dir_all(&root).unwrap();
+
+    let result = match result.state {
+        FullDataBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        FullDataBackupResultState::Success(result) => result,
+    };
+    assert_eq!(result.encrypted, true);
+    assert!(result.remote_filename.ends_with(".bin"));
+
+    let history = fake_dest_debug.history();
+    let entry = history.entries.last().unwrap();
+    assert_eq!(entry.backup_type, BackupType::Full);
+    assert_eq!(entry.parent, None);
+    assert_eq!(entry.id, result.id);
+
+    let encoded = fake_dest_debug.file(&result.remote_filename).unwrap();
+    let (tx, rx) = mpsc::channel();
+    DecodingDataTunnel {
+        compression: CompressionAlgorithm::Zstd,
+        encryption_level: EncryptionLevel::Symmetrical {
+            password: password.into(),
+        },
+    }
+    .transfer(Cursor::new(encoded), ChannelWriter::new(tx))
+    .unwrap();
+    let archive_bytes: Vec<u8> = rx.iter().collect();
+    assert_eq!(archive_bytes.len() as u64, result.bytes_read);
+
+    let mut archive = tar::Archive::new(Cursor::new(archive_bytes));
+    let mut files = Vec::new();
+    for archive_entry in archive.entries().unwrap() {
+        let mut archive_entry = archive_entry.unwrap();
+        if archive_entry.header().entry_type().is_file() {
+            let mut content = String::new();
+            archive_entry.read_to_string(&mut content).unwrap();
+            files.push((archive_entry.path().unwrap().to_path_buf(), content));
+        }
+    }
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            (
+                PathBuf::from("documents/report.txt"),
+                "quarterly report".to_string()
+            ),
+            (PathBuf::from("notes.txt"), "some notes".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn full_backup_missing_root_fails() {
+    let fake_dest = FakeDestService::new(BackupHistory::default());
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let job = FullDataBackupJob::new(
+        FullDataBackupJobOptions {
+            filesystem_root: Some(std::env::temp_dir().join("data-dance-does-not-exist")),
+            remote_root: PathBuf::new(),
+            compression: CompressionLevel::Fast.into(),
+            encryption: EncryptionLevel::None,
+            key_id: None,
+            retention: None,
+        },
+        Box::new(fake_dest),
+    );
+
+    assert!(matches!(
+        job.run().state,
+        FullDataBackupResultState::Error(_)
+    ));
+    assert!(fake_dest_debug.history().entries.is_empty());
+}
+
+#[test]
+fn full_backup_without_config_fails() {
+    let fake_dest = FakeDestService::new(BackupHistory::default());
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let job = FullDataBackupJob::new(
+        FullDataBackupJobOptions {
+            filesystem_root: None,
+            remote_root: PathBuf::new(),
+            compression: CompressionLevel::Fast.into(),
+            encryption: EncryptionLevel::None,
+            key_id: None,
+            retention: None,
+        },
+        Box::new(fake_dest),
+    );
+
+    match job.run().state {
+        FullDataBackupResultState::Error(err) => assert_eq!(err, "no full backup is configured"),
+        FullDataBackupResultState::Success(_) => panic!("Job without a config succeeded"),
+    }
+    assert!(fake_dest_debug.history().entries.is_empty());
+}
+
+#[test]
+fn full_backup_prunes_old_archives_in_remote_root() {
+    let root = make_filesystem_root();
+    let entry = |id: u32, remote_filename: &str, source| BackupEntry {
+        timestamp: id as u64 * 1000,
+        remote_filename: remote_filename.into(),
+        local_snapshot: "snapshot/".into(),
+        source,
+        ..BackupEntry::test(id, None)
+    };
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: vec![
+            entry(1, "1.bin", None),
+            entry(2, "archives/files_old.bin", Some(BackupSource::Tar)),
+        ],
+    });
+    let fake_dest_debug = fake_dest.live_debug_data();
+    (fake_dest.get_backup_writer("archives/files_old.bin".into()))
+        .unwrap()
+        .write_all(b"old archive")
+        .unwrap();
+    assert!(fake_dest_debug.file("archives/files_old.bin").is_some());
+    let job = FullDataBackupJob::new(
+        FullDataBackupJobOptions {
+            filesystem_root: Some(root.clone()),
+            remote_root: PathBuf::from("archives"),
+            compression: CompressionLevel::Fast.into(),
+            encryption: EncryptionLevel::None,
+            key_id: None,
+            retention: Some(RetentionPolicy::default()),
+        },
+        Box::new(fake_dest),
+    );
+    let result = job.run();
+    fs::remove_dir_all(&root).unwrap();
+
+    let result = match result.state {
+        FullDataBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        FullDataBackupResultState::Success(result) => result,
+    };
+    assert!(result.remote_filename.starts_with("archives/"));
+    let ids: Vec<u32> = (fake_dest_debug.history().entries.iter())
+        .map(|entry| entry.id)
+        .collect();
+    assert_eq!(ids, vec![1, result.id]);
+    assert!(fake_dest_debug.file(&result.remote_filename).is_some());
+    assert!(fake_dest_debug.file("archives/files_old.bin").is_none());
+}
+
+#[test]
+fn full_backup_uploads_archive_to_replicas() {
+    let root = make_filesystem_root();
+    let dest = FakeDestService::new(BackupHistory::default());
+    let replica = FakeDestService::new(BackupHistory::default());
+    let dest_debug = dest.live_debug_data();
+    let replica_debug = replica.live_debug_data();
+    let job = FullDataBackupJob::new(
+        FullDataBackupJobOptions {
+            filesystem_root: Some(root.clone()),
+            remote_root: PathBuf::from("archives"),
+            compression: CompressionLevel::Fast.into(),
+            encryption: EncryptionLevel::None,
+            key_id: None,
+            retention: None,
+        },
+        Box::new(dest),
+    )
+    .with_replica("nas", ReplicationPolicy::Required, Box::new(replica));
+    let result = job.run();
+    fs::remove_dir_all(&root).unwrap();
+
+    let result = match result.state {
+        FullDataBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        FullDataBackupResultState::Success(result) => result,
+    };
+    assert_eq!(result.replicas.len(), 1);
+    assert_eq!(result.replicas[0].error, None);
+    let uploaded = dest_debug.file(&result.remote_filename).unwrap();
+    assert_eq!(replica_debug.file(&result.remote_filename), Some(uploaded));
+    assert_eq!(replica_debug.history(), dest_debug.history());
+}
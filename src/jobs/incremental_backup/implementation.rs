@@ -2,17 +2,12 @@ use crate::config::DataDanceConfiguration;
 use crate::jobs::incremental_backup::state::IncrementalBackupJobState;
 use crate::jobs::incremental_backup::IncrementalBackupJob;
 use crate::jobs::Job;
+use crate::objects;
 use crate::objects::job_result::IncrementalBackupUploadResult;
 use crate::objects::job_state::{FetchingMetadataState, IncrementalBackupStage};
 use crate::objects::{CompressionLevel, EncryptionLevel};
-use crate::services::data_dest::bare_fs::BareFsDestService;
-use crate::services::data_dest::fake::FakeDestService;
-use crate::services::data_dest::ssh::SshDestService;
-use crate::services::data_dest::DestService;
-use crate::services::data_source::btrfs::BtrfsSourceService;
-use crate::services::data_source::fake::FakeSourceService;
-use crate::services::data_source::SourceService;
-use crate::{config, objects};
+use crate::services::data_dest::{dest_service_from_config, dest_service_from_destination};
+use crate::services::data_source::source_service_from_config;
 use objects::job_state::IncrementalBackupUploadState;
 use std::ops::{Deref, DerefMut};
 
@@ -20,35 +15,18 @@ impl Job for IncrementalBackupJob {
     type CompletionStats = objects::job_result::IncrementalBackupResult;
     type RunningStats = objects::job_state::IncrementalBackupState;
 
-    fn from_config(config: DataDanceConfiguration) -> Self {
-        let src_service = match config.local_storage.source.clone() {
-            config::LocalSource::Btrfs {
-                snapshots_folder,
-                source_folder,
-                send_compressed_data,
-            } => Box::new(BtrfsSourceService::new(
-                snapshots_folder,
-                source_folder,
-                send_compressed_data,
-            )) as Box<dyn SourceService + Send>,
-            config::LocalSource::Fake { backup_byte_size } => Box::new(FakeSourceService::new(
-                "fake_snapshot".into(),
-                backup_byte_size,
-            ))
-                as Box<dyn SourceService + Send>,
-        };
-        let dest_service: Box<dyn DestService + Send> = match config.remote_storage.dest.clone() {
-            config::RemoteDestination::Local { folder } => Box::new(BareFsDestService::new(folder)),
-            config::RemoteDestination::Ssh {
-                hostname,
-                port,
-                username,
-                folder,
-            } => Box::new(SshDestService::new(port, hostname, username, folder)),
-            config::RemoteDestination::Fake => Box::new(FakeDestService::empty()),
-        };
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let src_service = source_service_from_config(&config);
+        let dest_service = dest_service_from_config(&config)?;
+        let replicas = config.remote_storage.replicas.clone();
 
-        IncrementalBackupJob::new(config, src_service, dest_service)
+        replicas.into_iter().try_fold(
+            IncrementalBackupJob::new(config, src_service, dest_service),
+            |job, replica| {
+                let service = dest_service_from_destination(replica.dest)?;
+                Ok(job.with_replica(replica.name, replica.policy, service))
+            },
+        )
     }
 
     fn run(&self) -> Self::CompletionStats {
@@ -103,10 +81,11 @@ impl Job for IncrementalBackupJob {
                         .to_string(),
                     bytes_read: uploading_state.read_bytes.value(),
                     bytes_written: uploading_state.written_bytes.value(),
-                    compression_level: self.encoding_data_tunnel.compression_level,
+                    compression: self.encoding_data_tunnel.compression,
                     encrypted: match &self.encoding_data_tunnel.encryption_level {
                         EncryptionLevel::None => false,
                         EncryptionLevel::Symmetrical { .. } => true,
+                        EncryptionLevel::Recipients { .. } => true,
                     },
                     finishing: uploading_state.finishing,
                 }),
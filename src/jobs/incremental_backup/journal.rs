@@ -0,0 +1,67 @@
+use crate::services::chunk_store::ChunkRef;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Progress of a resumable upload, kept in the jobs folder until the backup is recorded in the
+/// history. A run finding it sends the same snapshot again instead of making a new one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct UploadJournal {
+    pub local_snapshot: PathBuf,
+    pub parent: Option<u32>,
+    /// The segments confirmed by the remote, numbered by their position in the stream. Those the
+    /// resent stream still starts with are skipped.
+    pub segments: Vec<JournalSegment>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct JournalSegment {
+    pub id: String,
+    pub size: u64,
+}
+
+impl UploadJournal {
+    pub fn path(jobs_folder: &Path) -> PathBuf {
+        jobs_folder.join("upload_journal.json")
+    }
+
+    /// Returns `None` if there is no journal.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let handle = match File::open(path) {
+            Ok(handle) => handle,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        Ok(Some(serde_json::from_reader(BufReader::new(handle))?))
+    }
+
+    /// Replaces the journal at `path` at once, so an interruption leaves the previous one.
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        let part_path = path.with_added_extension("part");
+        {
+            let mut writer = BufWriter::new(File::create(&part_path)?);
+            serde_json::to_writer(&mut writer, self)?;
+            writer.flush()?;
+        }
+        std::fs::rename(part_path, path)
+    }
+
+    /// Records segment `number` of the stream, the segments after it belonged to an attempt
+    /// that failed.
+    pub fn confirm(&mut self, number: usize, segment: &ChunkRef) {
+        self.segments.truncate(number);
+        self.segments.push(JournalSegment {
+            id: segment.id.clone(),
+            size: segment.size,
+        });
+    }
+
+    pub fn remove(path: &Path) -> io::Result<()> {
+        match std::fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,23 +1,43 @@
 mod implementation;
+mod journal;
 mod run;
 mod state;
 #[cfg(test)]
 mod tests;
 
-use crate::config::DataDanceConfiguration;
+use crate::config::{
+    ConsolidationPolicy, DataDanceConfiguration, RemoteStorageConfig, ReplicationPolicy,
+    RetentionPolicy,
+};
+use crate::jobs::incremental_backup::journal::UploadJournal;
 use crate::jobs::incremental_backup::run::IncrementalBackupRunError;
 use crate::jobs::incremental_backup::state::IncrementalBackupJobState;
+use crate::jobs::replica::Replica;
+use crate::objects::{BackupSource, CompressionAlgorithm};
 use crate::services::data_dest::DestService;
 use crate::services::data_source::SourceService;
-use crate::services::data_tunnel::{DataTunnel, EncodingDataTunnel, TrackedTransfer};
+use crate::services::data_tunnel::{
+    DataTunnel, DecodingDataTunnel, EncodingDataTunnel, TrackedTransfer,
+};
 use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 use std::sync::Mutex;
 
 pub struct IncrementalBackupJob {
     encoding_data_tunnel: EncodingDataTunnel,
+    /// Recorded with every new backup, see `RemoteStorageConfig::current_key_id`.
+    key_id: Option<String>,
+    retention: Option<RetentionPolicy>,
+    consolidation: ConsolidationPolicy,
+    /// Reads the chunk indexes of the history to collect the garbage of the chunk repository.
+    decoding_data_tunnel: DecodingDataTunnel,
+    remote_storage: RemoteStorageConfig,
+    /// Where resumable uploads keep their `UploadJournal`.
+    journal_path: PathBuf,
 
     remote_service: Mutex<Box<dyn DestService + Send>>,
+    /// Receive the same stream as `remote_service`, see `ReplicaConfig`.
+    replicas: Vec<Replica>,
     local_service: Mutex<Box<dyn SourceService + Send>>,
 
     state: Mutex<IncrementalBackupJobState>,
@@ -30,20 +50,43 @@ impl IncrementalBackupJob {
         remote_service: Box<dyn DestService + Send>,
     ) -> Self {
         let data_tunnel = EncodingDataTunnel {
-            compression_level: config.remote_storage.compression,
-            encryption_level: config.remote_storage.encryption.clone().into(),
+            compression: config.remote_storage.compression,
+            encryption_level: config.remote_storage.encryption_level(),
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         Self {
             encoding_data_tunnel: data_tunnel,
+            key_id: Some(config.remote_storage.current_key_id()),
+            retention: config.remote_storage.retention.clone(),
+            consolidation: config.remote_storage.consolidation.clone(),
+            decoding_data_tunnel: DecodingDataTunnel {
+                compression: CompressionAlgorithm::Zstd,
+                encryption_level: config.remote_storage.encryption_level(),
+            },
+            remote_storage: config.remote_storage,
+            journal_path: UploadJournal::path(&config.local_storage.jobs_folder),
 
             remote_service: Mutex::new(remote_service),
+            replicas: Vec::new(),
             local_service: Mutex::new(local_service),
 
             state: Mutex::default(),
         }
     }
 
+    pub fn with_replica(
+        mut self,
+        name: impl Into<String>,
+        policy: ReplicationPolicy,
+        service: Box<dyn DestService + Send>,
+    ) -> Self {
+        self.replicas.push(Replica::new(name, policy, service));
+        self
+    }
+
     pub fn set_internal_state(&self, new_state: IncrementalBackupJobState) {
         {
             let mut state_lock = self.state.lock().unwrap();
@@ -2,14 +2,39 @@ use crate::jobs::incremental_backup::state::{
     IncrementalBackupJobState, IncrementalBackupJobUploadState,
 };
 use crate::jobs::incremental_backup::IncrementalBackupJob;
-use crate::objects::job_result::IncrementalBackupUploadResult;
-use crate::objects::job_state::IncrementalBackupUploadState;
-use crate::objects::{BackupEntry, BackupType, EncryptionLevel, Path};
-use crate::services::data_tunnel::{DataTunnel, TrackedTransfer};
-use rand::{random, thread_rng, Rng};
-use std::ops::{Deref, DerefMut};
+use crate::jobs::incremental_backup::journal::UploadJournal;
+use crate::jobs::replica::{ReplicaError, ReplicaUpload};
+use crate::objects::job_result::{
+    IncrementalBackupUploadResult, RepositoryUploadResult, ResumableUploadResult,
+};
+use crate::objects::{BackupEntry, BackupHistory, BackupSource, BackupType, EncryptionLevel, Path};
+use crate::services::chunk_store::{ChunkRef, ChunkStore, collect_garbage};
+use crate::services::data_dest::DestService;
+use crate::services::data_source::SourceBackup;
+use crate::services::data_tunnel::{
+    ChunkCut, ChunkStats, ChunkingDataTunnel, DataTunnel, EncodingDataTunnel,
+};
+use crate::services::tracking::CompressionMixCounter;
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 use thiserror::Error;
 
+/// Bytes and hash of the snapshot stream, then of what was stored.
+type UploadChecksums = (u64, String, u64, String);
+
+/// Outcome of an upload, chunked ones may have been resumed.
+struct Upload {
+    result: std::io::Result<UploadChecksums>,
+    /// Of the last attempt.
+    chunk_stats: ChunkStats,
+    retries: u32,
+    resumed_segments: u64,
+}
+
 impl IncrementalBackupJob {
     pub fn run_impl(&self) -> Result<IncrementalBackupUploadResult, IncrementalBackupRunError> {
         let mut history = {
@@ -21,16 +46,13 @@ impl IncrementalBackupJob {
                 }
             })?
         };
+        let mut replicas =
+            ReplicaUpload::start(&self.replicas, IncrementalBackupRunStage::FetchingMetadata)?;
 
-        let backup_src = {
-            let local_service_lock = self.local_service.lock().unwrap();
-            local_service_lock
-                .get_backup_source(&history)
-                .map_err(|err| IncrementalBackupRunError::IoError {
-                    stage: IncrementalBackupRunStage::CreatingSnapshot,
-                    source: err,
-                })?
-        };
+        // The parent of an incremental backup has to be on every required destination
+        let parent_candidates = replicas.shared_entries(&history);
+        let (backup_src, journal) = self.open_source(&parent_candidates)?;
+        let journal = journal.map(RefCell::new);
 
         let dest_filename = if backup_src.parent_backup_id.is_none() {
             backup_src
@@ -42,6 +64,27 @@ impl IncrementalBackupJob {
                 .with_added_extension("dbin")
         };
 
+        if let Some(parent) = backup_src.parent_backup_id {
+            replicas.require_entry(parent, IncrementalBackupRunStage::Uploading, || {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("the parent backup {parent} is missing until the next full backup"),
+                )
+            })?;
+        }
+
+        let cut = self.chunk_cut();
+        // The chunks are only stored on the remote, an index alone would be of no use to a
+        // replica. The configuration does not allow it, but the replicas are never sent one.
+        if cut.is_some() {
+            replicas.leave_out_all(IncrementalBackupRunStage::Uploading, || {
+                std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "chunked backups cannot be replicated",
+                )
+            })?;
+        }
+
         let dest_writer = {
             let remote_service_lock = self.remote_service.lock().unwrap();
             remote_service_lock
@@ -51,40 +94,59 @@ impl IncrementalBackupJob {
                     source: err,
                 })?
         };
+        let (dest_writer, upload_failures) = replicas.fan_out(
+            dest_writer,
+            &dest_filename,
+            IncrementalBackupRunStage::Uploading,
+        )?;
 
-        let transfer = self
-            .encoding_data_tunnel
-            .clone()
-            .tracked_transfer(backup_src.data_stream, dest_writer);
-
-        self.update_internal_state(|old_state| {
-            let started_at = match old_state {
-                IncrementalBackupJobState::Started { started_at } => started_at,
-                IncrementalBackupJobState::Uploading { started_at, .. } => started_at,
-                _ => Err(IncrementalBackupRunError::ConcurrentStateManipulation {
-                    message: "Cannot be initial state when upload starts".to_string(),
-                })?,
-            };
-
-            Ok(IncrementalBackupJobState::Uploading {
-                started_at: *started_at,
-                uploading_state: IncrementalBackupJobUploadState {
-                    parent_backup_id: backup_src.parent_backup_id,
-                    local_folder_relative: backup_src.local_snapshot_relative.clone(),
-                    remote_path_relative: dest_filename.clone(),
+        let compression_mix = CompressionMixCounter::default();
+        let encoding_data_tunnel = EncodingDataTunnel {
+            parent: backup_src.parent_backup_id,
+            compression_mix: compression_mix.clone(),
+            ..self.encoding_data_tunnel.clone()
+        };
+        let uploading_state = IncrementalBackupJobUploadState {
+            parent_backup_id: backup_src.parent_backup_id,
+            local_folder_relative: backup_src.local_snapshot_relative.clone(),
+            remote_path_relative: dest_filename.clone(),
+            read_bytes: Default::default(),
+            written_bytes: Default::default(),
+            finishing: false,
+        };
+        let upload = match cut {
+            None => {
+                let transfer =
+                    encoding_data_tunnel.tracked_transfer(backup_src.data_stream, dest_writer);
+                self.set_uploading(IncrementalBackupJobUploadState {
                     read_bytes: transfer.reader_bytes_counter(),
                     written_bytes: transfer.writer_bytes_counter(),
-                    finishing: false,
-                },
-            })
-        })?;
+                    ..uploading_state
+                })?;
+                Upload {
+                    result: transfer.run().map(|()| transfer.checksums()),
+                    chunk_stats: ChunkStats::default(),
+                    retries: 0,
+                    resumed_segments: 0,
+                }
+            }
+            Some(cut) => self.upload_chunked(
+                (backup_src.data_stream, Box::new(dest_writer)),
+                encoding_data_tunnel,
+                cut,
+                journal.as_ref(),
+                uploading_state,
+                &history,
+            )?,
+        };
+        let chunk_stats = upload.chunk_stats;
 
-        transfer
-            .run()
-            .map_err(|err| IncrementalBackupRunError::IoError {
-                stage: IncrementalBackupRunStage::Uploading,
-                source: err,
-            })?;
+        let (read_bytes, read_hash, written_bytes, written_hash) = self.verify_upload(
+            upload.result,
+            upload_failures.take(),
+            &mut replicas,
+            &dest_filename,
+        )?;
 
         self.update_internal_state(|old_state| match old_state {
             IncrementalBackupJobState::Uploading {
@@ -102,6 +164,14 @@ impl IncrementalBackupJob {
             }),
         })?;
 
+        // Only used by restore drills, so a snapshot that cannot be described is not fatal
+        let manifest = {
+            let local_service_lock = self.local_service.lock().unwrap();
+            local_service_lock.snapshot_manifest(backup_src.local_snapshot_relative.clone())
+        };
+        let manifest_error = manifest.as_ref().err().map(ToString::to_string);
+        let manifest = manifest.ok();
+
         let now = chrono::Utc::now();
         let new_backup_id = now.timestamp() as u32;
         let new_backup_entry = BackupEntry {
@@ -114,42 +184,25 @@ impl IncrementalBackupJob {
                 None => BackupType::Full,
                 Some(_) => BackupType::Incremental,
             },
+            size: Some(written_bytes),
+            key_id: self.key_id.clone(),
+            compression: Some(self.encoding_data_tunnel.compression),
+            source_size: Some(read_bytes),
+            source_hash: Some(read_hash),
+            // Every replica was sent the same bytes
+            stored_hash: Some(written_hash),
+            manifest,
+            chunk_count: cut.map(|_| chunk_stats.chunks()),
+            source: Some(BackupSource::BtrfsStream),
+            rekey_pending: false,
         };
 
-        history.entries.push(new_backup_entry);
-        {
-            let remote_service_lock = self.remote_service.lock().unwrap();
-            remote_service_lock
-                .set_backup_history(history.clone())
-                .map_err(|err| IncrementalBackupRunError::IoError {
-                    stage: IncrementalBackupRunStage::StoringMetadata,
-                    source: err,
-                })?
-        }
-
-        {
-            let local_service_lock = self.local_service.lock().unwrap();
-            local_service_lock
-                .clear_local_snapshots(&history)
-                .map_err(|err| IncrementalBackupRunError::IoError {
-                    stage: IncrementalBackupRunStage::ClearingSnapshots,
-                    source: err,
-                })?
-        };
-
-        {
-            let remote_service_lock = self.remote_service.lock().unwrap();
-            remote_service_lock
-                .clear_orphaned_backups(&history)
-                .map_err(|err| IncrementalBackupRunError::IoError {
-                    stage: IncrementalBackupRunStage::ClearingOrphanedBackups,
-                    source: err,
-                })?
-        };
-
-        fn convert_id_to_incremental_backup_job_id(id: u32) -> u32 {
-            id.wrapping_mul(10) / 10 * 10 + 1
-        }
+        history.entries.push(new_backup_entry.clone());
+        self.store_history(&mut history, journal.is_some())?;
+        replicas.store_histories(&new_backup_entry, |service, replica_history| {
+            self.store_replica_history(service, replica_history)
+        })?;
+        let removed_chunks = self.clean_up(&history)?;
 
         let state_lock = self.state.lock().unwrap();
         let state = state_lock.deref();
@@ -157,10 +210,8 @@ impl IncrementalBackupJob {
             IncrementalBackupJobState::Uploading {
                 uploading_state, ..
             } => Ok(IncrementalBackupUploadResult {
-                id: convert_id_to_incremental_backup_job_id(new_backup_id),
-                parent: uploading_state
-                    .parent_backup_id
-                    .map(convert_id_to_incremental_backup_job_id),
+                id: new_backup_id,
+                parent: uploading_state.parent_backup_id,
                 remote_filename: uploading_state
                     .remote_path_relative
                     .to_string_lossy()
@@ -171,17 +222,387 @@ impl IncrementalBackupJob {
                     .to_string(),
                 bytes_read: uploading_state.read_bytes.value(),
                 bytes_written: uploading_state.written_bytes.value(),
-                compression_level: self.encoding_data_tunnel.compression_level,
+                compression: self.encoding_data_tunnel.compression,
+                compression_mix: self
+                    .encoding_data_tunnel
+                    .compression
+                    .adaptive
+                    .map(|_| compression_mix.value()),
                 encrypted: match self.encoding_data_tunnel.encryption_level {
                     EncryptionLevel::None => false,
                     EncryptionLevel::Symmetrical { .. } => true,
+                    EncryptionLevel::Recipients { .. } => true,
                 },
+                replicas: replicas.results(),
+                repository: cut.map(|_| RepositoryUploadResult {
+                    chunks: chunk_stats.chunks(),
+                    new_chunks: chunk_stats.new_chunks(),
+                    removed_chunks,
+                }),
+                resumable_upload: self.remote_storage.resumable_upload.as_ref().map(|_| {
+                    ResumableUploadResult {
+                        resumed_segments: upload.resumed_segments,
+                        retries: upload.retries,
+                    }
+                }),
+                manifest_error,
             }),
             _ => Err(IncrementalBackupRunError::ConcurrentStateManipulation {
                 message: "Initial state".to_string(),
             }),
         }
     }
+
+    /// With the repository the chunks are the segments of resumable uploads.
+    fn chunk_cut(&self) -> Option<ChunkCut> {
+        match (
+            &self.remote_storage.repository,
+            &self.remote_storage.resumable_upload,
+        ) {
+            (Some(repository), _) => Some(ChunkCut::ContentDefined {
+                average_size: repository.average_chunk_size,
+            }),
+            (None, Some(resumable_upload)) => Some(ChunkCut::FixedSize {
+                size: resumable_upload.segment_size,
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// The snapshot stream of an upload an earlier run did not finish, otherwise the one of a
+    /// new snapshot. The journal of resumable uploads is stored before anything is sent.
+    fn open_source(
+        &self,
+        parent_candidates: &BackupHistory,
+    ) -> Result<(SourceBackup, Option<UploadJournal>), IncrementalBackupRunError> {
+        let resumed = match &self.remote_storage.resumable_upload {
+            Some(_) => self.resume_source(parent_candidates),
+            None => None,
+        };
+        let (backup_src, journal) = match resumed {
+            Some((backup_src, journal)) => (backup_src, Some(journal)),
+            None => {
+                let local_service_lock = self.local_service.lock().unwrap();
+                let backup_src = local_service_lock
+                    .get_backup_source(parent_candidates, &self.consolidation)
+                    .map_err(|err| IncrementalBackupRunError::IoError {
+                        stage: IncrementalBackupRunStage::CreatingSnapshot,
+                        source: err,
+                    })?;
+                let journal = self.remote_storage.resumable_upload.is_some().then(|| {
+                    UploadJournal {
+                        local_snapshot: backup_src.local_snapshot_relative.clone(),
+                        parent: backup_src.parent_backup_id,
+                        segments: Vec::new(),
+                    }
+                });
+                (backup_src, journal)
+            }
+        };
+        if let Some(journal) = &journal {
+            journal.store(&self.journal_path).map_err(|err| {
+                IncrementalBackupRunError::IoError {
+                    stage: IncrementalBackupRunStage::StoringJournal,
+                    source: err,
+                }
+            })?;
+        }
+        Ok((backup_src, journal))
+    }
+
+    /// Stores the chunks while the stream is read, the file only gets the index. A failed
+    /// resumable upload is retried, the segments already on the remote are skipped.
+    fn upload_chunked(
+        &self,
+        opened: (Box<dyn Read>, Box<dyn Write + Send>),
+        encoding_data_tunnel: EncodingDataTunnel,
+        cut: ChunkCut,
+        journal: Option<&RefCell<UploadJournal>>,
+        uploading_state: IncrementalBackupJobUploadState,
+        history: &BackupHistory,
+    ) -> Result<Upload, IncrementalBackupRunError> {
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        let on_stored = |number: usize, chunk: &ChunkRef| match journal {
+            Some(journal) => {
+                let mut journal = journal.borrow_mut();
+                journal.confirm(number, chunk);
+                journal.store(&self.journal_path)
+            }
+            None => Ok(()),
+        };
+        let mut retries = 0;
+        let mut resumed_segments = 0;
+        let mut opened: std::io::Result<(Box<dyn Read>, Box<dyn Write + Send>)> = Ok(opened);
+        loop {
+            let chunk_stats = ChunkStats::default();
+            // The confirmed segments of the journal are checked against the stream
+            let resumed: Vec<ChunkRef> = journal.map_or_else(Vec::new, |journal| {
+                let journal = journal.borrow();
+                let segments = journal.segments.iter();
+                segments
+                    .map(|segment| ChunkRef {
+                        id: segment.id.clone(),
+                        size: segment.size,
+                    })
+                    .collect()
+            });
+            let upload_result = match opened {
+                Ok((data_stream, dest_writer)) => {
+                    let transfer = ChunkingDataTunnel {
+                        encoding_data_tunnel: encoding_data_tunnel.clone(),
+                        store: ChunkStore::new(
+                            &**remote_service_lock,
+                            &self.encoding_data_tunnel.encryption_level,
+                        ),
+                        cut,
+                        stats: chunk_stats.clone(),
+                        on_stored: Some(&on_stored),
+                        resumed: &resumed,
+                    }
+                    .tracked_transfer(data_stream, dest_writer);
+                    self.set_uploading(IncrementalBackupJobUploadState {
+                        read_bytes: transfer.reader_bytes_counter(),
+                        written_bytes: chunk_stats.stored_bytes_counter(),
+                        ..uploading_state.clone()
+                    })?;
+                    transfer.run().map(|()| transfer.checksums())
+                }
+                Err(err) => Err(err),
+            };
+            // Later attempts resume segments of this run
+            if retries == 0 {
+                resumed_segments = chunk_stats.resumed_chunks();
+            }
+            let retry_delay = match (&upload_result, &self.remote_storage.resumable_upload) {
+                (Err(err), Some(resumable_upload)) if retries < resumable_upload.retries => {
+                    let delay = resumable_upload
+                        .retry_delay_seconds
+                        .saturating_mul(1 << retries.min(16));
+                    eprintln!("Upload failed, resuming it in {delay}s: {err}");
+                    Duration::from_secs(delay)
+                }
+                _ => {
+                    return Ok(Upload {
+                        result: upload_result,
+                        chunk_stats,
+                        retries,
+                        resumed_segments,
+                    });
+                }
+            };
+            thread::sleep(retry_delay);
+            retries += 1;
+
+            // Segments already on the remote are skipped
+            opened = self
+                .resend_source(
+                    &uploading_state.local_folder_relative,
+                    uploading_state.parent_backup_id,
+                    history,
+                )
+                .and_then(|data_stream| {
+                    let dest_writer = remote_service_lock
+                        .get_backup_writer(uploading_state.remote_path_relative.clone())?;
+                    Ok((data_stream, dest_writer))
+                });
+        }
+    }
+
+    /// Checks that every destination stored the whole upload. Replicas that did not are left
+    /// out, a failed upload to the remote is removed everywhere.
+    fn verify_upload(
+        &self,
+        upload_result: std::io::Result<UploadChecksums>,
+        writer_failures: Vec<(usize, std::io::Error)>,
+        replicas: &mut ReplicaUpload,
+        dest_filename: &std::path::Path,
+    ) -> Result<UploadChecksums, IncrementalBackupRunError> {
+        let mut upload_result = upload_result;
+        // The error of `remote_service` may only show up here if it failed on the last bytes,
+        // written once the stream ended
+        let upload_error = replicas.writers_failed(
+            writer_failures,
+            dest_filename,
+            IncrementalBackupRunStage::Uploading,
+        )?;
+        if let Some(err) = upload_error
+            && upload_result.is_ok()
+        {
+            upload_result = Err(err);
+        }
+        let upload_result = upload_result.and_then(|checksums| {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock
+                .verify_backup_file_size(dest_filename.to_path_buf(), checksums.2)?;
+            Ok(checksums)
+        });
+        let checksums = match upload_result {
+            Ok(checksums) => checksums,
+            Err(err) => {
+                // Some destinations complete whatever was written when the writer is dropped
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                let _ = remote_service_lock.remove_backup_file(dest_filename.to_path_buf());
+                replicas.remove_uploads(dest_filename);
+                return Err(IncrementalBackupRunError::IoError {
+                    stage: IncrementalBackupRunStage::Uploading,
+                    source: err,
+                });
+            }
+        };
+        replicas.verify_sizes(dest_filename, checksums.2, IncrementalBackupRunStage::Uploading)?;
+        Ok(checksums)
+    }
+
+    /// Records the new backup in the history of the remote and applies the retention to it.
+    fn store_history(
+        &self,
+        history: &mut BackupHistory,
+        journaled: bool,
+    ) -> Result<(), IncrementalBackupRunError> {
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        remote_service_lock
+            .set_backup_history(history.clone())
+            .map_err(|err| IncrementalBackupRunError::IoError {
+                stage: IncrementalBackupRunStage::StoringMetadata,
+                source: err,
+            })?;
+        // The backup is recorded, a later run must not resume it
+        if journaled {
+            UploadJournal::remove(&self.journal_path)
+                .unwrap_or_else(|err| eprintln!("Failed to remove the upload journal: {err}"));
+        }
+
+        if let Some(retention) = &self.retention
+            && !history.prune(retention).is_empty()
+        {
+            remote_service_lock
+                .set_backup_history(history.clone())
+                .map_err(|err| IncrementalBackupRunError::IoError {
+                    stage: IncrementalBackupRunStage::PruningHistory,
+                    source: err,
+                })?
+        }
+        Ok(())
+    }
+
+    /// Removes the snapshots, files and chunks `history` no longer needs. Returns the number
+    /// of removed chunks.
+    fn clean_up(&self, history: &BackupHistory) -> Result<usize, IncrementalBackupRunError> {
+        {
+            let local_service_lock = self.local_service.lock().unwrap();
+            local_service_lock
+                .clear_local_snapshots(history)
+                .map_err(|err| IncrementalBackupRunError::IoError {
+                    stage: IncrementalBackupRunStage::ClearingSnapshots,
+                    source: err,
+                })?
+        };
+
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        remote_service_lock
+            .clear_orphaned_backups(history)
+            .map_err(|err| IncrementalBackupRunError::IoError {
+                stage: IncrementalBackupRunStage::ClearingOrphanedBackups,
+                source: err,
+            })?;
+
+        // Runs without the repository as well, the history may still hold chunked backups
+        collect_garbage(
+            &**remote_service_lock,
+            history,
+            &self.decoding_data_tunnel,
+            &self.remote_storage,
+        )
+        .map_err(|err| IncrementalBackupRunError::IoError {
+            stage: IncrementalBackupRunStage::CollectingGarbage,
+            source: err,
+        })
+    }
+
+    fn set_uploading(
+        &self,
+        uploading_state: IncrementalBackupJobUploadState,
+    ) -> Result<(), IncrementalBackupRunError> {
+        self.update_internal_state(|old_state| {
+            let started_at = match old_state {
+                IncrementalBackupJobState::Started { started_at } => started_at,
+                IncrementalBackupJobState::Uploading { started_at, .. } => started_at,
+                _ => Err(IncrementalBackupRunError::ConcurrentStateManipulation {
+                    message: "Cannot be initial state when upload starts".to_string(),
+                })?,
+            };
+
+            Ok(IncrementalBackupJobState::Uploading {
+                started_at: *started_at,
+                uploading_state: uploading_state.clone(),
+            })
+        })
+    }
+
+    /// Sends the snapshot of an upload that an earlier run did not finish again, unless the
+    /// upload cannot be resumed any more.
+    fn resume_source(&self, history: &BackupHistory) -> Option<(SourceBackup, UploadJournal)> {
+        let journal = UploadJournal::load(&self.journal_path)
+            .inspect_err(|err| eprintln!("Failed to read the upload journal: {err}"))
+            .ok()??;
+        let recorded = history
+            .entries
+            .iter()
+            .any(|entry| *entry.local_snapshot == journal.local_snapshot);
+        let parent_missing = journal
+            .parent
+            .is_some_and(|parent| !history.entries.iter().any(|entry| entry.id == parent));
+        if recorded || parent_missing {
+            return None;
+        }
+
+        let data_stream = self
+            .resend_source(&journal.local_snapshot, journal.parent, history)
+            .inspect_err(|err| eprintln!("Failed to resume the last upload: {err}"))
+            .ok()?;
+        let backup_src = SourceBackup {
+            parent_backup_id: journal.parent,
+            local_snapshot_relative: journal.local_snapshot.clone(),
+            data_stream,
+        };
+        Some((backup_src, journal))
+    }
+
+    fn resend_source(
+        &self,
+        local_snapshot: &std::path::Path,
+        parent_backup_id: Option<u32>,
+        history: &BackupHistory,
+    ) -> std::io::Result<Box<dyn Read>> {
+        let parent = parent_backup_id
+            .and_then(|parent| history.entries.iter().find(|entry| entry.id == parent));
+        let local_service_lock = self.local_service.lock().unwrap();
+        let backup_src =
+            local_service_lock.resend_backup_source(local_snapshot.to_path_buf(), parent)?;
+        Ok(backup_src.data_stream)
+    }
+
+    /// Records the new backup in the replica's own history and applies the retention to it.
+    fn store_replica_history(
+        &self,
+        service: &dyn DestService,
+        mut history: BackupHistory,
+    ) -> Result<(), (IncrementalBackupRunStage, std::io::Error)> {
+        service
+            .set_backup_history(history.clone())
+            .map_err(|err| (IncrementalBackupRunStage::StoringMetadata, err))?;
+        if let Some(retention) = &self.retention
+            && !history.prune(retention).is_empty()
+        {
+            service
+                .set_backup_history(history.clone())
+                .map_err(|err| (IncrementalBackupRunStage::PruningHistory, err))?;
+        }
+        service
+            .clear_orphaned_backups(&history)
+            .map_err(|err| (IncrementalBackupRunStage::ClearingOrphanedBackups, err))?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Error)]
@@ -192,16 +613,36 @@ pub enum IncrementalBackupRunError {
         #[source]
         source: std::io::Error,
     },
+    #[error("IO error on replica '{name}' during incremental backup stage {stage:?}: {source}")]
+    ReplicaIoError {
+        name: String,
+        stage: IncrementalBackupRunStage,
+        #[source]
+        source: std::io::Error,
+    },
     #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
     ConcurrentStateManipulation { message: String },
 }
 
-#[derive(Debug)]
+impl From<ReplicaError<IncrementalBackupRunStage>> for IncrementalBackupRunError {
+    fn from(err: ReplicaError<IncrementalBackupRunStage>) -> Self {
+        IncrementalBackupRunError::ReplicaIoError {
+            name: err.name,
+            stage: err.stage,
+            source: err.source,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum IncrementalBackupRunStage {
     FetchingMetadata,
     CreatingSnapshot,
+    StoringJournal,
     Uploading,
     StoringMetadata,
+    PruningHistory,
     ClearingSnapshots,
     ClearingOrphanedBackups,
+    CollectingGarbage,
 }
@@ -1,15 +1,24 @@
-use crate::config::{DataDanceConfiguration, LocalStorageConfig, RemoteStorageConfig, WebConfig};
+use crate::config::{
+    ConsolidationPolicy, DataDanceConfiguration, LocalStorageConfig, RemoteStorageConfig,
+    ReplicationPolicy, RepositoryConfig, ResumableUploadConfig, RetentionPolicy, WebConfig,
+};
 use crate::jobs::incremental_backup::IncrementalBackupJob;
+use crate::jobs::incremental_backup::journal::UploadJournal;
 use crate::jobs::Job;
 use crate::objects::job_result::{IncrementalBackupResult, IncrementalBackupResultState};
-use crate::objects::{BackupEntry, BackupHistory, BackupType, CompressionLevel};
+use crate::objects::{
+    AdaptiveCompression, BackupEntry, BackupHistory, BackupType, CompressionLevel, Path,
+    SnapshotManifest,
+};
 use crate::services::data_dest::fake::FakeDestService;
 use crate::services::data_dest::DestService;
 use crate::services::data_source::fake::FakeSourceService;
-use crate::services::data_source::SourceService;
+use crate::services::data_source::{SourceBackup, SourceService};
 use crate::{config, objects};
 use std::cell::RefCell;
+use std::io::Write;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 fn run_job(
     config: DataDanceConfiguration,
@@ -28,7 +37,16 @@ fn run_fake_job(
     compression_level: CompressionLevel,
     source_bytes_count: usize,
 ) -> IncrementalBackupTestData {
-    let config = DataDanceConfiguration {
+    run_fake_job_with_config(
+        fake_config(password, compression_level),
+        history,
+        new_local_snapshot,
+        source_bytes_count,
+    )
+}
+
+fn fake_config(password: Option<&str>, compression_level: CompressionLevel) -> DataDanceConfiguration {
+    DataDanceConfiguration {
         web: WebConfig {
             port: 3000,
             host: "0.0.0.0".to_string(),
@@ -38,6 +56,8 @@ fn run_fake_job(
                 snapshots_folder: ".snapshots/".into(),
                 source_folder: "export/".into(),
                 send_compressed_data: true,
+                restore_folder: None,
+                snapshot_retention: Default::default(),
             },
             jobs_folder: "./".into(),
         },
@@ -45,11 +65,30 @@ fn run_fake_job(
             dest: config::RemoteDestination::Local {
                 folder: "backups/".into(),
             },
+            replicas: Vec::new(),
             encryption: password.map(|pw| pw.into()),
-            compression: compression_level,
+            encryption_key_id: None,
+            keyring: Vec::new(),
+            recipients: Vec::new(),
+            compression: compression_level.into(),
+            retention: None,
+            consolidation: Default::default(),
+            repository: None,
+            resumable_upload: None,
         },
-    };
+        full_backup: None,
+        schedule: None,
+        migration: None,
+        restore_drill: None,
+    }
+}
 
+fn run_fake_job_with_config(
+    config: DataDanceConfiguration,
+    history: BackupHistory,
+    new_local_snapshot: &str,
+    source_bytes_count: usize,
+) -> IncrementalBackupTestData {
     let fake_source = FakeSourceService::new(new_local_snapshot.into(), source_bytes_count);
     let fake_dest = FakeDestService::new(history);
 
@@ -85,25 +124,40 @@ fn incremental_backup_initial_full() {
     match &result.state {
         IncrementalBackupResultState::Error(_) => panic!("Job errored"),
         IncrementalBackupResultState::Success(result) => {
-            assert_eq!(result.compression_level, CompressionLevel::Best);
+            assert_eq!(result.compression, CompressionLevel::Best.into());
             assert_eq!(result.encrypted, true);
             assert_eq!(result.bytes_read, 100 * 1024 * 1024);
             assert_eq!(result.parent, None);
             assert_eq!(result.local_snapshot, "2024_01_01_12_00_00/");
             assert_eq!(result.remote_filename, "2024_01_01_12_00_00.bin");
+            assert_eq!(result.manifest_error, None);
         }
     }
     assert_eq!(test_data.local_snapshots_clear, true);
     let latest_history_entry = test_data.stored_backup_history.entries.last().unwrap();
     assert_eq!(latest_history_entry.backup_type, BackupType::Full);
     assert_eq!(latest_history_entry.parent, None);
+    assert_eq!(latest_history_entry.source_size, Some(100 * 1024 * 1024));
+    assert!(latest_history_entry.source_hash.is_some());
+    assert!(latest_history_entry.stored_hash.is_some());
+    // The fake snapshot is a single file holding the backup stream
+    let manifest = latest_history_entry.manifest.as_ref().unwrap();
+    assert_eq!(manifest.file_count, 1);
+    assert_eq!(
+        Some(&manifest.tree_hash),
+        latest_history_entry.source_hash.as_ref()
+    );
+    assert_eq!(
+        latest_history_entry.compression,
+        Some(CompressionLevel::Best.into())
+    );
     assert_eq!(
         latest_history_entry.local_snapshot,
-        PathBuf::from("2024_01_01_12_00_00/")
+        Path::from("2024_01_01_12_00_00/")
     );
     assert_eq!(
         latest_history_entry.remote_filename,
-        PathBuf::from("2024_01_01_12_00_00.bin")
+        Path::from("2024_01_01_12_00_00.bin")
     );
 }
 
@@ -113,20 +167,16 @@ fn incremental_backup_with_parent() {
         BackupHistory {
             entries: vec![
                 BackupEntry {
-                    id: 10,
-                    parent: None,
                     timestamp: 100,
                     remote_filename: "2024_01_01_12_00_00.bin".into(),
                     local_snapshot: "2024_01_01_12_00_00/".into(),
-                    backup_type: BackupType::Full,
+                    ..BackupEntry::test(10, None)
                 },
                 BackupEntry {
-                    id: 20,
-                    parent: Some(10),
                     timestamp: 200,
                     remote_filename: "2024_01_02_12_00_00.dbin".into(),
                     local_snapshot: "2024_01_02_12_00_00/".into(),
-                    backup_type: BackupType::Incremental,
+                    ..BackupEntry::test(20, Some(10))
                 },
             ],
         },
@@ -152,11 +202,11 @@ fn incremental_backup_with_parent() {
     assert_eq!(latest_history_entry.parent, Some(20));
     assert_eq!(
         latest_history_entry.local_snapshot,
-        PathBuf::from("2024_01_03_12_00_00/")
+        Path::from("2024_01_03_12_00_00/")
     );
     assert_eq!(
         latest_history_entry.remote_filename,
-        PathBuf::from("2024_01_03_12_00_00.dbin")
+        Path::from("2024_01_03_12_00_00.dbin")
     );
 }
 
@@ -175,7 +225,7 @@ fn incremental_backup_encryption() {
         IncrementalBackupResultState::Error(_) => panic!("Job errored"),
         IncrementalBackupResultState::Success(result) => {
             assert_eq!(result.encrypted, true);
-            assert_eq!(result.compression_level, CompressionLevel::None);
+            assert_eq!(result.compression, CompressionLevel::None.into());
         }
     }
 }
@@ -195,7 +245,680 @@ fn incremental_backup_compression() {
         IncrementalBackupResultState::Error(_) => panic!("Job errored"),
         IncrementalBackupResultState::Success(result) => {
             assert_eq!(result.encrypted, false);
-            assert_eq!(result.compression_level, CompressionLevel::Balanced);
+            assert_eq!(result.compression, CompressionLevel::Balanced.into());
+            assert_eq!(result.compression_mix, None);
+        }
+    }
+}
+
+#[test]
+fn incremental_backup_adaptive_compression_reports_mix() {
+    let mut config = fake_config(None, CompressionLevel::Best);
+    config.remote_storage.compression.adaptive = Some(AdaptiveCompression {
+        fast_level: 1,
+        window_bytes: 64 * 1024,
+    });
+
+    // The fake source sends random bytes, they do not compress
+    let test_data =
+        run_fake_job_with_config(config, BackupHistory { entries: vec![] }, "2024_01_01/", 1 << 20);
+
+    match &test_data.run_result.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => {
+            let mix = result.compression_mix.unwrap();
+            assert_eq!(mix.high_level_bytes, 64 * 1024);
+            assert!(mix.stored_bytes > mix.fast_level_bytes);
+            assert_eq!(
+                mix.high_level_bytes + mix.fast_level_bytes + mix.stored_bytes,
+                result.bytes_read
+            );
+        }
+    }
+}
+
+#[test]
+fn incremental_backup_prunes_expired_chains() {
+    let day = 24 * 60 * 60 * 1000;
+    let mut config = fake_config(None, CompressionLevel::Fast);
+    config.remote_storage.retention = Some(RetentionPolicy {
+        daily: 2,
+        ..RetentionPolicy::default()
+    });
+    let entry = |id: u32, parent: Option<u32>, timestamp: u64| BackupEntry {
+        timestamp,
+        remote_filename: format!("{id}.bin").into(),
+        local_snapshot: format!("{id}/").into(),
+        ..BackupEntry::test(id, parent)
+    };
+
+    let test_data = run_fake_job_with_config(
+        config,
+        BackupHistory {
+            entries: vec![
+                entry(1, None, day),
+                entry(2, Some(1), 2 * day),
+                entry(3, None, 3 * day),
+                entry(4, Some(3), 4 * day),
+            ],
+        },
+        "2024_01_05/",
+        1024,
+    );
+
+    match &test_data.run_result.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => assert_eq!(result.parent, Some(4)),
+    }
+    let retained_ids: Vec<u32> = test_data
+        .stored_backup_history
+        .entries
+        .iter()
+        .map(|entry| entry.id)
+        .collect();
+    assert_eq!(retained_ids.len(), 3);
+    assert_eq!(retained_ids[..2], [3, 4]);
+}
+
+#[test]
+fn incremental_backup_stores_chunks_in_the_repository() {
+    let mut config = fake_config(Some("123456"), CompressionLevel::Fast);
+    config.remote_storage.repository = Some(RepositoryConfig {
+        average_chunk_size: 64 * 1024,
+    });
+    let fake_dest = FakeDestService::empty();
+    let fake_dest_debug = fake_dest.live_debug_data();
+    for (file, content) in [
+        ("unreferenced.chunk", &b"left by a pruned backup"[..]),
+        ("interrupted.chunk.part", &b"left by an interrupted upload"[..]),
+    ] {
+        fake_dest
+            .get_backup_writer(file.into())
+            .unwrap()
+            .write_all(content)
+            .unwrap();
+    }
+
+    let result = run_job(
+        config,
+        FakeSourceService::new("2024_01_01/".into(), 1024 * 1024),
+        fake_dest,
+    );
+
+    let repository = match &result.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => result.repository.clone().unwrap(),
+    };
+    assert!(repository.chunks > 1);
+    assert_eq!(repository.new_chunks, repository.chunks);
+    assert_eq!(repository.removed_chunks, 1);
+    let history = fake_dest_debug.history();
+    assert_eq!(history.entries[0].chunk_count, Some(repository.chunks));
+    assert!(fake_dest_debug.file("unreferenced.chunk").is_none());
+    assert!(fake_dest_debug.file("interrupted.chunk.part").is_none());
+}
+
+#[test]
+fn incremental_backup_keeps_chunks_if_an_index_is_unreadable() {
+    let mut config = fake_config(Some("123456"), CompressionLevel::Fast);
+    config.remote_storage.repository = Some(RepositoryConfig {
+        average_chunk_size: 64 * 1024,
+    });
+    let mut unreadable = full_entry(10);
+    unreadable.chunk_count = Some(1);
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: vec![unreadable],
+    });
+    let fake_dest_debug = fake_dest.live_debug_data();
+    for (file, content) in [
+        ("10.bin", &b"not an index"[..]),
+        ("unreferenced.chunk", &b"left by a pruned backup"[..]),
+    ] {
+        fake_dest
+            .get_backup_writer(file.into())
+            .unwrap()
+            .write_all(content)
+            .unwrap();
+    }
+
+    let result = run_job(
+        config,
+        FakeSourceService::new("2024_01_02/".into(), 1024),
+        fake_dest,
+    );
+
+    let repository = match &result.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => result.repository.clone().unwrap(),
+    };
+    assert_eq!(repository.removed_chunks, 0);
+    assert!(fake_dest_debug.file("unreferenced.chunk").is_some());
+}
+
+#[test]
+fn incremental_backup_starts_new_chain() {
+    let mut config = fake_config(None, CompressionLevel::Fast);
+    config.remote_storage.consolidation = ConsolidationPolicy {
+        max_incrementals: Some(1),
+        ..ConsolidationPolicy::default()
+    };
+
+    let test_data = run_fake_job_with_config(
+        config,
+        BackupHistory {
+            entries: vec![
+                BackupEntry {
+                    timestamp: 100,
+                    remote_filename: "2024_01_01_12_00_00.bin".into(),
+                    local_snapshot: "2024_01_01_12_00_00/".into(),
+                    size: Some(1024),
+                    ..BackupEntry::test(10, None)
+                },
+                BackupEntry {
+                    timestamp: 200,
+                    remote_filename: "2024_01_02_12_00_00.dbin".into(),
+                    local_snapshot: "2024_01_02_12_00_00/".into(),
+                    size: Some(16),
+                    ..BackupEntry::test(20, Some(10))
+                },
+            ],
+        },
+        "2024_01_03_12_00_00/",
+        1024,
+    );
+
+    match &test_data.run_result.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => {
+            assert_eq!(result.parent, None);
+            assert_eq!(result.remote_filename, "2024_01_03_12_00_00.bin");
+        }
+    }
+    let latest_history_entry = test_data.stored_backup_history.entries.last().unwrap();
+    assert_eq!(latest_history_entry.backup_type, BackupType::Full);
+    assert!(latest_history_entry.size.is_some_and(|size| size > 0));
+}
+
+/// A destination that cannot be reached at all.
+struct UnreachableDestService;
+
+impl DestService for UnreachableDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn get_backup_writer(&self, _: PathBuf) -> std::io::Result<Box<dyn std::io::Write + Send>> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn get_backup_reader(&self, _: PathBuf) -> std::io::Result<Box<dyn std::io::Read>> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn backup_file_size(&self, _: PathBuf) -> std::io::Result<u64> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn set_backup_history(&self, _: BackupHistory) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn clear_orphaned_backups(&self, _: &BackupHistory) -> std::io::Result<usize> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn replace_backup_file(&self, _: PathBuf, _: PathBuf) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+
+    fn remove_backup_file(&self, _: PathBuf) -> std::io::Result<()> {
+        Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+    }
+}
+
+fn full_entry(id: u32) -> BackupEntry {
+    BackupEntry {
+        timestamp: id as u64,
+        remote_filename: format!("{id}.bin").into(),
+        local_snapshot: format!("{id}/").into(),
+        ..BackupEntry::test(id, None)
+    }
+}
+
+#[test]
+fn incremental_backup_replicates_to_every_destination() {
+    let history = BackupHistory {
+        entries: vec![full_entry(10)],
+    };
+    let dest = FakeDestService::new(history.clone());
+    let required_replica = FakeDestService::new(history.clone());
+    let lagging_replica = FakeDestService::new(BackupHistory { entries: vec![] });
+    let dest_debug = dest.live_debug_data();
+    let required_replica_debug = required_replica.live_debug_data();
+    let lagging_replica_debug = lagging_replica.live_debug_data();
+
+    let job = IncrementalBackupJob::new(
+        fake_config(None, CompressionLevel::Fast),
+        Box::new(FakeSourceService::new("2024_01_02/".into(), 1024)),
+        Box::new(dest),
+    )
+    .with_replica("nas", ReplicationPolicy::Required, Box::new(required_replica))
+    .with_replica("offsite", ReplicationPolicy::BestEffort, Box::new(lagging_replica))
+    .with_replica("usb", ReplicationPolicy::BestEffort, Box::new(UnreachableDestService));
+    let result = job.run();
+
+    let IncrementalBackupResultState::Success(result) = &result.state else {
+        panic!("Job errored: {:?}", result.state);
+    };
+    assert_eq!(result.parent, Some(10));
+    let errors: Vec<(&str, Option<&str>)> = result
+        .replicas
+        .iter()
+        .map(|replica| (replica.name.as_str(), replica.error.as_deref()))
+        .collect();
+    assert_eq!(errors[0], ("nas", None));
+    assert_eq!(
+        errors[1],
+        (
+            "offsite",
+            Some("Uploading: the parent backup 10 is missing until the next full backup")
+        )
+    );
+    assert_eq!(errors[2].0, "usb");
+    assert!(errors[2].1.unwrap().starts_with("FetchingMetadata: "));
+
+    let uploaded = dest_debug.file("2024_01_02.dbin").unwrap();
+    assert_eq!(required_replica_debug.file("2024_01_02.dbin"), Some(uploaded));
+    assert_eq!(
+        required_replica_debug.history(),
+        dest_debug.history(),
+        "every destination records the backup in its own history"
+    );
+    assert_eq!(lagging_replica_debug.file("2024_01_02.dbin"), None);
+    assert_eq!(lagging_replica_debug.history().entries, vec![]);
+}
+
+#[test]
+fn incremental_backup_builds_on_backups_every_required_destination_has() {
+    let dest = FakeDestService::new(BackupHistory {
+        entries: vec![full_entry(10)],
+    });
+    let new_replica = FakeDestService::new(BackupHistory { entries: vec![] });
+    let new_replica_debug = new_replica.live_debug_data();
+
+    let job = IncrementalBackupJob::new(
+        fake_config(None, CompressionLevel::Fast),
+        Box::new(FakeSourceService::new("2024_01_02/".into(), 1024)),
+        Box::new(dest),
+    )
+    .with_replica("nas", ReplicationPolicy::Required, Box::new(new_replica));
+    let result = job.run();
+
+    let IncrementalBackupResultState::Success(result) = &result.state else {
+        panic!("Job errored: {:?}", result.state);
+    };
+    assert_eq!(result.parent, None);
+    assert!(new_replica_debug.file("2024_01_02.bin").is_some());
+    assert_eq!(new_replica_debug.history().entries.len(), 1);
+}
+
+#[test]
+fn incremental_backup_does_not_replicate_chunked_backups() {
+    let mut config = fake_config(Some("123456"), CompressionLevel::Fast);
+    config.remote_storage.repository = Some(RepositoryConfig {
+        average_chunk_size: 64 * 1024,
+    });
+    let replica = FakeDestService::empty();
+    let replica_debug = replica.live_debug_data();
+
+    let job = IncrementalBackupJob::new(
+        config,
+        Box::new(FakeSourceService::new("2024_01_02/".into(), 1024)),
+        Box::new(FakeDestService::empty()),
+    )
+    .with_replica("offsite", ReplicationPolicy::BestEffort, Box::new(replica));
+    let result = job.run();
+
+    let IncrementalBackupResultState::Success(result) = &result.state else {
+        panic!("Job errored: {:?}", result.state);
+    };
+    assert_eq!(
+        result.replicas[0].error.as_deref(),
+        Some("Uploading: chunked backups cannot be replicated")
+    );
+    assert_eq!(replica_debug.file("2024_01_02.bin"), None);
+    assert_eq!(replica_debug.history().entries, vec![]);
+}
+
+#[test]
+fn incremental_backup_fails_without_required_replica() {
+    let job = IncrementalBackupJob::new(
+        fake_config(None, CompressionLevel::Fast),
+        Box::new(FakeSourceService::new("2024_01_02/".into(), 1024)),
+        Box::new(FakeDestService::empty()),
+    )
+    .with_replica("nas", ReplicationPolicy::Required, Box::new(UnreachableDestService));
+    let result = job.run();
+
+    let IncrementalBackupResultState::Error(err) = &result.state else {
+        panic!("Job succeeded without its required replica");
+    };
+    assert!(
+        err.starts_with(
+            "IO error on replica 'nas' during incremental backup stage FetchingMetadata: "
+        ),
+        "{err}"
+    );
+}
+
+/// Loses everything after the first `kept_bytes` of an upload without reporting it, like a
+/// destination failing to complete the upload once its writer is dropped.
+struct LossyDestService {
+    inner: FakeDestService,
+    kept_bytes: usize,
+}
+
+struct LossyWriter {
+    inner: Box<dyn std::io::Write + Send>,
+    remaining: usize,
+}
+
+impl std::io::Write for LossyWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let kept = buf.len().min(self.remaining);
+        self.inner.write_all(&buf[..kept])?;
+        self.remaining -= kept;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl DestService for LossyDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        self.inner.backup_history()
+    }
+
+    fn get_backup_writer(&self, path: PathBuf) -> std::io::Result<Box<dyn std::io::Write + Send>> {
+        Ok(Box::new(LossyWriter {
+            inner: self.inner.get_backup_writer(path)?,
+            remaining: self.kept_bytes,
+        }))
+    }
+
+    fn get_backup_reader(&self, path: PathBuf) -> std::io::Result<Box<dyn std::io::Read>> {
+        self.inner.get_backup_reader(path)
+    }
+
+    fn backup_file_size(&self, path: PathBuf) -> std::io::Result<u64> {
+        self.inner.backup_file_size(path)
+    }
+
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        self.inner.set_backup_history(history)
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.inner.list_backup_files()
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.inner.list_chunk_files()
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        self.inner.clear_orphaned_backups(history)
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.inner.replace_backup_file(from, to)
+    }
+
+    fn remove_backup_file(&self, path: PathBuf) -> std::io::Result<()> {
+        self.inner.remove_backup_file(path)
+    }
+}
+
+#[test]
+fn incremental_backup_fails_if_the_upload_is_incomplete() {
+    let dest = LossyDestService {
+        inner: FakeDestService::empty(),
+        kept_bytes: 100,
+    };
+    let dest_debug = dest.inner.live_debug_data();
+
+    let job = IncrementalBackupJob::new(
+        fake_config(None, CompressionLevel::Fast),
+        Box::new(FakeSourceService::new("2024_01_02/".into(), 1024)),
+        Box::new(dest),
+    );
+    let result = job.run();
+
+    let IncrementalBackupResultState::Error(err) = &result.state else {
+        panic!("Job succeeded with an incomplete upload");
+    };
+    assert!(err.starts_with("IO error during incremental backup stage Uploading"), "{err}");
+    assert_eq!(dest_debug.file("2024_01_02.bin"), None);
+    assert_eq!(dest_debug.history().entries, vec![]);
+}
+
+/// Shares the files of a `FakeDestService` and drops the connection when the
+/// `fail_at_upload`th file is written.
+struct FlakyDestService {
+    inner: Arc<FakeDestService>,
+    fail_at_upload: usize,
+    uploads: Mutex<usize>,
+}
+
+impl DestService for FlakyDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        self.inner.backup_history()
+    }
+
+    fn get_backup_writer(&self, path: PathBuf) -> std::io::Result<Box<dyn std::io::Write + Send>> {
+        let mut uploads = self.uploads.lock().unwrap();
+        *uploads += 1;
+        if *uploads == self.fail_at_upload {
+            return Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
         }
+        self.inner.get_backup_writer(path)
+    }
+
+    fn get_backup_reader(&self, path: PathBuf) -> std::io::Result<Box<dyn std::io::Read>> {
+        self.inner.get_backup_reader(path)
+    }
+
+    fn backup_file_size(&self, path: PathBuf) -> std::io::Result<u64> {
+        self.inner.backup_file_size(path)
+    }
+
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        self.inner.set_backup_history(history)
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.inner.list_backup_files()
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.inner.list_chunk_files()
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        self.inner.clear_orphaned_backups(history)
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.inner.replace_backup_file(from, to)
+    }
+
+    fn remove_backup_file(&self, path: PathBuf) -> std::io::Result<()> {
+        self.inner.remove_backup_file(path)
     }
 }
+
+fn resumable_config(jobs_folder: &std::path::Path, retries: u32) -> DataDanceConfiguration {
+    let mut config = fake_config(Some("123456"), CompressionLevel::Fast);
+    config.local_storage.jobs_folder = jobs_folder.to_path_buf();
+    config.remote_storage.resumable_upload = Some(ResumableUploadConfig {
+        segment_size: 64 * 1024,
+        retries,
+        retry_delay_seconds: 0,
+    });
+    config
+}
+
+fn jobs_folder() -> PathBuf {
+    let jobs_folder =
+        std::env::temp_dir().join(format!("data-dance-journal-{}", rand::random::<u64>()));
+    std::fs::create_dir_all(&jobs_folder).unwrap();
+    jobs_folder
+}
+
+#[test]
+fn incremental_backup_retries_interrupted_upload() {
+    let jobs_folder = jobs_folder();
+    let fake_dest = Arc::new(FakeDestService::empty());
+    let flaky_dest = FlakyDestService {
+        inner: Arc::clone(&fake_dest),
+        // The index writer is opened first, then one file per segment
+        fail_at_upload: 6,
+        uploads: Mutex::new(0),
+    };
+
+    let result = run_job(
+        resumable_config(&jobs_folder, 1),
+        FakeSourceService::new("2024_01_01/".into(), 1024 * 1024),
+        flaky_dest,
+    );
+
+    let result = match result.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => result,
+    };
+    let repository = result.repository.unwrap();
+    assert_eq!(repository.chunks, 16);
+    assert_eq!(repository.new_chunks, 12);
+    assert_eq!(result.resumable_upload.unwrap().retries, 1);
+    let history = fake_dest.live_debug_data().history();
+    assert_eq!(history.entries[0].chunk_count, Some(16));
+    assert!(!UploadJournal::path(&jobs_folder).exists());
+    std::fs::remove_dir_all(jobs_folder).unwrap();
+}
+
+#[test]
+fn incremental_backup_resumes_upload_of_previous_run() {
+    let jobs_folder = jobs_folder();
+    let fake_dest = Arc::new(FakeDestService::empty());
+    let run = |fail_at_upload: usize| {
+        run_job(
+            resumable_config(&jobs_folder, 0),
+            FakeSourceService::new("2024_01_01/".into(), 1024 * 1024).with_seed(7),
+            FlakyDestService {
+                inner: Arc::clone(&fake_dest),
+                fail_at_upload,
+                uploads: Mutex::new(0),
+            },
+        )
+    };
+
+    let interrupted = run(6);
+    let resumed = run(0);
+
+    assert!(matches!(
+        interrupted.state,
+        IncrementalBackupResultState::Error(_)
+    ));
+    let resumed = match resumed.state {
+        IncrementalBackupResultState::Error(err) => panic!("Job errored: {err}"),
+        IncrementalBackupResultState::Success(result) => result,
+    };
+    assert_eq!(resumed.local_snapshot, "2024_01_01/");
+    assert_eq!(resumed.resumable_upload.unwrap().resumed_segments, 4);
+    assert_eq!(resumed.repository.unwrap().new_chunks, 12);
+    assert_eq!(fake_dest.live_debug_data().history().entries.len(), 1);
+    assert!(!UploadJournal::path(&jobs_folder).exists());
+    std::fs::remove_dir_all(jobs_folder).unwrap();
+}
+
+/// A `FakeSourceService` whose snapshots cannot be described.
+struct UndescribedSourceService {
+    inner: FakeSourceService,
+}
+
+impl SourceService for UndescribedSourceService {
+    fn get_backup_source(
+        &self,
+        backup_history: &BackupHistory,
+        consolidation: &ConsolidationPolicy,
+    ) -> std::io::Result<SourceBackup> {
+        self.inner.get_backup_source(backup_history, consolidation)
+    }
+
+    fn resend_backup_source(
+        &self,
+        local_snapshot: PathBuf,
+        parent: Option<&BackupEntry>,
+    ) -> std::io::Result<SourceBackup> {
+        self.inner.resend_backup_source(local_snapshot, parent)
+    }
+
+    fn clear_local_snapshots(&self, backup_history: &BackupHistory) -> std::io::Result<()> {
+        self.inner.clear_local_snapshots(backup_history)
+    }
+
+    fn is_restored(&self, restored_folder: PathBuf) -> std::io::Result<bool> {
+        self.inner.is_restored(restored_folder)
+    }
+
+    fn get_restore_writer(
+        &self,
+        restored_folder: PathBuf,
+        parent_folder: Option<PathBuf>,
+    ) -> std::io::Result<Box<dyn std::io::Write>> {
+        self.inner.get_restore_writer(restored_folder, parent_folder)
+    }
+
+    fn finish_restore(&self) -> std::io::Result<()> {
+        self.inner.finish_restore()
+    }
+
+    fn snapshot_manifest(&self, _: PathBuf) -> std::io::Result<SnapshotManifest> {
+        Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+    }
+
+    fn restored_manifest(&self, restored_folder: PathBuf) -> std::io::Result<SnapshotManifest> {
+        self.inner.restored_manifest(restored_folder)
+    }
+
+    fn clear_restored(&self) -> std::io::Result<()> {
+        self.inner.clear_restored()
+    }
+}
+
+#[test]
+fn incremental_backup_reports_an_unrecorded_manifest() {
+    let source = UndescribedSourceService {
+        inner: FakeSourceService::new("2024_01_01/".into(), 1024),
+    };
+    let dest = FakeDestService::new(BackupHistory { entries: vec![] });
+    let dest_debug = dest.live_debug_data();
+
+    let result = run_job(fake_config(None, CompressionLevel::Fast), source, dest);
+
+    let IncrementalBackupResultState::Success(result) = &result.state else {
+        panic!("Job failed without a manifest: {:?}", result.state);
+    };
+    assert_eq!(result.manifest_error, Some("permission denied".to_string()));
+    assert_eq!(dest_debug.history().entries[0].manifest, None);
+}
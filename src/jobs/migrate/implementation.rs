@@ -0,0 +1,82 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::Job;
+use crate::jobs::migrate::MigrateJob;
+use crate::jobs::migrate::state::MigrateJobState;
+use crate::objects;
+use crate::objects::job_state::{FetchingMetadataState, MigrateProgressState, MigrateStage};
+use crate::services::data_dest::{dest_service_from_config, dest_service_from_destination};
+use std::ops::Deref;
+
+impl Job for MigrateJob {
+    type CompletionStats = objects::job_result::MigrateResult;
+    type RunningStats = objects::job_state::MigrateState;
+
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let source_service = config
+            .migration
+            .as_ref()
+            .map(|migration| dest_service_from_destination(migration.source.clone()))
+            .transpose()?;
+        let target_service = dest_service_from_config(&config)?;
+
+        Ok(MigrateJob::new(config, source_service, target_service))
+    }
+
+    fn run(&self) -> Self::CompletionStats {
+        let started_at = chrono::Utc::now();
+        self.set_internal_state(MigrateJobState::Started { started_at });
+
+        let result = self.run_impl();
+
+        let finished_at = chrono::Utc::now();
+
+        objects::job_result::MigrateResult {
+            started_at,
+            finished_at,
+            state: match result {
+                Ok(result) => objects::job_result::MigrateResultState::Success(result),
+                Err(err) => objects::job_result::MigrateResultState::Error(err.to_string()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Self::RunningStats {
+        let mode = self.mode.into();
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            MigrateJobState::Initial => objects::job_state::MigrateState {
+                started_at: chrono::Utc::now(),
+                mode,
+                stage: FetchingMetadataState.into(),
+            },
+            MigrateJobState::Started { started_at } => objects::job_state::MigrateState {
+                started_at: *started_at,
+                mode,
+                stage: FetchingMetadataState.into(),
+            },
+            MigrateJobState::Migrating {
+                started_at,
+                migrating_state,
+            } => objects::job_state::MigrateState {
+                started_at: *started_at,
+                mode,
+                stage: MigrateStage::Migrating(MigrateProgressState {
+                    timestamp: chrono::Utc::now(),
+                    current: migrating_state.backup_id,
+                    position: migrating_state.position as u32,
+                    missing: migrating_state.missing as u32,
+                    remote_filename: migrating_state
+                        .remote_path_relative
+                        .to_string_lossy()
+                        .to_string(),
+                    verifying: migrating_state.verifying,
+                    bytes_read: migrating_state.previous_read_bytes
+                        + migrating_state.read_bytes.value(),
+                    bytes_written: migrating_state.previous_written_bytes
+                        + migrating_state.written_bytes.value(),
+                }),
+            },
+        }
+    }
+}
@@ -0,0 +1,89 @@
+mod implementation;
+mod run;
+mod state;
+#[cfg(test)]
+mod tests;
+
+use crate::config::{DataDanceConfiguration, MigrationMode, RemoteStorageConfig};
+use crate::jobs::migrate::run::MigrateRunError;
+use crate::jobs::migrate::state::MigrateJobState;
+use crate::objects::{BackupSource, CompressionAlgorithm};
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::{DecodingDataTunnel, EncodingDataTunnel};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// Copies the backups of another destination into the remote destination and merges them into
+/// its history. Every file is read back and compared before it is recorded, so an interrupted
+/// migration continues with the first backup the target does not have yet.
+pub struct MigrateJob {
+    remote_storage: RemoteStorageConfig,
+    mode: MigrationMode,
+    decoding_data_tunnel: DecodingDataTunnel,
+    encoding_data_tunnel: EncodingDataTunnel,
+
+    /// `None` if no migration is configured, the job then fails right away.
+    source_service: Option<Mutex<Box<dyn DestService + Send>>>,
+    target_service: Mutex<Box<dyn DestService + Send>>,
+
+    state: Mutex<MigrateJobState>,
+}
+
+impl MigrateJob {
+    pub fn new(
+        config: DataDanceConfiguration,
+        source_service: Option<Box<dyn DestService + Send>>,
+        target_service: Box<dyn DestService + Send>,
+    ) -> Self {
+        let mode = config
+            .migration
+            .as_ref()
+            .map(|migration| migration.mode)
+            .unwrap_or_default();
+        let remote_storage = config.remote_storage;
+        let decoding_data_tunnel = DecodingDataTunnel {
+            compression: CompressionAlgorithm::Zstd,
+            encryption_level: remote_storage.encryption_level(),
+        };
+        // Source and parent are taken from every re-encoded file
+        let encoding_data_tunnel = EncodingDataTunnel {
+            compression: remote_storage.compression,
+            encryption_level: remote_storage.encryption_level(),
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
+        };
+
+        Self {
+            remote_storage,
+            mode,
+            decoding_data_tunnel,
+            encoding_data_tunnel,
+
+            source_service: source_service.map(Mutex::new),
+            target_service: Mutex::new(target_service),
+
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn set_internal_state(&self, new_state: MigrateJobState) {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            let state = state_lock.deref_mut();
+            *state = new_state
+        }
+    }
+
+    pub fn update_internal_state(
+        &self,
+        map_state: impl Fn(&MigrateJobState) -> Result<MigrateJobState, MigrateRunError>,
+    ) -> Result<(), MigrateRunError> {
+        let mut state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        let new_state = map_state(state)?;
+        drop(state_lock);
+        self.set_internal_state(new_state);
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+use crate::config::MigrationMode;
+use crate::jobs::migrate::MigrateJob;
+use crate::jobs::migrate::state::{MigrateJobProgressState, MigrateJobState};
+use crate::objects::{BackupSource, ChecksumMismatch};
+use crate::objects::job_result::MigrateSuccess;
+use crate::services::data_tunnel::{DataTunnel, EncodingDataTunnel, PassThroughDataTunnel};
+use crate::services::tracking::{BytesCountingReader, ContentHash, HashingReader};
+use std::io::Read;
+use thiserror::Error;
+
+impl MigrateJob {
+    pub fn run_impl(&self) -> Result<MigrateSuccess, MigrateRunError> {
+        let Some(source_service) = &self.source_service else {
+            return Err(MigrateRunError::NotConfigured);
+        };
+        let source_history = {
+            let source_service_lock = source_service.lock().unwrap();
+            source_service_lock
+                .backup_history()
+                .map_err(|err| MigrateRunError::IoError {
+                    stage: MigrateRunStage::FetchingMetadata,
+                    source: err,
+                })?
+        };
+        let mut target_history = {
+            let target_service_lock = self.target_service.lock().unwrap();
+            target_service_lock
+                .backup_history()
+                .map_err(|err| MigrateRunError::IoError {
+                    stage: MigrateRunStage::FetchingMetadata,
+                    source: err,
+                })?
+        };
+
+        // Entries the target already has were migrated by an interrupted run or made there
+        let mut skipped = Vec::new();
+        let mut missing = Vec::new();
+        for entry in source_history.entries {
+            if let Some(other) = target_history
+                .entries
+                .iter()
+                .find(|other| other.remote_filename == entry.remote_filename)
+            {
+                // A re-encoded copy only shares the timestamp and the content with its source
+                let same_backup = other.timestamp == entry.timestamp
+                    || (other.source_hash.is_some() && other.source_hash == entry.source_hash);
+                if same_backup {
+                    skipped.push(entry.id);
+                    continue;
+                }
+                return Err(MigrateRunError::FileNameTaken {
+                    backup_id: entry.id,
+                    other_backup_id: other.id,
+                    remote_filename: entry.remote_filename.to_string_lossy().to_string(),
+                });
+            }
+            if let Some(other) = target_history
+                .entries
+                .iter()
+                .find(|other| other.id == entry.id)
+            {
+                return Err(MigrateRunError::IdTaken {
+                    backup_id: entry.id,
+                    remote_filename: other.remote_filename.to_string_lossy().to_string(),
+                });
+            }
+            // Only the index would be copied, the chunks stay behind
+            if entry.chunk_count.is_some() {
+                return Err(MigrateRunError::Chunked { backup_id: entry.id });
+            }
+            missing.push(entry);
+        }
+
+        let mut migrated = Vec::with_capacity(missing.len());
+        let mut previous_read_bytes = 0;
+        let mut previous_written_bytes = 0;
+        for (position, entry) in missing.iter().enumerate() {
+            let remote_path = entry.remote_filename.to_path_buf();
+            let migrated_path = remote_path.with_added_extension("migrate");
+            let mut migrated_entry = entry.clone();
+
+            let src_reader = {
+                let source_service_lock = source_service.lock().unwrap();
+                source_service_lock
+                    .get_backup_reader(remote_path.clone())
+                    .map_err(|err| MigrateRunError::IoError {
+                        stage: MigrateRunStage::Downloading,
+                        source: err,
+                    })?
+            };
+            // Checked against the recorded checksums once the file was read
+            let src_reader = HashingReader::new(src_reader);
+            let stored_hash = src_reader.hash();
+            let src_reader = BytesCountingReader::new(src_reader);
+            let stored_bytes = src_reader.counter();
+            let (src_reader, encoding_data_tunnel) = match self.mode {
+                MigrationMode::Copy => (Box::new(src_reader) as Box<dyn Read>, None),
+                MigrationMode::Reencode => {
+                    let decoding_data_tunnel = self
+                        .decoding_data_tunnel
+                        .for_entry(&self.remote_storage, entry)
+                        .map_err(|err| MigrateRunError::IoError {
+                            stage: MigrateRunStage::FetchingMetadata,
+                            source: err,
+                        })?;
+                    let (header, decoded_reader) = decoding_data_tunnel
+                        .decoder(src_reader)
+                        .map_err(|err| MigrateRunError::IoError {
+                            stage: MigrateRunStage::Downloading,
+                            source: err,
+                        })?;
+                    let (source, parent) = match header {
+                        Some(header) => (header.source, header.parent),
+                        None => (BackupSource::BtrfsStream, entry.parent),
+                    };
+                    migrated_entry.key_id = Some(self.remote_storage.current_key_id());
+                    migrated_entry.compression = Some(self.encoding_data_tunnel.compression);
+                    let encoding_data_tunnel = EncodingDataTunnel {
+                        source,
+                        parent,
+                        ..self.encoding_data_tunnel.clone()
+                    };
+                    (decoded_reader, Some(encoding_data_tunnel))
+                }
+            };
+
+            let dest_writer = {
+                let target_service_lock = self.target_service.lock().unwrap();
+                // Left behind if a previous run was interrupted
+                let _ = target_service_lock.remove_backup_file(migrated_path.clone());
+                target_service_lock
+                    .get_backup_writer(migrated_path.clone())
+                    .map_err(|err| MigrateRunError::IoError {
+                        stage: MigrateRunStage::Uploading,
+                        source: err,
+                    })?
+            };
+
+            let progress = |read_bytes, written_bytes| MigrateJobProgressState {
+                backup_id: entry.id,
+                position,
+                missing: missing.len(),
+                remote_path_relative: remote_path.clone(),
+                verifying: false,
+                read_bytes,
+                written_bytes,
+                previous_read_bytes,
+                previous_written_bytes,
+            };
+            // Dropping the writer at the end of the transfer completes the upload
+            let transferred = match encoding_data_tunnel {
+                None => {
+                    let transfer = PassThroughDataTunnel.tracked_transfer(src_reader, dest_writer);
+                    self.set_progress(progress(
+                        transfer.reader_bytes_counter(),
+                        transfer.writer_bytes_counter(),
+                    ))?;
+                    transfer.run().map(|_| transfer.checksums())
+                }
+                Some(encoding_data_tunnel) => {
+                    let transfer = encoding_data_tunnel.tracked_transfer(src_reader, dest_writer);
+                    self.set_progress(progress(
+                        transfer.reader_bytes_counter(),
+                        transfer.writer_bytes_counter(),
+                    ))?;
+                    transfer.run().map(|_| transfer.checksums())
+                }
+            };
+            let (read_bytes, read_hash, written_bytes, written_hash) = match transferred {
+                Ok(transferred) => transferred,
+                Err(err) => {
+                    let target_service_lock = self.target_service.lock().unwrap();
+                    let _ = target_service_lock.remove_backup_file(migrated_path);
+                    return Err(MigrateRunError::IoError {
+                        stage: MigrateRunStage::Uploading,
+                        source: err,
+                    });
+                }
+            };
+            let source_verified = entry
+                .verify_stored(stored_bytes.value(), &stored_hash.value())
+                .and_then(|_| match self.mode {
+                    MigrationMode::Copy => Ok(()),
+                    MigrationMode::Reencode => entry.verify_source(read_bytes, &read_hash),
+                });
+            if let Err(err) = source_verified {
+                let target_service_lock = self.target_service.lock().unwrap();
+                let _ = target_service_lock.remove_backup_file(migrated_path);
+                return Err(err.into());
+            }
+
+            self.update_internal_state(|old_state| match old_state {
+                MigrateJobState::Migrating {
+                    started_at,
+                    migrating_state,
+                } => Ok(MigrateJobState::Migrating {
+                    started_at: *started_at,
+                    migrating_state: MigrateJobProgressState {
+                        verifying: true,
+                        ..migrating_state.clone()
+                    },
+                }),
+                _ => Err(MigrateRunError::ConcurrentStateManipulation {
+                    message: "Must be migrating when verifying starts".to_string(),
+                }),
+            })?;
+            let stored_hash = {
+                let stored_reader = {
+                    let target_service_lock = self.target_service.lock().unwrap();
+                    target_service_lock.get_backup_reader(migrated_path.clone())
+                };
+                stored_reader.and_then(ContentHash::of_reader)
+            };
+            let verified = match stored_hash {
+                Ok(stored_hash) if stored_hash == written_hash => Ok(()),
+                Ok(_) => Err(MigrateRunError::VerificationFailed {
+                    backup_id: entry.id,
+                    remote_filename: entry.remote_filename.to_string_lossy().to_string(),
+                }),
+                Err(err) => Err(MigrateRunError::IoError {
+                    stage: MigrateRunStage::Verifying,
+                    source: err,
+                }),
+            };
+            if let Err(err) = verified {
+                let target_service_lock = self.target_service.lock().unwrap();
+                let _ = target_service_lock.remove_backup_file(migrated_path);
+                return Err(err);
+            }
+
+            {
+                let target_service_lock = self.target_service.lock().unwrap();
+                target_service_lock
+                    .replace_backup_file(migrated_path, remote_path.clone())
+                    .map_err(|err| MigrateRunError::IoError {
+                        stage: MigrateRunStage::Replacing,
+                        source: err,
+                    })?
+            }
+
+            // Stored after every file so an interrupted run keeps the history in sync
+            migrated_entry.size = Some(written_bytes);
+            migrated_entry.stored_hash = Some(written_hash);
+            if self.mode == MigrationMode::Reencode {
+                migrated_entry.source_size = Some(read_bytes);
+                migrated_entry.source_hash = Some(read_hash);
+            }
+            target_history.entries.push(migrated_entry);
+            target_history.entries.sort_by_key(|entry| entry.timestamp);
+            {
+                let target_service_lock = self.target_service.lock().unwrap();
+                target_service_lock
+                    .set_backup_history(target_history.clone())
+                    .map_err(|err| MigrateRunError::IoError {
+                        stage: MigrateRunStage::StoringMetadata,
+                        source: err,
+                    })?
+            }
+
+            migrated.push(entry.id);
+            previous_read_bytes += read_bytes;
+            previous_written_bytes += written_bytes;
+        }
+
+        Ok(MigrateSuccess {
+            mode: self.mode,
+            migrated,
+            skipped,
+            bytes_read: previous_read_bytes,
+            bytes_written: previous_written_bytes,
+        })
+    }
+
+    fn set_progress(
+        &self,
+        migrating_state: MigrateJobProgressState,
+    ) -> Result<(), MigrateRunError> {
+        self.update_internal_state(|old_state| {
+            let started_at = match old_state {
+                MigrateJobState::Started { started_at } => started_at,
+                MigrateJobState::Migrating { started_at, .. } => started_at,
+                _ => Err(MigrateRunError::ConcurrentStateManipulation {
+                    message: "Cannot be initial state when migrating starts".to_string(),
+                })?,
+            };
+
+            Ok(MigrateJobState::Migrating {
+                started_at: *started_at,
+                migrating_state: migrating_state.clone(),
+            })
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MigrateRunError {
+    #[error("no migration source is configured")]
+    NotConfigured,
+    #[error(
+        "backup {backup_id} cannot be migrated, backup {other_backup_id} already uses \
+        {remote_filename} at the target"
+    )]
+    FileNameTaken {
+        backup_id: u32,
+        other_backup_id: u32,
+        remote_filename: String,
+    },
+    #[error(
+        "backup {backup_id} cannot be migrated, the target already has a backup {backup_id} \
+        stored as {remote_filename}"
+    )]
+    IdTaken {
+        backup_id: u32,
+        remote_filename: String,
+    },
+    #[error("backup {backup_id} is stored in the chunk repository, which cannot be migrated")]
+    Chunked { backup_id: u32 },
+    #[error("the source of the migration failed its checksum: {0}")]
+    ChecksumMismatch(#[from] ChecksumMismatch),
+    #[error("the migrated copy of backup {backup_id} ({remote_filename}) differs from the source")]
+    VerificationFailed {
+        backup_id: u32,
+        remote_filename: String,
+    },
+    #[error("IO error during migration stage {stage:?}")]
+    IoError {
+        stage: MigrateRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
+    ConcurrentStateManipulation { message: String },
+}
+
+#[derive(Debug)]
+pub enum MigrateRunStage {
+    FetchingMetadata,
+    Downloading,
+    Uploading,
+    Verifying,
+    Replacing,
+    StoringMetadata,
+}
@@ -0,0 +1,33 @@
+use crate::services::tracking::BytesCounter;
+use std::path::PathBuf;
+
+pub(crate) enum MigrateJobState {
+    Initial,
+    Started {
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Migrating {
+        started_at: chrono::DateTime<chrono::Utc>,
+        migrating_state: MigrateJobProgressState,
+    },
+}
+
+#[derive(Clone)]
+pub struct MigrateJobProgressState {
+    pub backup_id: u32,
+    pub position: usize,
+    pub missing: usize,
+    pub remote_path_relative: PathBuf,
+    pub verifying: bool,
+    pub read_bytes: BytesCounter,
+    pub written_bytes: BytesCounter,
+    /// Bytes transferred by entries that were already migrated.
+    pub previous_read_bytes: u64,
+    pub previous_written_bytes: u64,
+}
+
+impl Default for MigrateJobState {
+    fn default() -> Self {
+        Self::Initial
+    }
+}
@@ -0,0 +1,342 @@
+use crate::config;
+use crate::config::{DEFAULT_KEY_ID, DataDanceConfiguration, MigrationConfig, MigrationMode};
+use crate::jobs::Job;
+use crate::jobs::migrate::MigrateJob;
+use crate::jobs::test_support::{decode, make_rotated_config, upload};
+use crate::objects::job_result::{MigrateResultState, MigrateSuccess};
+use crate::objects::{BackupEntry, BackupHistory};
+use crate::services::data_dest::DestService;
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::tracking::ContentHash;
+use std::io::{Cursor, Read, Write};
+use std::path::PathBuf;
+
+fn make_config(mode: MigrationMode) -> DataDanceConfiguration {
+    DataDanceConfiguration {
+        migration: Some(MigrationConfig {
+            source: config::RemoteDestination::Fake,
+            mode,
+        }),
+        ..make_rotated_config()
+    }
+}
+
+fn make_entry(id: u32, parent: Option<u32>) -> BackupEntry {
+    BackupEntry {
+        key_id: Some(DEFAULT_KEY_ID.to_string()),
+        ..BackupEntry::test(id, parent)
+    }
+}
+
+fn store(dest: &FakeDestService, entry: &BackupEntry, content: &[u8]) {
+    dest.get_backup_writer(entry.remote_filename.to_path_buf())
+        .unwrap()
+        .write_all(content)
+        .unwrap();
+}
+
+fn expect_success(job: MigrateJob) -> MigrateSuccess {
+    match job.run().state {
+        MigrateResultState::Error(err) => panic!("Job errored: {err}"),
+        MigrateResultState::Success(result) => result,
+    }
+}
+
+/// Hands out every migrated file with its first byte flipped when it is read back.
+struct CorruptingDestService(FakeDestService);
+
+impl DestService for CorruptingDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        self.0.backup_history()
+    }
+
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        self.0.get_backup_writer(relative_file_path)
+    }
+
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let mut content = vec![];
+        self.0
+            .get_backup_reader(relative_file_path)?
+            .read_to_end(&mut content)?;
+        if let Some(first) = content.first_mut() {
+            *first ^= 1;
+        }
+        Ok(Box::new(Cursor::new(content)))
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        self.0.backup_file_size(relative_file_path)
+    }
+
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        self.0.set_backup_history(history)
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.0.list_backup_files()
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.0.list_chunk_files()
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        self.0.clear_orphaned_backups(history)
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.0.replace_backup_file(from, to)
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        self.0.remove_backup_file(relative_file_path)
+    }
+}
+
+#[test]
+fn migrate_copies_backups_and_merges_history() {
+    let entries = vec![make_entry(10, None), make_entry(20, Some(10))];
+    let source = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    store(&source, &entries[0], b"full");
+    store(&source, &entries[1], b"first increment");
+    let target_entry = make_entry(15, None);
+    let target = FakeDestService::new(BackupHistory {
+        entries: vec![target_entry.clone()],
+    });
+    store(&target, &target_entry, b"made at the target");
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Copy),
+        Some(Box::new(source)),
+        Box::new(target),
+    );
+    let result = expect_success(job);
+
+    assert_eq!(result.mode, MigrationMode::Copy);
+    assert_eq!(result.migrated, vec![10, 20]);
+    assert!(result.skipped.is_empty());
+    assert_eq!(result.bytes_read, result.bytes_written);
+
+    let history = target_debug.history();
+    let ids: Vec<u32> = history.entries.iter().map(|entry| entry.id).collect();
+    assert_eq!(ids, vec![10, 15, 20]);
+    assert_eq!(history.entries[0].size, Some(4));
+    assert_eq!(
+        history.entries[0].stored_hash,
+        Some(ContentHash::of_reader(Cursor::new(b"full")).unwrap())
+    );
+    assert_eq!(history.entries[0].key_id.as_deref(), Some(DEFAULT_KEY_ID));
+    assert_eq!(target_debug.file("backup_10.bin").unwrap(), b"full");
+    assert_eq!(
+        target_debug.file("backup_15.bin").unwrap(),
+        b"made at the target"
+    );
+    assert_eq!(
+        target_debug.file("backup_20.bin").unwrap(),
+        b"first increment"
+    );
+    assert!(target_debug.file("backup_20.bin.migrate").is_none());
+}
+
+#[test]
+fn migrate_continues_after_an_interrupted_run() {
+    let entries = vec![make_entry(10, None), make_entry(20, Some(10))];
+    let source = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    store(&source, &entries[0], b"full");
+    store(&source, &entries[1], b"first increment");
+    let target = FakeDestService::new(BackupHistory {
+        entries: vec![entries[0].clone()],
+    });
+    store(&target, &entries[0], b"full");
+    target
+        .get_backup_writer("backup_20.bin.migrate".into())
+        .unwrap()
+        .write_all(b"first incr")
+        .unwrap();
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Copy),
+        Some(Box::new(source)),
+        Box::new(target),
+    );
+    let result = expect_success(job);
+
+    assert_eq!(result.migrated, vec![20]);
+    assert_eq!(result.skipped, vec![10]);
+    assert_eq!(target_debug.history().entries.len(), 2);
+    assert_eq!(
+        target_debug.file("backup_20.bin").unwrap(),
+        b"first increment"
+    );
+    assert!(target_debug.file("backup_20.bin.migrate").is_none());
+}
+
+#[test]
+fn migrate_reencodes_with_current_settings() {
+    let entries = vec![make_entry(10, None)];
+    let source = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload(&source, &entries[0], "old password", b"full");
+    let target = FakeDestService::empty();
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Reencode),
+        Some(Box::new(source)),
+        Box::new(target),
+    );
+    let result = expect_success(job);
+
+    assert_eq!(result.migrated, vec![10]);
+    let history = target_debug.history();
+    assert_eq!(history.entries[0].key_id.as_deref(), Some("2"));
+    let migrated = target_debug.file("backup_10.bin").unwrap();
+    assert_eq!(history.entries[0].size, Some(migrated.len() as u64));
+    assert_eq!(decode(migrated.clone(), "new password").unwrap(), b"full");
+    assert!(decode(migrated, "old password").is_err());
+}
+
+#[test]
+fn migrate_rejects_a_copy_that_differs() {
+    let entries = vec![make_entry(10, None)];
+    let source = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    store(&source, &entries[0], b"full");
+    let target = FakeDestService::empty();
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Copy),
+        Some(Box::new(source)),
+        Box::new(CorruptingDestService(target)),
+    );
+    let err = match job.run().state {
+        MigrateResultState::Error(err) => err,
+        MigrateResultState::Success(_) => panic!("Job should have failed"),
+    };
+
+    assert_eq!(
+        err,
+        "the migrated copy of backup 10 (backup_10.bin) differs from the source"
+    );
+    assert!(target_debug.history().entries.is_empty());
+    assert!(target_debug.file("backup_10.bin").is_none());
+    assert!(target_debug.file("backup_10.bin.migrate").is_none());
+}
+
+#[test]
+fn migrate_checks_the_recorded_checksums_of_the_source() {
+    let mut entries = vec![make_entry(10, None)];
+    entries[0].size = Some(4);
+    entries[0].stored_hash = Some(ContentHash::of_reader(Cursor::new(b"full")).unwrap());
+    let source = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    store(&source, &entries[0], b"fall");
+    let target = FakeDestService::empty();
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Copy),
+        Some(Box::new(source)),
+        Box::new(target),
+    );
+    let err = match job.run().state {
+        MigrateResultState::Error(err) => err,
+        MigrateResultState::Success(_) => panic!("Job should have failed"),
+    };
+
+    assert_eq!(
+        err,
+        "the source of the migration failed its checksum: backup 10 is corrupted, its remote \
+        file does not match the recorded hash"
+    );
+    assert!(target_debug.history().entries.is_empty());
+    assert!(target_debug.file("backup_10.bin.migrate").is_none());
+}
+
+#[test]
+fn migrate_without_source_fails() {
+    let config = DataDanceConfiguration {
+        migration: None,
+        ..make_config(MigrationMode::Copy)
+    };
+    let job = MigrateJob::new(config, None, Box::new(FakeDestService::empty()));
+
+    match job.run().state {
+        MigrateResultState::Error(err) => assert_eq!(err, "no migration source is configured"),
+        MigrateResultState::Success(_) => panic!("Job should have failed"),
+    }
+}
+
+#[test]
+fn migrate_fails_on_a_backup_id_taken_at_the_target() {
+    let entry = make_entry(10, None);
+    let source = FakeDestService::new(BackupHistory {
+        entries: vec![entry.clone()],
+    });
+    store(&source, &entry, b"full");
+    let mut other = make_entry(10, None);
+    other.remote_filename = "other_10.bin".into();
+    let target = FakeDestService::new(BackupHistory {
+        entries: vec![other.clone()],
+    });
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Copy),
+        Some(Box::new(source)),
+        Box::new(target),
+    );
+    match job.run().state {
+        MigrateResultState::Error(err) => assert_eq!(
+            err,
+            "backup 10 cannot be migrated, the target already has a backup 10 stored as \
+            other_10.bin"
+        ),
+        MigrateResultState::Success(_) => panic!("Job succeeded with a taken backup id"),
+    }
+    assert_eq!(target_debug.history().entries, vec![other]);
+    assert!(target_debug.file("backup_10.bin").is_none());
+}
+
+#[test]
+fn migrate_fails_on_a_file_name_taken_by_another_backup() {
+    let entry = make_entry(10, None);
+    let source = FakeDestService::new(BackupHistory {
+        entries: vec![entry.clone()],
+    });
+    store(&source, &entry, b"full");
+    let mut other = make_entry(20, None);
+    other.remote_filename = entry.remote_filename.clone();
+    let target = FakeDestService::new(BackupHistory {
+        entries: vec![other.clone()],
+    });
+    let target_debug = target.live_debug_data();
+
+    let job = MigrateJob::new(
+        make_config(MigrationMode::Copy),
+        Some(Box::new(source)),
+        Box::new(target),
+    );
+    match job.run().state {
+        MigrateResultState::Error(err) => assert_eq!(
+            err,
+            "backup 10 cannot be migrated, backup 20 already uses backup_10.bin at the target"
+        ),
+        MigrateResultState::Success(_) => panic!("Job succeeded with a taken file name"),
+    }
+    assert_eq!(target_debug.history().entries, vec![other]);
+}
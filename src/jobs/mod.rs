@@ -1,11 +1,19 @@
 use crate::config::DataDanceConfiguration;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::io;
 
 mod executor;
-mod full_backup;
+pub mod full_backup;
 pub mod incremental_backup;
-mod restore;
+pub mod migrate;
+mod replica;
+pub mod rekey;
+pub mod restore;
+pub mod restore_drill;
+#[cfg(test)]
+mod test_support;
+pub mod verify;
 mod variants;
 
 pub use executor::*;
@@ -15,7 +23,10 @@ pub trait Job {
     type CompletionStats: Serialize + DeserializeOwned;
     type RunningStats: Serialize + DeserializeOwned;
 
-    fn from_config(config: DataDanceConfiguration) -> Self;
+    /// Fails if a destination of the configuration cannot be set up.
+    fn from_config(config: DataDanceConfiguration) -> io::Result<Self>
+    where
+        Self: Sized;
 
     fn run(&self) -> Self::CompletionStats;
 
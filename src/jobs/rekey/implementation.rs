@@ -0,0 +1,76 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::Job;
+use crate::jobs::rekey::RekeyJob;
+use crate::jobs::rekey::state::RekeyJobState;
+use crate::objects;
+use crate::objects::job_state::{FetchingMetadataState, RekeyProgressState, RekeyStage};
+use crate::services::data_dest::dest_service_from_config;
+use std::ops::Deref;
+
+impl Job for RekeyJob {
+    type CompletionStats = objects::job_result::RekeyResult;
+    type RunningStats = objects::job_state::RekeyState;
+
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let dest_service = dest_service_from_config(&config)?;
+
+        Ok(RekeyJob::new(config, dest_service))
+    }
+
+    fn run(&self) -> Self::CompletionStats {
+        let started_at = chrono::Utc::now();
+        self.set_internal_state(RekeyJobState::Started { started_at });
+
+        let result = self.run_impl();
+
+        let finished_at = chrono::Utc::now();
+
+        objects::job_result::RekeyResult {
+            started_at,
+            finished_at,
+            state: match result {
+                Ok(result) => objects::job_result::RekeyResultState::Success(result),
+                Err(err) => objects::job_result::RekeyResultState::Error(err.to_string()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Self::RunningStats {
+        let key_id = Some(self.remote_storage.current_key_id());
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            RekeyJobState::Initial => objects::job_state::RekeyState {
+                started_at: chrono::Utc::now(),
+                key_id,
+                stage: FetchingMetadataState.into(),
+            },
+            RekeyJobState::Started { started_at } => objects::job_state::RekeyState {
+                started_at: *started_at,
+                key_id,
+                stage: FetchingMetadataState.into(),
+            },
+            RekeyJobState::Rekeying {
+                started_at,
+                rekeying_state,
+            } => objects::job_state::RekeyState {
+                started_at: *started_at,
+                key_id,
+                stage: RekeyStage::Rekeying(RekeyProgressState {
+                    timestamp: chrono::Utc::now(),
+                    current: rekeying_state.backup_id,
+                    position: rekeying_state.position as u32,
+                    outdated: rekeying_state.outdated as u32,
+                    remote_filename: rekeying_state
+                        .remote_path_relative
+                        .to_string_lossy()
+                        .to_string(),
+                    bytes_read: rekeying_state.previous_read_bytes
+                        + rekeying_state.read_bytes.value(),
+                    bytes_written: rekeying_state.previous_written_bytes
+                        + rekeying_state.written_bytes.value(),
+                }),
+            },
+        }
+    }
+}
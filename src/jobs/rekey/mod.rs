@@ -0,0 +1,77 @@
+mod implementation;
+mod run;
+mod state;
+#[cfg(test)]
+mod tests;
+
+use crate::config::{DataDanceConfiguration, RemoteStorageConfig};
+use crate::jobs::rekey::run::RekeyRunError;
+use crate::jobs::rekey::state::RekeyJobState;
+use crate::objects::{BackupSource, CompressionAlgorithm};
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::{DecodingDataTunnel, EncodingDataTunnel};
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// Re-encrypts every remote backup that is not encrypted with the current key. Older keys are
+/// looked up in the keyring, files are rewritten next to the original and swapped in atomically.
+pub struct RekeyJob {
+    remote_storage: RemoteStorageConfig,
+    decoding_data_tunnel: DecodingDataTunnel,
+    encoding_data_tunnel: EncodingDataTunnel,
+
+    remote_service: Mutex<Box<dyn DestService + Send>>,
+
+    state: Mutex<RekeyJobState>,
+}
+
+impl RekeyJob {
+    pub fn new(
+        config: DataDanceConfiguration,
+        remote_service: Box<dyn DestService + Send>,
+    ) -> Self {
+        let remote_storage = config.remote_storage;
+        let decoding_data_tunnel = DecodingDataTunnel {
+            compression: CompressionAlgorithm::Zstd,
+            encryption_level: remote_storage.encryption_level(),
+        };
+        // Source and parent are taken from every rewritten file
+        let encoding_data_tunnel = EncodingDataTunnel {
+            compression: remote_storage.compression,
+            encryption_level: remote_storage.encryption_level(),
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
+        };
+
+        Self {
+            remote_storage,
+            decoding_data_tunnel,
+            encoding_data_tunnel,
+
+            remote_service: Mutex::new(remote_service),
+
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn set_internal_state(&self, new_state: RekeyJobState) {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            let state = state_lock.deref_mut();
+            *state = new_state
+        }
+    }
+
+    pub fn update_internal_state(
+        &self,
+        map_state: impl Fn(&RekeyJobState) -> Result<RekeyJobState, RekeyRunError>,
+    ) -> Result<(), RekeyRunError> {
+        let mut state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        let new_state = map_state(state)?;
+        drop(state_lock);
+        self.set_internal_state(new_state);
+        Ok(())
+    }
+}
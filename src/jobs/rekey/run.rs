@@ -0,0 +1,327 @@
+use crate::config::{DEFAULT_KEY_ID, RECIPIENTS_KEY_ID, UNENCRYPTED_KEY_ID};
+use crate::jobs::rekey::RekeyJob;
+use crate::jobs::rekey::state::{RekeyJobProgressState, RekeyJobState};
+use crate::objects::{
+    BackupEntry, BackupHistory, BackupSource, ChecksumMismatch, ContainerHeader, EncryptionScheme,
+};
+use crate::objects::job_result::RekeySuccess;
+use crate::services::data_tunnel::{DataTunnel, EncodingDataTunnel};
+use crate::services::tracking::{BytesCountingReader, ContentHash, HashingReader};
+use thiserror::Error;
+
+impl RekeyJob {
+    pub fn run_impl(&self) -> Result<RekeySuccess, RekeyRunError> {
+        let mut history = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock
+                .backup_history()
+                .map_err(|err| RekeyRunError::IoError {
+                    stage: RekeyRunStage::FetchingMetadata,
+                    source: err,
+                })?
+        };
+        let key_id = Some(self.remote_storage.current_key_id());
+
+        // Left behind by a run interrupted while replacing the file of a backup
+        let mut rekeyed = Vec::new();
+        for index in 0..history.entries.len() {
+            if history.entries[index].rekey_pending {
+                self.replace_file(&history.entries[index])?;
+                self.describe_replaced(&mut history, index)?;
+                history.entries[index].rekey_pending = false;
+                self.store_history(&history)?;
+                rekeyed.push(history.entries[index].id);
+            }
+        }
+
+        self.detect_key_ids(&mut history)?;
+
+        let (chunked, outdated): (Vec<usize>, Vec<usize>) = history
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !self.remote_storage.is_current_key(entry.key_id.as_deref()))
+            .map(|(index, _)| index)
+            .partition(|&index| history.entries[index].chunk_count.is_some());
+        // Their chunks are shared with other backups and addressed by the old key
+        let skipped = chunked
+            .into_iter()
+            .map(|index| history.entries[index].id)
+            .collect();
+
+        let mut previous_read_bytes = 0;
+        let mut previous_written_bytes = 0;
+        for (position, &index) in outdated.iter().enumerate() {
+            let entry = history.entries[index].clone();
+            let decoding_data_tunnel = self
+                .decoding_data_tunnel
+                .for_entry(&self.remote_storage, &entry)
+                .map_err(|err| RekeyRunError::IoError {
+                    stage: RekeyRunStage::FetchingMetadata,
+                    source: err,
+                })?;
+
+            let remote_path = entry.remote_filename.to_path_buf();
+            let rewritten_path = remote_path.with_added_extension("rekey");
+
+            let src_reader = {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                // Left behind if a previous run was interrupted
+                let _ = remote_service_lock.remove_backup_file(rewritten_path.clone());
+                remote_service_lock
+                    .get_backup_reader(remote_path.clone())
+                    .map_err(|err| RekeyRunError::IoError {
+                        stage: RekeyRunStage::Downloading,
+                        source: err,
+                    })?
+            };
+            // Checked against the recorded checksums once the file was read
+            let src_reader = HashingReader::new(src_reader);
+            let stored_hash = src_reader.hash();
+            let src_reader = BytesCountingReader::new(src_reader);
+            let stored_bytes = src_reader.counter();
+            let (header, decoded_reader) =
+                decoding_data_tunnel
+                    .decoder(src_reader)
+                    .map_err(|err| RekeyRunError::IoError {
+                        stage: RekeyRunStage::Downloading,
+                        source: err,
+                    })?;
+            let (source, parent) = match header {
+                Some(header) => (header.source, header.parent),
+                None => (BackupSource::BtrfsStream, entry.parent),
+            };
+
+            let dest_writer = {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                remote_service_lock
+                    .get_backup_writer(rewritten_path.clone())
+                    .map_err(|err| RekeyRunError::IoError {
+                        stage: RekeyRunStage::Uploading,
+                        source: err,
+                    })?
+            };
+
+            let transfer = EncodingDataTunnel {
+                source,
+                parent,
+                ..self.encoding_data_tunnel.clone()
+            }
+            .tracked_transfer(decoded_reader, dest_writer);
+
+            self.update_internal_state(|old_state| {
+                let started_at = match old_state {
+                    RekeyJobState::Started { started_at } => started_at,
+                    RekeyJobState::Rekeying { started_at, .. } => started_at,
+                    _ => Err(RekeyRunError::ConcurrentStateManipulation {
+                        message: "Cannot be initial state when rekeying starts".to_string(),
+                    })?,
+                };
+
+                Ok(RekeyJobState::Rekeying {
+                    started_at: *started_at,
+                    rekeying_state: RekeyJobProgressState {
+                        backup_id: entry.id,
+                        position,
+                        outdated: outdated.len(),
+                        remote_path_relative: remote_path.clone(),
+                        read_bytes: transfer.reader_bytes_counter(),
+                        written_bytes: transfer.writer_bytes_counter(),
+                        previous_read_bytes,
+                        previous_written_bytes,
+                    },
+                })
+            })?;
+
+            let uploaded = transfer.run().and_then(|()| {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                remote_service_lock
+                    .verify_backup_file_size(rewritten_path.clone(), transfer.writer_bytes_count())
+            });
+            if let Err(err) = uploaded {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                let _ = remote_service_lock.remove_backup_file(rewritten_path);
+                return Err(RekeyRunError::IoError {
+                    stage: RekeyRunStage::Uploading,
+                    source: err,
+                });
+            }
+            let verified = entry
+                .verify_stored(stored_bytes.value(), &stored_hash.value())
+                .and_then(|_| {
+                    entry.verify_source(transfer.reader_bytes_count(), &transfer.reader_hash())
+                });
+            if let Err(err) = verified {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                let _ = remote_service_lock.remove_backup_file(rewritten_path);
+                return Err(err.into());
+            }
+
+            // The history keeps describing the old file until it is replaced, a run
+            // interrupted in between finishes the replacement
+            history.entries[index].rekey_pending = true;
+            self.store_history(&history)?;
+            self.replace_file(&entry)?;
+            history.entries[index].key_id = key_id.clone();
+            history.entries[index].size = Some(transfer.writer_bytes_count());
+            history.entries[index].compression = Some(self.encoding_data_tunnel.compression);
+            history.entries[index].stored_hash = Some(transfer.writer_hash());
+            history.entries[index].source_size = Some(transfer.reader_bytes_count());
+            history.entries[index].source_hash = Some(transfer.reader_hash());
+            history.entries[index].rekey_pending = false;
+            self.store_history(&history)?;
+
+            rekeyed.push(entry.id);
+            previous_read_bytes += transfer.reader_bytes_count();
+            previous_written_bytes += transfer.writer_bytes_count();
+        }
+
+        Ok(RekeySuccess {
+            key_id,
+            rekeyed,
+            skipped,
+            bytes_read: previous_read_bytes,
+            bytes_written: previous_written_bytes,
+        })
+    }
+
+    /// Records the key of backups made before key ids were recorded as far as their container
+    /// header tells, unencrypted ones are then rekeyed as well. Files from before the container
+    /// format keep counting as encrypted with the default key.
+    fn detect_key_ids(&self, history: &mut BackupHistory) -> Result<(), RekeyRunError> {
+        let mut detected = false;
+        for entry in &mut history.entries {
+            if entry.key_id.is_some() || entry.chunk_count.is_some() {
+                continue;
+            }
+            let header: std::io::Result<_> = try {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                let src_reader =
+                    remote_service_lock.get_backup_reader(entry.remote_filename.to_path_buf())?;
+                ContainerHeader::read_from(src_reader)?.0
+            };
+            let header = header.map_err(|err| RekeyRunError::IoError {
+                stage: RekeyRunStage::FetchingMetadata,
+                source: err,
+            })?;
+            let key_id = match header.map(|(header, _)| header.encryption) {
+                Some(EncryptionScheme::None) => UNENCRYPTED_KEY_ID,
+                Some(EncryptionScheme::X25519Recipients { .. }) => RECIPIENTS_KEY_ID,
+                Some(EncryptionScheme::Aes256GcmChunked { .. }) => DEFAULT_KEY_ID,
+                None => continue,
+            };
+            entry.key_id = Some(key_id.to_string());
+            detected = true;
+        }
+        if detected {
+            self.store_history(history)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the file of a backup with its re-encrypted `.rekey` file.
+    fn replace_file(&self, entry: &BackupEntry) -> Result<(), RekeyRunError> {
+        let remote_path = entry.remote_filename.to_path_buf();
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        let replaced = remote_service_lock
+            .replace_backup_file(remote_path.with_added_extension("rekey"), remote_path);
+        match replaced {
+            // Replaced before the run was interrupted
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            replaced => replaced.map_err(|err| RekeyRunError::IoError {
+                stage: RekeyRunStage::Replacing,
+                source: err,
+            }),
+        }
+    }
+
+    /// Records the re-encrypted file of a backup whose replacement was interrupted before the
+    /// history described it. The file is read again, its content must still match.
+    fn describe_replaced(
+        &self,
+        history: &mut BackupHistory,
+        index: usize,
+    ) -> Result<(), RekeyRunError> {
+        let entry = &history.entries[index];
+        let rekeyed_entry = BackupEntry {
+            key_id: Some(self.remote_storage.current_key_id()),
+            ..entry.clone()
+        };
+        let decoding_data_tunnel = self
+            .decoding_data_tunnel
+            .for_entry(&self.remote_storage, &rekeyed_entry)
+            .map_err(|err| RekeyRunError::IoError {
+                stage: RekeyRunStage::FetchingMetadata,
+                source: err,
+            })?;
+        let src_reader = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock
+                .get_backup_reader(entry.remote_filename.to_path_buf())
+                .map_err(|err| RekeyRunError::IoError {
+                    stage: RekeyRunStage::Downloading,
+                    source: err,
+                })?
+        };
+        let src_reader = HashingReader::new(src_reader);
+        let stored_hash = src_reader.hash();
+        let src_reader = BytesCountingReader::new(src_reader);
+        let stored_bytes = src_reader.counter();
+        let read: std::io::Result<_> = try {
+            let (header, decoded_reader) = decoding_data_tunnel.decoder(src_reader)?;
+            let decoded_reader = BytesCountingReader::new(decoded_reader);
+            let source_bytes = decoded_reader.counter();
+            let source_hash = ContentHash::of_reader(decoded_reader)?;
+            (header, source_bytes.value(), source_hash)
+        };
+        let (header, source_bytes, source_hash) =
+            read.map_err(|err| RekeyRunError::IoError {
+                stage: RekeyRunStage::Downloading,
+                source: err,
+            })?;
+        entry.verify_source(source_bytes, &source_hash)?;
+
+        let entry = &mut history.entries[index];
+        entry.key_id = rekeyed_entry.key_id;
+        entry.size = Some(stored_bytes.value());
+        entry.compression = Some(match header {
+            Some(header) => header.compression,
+            None => self.encoding_data_tunnel.compression,
+        });
+        entry.stored_hash = Some(stored_hash.value());
+        Ok(())
+    }
+
+    fn store_history(&self, history: &BackupHistory) -> Result<(), RekeyRunError> {
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        remote_service_lock
+            .set_backup_history(history.clone())
+            .map_err(|err| RekeyRunError::IoError {
+                stage: RekeyRunStage::StoringMetadata,
+                source: err,
+            })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RekeyRunError {
+    #[error("IO error during rekey stage {stage:?}")]
+    IoError {
+        stage: RekeyRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("The backup to re-encrypt failed its checksum: {0}")]
+    ChecksumMismatch(#[from] ChecksumMismatch),
+    #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
+    ConcurrentStateManipulation { message: String },
+}
+
+#[derive(Debug)]
+pub enum RekeyRunStage {
+    FetchingMetadata,
+    Downloading,
+    Uploading,
+    Replacing,
+    StoringMetadata,
+}
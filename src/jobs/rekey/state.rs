@@ -0,0 +1,32 @@
+use crate::services::tracking::BytesCounter;
+use std::path::PathBuf;
+
+pub(crate) enum RekeyJobState {
+    Initial,
+    Started {
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Rekeying {
+        started_at: chrono::DateTime<chrono::Utc>,
+        rekeying_state: RekeyJobProgressState,
+    },
+}
+
+#[derive(Clone)]
+pub struct RekeyJobProgressState {
+    pub backup_id: u32,
+    pub position: usize,
+    pub outdated: usize,
+    pub remote_path_relative: PathBuf,
+    pub read_bytes: BytesCounter,
+    pub written_bytes: BytesCounter,
+    /// Bytes transferred by entries that were already re-encrypted.
+    pub previous_read_bytes: u64,
+    pub previous_written_bytes: u64,
+}
+
+impl Default for RekeyJobState {
+    fn default() -> Self {
+        Self::Initial
+    }
+}
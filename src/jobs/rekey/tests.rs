@@ -0,0 +1,249 @@
+use crate::config::{DEFAULT_KEY_ID, RECIPIENTS_KEY_ID, UNENCRYPTED_KEY_ID};
+use crate::jobs::Job;
+use crate::jobs::rekey::RekeyJob;
+use crate::jobs::test_support::{
+    decode, decode_encrypted, make_rotated_config, upload, upload_encrypted,
+};
+use crate::objects::job_result::RekeyResultState;
+use crate::objects::{BackupEntry, BackupHistory, EncryptionLevel};
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::encryption::recipients::generate_identity;
+use crate::services::tracking::ContentHash;
+use std::io::Cursor;
+
+fn make_entry(id: u32, parent: Option<u32>, key_id: Option<&str>) -> BackupEntry {
+    BackupEntry {
+        key_id: key_id.map(str::to_string),
+        ..BackupEntry::test(id, parent)
+    }
+}
+
+#[test]
+fn rekey_moves_outdated_backups_to_current_key() {
+    let entries = vec![
+        make_entry(10, None, None),
+        make_entry(20, Some(10), Some(DEFAULT_KEY_ID)),
+        make_entry(30, Some(20), Some("2")),
+    ];
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload(&fake_dest, &entries[0], "old password", b"full");
+    upload(&fake_dest, &entries[1], "old password", b"first increment");
+    upload(&fake_dest, &entries[2], "new password", b"second increment");
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let untouched = fake_dest_debug.file("backup_30.bin").unwrap();
+
+    let job = RekeyJob::new(make_rotated_config(), Box::new(fake_dest));
+    let result = match job.run().state {
+        RekeyResultState::Error(err) => panic!("Job errored: {err}"),
+        RekeyResultState::Success(result) => result,
+    };
+
+    assert_eq!(result.key_id.as_deref(), Some("2"));
+    assert_eq!(result.rekeyed, vec![10, 20]);
+
+    let history = fake_dest_debug.history();
+    for entry in &history.entries {
+        assert_eq!(entry.key_id.as_deref(), Some("2"));
+    }
+    let full = fake_dest_debug.file("backup_10.bin").unwrap();
+    assert_eq!(history.entries[0].size, Some(full.len() as u64));
+    assert_eq!(
+        history.entries[0].stored_hash,
+        Some(ContentHash::of_reader(Cursor::new(&full)).unwrap())
+    );
+    assert_eq!(
+        history.entries[0].source_hash,
+        Some(ContentHash::of_reader(Cursor::new(b"full")).unwrap())
+    );
+    assert_eq!(decode(full.clone(), "new password").unwrap(), b"full");
+    assert!(decode(full, "old password").is_err());
+    assert_eq!(
+        decode(
+            fake_dest_debug.file("backup_20.bin").unwrap(),
+            "new password"
+        )
+        .unwrap(),
+        b"first increment"
+    );
+    assert_eq!(fake_dest_debug.file("backup_30.bin").unwrap(), untouched);
+    assert!(fake_dest_debug.file("backup_10.bin.rekey").is_none());
+}
+
+#[test]
+fn rekey_moves_password_backups_to_recipients() {
+    let (recipient, identity) = generate_identity().unwrap();
+    let mut config = make_rotated_config();
+    config.remote_storage.encryption = None;
+    config.remote_storage.encryption_key_id = None;
+    config.remote_storage.recipients = vec![recipient.clone()];
+    let entries = vec![
+        make_entry(10, None, None),
+        make_entry(20, Some(10), Some(DEFAULT_KEY_ID)),
+    ];
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload(&fake_dest, &entries[0], "old password", b"full");
+    upload(&fake_dest, &entries[1], "old password", b"first increment");
+    let fake_dest_debug = fake_dest.live_debug_data();
+
+    let job = RekeyJob::new(config, Box::new(fake_dest));
+    let result = match job.run().state {
+        RekeyResultState::Error(err) => panic!("Job errored: {err}"),
+        RekeyResultState::Success(result) => result,
+    };
+
+    assert_eq!(result.key_id.as_deref(), Some(RECIPIENTS_KEY_ID));
+    assert_eq!(result.rekeyed, vec![10, 20]);
+    for entry in &fake_dest_debug.history().entries {
+        assert_eq!(entry.key_id.as_deref(), Some(RECIPIENTS_KEY_ID));
+    }
+    let recipients = EncryptionLevel::Recipients {
+        recipients: vec![recipient],
+        identities: vec![identity.into()],
+    };
+    let full = fake_dest_debug.file("backup_10.bin").unwrap();
+    assert_eq!(decode_encrypted(full, recipients).unwrap(), b"full");
+}
+
+#[test]
+fn rekey_encrypts_unencrypted_backups() {
+    let mut config = make_rotated_config();
+    config.remote_storage.encryption_key_id = None;
+    config.remote_storage.keyring = Vec::new();
+    let entries = vec![
+        make_entry(10, None, None),
+        make_entry(20, Some(10), Some(UNENCRYPTED_KEY_ID)),
+    ];
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload_encrypted(&fake_dest, &entries[0], EncryptionLevel::None, b"full");
+    upload_encrypted(&fake_dest, &entries[1], EncryptionLevel::None, b"first increment");
+    let fake_dest_debug = fake_dest.live_debug_data();
+
+    let job = RekeyJob::new(config, Box::new(fake_dest));
+    let result = match job.run().state {
+        RekeyResultState::Error(err) => panic!("Job errored: {err}"),
+        RekeyResultState::Success(result) => result,
+    };
+
+    assert_eq!(result.key_id.as_deref(), Some(DEFAULT_KEY_ID));
+    assert_eq!(result.rekeyed, vec![10, 20]);
+    for entry in &fake_dest_debug.history().entries {
+        assert_eq!(entry.key_id.as_deref(), Some(DEFAULT_KEY_ID));
+    }
+    let full = fake_dest_debug.file("backup_10.bin").unwrap();
+    assert_eq!(decode(full, "new password").unwrap(), b"full");
+    let increment = fake_dest_debug.file("backup_20.bin").unwrap();
+    assert_eq!(decode(increment, "new password").unwrap(), b"first increment");
+}
+
+#[test]
+fn rekey_unknown_key_keeps_backup() {
+    let entries = vec![make_entry(10, None, Some("lost"))];
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload(&fake_dest, &entries[0], "lost password", b"full");
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let original = fake_dest_debug.file("backup_10.bin").unwrap();
+
+    let job = RekeyJob::new(make_rotated_config(), Box::new(fake_dest));
+    assert!(matches!(job.run().state, RekeyResultState::Error(_)));
+
+    assert_eq!(fake_dest_debug.file("backup_10.bin").unwrap(), original);
+    assert_eq!(fake_dest_debug.history().entries, entries);
+}
+
+#[test]
+fn rekey_wrong_keyring_password_keeps_backup() {
+    let entries = vec![make_entry(10, None, Some(DEFAULT_KEY_ID))];
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload(&fake_dest, &entries[0], "some other password", b"full");
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let original = fake_dest_debug.file("backup_10.bin").unwrap();
+
+    let job = RekeyJob::new(make_rotated_config(), Box::new(fake_dest));
+    assert!(matches!(job.run().state, RekeyResultState::Error(_)));
+
+    assert_eq!(fake_dest_debug.file("backup_10.bin").unwrap(), original);
+    assert!(fake_dest_debug.file("backup_10.bin.rekey").is_none());
+    assert_eq!(fake_dest_debug.history().entries, entries);
+}
+
+#[test]
+fn rekey_checksum_mismatch_keeps_backup() {
+    let mut entries = vec![make_entry(10, None, Some(DEFAULT_KEY_ID))];
+    entries[0].source_hash = Some(ContentHash::of_reader(Cursor::new(b"other")).unwrap());
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    upload(&fake_dest, &entries[0], "old password", b"full");
+    let fake_dest_debug = fake_dest.live_debug_data();
+    let original = fake_dest_debug.file("backup_10.bin").unwrap();
+
+    let job = RekeyJob::new(make_rotated_config(), Box::new(fake_dest));
+    match job.run().state {
+        RekeyResultState::Error(err) => assert_eq!(
+            err,
+            "The backup to re-encrypt failed its checksum: backup 10 is corrupted, its decoded \
+            content does not match the recorded hash"
+        ),
+        RekeyResultState::Success(_) => panic!("Job succeeded with a corrupted backup"),
+    }
+
+    assert_eq!(fake_dest_debug.file("backup_10.bin").unwrap(), original);
+    assert!(fake_dest_debug.file("backup_10.bin.rekey").is_none());
+    assert_eq!(fake_dest_debug.history().entries, entries);
+}
+
+/// A history of backup 10 interrupted while its re-encrypted file replaced the old one.
+fn interrupted_replacement(replaced: bool) -> (FakeDestService, BackupEntry) {
+    let mut entry = make_entry(10, None, Some(DEFAULT_KEY_ID));
+    entry.source_size = Some(4);
+    entry.source_hash = Some(ContentHash::of_reader(Cursor::new(b"full")).unwrap());
+    entry.rekey_pending = true;
+    let fake_dest = FakeDestService::new(BackupHistory {
+        entries: vec![entry.clone()],
+    });
+    let mut rewritten = entry.clone();
+    if !replaced {
+        upload(&fake_dest, &entry, "old password", b"full");
+        rewritten.remote_filename = "backup_10.bin.rekey".into();
+    }
+    upload(&fake_dest, &rewritten, "new password", b"full");
+    (fake_dest, entry)
+}
+
+#[test]
+fn rekey_finishes_interrupted_replacement() {
+    for replaced in [false, true] {
+        let (fake_dest, entry) = interrupted_replacement(replaced);
+        let fake_dest_debug = fake_dest.live_debug_data();
+
+        let job = RekeyJob::new(make_rotated_config(), Box::new(fake_dest));
+        let result = match job.run().state {
+            RekeyResultState::Error(err) => panic!("Job errored: {err}"),
+            RekeyResultState::Success(result) => result,
+        };
+
+        assert_eq!(result.rekeyed, vec![10]);
+        let full = fake_dest_debug.file("backup_10.bin").unwrap();
+        let history = fake_dest_debug.history();
+        assert!(!history.entries[0].rekey_pending);
+        assert_eq!(history.entries[0].key_id.as_deref(), Some("2"));
+        assert_eq!(history.entries[0].size, Some(full.len() as u64));
+        assert_eq!(
+            history.entries[0].stored_hash,
+            Some(ContentHash::of_reader(Cursor::new(&full)).unwrap())
+        );
+        assert_eq!(history.entries[0].source_hash, entry.source_hash);
+        assert_eq!(decode(full, "new password").unwrap(), b"full");
+        assert!(fake_dest_debug.file("backup_10.bin.rekey").is_none());
+    }
+}
@@ -0,0 +1,268 @@
+use crate::config::ReplicationPolicy;
+use crate::objects::job_result::ReplicaUploadResult;
+use crate::objects::{BackupEntry, BackupHistory};
+use crate::services::data_dest::DestService;
+use crate::services::data_dest::fan_out::{FanOutFailures, FanOutWriter};
+use std::fmt::Debug;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// A further destination a backup job uploads to, see `ReplicaConfig`. Every replica keeps a
+/// backup history of its own.
+pub(crate) struct Replica {
+    pub(crate) name: String,
+    pub(crate) policy: ReplicationPolicy,
+    pub(crate) service: Mutex<Box<dyn DestService + Send>>,
+}
+
+impl Replica {
+    pub(crate) fn new(
+        name: impl Into<String>,
+        policy: ReplicationPolicy,
+        service: Box<dyn DestService + Send>,
+    ) -> Self {
+        Replica {
+            name: name.into(),
+            policy,
+            service: Mutex::new(service),
+        }
+    }
+}
+
+/// A required replica failed in stage `S` of a backup job, the job fails with it.
+#[derive(Debug)]
+pub(crate) struct ReplicaError<S> {
+    pub(crate) name: String,
+    pub(crate) stage: S,
+    pub(crate) source: std::io::Error,
+}
+
+/// The replicas of a single backup. A replica that fails is left out for the rest of the run,
+/// required ones fail the backup, for best-effort ones the error is only reported.
+pub(crate) struct ReplicaUpload<'a> {
+    replicas: &'a [Replica],
+    /// `None` for the replicas that were left out.
+    histories: Vec<Option<BackupHistory>>,
+    errors: Vec<Option<String>>,
+    /// Replica index of every writer after the first of the fan-out.
+    writers: Vec<usize>,
+}
+
+impl<'a> ReplicaUpload<'a> {
+    /// Fetches the history of every replica, the replicas without one are left out.
+    pub(crate) fn start<S: Debug + Copy>(
+        replicas: &'a [Replica],
+        stage: S,
+    ) -> Result<Self, ReplicaError<S>> {
+        let mut upload = ReplicaUpload {
+            replicas,
+            histories: Vec::with_capacity(replicas.len()),
+            errors: vec![None; replicas.len()],
+            writers: Vec::new(),
+        };
+        for (index, replica) in replicas.iter().enumerate() {
+            let history = replica.service.lock().unwrap().backup_history();
+            upload.histories.push(None);
+            match history {
+                Ok(history) => upload.histories[index] = Some(history),
+                Err(err) => upload.failed(index, stage, err)?,
+            }
+        }
+        Ok(upload)
+    }
+
+    /// The entries of `history` every required replica that takes part holds as well.
+    pub(crate) fn shared_entries(&self, history: &BackupHistory) -> BackupHistory {
+        let on_replicas = |entry: &BackupEntry| {
+            self.replicas
+                .iter()
+                .zip(&self.histories)
+                .all(|(replica, replica_history)| {
+                    replica.policy == ReplicationPolicy::BestEffort
+                        || replica_history.as_ref().is_none_or(|replica_history| {
+                            replica_history.entries.iter().any(|e| e.id == entry.id)
+                        })
+                })
+        };
+        BackupHistory {
+            entries: history
+                .entries
+                .iter()
+                .filter(|entry| on_replicas(entry))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Leaves out the replicas that do not hold the backup `id`.
+    pub(crate) fn require_entry<S: Debug + Copy>(
+        &mut self,
+        id: u32,
+        stage: S,
+        err: impl Fn() -> std::io::Error,
+    ) -> Result<(), ReplicaError<S>> {
+        for index in 0..self.replicas.len() {
+            if self.histories[index]
+                .as_ref()
+                .is_some_and(|history| !history.entries.iter().any(|e| e.id == id))
+            {
+                self.failed(index, stage, err())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves out every replica that still takes part.
+    pub(crate) fn leave_out_all<S: Debug + Copy>(
+        &mut self,
+        stage: S,
+        err: impl Fn() -> std::io::Error,
+    ) -> Result<(), ReplicaError<S>> {
+        for index in 0..self.replicas.len() {
+            if self.histories[index].is_some() {
+                self.failed(index, stage, err())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes to `writer` and the file `dest_filename` of every replica that takes part.
+    pub(crate) fn fan_out<S: Debug + Copy>(
+        &mut self,
+        writer: Box<dyn Write + Send>,
+        dest_filename: &Path,
+        stage: S,
+    ) -> Result<(FanOutWriter, FanOutFailures), ReplicaError<S>> {
+        let mut dest_writers = vec![(ReplicationPolicy::Required, writer)];
+        for (index, replica) in self.replicas.iter().enumerate() {
+            if self.histories[index].is_none() {
+                continue;
+            }
+            let writer = replica
+                .service
+                .lock()
+                .unwrap()
+                .get_backup_writer(dest_filename.to_path_buf());
+            match writer {
+                Ok(writer) => {
+                    dest_writers.push((replica.policy, writer));
+                    self.writers.push(index);
+                }
+                Err(err) => self.failed(index, stage, err)?,
+            }
+        }
+        let dest_writer = FanOutWriter::new(dest_writers);
+        let failures = dest_writer.failures();
+        Ok((dest_writer, failures))
+    }
+
+    /// Leaves out the replicas whose writer failed and removes their partial file. The error
+    /// of the first writer is the upload's, it is returned.
+    pub(crate) fn writers_failed<S: Debug + Copy>(
+        &mut self,
+        failures: Vec<(usize, std::io::Error)>,
+        dest_filename: &Path,
+        stage: S,
+    ) -> Result<Option<std::io::Error>, ReplicaError<S>> {
+        let mut upload_error = None;
+        for (position, err) in failures {
+            let Some(&index) = position.checked_sub(1).and_then(|i| self.writers.get(i)) else {
+                upload_error.get_or_insert(err);
+                continue;
+            };
+            self.remove_file(index, dest_filename);
+            self.failed(index, stage, err)?;
+        }
+        Ok(upload_error)
+    }
+
+    /// Leaves out the replicas that did not store all `written_bytes` of the upload.
+    pub(crate) fn verify_sizes<S: Debug + Copy>(
+        &mut self,
+        dest_filename: &Path,
+        written_bytes: u64,
+        stage: S,
+    ) -> Result<(), ReplicaError<S>> {
+        for position in 0..self.writers.len() {
+            let index = self.writers[position];
+            if self.histories[index].is_none() {
+                continue;
+            }
+            let verified = self.replicas[index]
+                .service
+                .lock()
+                .unwrap()
+                .verify_backup_file_size(dest_filename.to_path_buf(), written_bytes);
+            if let Err(err) = verified {
+                self.remove_file(index, dest_filename);
+                self.failed(index, stage, err)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the file of a failed upload from every replica that was written to.
+    pub(crate) fn remove_uploads(&self, dest_filename: &Path) {
+        for &index in &self.writers {
+            self.remove_file(index, dest_filename);
+        }
+    }
+
+    /// Records `entry` in the history of every replica that takes part with `store`.
+    pub(crate) fn store_histories<S: Debug + Copy>(
+        &mut self,
+        entry: &BackupEntry,
+        store: impl Fn(&dyn DestService, BackupHistory) -> Result<(), (S, std::io::Error)>,
+    ) -> Result<(), ReplicaError<S>> {
+        for index in 0..self.replicas.len() {
+            let Some(mut history) = self.histories[index].take() else {
+                continue;
+            };
+            history.entries.push(entry.clone());
+            let stored = store(&**self.replicas[index].service.lock().unwrap(), history);
+            if let Err((stage, err)) = stored {
+                self.failed(index, stage, err)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn results(self) -> Vec<ReplicaUploadResult> {
+        self.replicas
+            .iter()
+            .zip(self.errors)
+            .map(|(replica, error)| ReplicaUploadResult {
+                name: replica.name.clone(),
+                policy: replica.policy,
+                error,
+            })
+            .collect()
+    }
+
+    fn remove_file(&self, index: usize, dest_filename: &Path) {
+        let service = self.replicas[index].service.lock().unwrap();
+        let _ = service.remove_backup_file(dest_filename.to_path_buf());
+    }
+
+    fn failed<S: Debug>(
+        &mut self,
+        index: usize,
+        stage: S,
+        source: std::io::Error,
+    ) -> Result<(), ReplicaError<S>> {
+        self.histories[index] = None;
+        let replica = &self.replicas[index];
+        match replica.policy {
+            ReplicationPolicy::Required => Err(ReplicaError {
+                name: replica.name.clone(),
+                stage,
+                source,
+            }),
+            ReplicationPolicy::BestEffort => {
+                self.errors[index] = Some(format!("{stage:?}: {source}"));
+                Ok(())
+            }
+        }
+    }
+}
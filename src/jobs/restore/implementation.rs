@@ -0,0 +1,88 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::restore::state::RestoreBackupJobState;
+use crate::jobs::restore::RestoreBackupJob;
+use crate::jobs::Job;
+use crate::objects;
+use crate::objects::job_state::{RestoreDownloadState, RestoreFetchingMetadataState, RestoreStage};
+use crate::objects::EncryptionLevel;
+use crate::services::data_dest::dest_service_from_config;
+use crate::services::data_source::source_service_from_config;
+use std::ops::Deref;
+
+impl Job for RestoreBackupJob {
+    type CompletionStats = objects::job_result::RestoreResult;
+    type RunningStats = objects::job_state::RestoreJobState;
+
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let src_service = source_service_from_config(&config);
+        let dest_service = dest_service_from_config(&config)?;
+
+        Ok(RestoreBackupJob::new(config, None, src_service, dest_service))
+    }
+
+    fn run(&self) -> Self::CompletionStats {
+        let started_at = chrono::Utc::now();
+        self.set_internal_state(RestoreBackupJobState::Started { started_at });
+
+        let result = self.run_impl();
+
+        let finished_at = chrono::Utc::now();
+
+        objects::job_result::RestoreResult {
+            started_at,
+            finished_at,
+            state: match result {
+                Ok(result) => objects::job_result::RestoreResultState::Success(result),
+                Err(err) => objects::job_result::RestoreResultState::Error(err.to_string()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Self::RunningStats {
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            RestoreBackupJobState::Initial => objects::job_state::RestoreJobState {
+                started_at: chrono::Utc::now(),
+                backup_id: self.backup_id,
+                stage: RestoreFetchingMetadataState.into(),
+            },
+            RestoreBackupJobState::Started { started_at } => objects::job_state::RestoreJobState {
+                started_at: *started_at,
+                backup_id: self.backup_id,
+                stage: RestoreFetchingMetadataState.into(),
+            },
+            RestoreBackupJobState::Downloading {
+                started_at,
+                downloading_state,
+            } => objects::job_state::RestoreJobState {
+                started_at: *started_at,
+                backup_id: self.backup_id,
+                stage: RestoreStage::Downloading(RestoreDownloadState {
+                    timestamp: chrono::Utc::now(),
+                    current: downloading_state.backup_id,
+                    chain_position: downloading_state.chain_position as u32,
+                    chain_length: downloading_state.chain_length as u32,
+                    remote_filename: downloading_state
+                        .remote_path_relative
+                        .to_string_lossy()
+                        .to_string(),
+                    local_snapshot: downloading_state
+                        .local_folder_relative
+                        .to_string_lossy()
+                        .to_string(),
+                    bytes_read: downloading_state.previous_read_bytes
+                        + downloading_state.read_bytes.value(),
+                    bytes_written: downloading_state.previous_written_bytes
+                        + downloading_state.written_bytes.value(),
+                    compression: downloading_state.compression,
+                    encrypted: match &self.decoding_data_tunnel.encryption_level {
+                        EncryptionLevel::None => false,
+                        EncryptionLevel::Symmetrical { .. } => true,
+                        EncryptionLevel::Recipients { .. } => true,
+                    },
+                }),
+            },
+        }
+    }
+}
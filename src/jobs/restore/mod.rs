@@ -1,126 +1,99 @@
-use crate::config::DataDanceConfiguration;
-use crate::jobs::incremental_backup::IncrementalBackupJob;
-use crate::jobs::restore::state::RestoreBackupState;
-use crate::jobs::Job;
-use crate::objects::job_state::{IncrementalBackupStage, IncrementalBackupUploadState};
-use crate::objects::EncryptionLevel;
-use crate::services::data_dest::bare_fs::BareFsDestService;
-use crate::services::data_dest::fake::FakeDestService;
-use crate::services::data_dest::ssh::SshDestService;
+mod implementation;
+mod run;
+mod state;
+#[cfg(test)]
+mod tests;
+
+pub(crate) use run::RestoreRunError;
+pub(crate) use state::RestoreBackupJobState;
+
+use crate::config::{DataDanceConfiguration, RemoteStorageConfig};
+use crate::objects::{CompressionAlgorithm, EncryptionLevel, SensitiveString};
 use crate::services::data_dest::DestService;
-use crate::services::data_source::btrfs::BtrfsSourceService;
-use crate::services::data_source::fake::FakeSourceService;
 use crate::services::data_source::SourceService;
-use crate::services::data_tunnel::{DecodingDataTunnel, EncodingDataTunnel};
-use crate::{config, objects};
-use std::sync::Mutex;
-
-mod state;
+use crate::services::data_tunnel::DecodingDataTunnel;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
 
 pub struct RestoreBackupJob {
     decoding_data_tunnel: DecodingDataTunnel,
+    /// Looks up the password of every restored entry by its key id.
+    remote_storage: RemoteStorageConfig,
+    /// The backup entry to restore. `None` restores the newest backup.
+    backup_id: Option<u32>,
+    /// Downloads are kept here until they are verified.
+    jobs_folder: PathBuf,
 
     remote_service: Mutex<Box<dyn DestService + Send>>,
     local_service: Mutex<Box<dyn SourceService + Send>>,
 
-    state: Mutex<RestoreBackupState>,
+    state: Mutex<RestoreBackupJobState>,
 }
 
-/*impl Job for IncrementalBackupJob {
-    type CompletionStats = ();
-    type RunningStats = RestoreBackupState;
-
-    fn from_config(config: DataDanceConfiguration) -> Self {
-        let src_service = match config.local_storage.source.clone() {
-            config::LocalSource::Btrfs {
-                snapshots_folder,
-                source_folder,
-                send_compressed_data,
-            } => Box::new(BtrfsSourceService::new(
-                snapshots_folder,
-                source_folder,
-                send_compressed_data,
-            )) as Box<dyn SourceService + Send>,
-            config::LocalSource::Fake { backup_byte_size } => Box::new(FakeSourceService::new(
-                "fake_snapshot".into(),
-                backup_byte_size,
-            ))
-                as Box<dyn SourceService + Send>,
-        };
-        let dest_service: Box<dyn DestService + Send> = match config.remote_storage.dest.clone() {
-            config::RemoteDestination::Local { folder } => Box::new(BareFsDestService::new(folder)),
-            config::RemoteDestination::Ssh {
-                hostname,
-                port,
-                username,
-                folder,
-            } => Box::new(SshDestService::new(port, hostname, username, folder)),
-            config::RemoteDestination::Fake => Box::new(FakeDestService::empty()),
+impl RestoreBackupJob {
+    pub fn new(
+        config: DataDanceConfiguration,
+        backup_id: Option<u32>,
+        local_service: Box<dyn SourceService + Send>,
+        remote_service: Box<dyn DestService + Send>,
+    ) -> Self {
+        let data_tunnel = DecodingDataTunnel {
+            // Only files from before the container format have no header, they are all zstd
+            compression: CompressionAlgorithm::Zstd,
+            encryption_level: config.remote_storage.encryption_level(),
         };
 
-        IncrementalBackupJob::new(config, src_service, dest_service)
-    }
+        Self {
+            decoding_data_tunnel: data_tunnel,
+            remote_storage: config.remote_storage,
+            backup_id,
+            jobs_folder: config.local_storage.jobs_folder,
+
+            remote_service: Mutex::new(remote_service),
+            local_service: Mutex::new(local_service),
 
-    fn run(&self) -> Self::CompletionStats {
-        let started_at = chrono::Utc::now();
-        self.set_internal_state(crate::jobs::incremental_backup::state::IncrementalBackupJobState::Started { started_at });
+            state: Mutex::default(),
+        }
+    }
 
-        let result = self.run_impl();
+    pub fn with_backup_id(mut self, backup_id: u32) -> Self {
+        self.backup_id = Some(backup_id);
+        self
+    }
 
-        let finished_at = chrono::Utc::now();
+    /// Secret keys opening backups encrypted to recipients. They are never part of the config.
+    pub fn with_identities(mut self, new_identities: Vec<SensitiveString>) -> Self {
+        if let EncryptionLevel::Recipients { identities, .. } =
+            &mut self.decoding_data_tunnel.encryption_level
+        {
+            *identities = new_identities;
+        }
+        self
+    }
 
-        objects::job_result::IncrementalBackupResult {
-            started_at,
-            finished_at,
-            state: match result {
-                Ok(result) => objects::job_result::IncrementalBackupResultState::Success(result),
-                Err(err) => {
-                    objects::job_result::IncrementalBackupResultState::Error(err.to_string())
-                }
-            },
+    pub fn set_internal_state(&self, new_state: RestoreBackupJobState) {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            let state = state_lock.deref_mut();
+            *state = new_state
         }
     }
 
-    fn stats(&self) -> Self::RunningStats {
-        let state_lock = self.state.lock().unwrap();
+    pub fn update_internal_state(
+        &self,
+        map_state: impl Fn(&RestoreBackupJobState) -> Result<RestoreBackupJobState, RestoreRunError>,
+    ) -> Result<(), RestoreRunError> {
+        let mut state_lock = self.state.lock().unwrap();
         let state = state_lock.deref();
-        match state {
-            crate::jobs::incremental_backup::state::IncrementalBackupJobState::Initial => objects::job_state::IncrementalBackupState {
-                started_at: chrono::Utc::now(),
-                stage: IncrementalBackupStage::FetchingMetadata,
-            },
-            crate::jobs::incremental_backup::state::IncrementalBackupJobState::Started { started_at } => {
-                objects::job_state::IncrementalBackupState {
-                    started_at: *started_at,
-                    stage: IncrementalBackupStage::FetchingMetadata,
-                }
-            }
-            crate::jobs::incremental_backup::state::IncrementalBackupJobState::Uploading {
-                started_at,
-                uploading_state,
-            } => objects::job_state::IncrementalBackupState {
-                started_at: *started_at,
-                stage: IncrementalBackupStage::Uploading(IncrementalBackupUploadState {
-                    timestamp: chrono::Utc::now(),
-                    parent: uploading_state.parent_backup_id,
-                    remote_filename: uploading_state
-                        .remote_path_relative
-                        .to_string_lossy()
-                        .to_string(),
-                    local_snapshot: uploading_state
-                        .local_folder_relative
-                        .to_string_lossy()
-                        .to_string(),
-                    bytes_read: uploading_state.read_bytes.value(),
-                    bytes_written: uploading_state.written_bytes.value(),
-                    compression_level: self.encoding_data_tunnel.compression_level,
-                    encrypted: match &self.encoding_data_tunnel.encryption_level {
-                        EncryptionLevel::None => false,
-                        EncryptionLevel::Symmetrical { .. } => true,
-                    },
-                    finishing: uploading_state.finishing,
-                }),
-            },
-        }
+        let new_state = map_state(state)?;
+        drop(state_lock);
+        self.set_internal_state(new_state);
+        Ok(())
+    }
+
+    /// Where the chain is received, for jobs that inspect the restored snapshots.
+    pub(crate) fn local_service(&self) -> MutexGuard<'_, Box<dyn SourceService + Send>> {
+        self.local_service.lock().unwrap()
     }
-}*/
+}
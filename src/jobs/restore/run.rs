@@ -0,0 +1,386 @@
+use crate::jobs::restore::state::{RestoreBackupJobDownloadState, RestoreBackupJobState};
+use crate::jobs::restore::RestoreBackupJob;
+use crate::objects::job_result::RestoreSuccess;
+use crate::objects::{
+    BackupChain, BackupChainError, BackupEntry, BackupHistory, BackupSource, ChecksumMismatch,
+    ContainerHeader, EncryptionLevel,
+};
+use crate::services::chunk_store::{read_index, ChunkStore};
+use crate::services::data_tunnel::{ChunkStats, DataTunnel, UnchunkingDataTunnel};
+use crate::services::tracking::{BytesCounter, BytesCountingReader, HashingReader};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek};
+use std::ops::Deref;
+use std::path::Path;
+use thiserror::Error;
+
+impl RestoreBackupJob {
+    pub fn run_impl(&self) -> Result<RestoreSuccess, RestoreRunError> {
+        let history = self.backup_history()?;
+
+        let backup_id = match self.backup_id {
+            Some(backup_id) => backup_id,
+            // Tar archives of full data backups cannot be received as a snapshot
+            None => history
+                .entries
+                .iter()
+                .filter(|entry| entry.source != Some(BackupSource::Tar))
+                .max_by_key(|entry| entry.timestamp)
+                .ok_or(RestoreRunError::NoBackups)?
+                .id,
+        };
+        self.restore_chain(&history, backup_id)
+    }
+
+    pub(crate) fn backup_history(&self) -> Result<BackupHistory, RestoreRunError> {
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        remote_service_lock
+            .backup_history()
+            .map_err(|err| RestoreRunError::IoError {
+                stage: RestoreRunStage::FetchingMetadata,
+                source: err,
+            })
+    }
+
+    /// Source of a backup made before it was recorded, taken from its container header.
+    /// Chunked backups and files without a header predate full data backups.
+    fn read_source(&self, entry: &BackupEntry) -> Result<BackupSource, RestoreRunError> {
+        if entry.chunk_count.is_some() {
+            return Ok(BackupSource::BtrfsStream);
+        }
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        let header = remote_service_lock
+            .get_backup_reader(entry.remote_filename.to_path_buf())
+            .and_then(ContainerHeader::read_from)
+            .map_err(|err| RestoreRunError::IoError {
+                stage: RestoreRunStage::FetchingMetadata,
+                source: err,
+            })?
+            .0;
+        Ok(header.map_or(BackupSource::BtrfsStream, |(header, _)| header.source))
+    }
+
+    /// Checks that the chunks the chunked links of `chain` reference are stored before any link
+    /// is received. The indexes are small, reading them again for the restore costs little.
+    fn verify_chunks(&self, chain: &BackupChain) -> Result<(), RestoreRunError> {
+        let metadata_error = |err| RestoreRunError::IoError {
+            stage: RestoreRunStage::FetchingMetadata,
+            source: err,
+        };
+        let chunked = chain.entries.iter().filter(|entry| entry.chunk_count.is_some());
+        if chunked.clone().next().is_none() {
+            return Ok(());
+        }
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        let stored_ids = ChunkStore::new(
+            &**remote_service_lock,
+            &self.decoding_data_tunnel.encryption_level,
+        )
+        .stored_ids()
+        .map_err(metadata_error)?;
+        let mut indexed_ids = HashMap::new();
+        for entry in chunked {
+            let index = read_index(
+                &**remote_service_lock,
+                entry,
+                &self.decoding_data_tunnel,
+                &self.remote_storage,
+            )
+            .map_err(metadata_error)?;
+            let chunk_ids = index.chunks.into_iter().map(|chunk| chunk.id).collect();
+            indexed_ids.insert(entry.id, chunk_ids);
+        }
+        Ok(chain.verify_chunks(&indexed_ids, &stored_ids)?)
+    }
+
+    /// Checks that the jobs folder can hold the largest link of `chain` that is downloaded to
+    /// be verified, before any link is received.
+    fn verify_download_space(&self, chain: &BackupChain) -> Result<(), RestoreRunError> {
+        let required_bytes = chain
+            .entries
+            .iter()
+            .filter(|entry| entry.chunk_count.is_none() && entry.stored_hash.is_some())
+            .filter_map(|entry| entry.size)
+            .max();
+        let Some(required_bytes) = required_bytes else {
+            return Ok(());
+        };
+        let available_bytes =
+            available_bytes(&self.jobs_folder).map_err(|err| RestoreRunError::IoError {
+                stage: RestoreRunStage::FetchingMetadata,
+                source: err,
+            })?;
+        if available_bytes < required_bytes {
+            return Err(RestoreRunError::NotEnoughSpace {
+                required_bytes,
+                available_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Downloads the file of `entry` and checks it against its recorded checksum before anything
+    /// is received, as `btrfs receive` applies a forged stream right away. The copy is kept in
+    /// the jobs folder, unlinked so it is gone once read.
+    fn download_verified(
+        &self,
+        entry: &BackupEntry,
+        src_reader: impl Read,
+    ) -> Result<File, RestoreRunError> {
+        let download_error = |err| RestoreRunError::IoError {
+            stage: RestoreRunStage::Downloading,
+            source: err,
+        };
+        let download_name = format!("restore_{}_{:016x}.download", entry.id, rand::random::<u64>());
+        let download_path = self.jobs_folder.join(download_name);
+        let mut download = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&download_path)
+            .map_err(download_error)?;
+        let _ = std::fs::remove_file(&download_path);
+
+        let mut src_reader = HashingReader::new(src_reader);
+        let stored_hash = src_reader.hash();
+        let stored_bytes = std::io::copy(&mut src_reader, &mut download).map_err(download_error)?;
+        entry.verify_stored(stored_bytes, &stored_hash.value())?;
+        download.rewind().map_err(download_error)?;
+        Ok(download)
+    }
+
+    /// Receives `backup_id` and every backup it builds on, oldest first.
+    pub(crate) fn restore_chain(
+        &self,
+        history: &BackupHistory,
+        backup_id: u32,
+    ) -> Result<RestoreSuccess, RestoreRunError> {
+        let chain = history.resolve_chain(backup_id)?;
+
+        // Detect missing links and unknown keys before any data is downloaded
+        let remote_files = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock
+                .list_backup_files()
+                .map_err(|err| RestoreRunError::IoError {
+                    stage: RestoreRunStage::FetchingMetadata,
+                    source: err,
+                })?
+        };
+        chain.verify_remote_files(&remote_files)?;
+        for entry in &chain.entries {
+            let source = match entry.source {
+                Some(source) => source,
+                None => self.read_source(entry)?,
+            };
+            if source != BackupSource::BtrfsStream {
+                return Err(RestoreRunError::UnsupportedSource {
+                    backup_id: entry.id,
+                    backup_source: source,
+                });
+            }
+        }
+        let decoding_data_tunnels = chain
+            .entries
+            .iter()
+            .map(|entry| {
+                self.decoding_data_tunnel
+                    .for_entry(&self.remote_storage, entry)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| RestoreRunError::IoError {
+                stage: RestoreRunStage::FetchingMetadata,
+                source: err,
+            })?;
+        self.verify_chunks(&chain)?;
+        self.verify_download_space(&chain)?;
+        let target = chain.target();
+
+        let mut previous_read_bytes = 0;
+        let mut parent_folder = None;
+        let mut previous_written_bytes = 0;
+        let chain_entries = chain.entries.iter().zip(decoding_data_tunnels);
+        for (chain_position, (entry, decoding_data_tunnel)) in chain_entries.enumerate() {
+            // Links restored for an earlier backup of the chain are the parent of the next one
+            if entry.id != target.id {
+                let restored = {
+                    let local_service_lock = self.local_service.lock().unwrap();
+                    local_service_lock
+                        .is_restored(entry.local_snapshot.to_path_buf())
+                        .map_err(|err| RestoreRunError::IoError {
+                            stage: RestoreRunStage::Restoring,
+                            source: err,
+                        })?
+                };
+                if restored {
+                    parent_folder = Some(entry.local_snapshot.to_path_buf());
+                    continue;
+                }
+            }
+
+            let src_reader = {
+                let remote_service_lock = self.remote_service.lock().unwrap();
+                remote_service_lock
+                    .get_backup_reader(entry.remote_filename.to_path_buf())
+                    .map_err(|err| RestoreRunError::IoError {
+                        stage: RestoreRunStage::Downloading,
+                        source: err,
+                    })?
+            };
+
+            let restore_writer = || {
+                let local_service_lock = self.local_service.lock().unwrap();
+                local_service_lock
+                    .get_restore_writer(entry.local_snapshot.to_path_buf(), parent_folder.clone())
+                    .map_err(|err| RestoreRunError::IoError {
+                        stage: RestoreRunStage::Restoring,
+                        source: err,
+                    })
+            };
+
+            let progress = |read_bytes: BytesCounter, written_bytes: BytesCounter| {
+                self.update_internal_state(|old_state| {
+                    let started_at = match old_state {
+                        RestoreBackupJobState::Started { started_at } => started_at,
+                        RestoreBackupJobState::Downloading { started_at, .. } => started_at,
+                        _ => Err(RestoreRunError::ConcurrentStateManipulation {
+                            message: "Cannot be initial state when download starts".to_string(),
+                        })?,
+                    };
+
+                    Ok(RestoreBackupJobState::Downloading {
+                        started_at: *started_at,
+                        downloading_state: RestoreBackupJobDownloadState {
+                            backup_id: entry.id,
+                            chain_position,
+                            chain_length: chain.entries.len(),
+                            remote_path_relative: entry.remote_filename.to_path_buf(),
+                            local_folder_relative: entry.local_snapshot.to_path_buf(),
+                            compression: entry.compression,
+                            read_bytes: read_bytes.clone(),
+                            written_bytes: written_bytes.clone(),
+                            previous_read_bytes,
+                            previous_written_bytes,
+                        },
+                    })
+                })
+            };
+            // Chunked backups are read chunk by chunk, the file only holds their index
+            let (transferred, read_bytes, checksums) = match entry.chunk_count {
+                None => {
+                    let src_reader: Box<dyn Read> = match &entry.stored_hash {
+                        Some(_) => {
+                            let src_reader = BytesCountingReader::new(src_reader);
+                            progress(src_reader.counter(), BytesCounter::default())?;
+                            Box::new(self.download_verified(entry, src_reader)?)
+                        }
+                        None => src_reader,
+                    };
+                    let transfer =
+                        decoding_data_tunnel.tracked_transfer(src_reader, restore_writer()?);
+                    let read_bytes = transfer.reader_bytes_counter();
+                    progress(read_bytes.clone(), transfer.writer_bytes_counter())?;
+                    (transfer.run(), read_bytes, transfer.checksums())
+                }
+                Some(_) => {
+                    let remote_service_lock = self.remote_service.lock().unwrap();
+                    let chunk_stats = ChunkStats::default();
+                    let transfer = UnchunkingDataTunnel {
+                        store: ChunkStore::new(
+                            &**remote_service_lock,
+                            &decoding_data_tunnel.encryption_level,
+                        ),
+                        decoding_data_tunnel,
+                        stats: chunk_stats.clone(),
+                    }
+                    .tracked_transfer(src_reader, restore_writer()?);
+                    let read_bytes = chunk_stats.stored_bytes_counter();
+                    progress(read_bytes.clone(), transfer.writer_bytes_counter())?;
+                    (transfer.run(), read_bytes, transfer.checksums())
+                }
+            };
+            transferred.map_err(|err| RestoreRunError::IoError {
+                stage: RestoreRunStage::Downloading,
+                source: err,
+            })?;
+            let (stored_bytes, stored_hash, transfer_written_bytes, written_hash) = checksums;
+            let verified = entry
+                .verify_stored(stored_bytes, &stored_hash)
+                .and_then(|_| entry.verify_source(transfer_written_bytes, &written_hash));
+
+            let finished = {
+                let local_service_lock = self.local_service.lock().unwrap();
+                local_service_lock.finish_restore()
+            };
+            // The corruption is the cause if receiving the snapshot failed as well
+            verified?;
+            finished.map_err(|err| RestoreRunError::IoError {
+                stage: RestoreRunStage::Restoring,
+                source: err,
+            })?;
+
+            previous_read_bytes += read_bytes.value();
+            previous_written_bytes += transfer_written_bytes;
+            parent_folder = Some(entry.local_snapshot.to_path_buf());
+        }
+
+        Ok(RestoreSuccess {
+            id: target.id,
+            restored_chain: chain.entries.iter().map(|entry| entry.id).collect(),
+            local_snapshot: target.local_snapshot.to_string_lossy().to_string(),
+            bytes_read: previous_read_bytes,
+            bytes_written: previous_written_bytes,
+            compression: target.compression,
+            encrypted: match self.decoding_data_tunnel.encryption_level {
+                EncryptionLevel::None => false,
+                EncryptionLevel::Symmetrical { .. } => true,
+                EncryptionLevel::Recipients { .. } => true,
+            },
+        })
+    }
+}
+
+/// Bytes the user running the restore can still write to the file system holding `folder`.
+fn available_bytes(folder: &Path) -> std::io::Result<u64> {
+    let stats = rustix::fs::statvfs(folder)?;
+    Ok(stats.f_bavail * stats.f_frsize)
+}
+
+#[derive(Debug, Error)]
+pub enum RestoreRunError {
+    #[error("IO error during restore stage {stage:?}")]
+    IoError {
+        stage: RestoreRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("The backup history does not contain any backups")]
+    NoBackups,
+    #[error("The backup {backup_id} holds {backup_source:?} data, not a snapshot")]
+    UnsupportedSource {
+        backup_id: u32,
+        backup_source: BackupSource,
+    },
+    #[error(
+        "The jobs folder has {available_bytes} bytes free, verifying a backup before it is \
+         restored needs {required_bytes}"
+    )]
+    NotEnoughSpace {
+        required_bytes: u64,
+        available_bytes: u64,
+    },
+    #[error("The backup chain is broken: {0}")]
+    BrokenChain(#[from] BackupChainError),
+    #[error("The restored data failed its checksum: {0}")]
+    ChecksumMismatch(#[from] ChecksumMismatch),
+    #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
+    ConcurrentStateManipulation { message: String },
+}
+
+#[derive(Debug)]
+pub enum RestoreRunStage {
+    FetchingMetadata,
+    Downloading,
+    Restoring,
+}
@@ -1,4 +1,35 @@
-pub struct RestoreBackupState {
-    pub job_id: String,
-    pub backup_id: String,
+use crate::objects::Compression;
+use crate::services::tracking::BytesCounter;
+use std::path::PathBuf;
+
+pub(crate) enum RestoreBackupJobState {
+    Initial,
+    Started {
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Downloading {
+        started_at: chrono::DateTime<chrono::Utc>,
+        downloading_state: RestoreBackupJobDownloadState,
+    },
+}
+
+#[derive(Clone)]
+pub struct RestoreBackupJobDownloadState {
+    pub backup_id: u32,
+    pub chain_position: usize,
+    pub chain_length: usize,
+    pub remote_path_relative: PathBuf,
+    pub local_folder_relative: PathBuf,
+    pub compression: Option<Compression>,
+    pub read_bytes: BytesCounter,
+    pub written_bytes: BytesCounter,
+    /// Bytes transferred by chain entries that were already restored.
+    pub previous_read_bytes: u64,
+    pub previous_written_bytes: u64,
+}
+
+impl Default for RestoreBackupJobState {
+    fn default() -> Self {
+        Self::Initial
+    }
 }
@@ -0,0 +1,488 @@
+use crate::config::{DataDanceConfiguration, KeyringEntry, DEFAULT_KEY_ID};
+use crate::jobs::restore::RestoreBackupJob;
+use crate::jobs::test_support::{make_config, upload_recorded};
+use crate::jobs::Job;
+use crate::objects::job_result::{RestoreResult, RestoreResultState};
+use crate::objects::{
+    BackupEntry, BackupHistory, BackupSource, CompressionAlgorithm, CompressionLevel,
+};
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::data_dest::DestService;
+use crate::services::data_source::fake::FakeSourceService;
+use crate::services::data_tunnel::{DataTunnel, EncodingDataTunnel};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+fn make_history() -> BackupHistory {
+    BackupHistory {
+        entries: vec![
+            BackupEntry {
+                timestamp: 100,
+                remote_filename: "2024_01_01_12_00_00.bin".into(),
+                local_snapshot: "2024_01_01_12_00_00/".into(),
+                ..BackupEntry::test(10, None)
+            },
+            BackupEntry {
+                timestamp: 200,
+                remote_filename: "2024_01_02_12_00_00.dbin".into(),
+                local_snapshot: "2024_01_02_12_00_00/".into(),
+                ..BackupEntry::test(20, Some(10))
+            },
+            BackupEntry {
+                timestamp: 300,
+                remote_filename: "2024_01_03_12_00_00.dbin".into(),
+                local_snapshot: "2024_01_03_12_00_00/".into(),
+                ..BackupEntry::test(30, Some(20))
+            },
+        ],
+    }
+}
+
+fn upload_file(dest: &FakeDestService, config: &DataDanceConfiguration, file: &str, content: &[u8]) {
+    let mut entry = BackupEntry {
+        remote_filename: file.into(),
+        ..BackupEntry::test(0, None)
+    };
+    upload_recorded(dest, config, &mut entry, content);
+}
+
+struct RestoreTestData {
+    run_result: RestoreResult,
+    restored_snapshots: Vec<(PathBuf, Vec<u8>)>,
+}
+
+fn run_fake_restore(
+    history: BackupHistory,
+    uploaded: &[(&str, &[u8])],
+    backup_id: Option<u32>,
+) -> RestoreTestData {
+    let config = make_config(Some("123456"), CompressionLevel::Best);
+
+    let fake_dest = FakeDestService::new(history);
+    for (file, content) in uploaded {
+        upload_file(&fake_dest, &config, file, content);
+    }
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, backup_id, Box::new(fake_source), Box::new(fake_dest));
+    let run_result = job.run();
+
+    RestoreTestData {
+        run_result,
+        restored_snapshots: fake_source_debug.restored_snapshots(),
+    }
+}
+
+#[test]
+fn restore_replays_chain_oldest_first() {
+    let test_data = run_fake_restore(
+        make_history(),
+        &[
+            ("2024_01_01_12_00_00.bin", b"full"),
+            ("2024_01_02_12_00_00.dbin", b"first increment"),
+            ("2024_01_03_12_00_00.dbin", b"second increment"),
+        ],
+        Some(20),
+    );
+
+    match &test_data.run_result.state {
+        RestoreResultState::Error(err) => panic!("Job errored: {err}"),
+        RestoreResultState::Success(result) => {
+            assert_eq!(result.id, 20);
+            assert_eq!(result.restored_chain, vec![10, 20]);
+            assert_eq!(result.local_snapshot, "2024_01_02_12_00_00/");
+            assert_eq!(result.bytes_written, ("full".len() + "first increment".len()) as u64);
+            assert_eq!(result.encrypted, true);
+        }
+    }
+    assert_eq!(
+        test_data.restored_snapshots,
+        vec![
+            (PathBuf::from("2024_01_01_12_00_00/"), b"full".to_vec()),
+            (
+                PathBuf::from("2024_01_02_12_00_00/"),
+                b"first increment".to_vec()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn restore_defaults_to_latest_backup() {
+    let test_data = run_fake_restore(
+        make_history(),
+        &[
+            ("2024_01_01_12_00_00.bin", b"full"),
+            ("2024_01_02_12_00_00.dbin", b"first increment"),
+            ("2024_01_03_12_00_00.dbin", b"second increment"),
+        ],
+        None,
+    );
+
+    match &test_data.run_result.state {
+        RestoreResultState::Error(err) => panic!("Job errored: {err}"),
+        RestoreResultState::Success(result) => {
+            assert_eq!(result.id, 30);
+            assert_eq!(result.restored_chain, vec![10, 20, 30]);
+        }
+    }
+    assert_eq!(test_data.restored_snapshots.len(), 3);
+}
+
+#[test]
+fn restore_reuses_links_restored_for_an_earlier_backup() {
+    let config = make_config(Some("123456"), CompressionLevel::Fast);
+    let make_dest = || {
+        let fake_dest = FakeDestService::new(make_history());
+        upload_file(&fake_dest, &config, "2024_01_01_12_00_00.bin", b"full");
+        upload_file(&fake_dest, &config, "2024_01_02_12_00_00.dbin", b"first increment");
+        upload_file(&fake_dest, &config, "2024_01_03_12_00_00.dbin", b"second increment");
+        fake_dest
+    };
+    let first_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = first_source.live_debug_data();
+
+    let first_job = RestoreBackupJob::new(
+        config.clone(),
+        Some(20),
+        Box::new(first_source),
+        Box::new(make_dest()),
+    );
+    if let RestoreResultState::Error(err) = first_job.run().state {
+        panic!("First job errored: {err}");
+    }
+    let second_source =
+        FakeSourceService::new("unused/".into(), 0).with_restored_snapshots_of(&fake_source_debug);
+    let second_job = RestoreBackupJob::new(
+        config.clone(),
+        Some(30),
+        Box::new(second_source),
+        Box::new(make_dest()),
+    );
+
+    match second_job.run().state {
+        RestoreResultState::Error(err) => panic!("Second job errored: {err}"),
+        RestoreResultState::Success(result) => {
+            assert_eq!(result.restored_chain, vec![10, 20, 30]);
+            assert_eq!(result.bytes_written, "second increment".len() as u64);
+        }
+    }
+    assert_eq!(fake_source_debug.restored_snapshots().len(), 3);
+
+    // Only links of the chain are reused, never the backup that is asked for
+    let third_source =
+        FakeSourceService::new("unused/".into(), 0).with_restored_snapshots_of(&fake_source_debug);
+    let third_job = RestoreBackupJob::new(
+        config.clone(),
+        Some(30),
+        Box::new(third_source),
+        Box::new(make_dest()),
+    );
+    match third_job.run().state {
+        RestoreResultState::Error(err) => assert!(err.contains("Restoring"), "{err}"),
+        RestoreResultState::Success(_) => panic!("Job restored a backup twice"),
+    }
+}
+
+#[test]
+fn restore_unknown_backup_fails() {
+    let test_data = run_fake_restore(make_history(), &[], Some(40));
+
+    assert!(matches!(
+        test_data.run_result.state,
+        RestoreResultState::Error(_)
+    ));
+    assert!(test_data.restored_snapshots.is_empty());
+}
+
+#[test]
+fn restore_missing_remote_file_fails_before_download() {
+    let test_data = run_fake_restore(
+        make_history(),
+        &[
+            ("2024_01_01_12_00_00.bin", b"full"),
+            ("2024_01_03_12_00_00.dbin", b"second increment"),
+        ],
+        Some(30),
+    );
+
+    match &test_data.run_result.state {
+        RestoreResultState::Error(err) => assert!(err.contains("2024_01_02_12_00_00.dbin")),
+        RestoreResultState::Success(_) => panic!("Job succeeded with a broken chain"),
+    }
+    assert!(test_data.restored_snapshots.is_empty());
+}
+
+#[test]
+fn restore_missing_chunk_fails_before_download() {
+    let mut history = make_history();
+    history.entries[1].chunk_count = Some(2);
+    let test_data = run_fake_restore(
+        history,
+        &[
+            ("2024_01_01_12_00_00.bin", b"full"),
+            ("2024_01_02_12_00_00.dbin", b"stored 4\nmissing 4\n"),
+            ("stored.chunk", b"data"),
+            ("2024_01_03_12_00_00.dbin", b"second increment"),
+        ],
+        Some(30),
+    );
+
+    match &test_data.run_result.state {
+        RestoreResultState::Error(err) => assert!(err.contains("'missing' of backup 20")),
+        RestoreResultState::Success(_) => panic!("Job succeeded with a missing chunk"),
+    }
+    assert!(test_data.restored_snapshots.is_empty());
+}
+
+#[test]
+fn restore_decrypts_with_recipient_identity() {
+    let (recipient, identity) =
+        crate::services::encryption::recipients::generate_identity().unwrap();
+    let mut config = make_config(None, CompressionLevel::Fast);
+    config.remote_storage.recipients = vec![recipient];
+
+    let fake_dest = FakeDestService::new(make_history());
+    upload_file(&fake_dest, &config, "2024_01_01_12_00_00.bin", b"full");
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, Some(10), Box::new(fake_source), Box::new(fake_dest))
+        .with_identities(vec![identity.into()]);
+    let run_result = job.run();
+
+    match &run_result.state {
+        RestoreResultState::Error(err) => panic!("Job errored: {err}"),
+        RestoreResultState::Success(result) => assert_eq!(result.encrypted, true),
+    }
+    assert_eq!(
+        fake_source_debug.restored_snapshots(),
+        vec![(PathBuf::from("2024_01_01_12_00_00/"), b"full".to_vec())]
+    );
+}
+
+#[test]
+fn restore_of_recipient_backup_ignores_default_keyring_entry() {
+    let (recipient, identity) =
+        crate::services::encryption::recipients::generate_identity().unwrap();
+    let mut config = make_config(None, CompressionLevel::Fast);
+    config.remote_storage.recipients = vec![recipient];
+    config.remote_storage.keyring = vec![KeyringEntry {
+        id: DEFAULT_KEY_ID.to_string(),
+        password: "old password".into(),
+    }];
+
+    let mut history = make_history();
+    history.entries[0].key_id = Some(config.remote_storage.current_key_id());
+    let fake_dest = FakeDestService::new(history);
+    upload_file(&fake_dest, &config, "2024_01_01_12_00_00.bin", b"full");
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, Some(10), Box::new(fake_source), Box::new(fake_dest))
+        .with_identities(vec![identity.into()]);
+    if let RestoreResultState::Error(err) = job.run().state {
+        panic!("Job errored: {err}");
+    }
+    assert_eq!(
+        fake_source_debug.restored_snapshots(),
+        vec![(PathBuf::from("2024_01_01_12_00_00/"), b"full".to_vec())]
+    );
+}
+
+#[test]
+fn restore_decrypts_rotated_keys_from_keyring() {
+    let old_config = make_config(Some("old password"), CompressionLevel::Fast);
+    let mut config = make_config(Some("new password"), CompressionLevel::Fast);
+    config.remote_storage.encryption_key_id = Some("2".to_string());
+    config.remote_storage.keyring = vec![KeyringEntry {
+        id: DEFAULT_KEY_ID.to_string(),
+        password: "old password".into(),
+    }];
+
+    let mut history = make_history();
+    history.entries[1].key_id = Some("2".to_string());
+    let fake_dest = FakeDestService::new(history);
+    upload_file(&fake_dest, &old_config, "2024_01_01_12_00_00.bin", b"full");
+    upload_file(&fake_dest, &config, "2024_01_02_12_00_00.dbin", b"first increment");
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, Some(20), Box::new(fake_source), Box::new(fake_dest));
+    if let RestoreResultState::Error(err) = job.run().state {
+        panic!("Job errored: {err}");
+    }
+    assert_eq!(
+        fake_source_debug.restored_snapshots(),
+        vec![
+            (PathBuf::from("2024_01_01_12_00_00/"), b"full".to_vec()),
+            (
+                PathBuf::from("2024_01_02_12_00_00/"),
+                b"first increment".to_vec()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn restore_unknown_key_fails_before_download() {
+    let mut history = make_history();
+    history.entries[1].key_id = Some("lost".to_string());
+    let test_data = run_fake_restore(
+        history,
+        &[
+            ("2024_01_01_12_00_00.bin", b"full"),
+            ("2024_01_02_12_00_00.dbin", b"first increment"),
+        ],
+        Some(20),
+    );
+
+    assert!(matches!(
+        test_data.run_result.state,
+        RestoreResultState::Error(_)
+    ));
+    assert!(test_data.restored_snapshots.is_empty());
+}
+
+#[test]
+fn restore_checks_recorded_checksums() {
+    for algorithm in [
+        CompressionAlgorithm::Zstd,
+        CompressionAlgorithm::Lz4,
+        CompressionAlgorithm::Gzip,
+        CompressionAlgorithm::Xz,
+        CompressionAlgorithm::None,
+    ] {
+        let mut config = make_config(Some("123456"), CompressionLevel::Fast);
+        config.remote_storage.compression = algorithm.into();
+        let mut history = make_history();
+        let fake_dest = FakeDestService::empty();
+        upload_recorded(&fake_dest, &config, &mut history.entries[0], b"full");
+        fake_dest.set_backup_history(history).unwrap();
+        let fake_source = FakeSourceService::new("unused/".into(), 0);
+
+        let job =
+            RestoreBackupJob::new(config, Some(10), Box::new(fake_source), Box::new(fake_dest));
+        if let RestoreResultState::Error(err) = job.run().state {
+            panic!("Job errored with {algorithm:?}: {err}");
+        }
+    }
+}
+
+#[test]
+fn restore_without_room_to_verify_fails_before_download() {
+    let config = make_config(Some("123456"), CompressionLevel::Fast);
+    let mut history = make_history();
+    let fake_dest = FakeDestService::empty();
+    upload_recorded(&fake_dest, &config, &mut history.entries[0], b"full");
+    history.entries[0].size = Some(u64::MAX);
+    fake_dest.set_backup_history(history).unwrap();
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, Some(10), Box::new(fake_source), Box::new(fake_dest));
+
+    match job.run().state {
+        RestoreResultState::Error(err) => assert!(err.contains("bytes free"), "{err}"),
+        RestoreResultState::Success(_) => panic!("Job succeeded without room to verify"),
+    }
+    assert!(fake_source_debug.restored_snapshots().is_empty());
+}
+
+#[test]
+fn restore_fails_on_checksum_mismatch() {
+    let config = make_config(Some("123456"), CompressionLevel::Fast);
+    let mut history = make_history();
+    let fake_dest = FakeDestService::empty();
+    upload_recorded(&fake_dest, &config, &mut history.entries[0], b"full");
+    upload_recorded(&fake_dest, &config, &mut history.entries[1], b"first increment");
+    // Replaced on the remote by a file that decodes fine but is not the recorded one
+    let recorded_entry = history.entries[1].clone();
+    upload_recorded(&fake_dest, &config, &mut history.entries[1], b"forged increment");
+    history.entries[1] = recorded_entry;
+    fake_dest.set_backup_history(history).unwrap();
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, Some(20), Box::new(fake_source), Box::new(fake_dest));
+
+    match job.run().state {
+        RestoreResultState::Error(err) => assert!(
+            err.starts_with("The restored data failed its checksum: backup 20 is corrupted"),
+            "{err}"
+        ),
+        RestoreResultState::Success(_) => panic!("Job succeeded with a forged backup"),
+    }
+    // Checked before it is received
+    assert_eq!(
+        fake_source_debug.restored_snapshots(),
+        vec![(PathBuf::from("2024_01_01_12_00_00/"), b"full".to_vec())]
+    );
+}
+
+fn tar_entry(source: Option<BackupSource>) -> BackupEntry {
+    BackupEntry {
+        timestamp: 400,
+        remote_filename: "full_2024_01_04_12_00_00.tar".into(),
+        local_snapshot: "full_2024_01_04_12_00_00/".into(),
+        source,
+        ..BackupEntry::test(40, None)
+    }
+}
+
+#[test]
+fn restore_defaults_to_latest_snapshot_backup() {
+    let mut history = make_history();
+    history.entries.push(tar_entry(Some(BackupSource::Tar)));
+    let test_data = run_fake_restore(
+        history,
+        &[
+            ("2024_01_01_12_00_00.bin", b"full"),
+            ("2024_01_02_12_00_00.dbin", b"first increment"),
+            ("2024_01_03_12_00_00.dbin", b"second increment"),
+            ("full_2024_01_04_12_00_00.tar", b"archive"),
+        ],
+        None,
+    );
+
+    match &test_data.run_result.state {
+        RestoreResultState::Error(err) => panic!("Job errored: {err}"),
+        RestoreResultState::Success(result) => assert_eq!(result.id, 30),
+    }
+}
+
+#[test]
+fn restore_of_tar_backup_fails_before_download() {
+    let config = make_config(Some("123456"), CompressionLevel::Fast);
+    // Recorded before entries named their source, so only the container header tells
+    let mut history = make_history();
+    history.entries.push(tar_entry(None));
+    let fake_dest = FakeDestService::new(history);
+    let tunnel = EncodingDataTunnel {
+        compression: config.remote_storage.compression,
+        encryption_level: config.remote_storage.encryption_level(),
+        source: BackupSource::Tar,
+        parent: None,
+        compression_mix: Default::default(),
+    };
+    let writer = fake_dest
+        .get_backup_writer("full_2024_01_04_12_00_00.tar".into())
+        .unwrap();
+    tunnel
+        .transfer(Cursor::new(b"archive".to_vec()), writer)
+        .unwrap();
+    let fake_source = FakeSourceService::new("unused/".into(), 0);
+    let fake_source_debug = fake_source.live_debug_data();
+
+    let job = RestoreBackupJob::new(config, Some(40), Box::new(fake_source), Box::new(fake_dest));
+
+    match job.run().state {
+        RestoreResultState::Error(err) => assert!(
+            err.starts_with("The backup 40 holds Tar data"),
+            "{err}"
+        ),
+        RestoreResultState::Success(_) => panic!("Job received a tar archive as a snapshot"),
+    }
+    assert!(fake_source_debug.restored_snapshots().is_empty());
+}
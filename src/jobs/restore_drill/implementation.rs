@@ -0,0 +1,98 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::Job;
+use crate::jobs::restore::RestoreBackupJobState;
+use crate::jobs::restore_drill::RestoreDrillJob;
+use crate::jobs::restore_drill::state::{RestoreDrillJobState, RestoreDrillStep};
+use crate::objects;
+use crate::objects::job_state::{
+    FetchingMetadataState, RestoreDrillCheckingState, RestoreDrillCleaningUpState,
+    RestoreDrillRestoringState, RestoreDrillStage, RestoreStage,
+};
+use crate::services::data_dest::dest_service_from_config;
+use crate::services::data_source::source_service_from_config;
+use std::ops::Deref;
+
+impl Job for RestoreDrillJob {
+    type CompletionStats = objects::job_result::RestoreDrillResult;
+    type RunningStats = objects::job_state::RestoreDrillState;
+
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let mut drill_config = config.clone();
+        if let Some(restore_drill) = &config.restore_drill {
+            // A folder of its own per drill, so leftovers of a failed cleanup never collide
+            let drill_folder = restore_drill.scratch_folder.join(format!(
+                "drill_{}",
+                chrono::Utc::now().format("%Y-%m-%d-%H-%M-%S")
+            ));
+            drill_config.local_storage.source = config
+                .local_storage
+                .source
+                .clone()
+                .with_restore_folder(drill_folder);
+        }
+        let local_service = source_service_from_config(&drill_config);
+        let remote_service = dest_service_from_config(&config)?;
+
+        Ok(RestoreDrillJob::new(config, local_service, remote_service))
+    }
+
+    fn run(&self) -> Self::CompletionStats {
+        let started_at = chrono::Utc::now();
+        self.set_internal_state(RestoreDrillJobState::Started { started_at });
+        self.restore
+            .set_internal_state(RestoreBackupJobState::Started { started_at });
+
+        let result = self.run_impl();
+
+        let finished_at = chrono::Utc::now();
+
+        objects::job_result::RestoreDrillResult {
+            started_at,
+            finished_at,
+            state: match result {
+                Ok(result) => objects::job_result::RestoreDrillResultState::Success(result),
+                Err(err) => objects::job_result::RestoreDrillResultState::Error(err.to_string()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Self::RunningStats {
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            RestoreDrillJobState::Initial => objects::job_state::RestoreDrillState {
+                started_at: chrono::Utc::now(),
+                backup_id: None,
+                stage: FetchingMetadataState.into(),
+            },
+            RestoreDrillJobState::Started { started_at } => objects::job_state::RestoreDrillState {
+                started_at: *started_at,
+                backup_id: None,
+                stage: FetchingMetadataState.into(),
+            },
+            RestoreDrillJobState::Drilling {
+                started_at,
+                backup_id,
+                step,
+            } => objects::job_state::RestoreDrillState {
+                started_at: *started_at,
+                backup_id: Some(*backup_id),
+                stage: match step {
+                    RestoreDrillStep::Restoring => {
+                        let download = match self.restore.stats().stage {
+                            RestoreStage::FetchingMetadata(_) => None,
+                            RestoreStage::Downloading(download) => Some(download),
+                        };
+                        RestoreDrillStage::Restoring(RestoreDrillRestoringState { download })
+                    }
+                    RestoreDrillStep::Checking => {
+                        RestoreDrillStage::Checking(RestoreDrillCheckingState)
+                    }
+                    RestoreDrillStep::CleaningUp => {
+                        RestoreDrillStage::CleaningUp(RestoreDrillCleaningUpState)
+                    }
+                },
+            },
+        }
+    }
+}
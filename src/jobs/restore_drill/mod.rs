@@ -0,0 +1,67 @@
+mod implementation;
+mod run;
+mod state;
+#[cfg(test)]
+mod tests;
+
+use crate::config::{DataDanceConfiguration, DrillPick};
+use crate::jobs::restore::RestoreBackupJob;
+use crate::jobs::restore_drill::run::RestoreDrillRunError;
+use crate::jobs::restore_drill::state::RestoreDrillJobState;
+use crate::services::data_dest::DestService;
+use crate::services::data_source::SourceService;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// Restores a backup that has a snapshot manifest, compares the received snapshot with it and
+/// deletes everything it received again. The local service has to receive into scratch space.
+pub struct RestoreDrillJob {
+    /// `None` if no restore drill is configured, the job then fails right away.
+    pick: Option<DrillPick>,
+    /// Replays the chain of the picked backup.
+    restore: RestoreBackupJob,
+
+    state: Mutex<RestoreDrillJobState>,
+}
+
+impl RestoreDrillJob {
+    pub fn new(
+        config: DataDanceConfiguration,
+        local_service: Box<dyn SourceService + Send>,
+        remote_service: Box<dyn DestService + Send>,
+    ) -> Self {
+        let pick = config
+            .restore_drill
+            .as_ref()
+            .map(|restore_drill| restore_drill.pick);
+
+        Self {
+            pick,
+            restore: RestoreBackupJob::new(config, None, local_service, remote_service),
+
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn set_internal_state(&self, new_state: RestoreDrillJobState) {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            let state = state_lock.deref_mut();
+            *state = new_state
+        }
+    }
+
+    pub fn update_internal_state(
+        &self,
+        map_state: impl Fn(
+            &RestoreDrillJobState,
+        ) -> Result<RestoreDrillJobState, RestoreDrillRunError>,
+    ) -> Result<(), RestoreDrillRunError> {
+        let mut state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        let new_state = map_state(state)?;
+        drop(state_lock);
+        self.set_internal_state(new_state);
+        Ok(())
+    }
+}
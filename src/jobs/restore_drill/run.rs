@@ -0,0 +1,152 @@
+use crate::config::DrillPick;
+use crate::jobs::restore::RestoreRunError;
+use crate::jobs::restore_drill::RestoreDrillJob;
+use crate::jobs::restore_drill::state::{RestoreDrillJobState, RestoreDrillStep};
+use crate::objects::job_result::RestoreDrillSuccess;
+use crate::objects::{BackupEntry, SnapshotManifest};
+use thiserror::Error;
+
+impl RestoreDrillJob {
+    pub fn run_impl(&self) -> Result<RestoreDrillSuccess, RestoreDrillRunError> {
+        let Some(pick) = self.pick else {
+            return Err(RestoreDrillRunError::NotConfigured);
+        };
+        let history = self.restore.backup_history()?;
+
+        // Backups without a manifest have nothing to compare the restore with
+        let candidates: Vec<(&BackupEntry, &SnapshotManifest)> = history
+            .entries
+            .iter()
+            .filter_map(|entry| Some((entry, entry.manifest.as_ref()?)))
+            .collect();
+        let target = match pick {
+            DrillPick::Latest => candidates.iter().max_by_key(|(entry, _)| entry.timestamp),
+            DrillPick::Random if candidates.is_empty() => None,
+            DrillPick::Random => candidates.get(rand::random_range(0..candidates.len())),
+        };
+        let Some(&(target, manifest)) = target else {
+            return Err(RestoreDrillRunError::NoCandidates);
+        };
+
+        self.set_step(target.id, RestoreDrillStep::Restoring)?;
+        let drilled = self
+            .restore
+            .restore_chain(&history, target.id)
+            .map_err(RestoreDrillRunError::from)
+            .and_then(|restored| {
+                self.set_step(target.id, RestoreDrillStep::Checking)?;
+                Ok((restored, self.check(target, manifest)?))
+            });
+
+        // Whatever was received is deleted, even if the drill failed
+        let step = self.set_step(target.id, RestoreDrillStep::CleaningUp);
+        let cleared = self.restore.local_service().clear_restored();
+        let (restored, file_count) = match (drilled, cleared) {
+            (Ok(drilled), Ok(())) => drilled,
+            (Ok(_), Err(err)) => {
+                return Err(RestoreDrillRunError::IoError {
+                    stage: RestoreDrillRunStage::CleaningUp,
+                    source: err,
+                });
+            }
+            (Err(err), cleared) => {
+                if let Err(cleanup_err) = cleared {
+                    eprintln!("Failed to clean up after the failed restore drill: {cleanup_err}");
+                }
+                return Err(err);
+            }
+        };
+        step?;
+
+        Ok(RestoreDrillSuccess {
+            pick,
+            id: restored.id,
+            restored_chain: restored.restored_chain,
+            local_snapshot: restored.local_snapshot,
+            file_count,
+            bytes_read: restored.bytes_read,
+        })
+    }
+
+    /// Compares the received snapshot with the manifest recorded by the backup.
+    /// Returns the number of files it has.
+    fn check(
+        &self,
+        target: &BackupEntry,
+        manifest: &SnapshotManifest,
+    ) -> Result<u64, RestoreDrillRunError> {
+        let restored_manifest = self
+            .restore
+            .local_service()
+            .restored_manifest(target.local_snapshot.to_path_buf())
+            .map_err(|err| RestoreDrillRunError::IoError {
+                stage: RestoreDrillRunStage::Checking,
+                source: err,
+            })?;
+
+        let SnapshotManifest {
+            file_count,
+            tree_hash,
+        } = restored_manifest;
+        if file_count != manifest.file_count {
+            return Err(RestoreDrillRunError::ManifestMismatch {
+                backup_id: target.id,
+                details: format!(
+                    "it has {file_count} files instead of {}",
+                    manifest.file_count
+                ),
+            });
+        }
+        if tree_hash != manifest.tree_hash {
+            return Err(RestoreDrillRunError::ManifestMismatch {
+                backup_id: target.id,
+                details: "its tree hash differs from the recorded one".to_string(),
+            });
+        }
+        Ok(file_count)
+    }
+
+    fn set_step(&self, backup_id: u32, step: RestoreDrillStep) -> Result<(), RestoreDrillRunError> {
+        self.update_internal_state(|old_state| {
+            let started_at = match old_state {
+                RestoreDrillJobState::Started { started_at } => started_at,
+                RestoreDrillJobState::Drilling { started_at, .. } => started_at,
+                _ => Err(RestoreDrillRunError::ConcurrentStateManipulation {
+                    message: "Cannot be initial state when the drill starts".to_string(),
+                })?,
+            };
+
+            Ok(RestoreDrillJobState::Drilling {
+                started_at: *started_at,
+                backup_id,
+                step,
+            })
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RestoreDrillRunError {
+    #[error("no restore drill is configured")]
+    NotConfigured,
+    #[error("no backup has a recorded snapshot manifest to drill against")]
+    NoCandidates,
+    #[error("The drill could not restore the backup: {0}")]
+    Restore(#[from] RestoreRunError),
+    #[error("the restored backup {backup_id} does not match its snapshot, {details}")]
+    ManifestMismatch { backup_id: u32, details: String },
+    #[error("IO error during restore drill stage {stage:?}")]
+    IoError {
+        stage: RestoreDrillRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
+    ConcurrentStateManipulation { message: String },
+}
+
+#[derive(Debug)]
+pub enum RestoreDrillRunStage {
+    Checking,
+    CleaningUp,
+}
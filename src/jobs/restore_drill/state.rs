@@ -0,0 +1,24 @@
+pub(crate) enum RestoreDrillJobState {
+    Initial,
+    Started {
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Drilling {
+        started_at: chrono::DateTime<chrono::Utc>,
+        backup_id: u32,
+        step: RestoreDrillStep,
+    },
+}
+
+#[derive(Clone, Copy)]
+pub enum RestoreDrillStep {
+    Restoring,
+    Checking,
+    CleaningUp,
+}
+
+impl Default for RestoreDrillJobState {
+    fn default() -> Self {
+        Self::Initial
+    }
+}
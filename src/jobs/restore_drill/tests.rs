@@ -0,0 +1,183 @@
+use crate::config::{DataDanceConfiguration, DrillPick, RestoreDrillConfig};
+use crate::jobs::Job;
+use crate::jobs::restore_drill::RestoreDrillJob;
+use crate::jobs::test_support;
+use crate::objects::job_result::{RestoreDrillResultState, RestoreDrillSuccess};
+use crate::objects::{BackupEntry, BackupHistory, BackupSource, CompressionLevel, SnapshotManifest};
+use crate::services::data_dest::DestService;
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::data_source::fake::FakeSourceService;
+use crate::services::data_tunnel::{DataTunnel, EncodingDataTunnel};
+use crate::services::tracking::ContentHash;
+use std::io::Cursor;
+
+fn make_config(pick: DrillPick) -> DataDanceConfiguration {
+    DataDanceConfiguration {
+        restore_drill: Some(RestoreDrillConfig {
+            scratch_folder: "./drills".into(),
+            pick,
+            schedule: None,
+        }),
+        ..test_support::make_config(Some("password"), CompressionLevel::Fast)
+    }
+}
+
+/// Uploads like a backup job does and records the manifest of the fake snapshot in `entry`.
+fn upload_with_manifest(dest: &FakeDestService, entry: &mut BackupEntry, content: &[u8]) {
+    let config = make_config(DrillPick::Latest);
+    let tunnel = EncodingDataTunnel {
+        compression: config.remote_storage.compression,
+        encryption_level: config.remote_storage.encryption_level(),
+        source: BackupSource::BtrfsStream,
+        parent: entry.parent,
+        compression_mix: Default::default(),
+    };
+    let writer = dest
+        .get_backup_writer(entry.remote_filename.to_path_buf())
+        .unwrap();
+    tunnel
+        .transfer(Cursor::new(content.to_vec()), writer)
+        .unwrap();
+    entry.compression = Some(config.remote_storage.compression);
+    entry.manifest = Some(SnapshotManifest {
+        file_count: 1,
+        tree_hash: ContentHash::of_reader(Cursor::new(content.to_vec())).unwrap(),
+    });
+}
+
+fn run_drill(
+    pick: DrillPick,
+    entries: Vec<BackupEntry>,
+    dest: FakeDestService,
+) -> RestoreDrillResultState {
+    dest.set_backup_history(BackupHistory { entries }).unwrap();
+    let local = FakeSourceService::new("fake_snapshot".into(), 0);
+    let local_debug = local.live_debug_data();
+
+    let job = RestoreDrillJob::new(make_config(pick), Box::new(local), Box::new(dest));
+    let state = job.run().state;
+    // Cleaned up whether the drill passed or not
+    assert!(local_debug.restored_snapshots().is_empty());
+    state
+}
+
+fn expect_success(state: RestoreDrillResultState) -> RestoreDrillSuccess {
+    match state {
+        RestoreDrillResultState::Error(err) => panic!("Job errored: {err}"),
+        RestoreDrillResultState::Success(result) => result,
+    }
+}
+
+#[test]
+fn drill_restores_the_latest_backup_with_a_manifest() {
+    let mut entries = vec![
+        BackupEntry::test(10, None),
+        BackupEntry::test(20, Some(10)),
+        BackupEntry::test(30, Some(20)),
+    ];
+    let dest = FakeDestService::empty();
+    upload_with_manifest(&dest, &mut entries[0], b"full");
+    upload_with_manifest(&dest, &mut entries[1], b"first increment");
+    // Made before manifests were recorded
+    upload_with_manifest(&dest, &mut entries[2], b"second increment");
+    entries[2].manifest = None;
+
+    let state = run_drill(DrillPick::Latest, entries, dest);
+    let result = expect_success(state);
+
+    assert_eq!(result.pick, DrillPick::Latest);
+    assert_eq!(result.id, 20);
+    assert_eq!(result.restored_chain, vec![10, 20]);
+    assert_eq!(result.local_snapshot, "snapshot_20/");
+    assert_eq!(result.file_count, 1);
+    assert!(result.bytes_read > 0);
+}
+
+#[test]
+fn drill_picks_a_random_backup_with_a_manifest() {
+    let mut entries = vec![BackupEntry::test(10, None), BackupEntry::test(20, Some(10))];
+    let dest = FakeDestService::empty();
+    upload_with_manifest(&dest, &mut entries[0], b"full");
+    upload_with_manifest(&dest, &mut entries[1], b"first increment");
+    entries[1].manifest = None;
+
+    let state = run_drill(DrillPick::Random, entries, dest);
+    let result = expect_success(state);
+
+    assert_eq!(result.pick, DrillPick::Random);
+    assert_eq!(result.id, 10);
+    assert_eq!(result.restored_chain, vec![10]);
+}
+
+#[test]
+fn drill_fails_if_the_restore_differs_from_the_snapshot() {
+    let mut entries = vec![BackupEntry::test(10, None)];
+    let dest = FakeDestService::empty();
+    upload_with_manifest(&dest, &mut entries[0], b"full");
+    entries[0].manifest.as_mut().unwrap().tree_hash =
+        ContentHash::of_reader(Cursor::new(b"other")).unwrap();
+
+    let state = run_drill(DrillPick::Latest, entries, dest);
+
+    match state {
+        RestoreDrillResultState::Error(err) => assert_eq!(
+            err,
+            "the restored backup 10 does not match its snapshot, its tree hash differs from the \
+            recorded one"
+        ),
+        RestoreDrillResultState::Success(_) => panic!("Job should have failed"),
+    }
+}
+
+#[test]
+fn drill_fails_if_the_chain_cannot_be_restored() {
+    let mut entries = vec![BackupEntry::test(10, None), BackupEntry::test(20, Some(10))];
+    let dest = FakeDestService::empty();
+    upload_with_manifest(&dest, &mut entries[0], b"full");
+    upload_with_manifest(&dest, &mut entries[1], b"first increment");
+    dest.remove_backup_file("backup_10.bin".into()).unwrap();
+
+    let state = run_drill(DrillPick::Latest, entries, dest);
+
+    match state {
+        RestoreDrillResultState::Error(err) => assert_eq!(
+            err,
+            "The drill could not restore the backup: The backup chain is broken: the file \
+            'backup_10.bin' of backup 10 is missing on the remote"
+        ),
+        RestoreDrillResultState::Success(_) => panic!("Job should have failed"),
+    }
+}
+
+#[test]
+fn drill_without_manifests_fails() {
+    let entries = vec![BackupEntry::test(10, None)];
+
+    let state = run_drill(DrillPick::Latest, entries, FakeDestService::empty());
+
+    match state {
+        RestoreDrillResultState::Error(err) => assert_eq!(
+            err,
+            "no backup has a recorded snapshot manifest to drill against"
+        ),
+        RestoreDrillResultState::Success(_) => panic!("Job should have failed"),
+    }
+}
+
+#[test]
+fn drill_without_configuration_fails() {
+    let config = DataDanceConfiguration {
+        restore_drill: None,
+        ..make_config(DrillPick::Latest)
+    };
+    let job = RestoreDrillJob::new(
+        config,
+        Box::new(FakeSourceService::new("fake_snapshot".into(), 0)),
+        Box::new(FakeDestService::empty()),
+    );
+
+    match job.run().state {
+        RestoreDrillResultState::Error(err) => assert_eq!(err, "no restore drill is configured"),
+        RestoreDrillResultState::Success(_) => panic!("Job should have failed"),
+    }
+}
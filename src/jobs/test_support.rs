@@ -0,0 +1,139 @@
+//! Configurations and remote files the tests of the jobs share.
+use crate::config;
+use crate::config::{
+    DEFAULT_KEY_ID, DataDanceConfiguration, KeyringEntry, LocalStorageConfig, RemoteStorageConfig,
+    WebConfig,
+};
+use crate::objects::{
+    BackupEntry, BackupSource, CompressionAlgorithm, CompressionLevel, EncryptionLevel,
+};
+use crate::services::channels::ChannelWriter;
+use crate::services::data_dest::DestService;
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::data_tunnel::{DataTunnel, DecodingDataTunnel, EncodingDataTunnel};
+use std::io::Cursor;
+use std::sync::mpsc;
+
+/// Fake local and remote storage, encrypted with `password` if there is one.
+pub(crate) fn make_config(
+    password: Option<&str>,
+    compression_level: CompressionLevel,
+) -> DataDanceConfiguration {
+    DataDanceConfiguration {
+        web: WebConfig {
+            port: 3000,
+            host: "0.0.0.0".to_string(),
+        },
+        local_storage: LocalStorageConfig {
+            source: config::LocalSource::Fake {
+                backup_byte_size: 0,
+            },
+            jobs_folder: "./".into(),
+        },
+        remote_storage: RemoteStorageConfig {
+            dest: config::RemoteDestination::Fake,
+            replicas: Vec::new(),
+            encryption: password.map(|pw| pw.into()),
+            encryption_key_id: None,
+            keyring: Vec::new(),
+            recipients: Vec::new(),
+            compression: compression_level.into(),
+            retention: None,
+            consolidation: Default::default(),
+            repository: None,
+            resumable_upload: None,
+        },
+        full_backup: None,
+        schedule: None,
+        migration: None,
+        restore_drill: None,
+    }
+}
+
+/// Current password "new password" with id "2", the default key "old password" is still in
+/// the keyring.
+pub(crate) fn make_rotated_config() -> DataDanceConfiguration {
+    let mut config = make_config(Some("new password"), CompressionLevel::Fast);
+    config.remote_storage.encryption_key_id = Some("2".to_string());
+    config.remote_storage.keyring = vec![KeyringEntry {
+        id: DEFAULT_KEY_ID.to_string(),
+        password: "old password".into(),
+    }];
+    config
+}
+
+/// Uploads `content` as the file of `entry`, encrypted with `password`.
+pub(crate) fn upload(dest: &FakeDestService, entry: &BackupEntry, password: &str, content: &[u8]) {
+    let encryption_level = EncryptionLevel::Symmetrical {
+        password: password.into(),
+    };
+    upload_encrypted(dest, entry, encryption_level, content);
+}
+
+pub(crate) fn upload_encrypted(
+    dest: &FakeDestService,
+    entry: &BackupEntry,
+    encryption_level: EncryptionLevel,
+    content: &[u8],
+) {
+    let tunnel = EncodingDataTunnel {
+        compression: CompressionLevel::Fast.into(),
+        encryption_level,
+        source: BackupSource::BtrfsStream,
+        parent: entry.parent,
+        compression_mix: Default::default(),
+    };
+    let writer = dest
+        .get_backup_writer(entry.remote_filename.to_path_buf())
+        .unwrap();
+    tunnel
+        .transfer(Cursor::new(content.to_vec()), writer)
+        .unwrap();
+}
+
+/// Uploads like a backup job with `config` does and records the checksums in `entry`.
+pub(crate) fn upload_recorded(
+    dest: &FakeDestService,
+    config: &DataDanceConfiguration,
+    entry: &mut BackupEntry,
+    content: &[u8],
+) {
+    let tunnel = EncodingDataTunnel {
+        compression: config.remote_storage.compression,
+        encryption_level: config.remote_storage.encryption_level(),
+        source: BackupSource::BtrfsStream,
+        parent: entry.parent,
+        compression_mix: Default::default(),
+    };
+    let writer = dest
+        .get_backup_writer(entry.remote_filename.to_path_buf())
+        .unwrap();
+    let transfer = tunnel.tracked_transfer(Cursor::new(content.to_vec()), writer);
+    transfer.run().unwrap();
+    entry.compression = Some(config.remote_storage.compression);
+    entry.size = Some(transfer.writer_bytes_count());
+    entry.stored_hash = Some(transfer.writer_hash());
+    entry.source_size = Some(transfer.reader_bytes_count());
+    entry.source_hash = Some(transfer.reader_hash());
+}
+
+/// Decodes a file uploaded with `upload`.
+pub(crate) fn decode(encoded: Vec<u8>, password: &str) -> std::io::Result<Vec<u8>> {
+    let encryption_level = EncryptionLevel::Symmetrical {
+        password: password.into(),
+    };
+    decode_encrypted(encoded, encryption_level)
+}
+
+pub(crate) fn decode_encrypted(
+    encoded: Vec<u8>,
+    encryption_level: EncryptionLevel,
+) -> std::io::Result<Vec<u8>> {
+    let tunnel = DecodingDataTunnel {
+        compression: CompressionAlgorithm::Zstd,
+        encryption_level,
+    };
+    let (tx, rx) = mpsc::channel();
+    tunnel.transfer(Cursor::new(encoded), ChannelWriter::new(tx))?;
+    Ok(rx.iter().collect())
+}
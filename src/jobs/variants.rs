@@ -1,4 +1,10 @@
+use crate::jobs::full_backup::FullDataBackupJob;
 use crate::jobs::incremental_backup::IncrementalBackupJob;
+use crate::jobs::migrate::MigrateJob;
+use crate::jobs::rekey::RekeyJob;
+use crate::jobs::restore::RestoreBackupJob;
+use crate::jobs::restore_drill::RestoreDrillJob;
+use crate::jobs::verify::VerifyJob;
 use crate::jobs::Job;
 
 pub enum JobVariant {
@@ -7,12 +13,41 @@ pub enum JobVariant {
 }
 
 pub enum RestorationJobVariant {
-    DataRestoration(),
+    DataRestoration(RestoreBackupJob),
 }
 
 pub enum BackupJobVariant {
-    FullDataBackup(),
+    FullDataBackup(FullDataBackupJob),
     IncrementalDataBackup(IncrementalBackupJob),
+    /// Rewrites remote backups, so it must not run next to a backup.
+    Rekey(RekeyJob),
+    /// Writes to the remote destination, so it must not run next to a backup either.
+    Migrate(MigrateJob),
+    /// Only reads, but a backup running next to it would show up as an extra file.
+    Verify(VerifyJob),
+    /// Reads the backup history, scheduled drills are skipped while a backup runs.
+    RestoreDrill(RestoreDrillJob),
+}
+
+impl RestorationJobVariant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RestorationJobVariant::DataRestoration(_) => "restore",
+        }
+    }
+}
+
+impl BackupJobVariant {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BackupJobVariant::FullDataBackup(_) => "full backup",
+            BackupJobVariant::IncrementalDataBackup(_) => "incremental backup",
+            BackupJobVariant::Rekey(_) => "rekey",
+            BackupJobVariant::Migrate(_) => "migrate",
+            BackupJobVariant::Verify(_) => "verify",
+            BackupJobVariant::RestoreDrill(_) => "restore drill",
+        }
+    }
 }
 
 impl From<IncrementalBackupJob> for JobVariant {
@@ -20,3 +55,39 @@ impl From<IncrementalBackupJob> for JobVariant {
         JobVariant::Backup(BackupJobVariant::IncrementalDataBackup(value))
     }
 }
+
+impl From<FullDataBackupJob> for JobVariant {
+    fn from(value: FullDataBackupJob) -> Self {
+        JobVariant::Backup(BackupJobVariant::FullDataBackup(value))
+    }
+}
+
+impl From<RekeyJob> for JobVariant {
+    fn from(value: RekeyJob) -> Self {
+        JobVariant::Backup(BackupJobVariant::Rekey(value))
+    }
+}
+
+impl From<MigrateJob> for JobVariant {
+    fn from(value: MigrateJob) -> Self {
+        JobVariant::Backup(BackupJobVariant::Migrate(value))
+    }
+}
+
+impl From<VerifyJob> for JobVariant {
+    fn from(value: VerifyJob) -> Self {
+        JobVariant::Backup(BackupJobVariant::Verify(value))
+    }
+}
+
+impl From<RestoreDrillJob> for JobVariant {
+    fn from(value: RestoreDrillJob) -> Self {
+        JobVariant::Backup(BackupJobVariant::RestoreDrill(value))
+    }
+}
+
+impl From<RestoreBackupJob> for JobVariant {
+    fn from(value: RestoreBackupJob) -> Self {
+        JobVariant::Restoration(RestorationJobVariant::DataRestoration(value))
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::Job;
+use crate::jobs::verify::VerifyJob;
+use crate::jobs::verify::state::VerifyJobState;
+use crate::objects;
+use crate::objects::job_state::{FetchingMetadataState, VerifyProgressState, VerifyStage};
+use crate::services::data_dest::dest_service_from_config;
+use std::ops::Deref;
+
+impl Job for VerifyJob {
+    type CompletionStats = objects::job_result::VerifyResult;
+    type RunningStats = objects::job_state::VerifyState;
+
+    fn from_config(config: DataDanceConfiguration) -> std::io::Result<Self> {
+        let remote_service = dest_service_from_config(&config)?;
+
+        Ok(VerifyJob::new(config, remote_service))
+    }
+
+    fn run(&self) -> Self::CompletionStats {
+        let started_at = chrono::Utc::now();
+        self.set_internal_state(VerifyJobState::Started { started_at });
+
+        let result = self.run_impl();
+
+        let finished_at = chrono::Utc::now();
+
+        objects::job_result::VerifyResult {
+            started_at,
+            finished_at,
+            mode: self.mode,
+            state: match result {
+                Ok(result) => objects::job_result::VerifyResultState::Success(result),
+                Err(err) => objects::job_result::VerifyResultState::Error(err.to_string()),
+            },
+        }
+    }
+
+    fn stats(&self) -> Self::RunningStats {
+        let mode = self.mode;
+        let state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        match state {
+            VerifyJobState::Initial => objects::job_state::VerifyState {
+                started_at: chrono::Utc::now(),
+                mode,
+                stage: FetchingMetadataState.into(),
+            },
+            VerifyJobState::Started { started_at } => objects::job_state::VerifyState {
+                started_at: *started_at,
+                mode,
+                stage: FetchingMetadataState.into(),
+            },
+            VerifyJobState::Verifying {
+                started_at,
+                verifying_state,
+            } => objects::job_state::VerifyState {
+                started_at: *started_at,
+                mode,
+                stage: VerifyStage::Verifying(VerifyProgressState {
+                    timestamp: chrono::Utc::now(),
+                    current: verifying_state.backup_id,
+                    position: verifying_state.position as u32,
+                    total: verifying_state.total as u32,
+                    remote_filename: verifying_state
+                        .remote_path_relative
+                        .to_string_lossy()
+                        .to_string(),
+                    bytes_read: verifying_state.previous_read_bytes
+                        + verifying_state
+                            .read_bytes
+                            .as_ref()
+                            .map_or(0, |read_bytes| read_bytes.value()),
+                    issues: verifying_state.issues as u32,
+                }),
+            },
+        }
+    }
+}
@@ -0,0 +1,77 @@
+mod implementation;
+mod run;
+mod state;
+#[cfg(test)]
+mod tests;
+
+use crate::config::{DataDanceConfiguration, RemoteStorageConfig};
+use crate::jobs::verify::run::VerifyRunError;
+use crate::jobs::verify::state::VerifyJobState;
+use crate::objects::CompressionAlgorithm;
+use crate::objects::job_result::VerifyMode;
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::DecodingDataTunnel;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// Checks the remote backups against their history without changing anything. Problems it
+/// finds are reported as issues of a successful run, the job only fails if the remote cannot
+/// be inspected at all.
+pub struct VerifyJob {
+    decoding_data_tunnel: DecodingDataTunnel,
+    /// Looks up the password of every decoded entry by its key id.
+    remote_storage: RemoteStorageConfig,
+    mode: VerifyMode,
+
+    remote_service: Mutex<Box<dyn DestService + Send>>,
+
+    state: Mutex<VerifyJobState>,
+}
+
+impl VerifyJob {
+    pub fn new(
+        config: DataDanceConfiguration,
+        remote_service: Box<dyn DestService + Send>,
+    ) -> Self {
+        let decoding_data_tunnel = DecodingDataTunnel {
+            // Only files from before the container format have no header, they are all zstd
+            compression: CompressionAlgorithm::Zstd,
+            encryption_level: config.remote_storage.encryption_level(),
+        };
+
+        Self {
+            decoding_data_tunnel,
+            remote_storage: config.remote_storage,
+            mode: VerifyMode::default(),
+
+            remote_service: Mutex::new(remote_service),
+
+            state: Mutex::default(),
+        }
+    }
+
+    pub fn with_mode(mut self, mode: VerifyMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn set_internal_state(&self, new_state: VerifyJobState) {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            let state = state_lock.deref_mut();
+            *state = new_state
+        }
+    }
+
+    pub fn update_internal_state(
+        &self,
+        map_state: impl Fn(&VerifyJobState) -> Result<VerifyJobState, VerifyRunError>,
+    ) -> Result<(), VerifyRunError> {
+        let mut state_lock = self.state.lock().unwrap();
+        let state = state_lock.deref();
+        let new_state = map_state(state)?;
+        drop(state_lock);
+        self.set_internal_state(new_state);
+        Ok(())
+    }
+}
@@ -0,0 +1,336 @@
+use crate::jobs::verify::VerifyJob;
+use crate::jobs::verify::state::{VerifyJobProgressState, VerifyJobState};
+use crate::objects::job_result::{VerifyIssue, VerifyIssueKind, VerifyMode, VerifySuccess};
+use crate::objects::{BackupEntry, ChecksumMismatch, ChecksummedData};
+use crate::services::chunk_store::{ChunkStore, chunk_id_of, chunk_path, read_index};
+use crate::services::data_tunnel::{
+    ChunkStats, DataTunnel, PassThroughDataTunnel, UnchunkingDataTunnel,
+};
+use crate::services::tracking::BytesCounter;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+impl VerifyJob {
+    pub fn run_impl(&self) -> Result<VerifySuccess, VerifyRunError> {
+        let (history, remote_files) = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            let fetched = remote_service_lock
+                .backup_history()
+                .and_then(|history| Ok((history, remote_service_lock.list_backup_files()?)));
+            fetched.map_err(|err| VerifyRunError::IoError {
+                stage: VerifyRunStage::FetchingMetadata,
+                source: err,
+            })?
+        };
+
+        let mut issues = Vec::new();
+        for remote_file in &remote_files {
+            if !history
+                .entries
+                .iter()
+                .any(|entry| *entry.remote_filename == **remote_file)
+            {
+                issues.push(VerifyIssue {
+                    backup_id: None,
+                    remote_filename: Some(remote_file.to_string_lossy().to_string()),
+                    kind: VerifyIssueKind::ExtraFile,
+                    details: "the file is not referenced by the backup history".to_string(),
+                });
+            }
+        }
+
+        let mut checked = Vec::with_capacity(history.entries.len());
+        let mut previous_read_bytes = 0;
+        for (position, entry) in history.entries.iter().enumerate() {
+            let remote_path = entry.remote_filename.to_path_buf();
+            let progress = |read_bytes, issues| VerifyJobProgressState {
+                backup_id: entry.id,
+                position,
+                total: history.entries.len(),
+                remote_path_relative: remote_path.clone(),
+                read_bytes,
+                previous_read_bytes,
+                issues,
+            };
+            self.set_progress(progress(None, issues.len()))?;
+            checked.push(entry.id);
+
+            if let Err(err) = history.resolve_chain(entry.id) {
+                issues.push(VerifyIssue::of(entry, VerifyIssueKind::BrokenChain, err));
+            }
+            if !remote_files.contains(&remote_path) && !self.is_stored_in_folder(&remote_path) {
+                issues.push(VerifyIssue::of(
+                    entry,
+                    VerifyIssueKind::MissingFile,
+                    "the file is missing on the remote",
+                ));
+                continue;
+            }
+
+            let issue = match self.mode {
+                VerifyMode::Quick => self.check_size(entry),
+                VerifyMode::Deep => {
+                    let (read_bytes, issue) = self.check_content(entry, |counter| {
+                        self.set_progress(progress(Some(counter), issues.len()))
+                    })?;
+                    previous_read_bytes += read_bytes;
+                    issue
+                }
+            };
+            issues.extend(issue);
+        }
+        issues.extend(self.check_chunks(&history.entries, &remote_files)?);
+
+        Ok(VerifySuccess {
+            checked,
+            issues,
+            bytes_read: previous_read_bytes,
+        })
+    }
+
+    /// Files in a folder of the destination, like archives in a remote root, are not listed.
+    fn is_stored_in_folder(&self, remote_path: &Path) -> bool {
+        let in_folder = remote_path
+            .parent()
+            .is_some_and(|folder| !folder.as_os_str().is_empty());
+        in_folder
+            && (self.remote_service.lock().unwrap())
+                .backup_file_size(remote_path.to_path_buf())
+                .is_ok()
+    }
+
+    /// Compares the size of the remote file with the recorded one, without downloading it.
+    fn check_size(&self, entry: &BackupEntry) -> Option<VerifyIssue> {
+        let recorded_size = entry.size?;
+        let size = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock.backup_file_size(entry.remote_filename.to_path_buf())
+        };
+        let size = match size {
+            Ok(size) => size,
+            Err(err) => return Some(VerifyIssue::of(entry, VerifyIssueKind::Unreadable, err)),
+        };
+        if size == recorded_size {
+            return None;
+        }
+        let mismatch = ChecksumMismatch::Size {
+            backup_id: entry.id,
+            data: ChecksummedData::StoredFile,
+            size,
+            recorded_size,
+        };
+        Some(VerifyIssue::of(
+            entry,
+            VerifyIssueKind::SizeMismatch,
+            mismatch,
+        ))
+    }
+
+    /// Compares the chunk store with the indices of the chunked backups. Chunks an index refers
+    /// to must be stored, stored ones nothing refers to are reported as extra files. The latter
+    /// is only known if every index could be read.
+    fn check_chunks(
+        &self,
+        entries: &[BackupEntry],
+        remote_files: &[PathBuf],
+    ) -> Result<Vec<VerifyIssue>, VerifyRunError> {
+        let remote_service_lock = self.remote_service.lock().unwrap();
+        let chunk_files =
+            remote_service_lock
+                .list_chunk_files()
+                .map_err(|err| VerifyRunError::IoError {
+                    stage: VerifyRunStage::FetchingMetadata,
+                    source: err,
+                })?;
+        let stored: HashSet<String> = chunk_files
+            .iter()
+            .filter_map(|chunk_file| chunk_id_of(chunk_file))
+            .collect();
+
+        let mut issues = Vec::new();
+        let mut referenced = HashSet::new();
+        let mut all_indices_read = true;
+        for entry in entries {
+            if entry.chunk_count.is_none() {
+                continue;
+            }
+            // A missing file was reported already
+            if !remote_files.contains(&entry.remote_filename.to_path_buf()) {
+                all_indices_read = false;
+                continue;
+            }
+            let index = match read_index(
+                &**remote_service_lock,
+                entry,
+                &self.decoding_data_tunnel,
+                &self.remote_storage,
+            ) {
+                Ok(index) => index,
+                Err(err) => {
+                    all_indices_read = false;
+                    // The deep check reported it already, and an index the secret keys of its
+                    // recipients are needed for is not an issue
+                    if self.mode == VerifyMode::Quick && self.decoding_data_tunnel.can_decode() {
+                        issues.push(VerifyIssue::of(entry, VerifyIssueKind::Unreadable, err));
+                    }
+                    continue;
+                }
+            };
+            for chunk in index.chunks {
+                if !stored.contains(&chunk.id) {
+                    issues.push(VerifyIssue {
+                        backup_id: Some(entry.id),
+                        remote_filename: Some(chunk_path(&chunk.id).to_string_lossy().to_string()),
+                        kind: VerifyIssueKind::MissingFile,
+                        details: "a chunk of the backup is missing on the remote".to_string(),
+                    });
+                }
+                referenced.insert(chunk.id);
+            }
+        }
+
+        if all_indices_read {
+            for chunk_file in chunk_files {
+                let details = match chunk_id_of(&chunk_file) {
+                    Some(id) if referenced.contains(&id) => continue,
+                    Some(_) => "the chunk is not referenced by any backup",
+                    // Other `.part` files are not ours to report
+                    None if chunk_id_of(Path::new(chunk_file.file_stem().unwrap_or_default()))
+                        .is_none() =>
+                    {
+                        continue;
+                    }
+                    None => "the chunk upload was interrupted",
+                };
+                issues.push(VerifyIssue {
+                    backup_id: None,
+                    remote_filename: Some(chunk_file.to_string_lossy().to_string()),
+                    kind: VerifyIssueKind::ExtraFile,
+                    details: details.to_string(),
+                });
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Downloads and decodes the remote file into nothing, checking every recorded checksum.
+    /// Returns the downloaded bytes along with the issue that was found.
+    fn check_content(
+        &self,
+        entry: &BackupEntry,
+        on_start: impl Fn(BytesCounter) -> Result<(), VerifyRunError>,
+    ) -> Result<(u64, Option<VerifyIssue>), VerifyRunError> {
+        let decoding_data_tunnel = match self
+            .decoding_data_tunnel
+            .for_entry(&self.remote_storage, entry)
+        {
+            Ok(decoding_data_tunnel) => decoding_data_tunnel,
+            Err(err) => {
+                let issue = VerifyIssue::of(entry, VerifyIssueKind::Unreadable, err);
+                return Ok((0, Some(issue)));
+            }
+        };
+        let src_reader = {
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            remote_service_lock.get_backup_reader(entry.remote_filename.to_path_buf())
+        };
+        let src_reader = match src_reader {
+            Ok(src_reader) => src_reader,
+            Err(err) => {
+                let issue = VerifyIssue::of(entry, VerifyIssueKind::Unreadable, err);
+                return Ok((0, Some(issue)));
+            }
+        };
+
+        let decodable = decoding_data_tunnel.can_decode();
+        let (transferred, read_bytes, verified) = if decodable && entry.chunk_count.is_some() {
+            // Every chunk is checked against its id while the backup is put together
+            let remote_service_lock = self.remote_service.lock().unwrap();
+            let chunk_stats = ChunkStats::default();
+            let transfer = UnchunkingDataTunnel {
+                store: ChunkStore::new(
+                    &**remote_service_lock,
+                    &decoding_data_tunnel.encryption_level,
+                ),
+                decoding_data_tunnel,
+                stats: chunk_stats.clone(),
+            }
+            .tracked_transfer(src_reader, std::io::sink());
+            on_start(chunk_stats.stored_bytes_counter())?;
+            let transferred = transfer.run();
+            let verified = entry
+                .verify_stored(transfer.reader_bytes_count(), &transfer.reader_hash())
+                .and_then(|_| {
+                    entry.verify_source(transfer.writer_bytes_count(), &transfer.writer_hash())
+                });
+            (transferred, chunk_stats.stored_bytes_counter().value(), verified)
+        } else if decodable {
+            let transfer = decoding_data_tunnel.tracked_transfer(src_reader, std::io::sink());
+            on_start(transfer.reader_bytes_counter())?;
+            let transferred = transfer.run();
+            let verified = entry
+                .verify_stored(transfer.reader_bytes_count(), &transfer.reader_hash())
+                .and_then(|_| {
+                    entry.verify_source(transfer.writer_bytes_count(), &transfer.writer_hash())
+                });
+            (transferred, transfer.reader_bytes_count(), verified)
+        } else {
+            let transfer = PassThroughDataTunnel.tracked_transfer(src_reader, std::io::sink());
+            on_start(transfer.reader_bytes_counter())?;
+            let transferred = transfer.run();
+            let verified =
+                entry.verify_stored(transfer.reader_bytes_count(), &transfer.reader_hash());
+            (transferred, transfer.reader_bytes_count(), verified)
+        };
+
+        // Checksums of a file that could not be read to its end say nothing
+        let issue = match (transferred, verified) {
+            (Err(err), _) => Some(VerifyIssue::of(entry, VerifyIssueKind::Unreadable, err)),
+            (Ok(()), Err(mismatch @ ChecksumMismatch::Size { .. })) => Some(VerifyIssue::of(
+                entry,
+                VerifyIssueKind::SizeMismatch,
+                mismatch,
+            )),
+            (Ok(()), Err(mismatch @ ChecksumMismatch::Hash { .. })) => {
+                Some(VerifyIssue::of(entry, VerifyIssueKind::Corrupted, mismatch))
+            }
+            (Ok(()), Ok(())) => None,
+        };
+        Ok((read_bytes, issue))
+    }
+
+    fn set_progress(&self, verifying_state: VerifyJobProgressState) -> Result<(), VerifyRunError> {
+        self.update_internal_state(|old_state| {
+            let started_at = match old_state {
+                VerifyJobState::Started { started_at } => started_at,
+                VerifyJobState::Verifying { started_at, .. } => started_at,
+                _ => Err(VerifyRunError::ConcurrentStateManipulation {
+                    message: "Cannot be initial state when verifying starts".to_string(),
+                })?,
+            };
+
+            Ok(VerifyJobState::Verifying {
+                started_at: *started_at,
+                verifying_state: verifying_state.clone(),
+            })
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum VerifyRunError {
+    #[error("IO error during verify stage {stage:?}")]
+    IoError {
+        stage: VerifyRunStage,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Inner job state was manipulated. Concurrent runs are not allowed: {message}")]
+    ConcurrentStateManipulation { message: String },
+}
+
+#[derive(Debug)]
+pub enum VerifyRunStage {
+    FetchingMetadata,
+}
@@ -0,0 +1,32 @@
+use crate::services::tracking::BytesCounter;
+use std::path::PathBuf;
+
+pub(crate) enum VerifyJobState {
+    Initial,
+    Started {
+        started_at: chrono::DateTime<chrono::Utc>,
+    },
+    Verifying {
+        started_at: chrono::DateTime<chrono::Utc>,
+        verifying_state: VerifyJobProgressState,
+    },
+}
+
+#[derive(Clone)]
+pub struct VerifyJobProgressState {
+    pub backup_id: u32,
+    pub position: usize,
+    pub total: usize,
+    pub remote_path_relative: PathBuf,
+    /// `None` unless the file is being downloaded.
+    pub read_bytes: Option<BytesCounter>,
+    /// Bytes downloaded for entries that were already verified.
+    pub previous_read_bytes: u64,
+    pub issues: usize,
+}
+
+impl Default for VerifyJobState {
+    fn default() -> Self {
+        Self::Initial
+    }
+}
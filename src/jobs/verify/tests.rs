@@ -0,0 +1,332 @@
+use crate::config::DataDanceConfiguration;
+use crate::jobs::Job;
+use crate::jobs::test_support;
+use crate::jobs::verify::VerifyJob;
+use crate::objects::job_result::{
+    VerifyIssue, VerifyIssueKind, VerifyMode, VerifyResultState, VerifySuccess,
+};
+use crate::objects::{BackupEntry, BackupHistory, BackupSource, CompressionLevel};
+use crate::services::chunk_store::ChunkStore;
+use crate::services::data_dest::DestService;
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::data_tunnel::{
+    ChunkCut, ChunkStats, ChunkingDataTunnel, DataTunnel, EncodingDataTunnel,
+};
+use crate::services::tracking::ContentHash;
+use std::io::{Cursor, Read, Write};
+use std::path::PathBuf;
+
+fn make_config() -> DataDanceConfiguration {
+    test_support::make_config(Some("password"), CompressionLevel::Fast)
+}
+
+fn store(dest: &FakeDestService, entry: &BackupEntry, content: &[u8]) {
+    dest.get_backup_writer(entry.remote_filename.to_path_buf())
+        .unwrap()
+        .write_all(content)
+        .unwrap();
+}
+
+fn upload_recorded(dest: &FakeDestService, entry: &mut BackupEntry, content: &[u8]) {
+    test_support::upload_recorded(dest, &make_config(), entry, content);
+}
+
+fn run_verify(dest: impl DestService + Send + 'static, mode: VerifyMode) -> VerifySuccess {
+    let job = VerifyJob::new(make_config(), Box::new(dest)).with_mode(mode);
+    let result = job.run();
+    assert_eq!(result.mode, mode);
+    match result.state {
+        VerifyResultState::Error(err) => panic!("Job errored: {err}"),
+        VerifyResultState::Success(result) => result,
+    }
+}
+
+fn issue(backup_id: u32, kind: VerifyIssueKind, details: &str) -> VerifyIssue {
+    VerifyIssue {
+        backup_id: Some(backup_id),
+        remote_filename: Some(format!("backup_{backup_id}.bin")),
+        kind,
+        details: details.to_string(),
+    }
+}
+
+#[test]
+fn verify_deep_finds_healthy_backups() {
+    let mut entries = vec![BackupEntry::test(10, None), BackupEntry::test(20, Some(10))];
+    let dest = FakeDestService::empty();
+    upload_recorded(&dest, &mut entries[0], b"full");
+    upload_recorded(&dest, &mut entries[1], b"first increment");
+    let stored_bytes = entries[0].size.unwrap() + entries[1].size.unwrap();
+    dest.set_backup_history(BackupHistory { entries }).unwrap();
+
+    let result = run_verify(dest, VerifyMode::Deep);
+
+    assert_eq!(result.checked, vec![10, 20]);
+    assert!(result.issues.is_empty(), "{:?}", result.issues);
+    assert_eq!(result.bytes_read, stored_bytes);
+}
+
+#[test]
+fn verify_quick_reports_missing_extra_and_broken_backups() {
+    let mut entries = vec![
+        BackupEntry::test(10, None),
+        BackupEntry::test(20, Some(10)),
+        BackupEntry::test(30, Some(25)),
+        BackupEntry::test(40, None),
+    ];
+    entries[0].size = Some(4);
+    entries[3].size = Some(10);
+    let dest = FakeDestService::new(BackupHistory {
+        entries: entries.clone(),
+    });
+    store(&dest, &entries[0], b"full");
+    store(&dest, &entries[2], b"orphaned increment");
+    store(&dest, &entries[3], b"full");
+    dest.get_backup_writer("backup_35.bin".into())
+        .unwrap()
+        .write_all(b"forgotten")
+        .unwrap();
+
+    let result = run_verify(dest, VerifyMode::Quick);
+
+    assert_eq!(result.checked, vec![10, 20, 30, 40]);
+    assert_eq!(result.bytes_read, 0);
+    assert_eq!(
+        result.issues,
+        vec![
+            VerifyIssue {
+                backup_id: None,
+                remote_filename: Some("backup_35.bin".to_string()),
+                kind: VerifyIssueKind::ExtraFile,
+                details: "the file is not referenced by the backup history".to_string(),
+            },
+            issue(
+                20,
+                VerifyIssueKind::MissingFile,
+                "the file is missing on the remote"
+            ),
+            issue(
+                30,
+                VerifyIssueKind::BrokenChain,
+                "backup 30 references parent 25, which is missing from the backup history"
+            ),
+            issue(
+                40,
+                VerifyIssueKind::SizeMismatch,
+                "backup 40 is corrupted, its remote file has 4 bytes instead of 10"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn verify_deep_reports_corrupted_and_unreadable_backups() {
+    let mut entries = vec![BackupEntry::test(10, None), BackupEntry::test(20, None)];
+    let dest = FakeDestService::empty();
+    upload_recorded(&dest, &mut entries[0], b"full");
+    upload_recorded(&dest, &mut entries[1], b"another full");
+    let debug_data = dest.live_debug_data();
+    let mut damaged = debug_data.file("backup_10.bin").unwrap();
+    let last = damaged.len() - 1;
+    damaged[last] ^= 1;
+    dest.remove_backup_file("backup_10.bin".into()).unwrap();
+    store(&dest, &entries[0], &damaged);
+    // Decodes fine, but is not the file that was uploaded
+    entries[1].stored_hash = Some(ContentHash::of_reader(Cursor::new(b"other")).unwrap());
+    dest.set_backup_history(BackupHistory { entries }).unwrap();
+
+    let result = run_verify(dest, VerifyMode::Deep);
+
+    assert_eq!(result.checked, vec![10, 20]);
+    assert_eq!(result.issues.len(), 2);
+    assert_eq!(result.issues[0].backup_id, Some(10));
+    assert_eq!(result.issues[0].kind, VerifyIssueKind::Unreadable);
+    assert_eq!(
+        result.issues[1],
+        issue(
+            20,
+            VerifyIssueKind::Corrupted,
+            "backup 20 is corrupted, its remote file does not match the recorded hash"
+        )
+    );
+}
+
+#[test]
+fn verify_quick_compares_chunk_store_with_indices() {
+    let mut entries = vec![BackupEntry::test(10, None)];
+    let dest = FakeDestService::empty();
+    let config = make_config();
+    let encryption_level = config.remote_storage.encryption_level();
+    let mut state = 0x2545f4914f6cdd1du64;
+    let content: Vec<u8> = (0..64 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let tunnel = ChunkingDataTunnel {
+        encoding_data_tunnel: EncodingDataTunnel {
+            compression: config.remote_storage.compression,
+            encryption_level: encryption_level.clone(),
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
+        },
+        store: ChunkStore::new(&dest, &encryption_level),
+        cut: ChunkCut::ContentDefined { average_size: 4096 },
+        stats: ChunkStats::default(),
+        on_stored: None,
+        resumed: &[],
+    };
+    let writer = dest.get_backup_writer("backup_10.bin".into()).unwrap();
+    tunnel.transfer(Cursor::new(content), writer).unwrap();
+    entries[0].compression = Some(config.remote_storage.compression);
+    entries[0].chunk_count = Some(dest.list_chunk_files().unwrap().len() as u64);
+    dest.set_backup_history(BackupHistory { entries }).unwrap();
+
+    let lost = dest.list_chunk_files().unwrap().remove(0);
+    dest.remove_backup_file(lost.clone()).unwrap();
+    for stray in ["stray.chunk", "interrupted.chunk.part"] {
+        dest.get_backup_writer(stray.into())
+            .unwrap()
+            .write_all(b"stray")
+            .unwrap();
+    }
+
+    let result = run_verify(dest, VerifyMode::Quick);
+
+    assert_eq!(result.checked, vec![10]);
+    assert_eq!(result.issues.len(), 3, "{:?}", result.issues);
+    assert!(result.issues.contains(&VerifyIssue {
+        backup_id: Some(10),
+        remote_filename: Some(lost.to_string_lossy().to_string()),
+        kind: VerifyIssueKind::MissingFile,
+        details: "a chunk of the backup is missing on the remote".to_string(),
+    }));
+    assert!(result.issues.contains(&VerifyIssue {
+        backup_id: None,
+        remote_filename: Some("stray.chunk".to_string()),
+        kind: VerifyIssueKind::ExtraFile,
+        details: "the chunk is not referenced by any backup".to_string(),
+    }));
+    assert!(result.issues.contains(&VerifyIssue {
+        backup_id: None,
+        remote_filename: Some("interrupted.chunk.part".to_string()),
+        kind: VerifyIssueKind::ExtraFile,
+        details: "the chunk upload was interrupted".to_string(),
+    }));
+}
+
+#[test]
+fn verify_quick_finds_archives_in_folders() {
+    let mut entries = vec![BackupEntry::test(10, None), BackupEntry::test(20, None)];
+    entries[1].remote_filename = "archives/files_20.bin".into();
+    entries[1].source = Some(BackupSource::Tar);
+    let mut missing = BackupEntry::test(30, None);
+    missing.remote_filename = "archives/files_30.bin".into();
+    missing.source = Some(BackupSource::Tar);
+    let dest = FakeDestService::empty();
+    store(&dest, &entries[0], b"full");
+    store(&dest, &entries[1], b"archive");
+    entries.push(missing);
+    dest.set_backup_history(BackupHistory { entries }).unwrap();
+
+    let result = run_verify(dest, VerifyMode::Quick);
+
+    assert_eq!(result.checked, vec![10, 20, 30]);
+    assert_eq!(
+        result.issues,
+        vec![VerifyIssue {
+            remote_filename: Some("archives/files_30.bin".to_string()),
+            ..issue(30, VerifyIssueKind::MissingFile, "the file is missing on the remote")
+        }]
+    );
+}
+
+/// A `FakeDestService` that lists `unreachable`, but fails to read it.
+struct UnreachableFileDestService {
+    inner: FakeDestService,
+    unreachable: PathBuf,
+}
+
+impl UnreachableFileDestService {
+    fn check_reachable(&self, relative_file_path: &PathBuf) -> std::io::Result<()> {
+        if *relative_file_path == self.unreachable {
+            return Err(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        }
+        Ok(())
+    }
+}
+
+impl DestService for UnreachableFileDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        self.inner.backup_history()
+    }
+
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        self.inner.get_backup_writer(relative_file_path)
+    }
+
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        self.check_reachable(&relative_file_path)?;
+        self.inner.get_backup_reader(relative_file_path)
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        self.check_reachable(&relative_file_path)?;
+        self.inner.backup_file_size(relative_file_path)
+    }
+
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        self.inner.set_backup_history(history)
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.inner.list_backup_files()
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.inner.list_chunk_files()
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        self.inner.clear_orphaned_backups(history)
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.inner.replace_backup_file(from, to)
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        self.inner.remove_backup_file(relative_file_path)
+    }
+}
+
+#[test]
+fn verify_reports_unreachable_files_and_continues() {
+    for mode in [VerifyMode::Quick, VerifyMode::Deep] {
+        let mut entries = vec![BackupEntry::test(10, None), BackupEntry::test(20, None)];
+        let dest = FakeDestService::empty();
+        upload_recorded(&dest, &mut entries[0], b"full");
+        upload_recorded(&dest, &mut entries[1], b"another full");
+        dest.set_backup_history(BackupHistory { entries }).unwrap();
+        let dest = UnreachableFileDestService {
+            inner: dest,
+            unreachable: "backup_10.bin".into(),
+        };
+
+        let result = run_verify(dest, mode);
+
+        assert_eq!(result.checked, vec![10, 20]);
+        assert_eq!(
+            result.issues,
+            vec![issue(10, VerifyIssueKind::Unreadable, "connection reset")],
+            "{mode:?}"
+        );
+    }
+}
@@ -8,6 +8,7 @@ pub mod config;
 pub mod context;
 pub mod jobs;
 pub mod objects;
+pub mod scheduler;
 pub mod bin;
 pub mod services;
 pub mod web;
@@ -9,14 +9,25 @@ use std::process::exit;
 #[tokio::main]
 async fn main() {
     use data_dance::jobs::JobExecutor;
+    use data_dance::scheduler::{Scheduler, run_restore_drill_scheduler, run_scheduler};
     use data_dance::web::routes::run_server;
+    use std::sync::Arc;
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench") {
+        exit(data_dance::bin::bench::bench_command(&args[1..]));
+    }
 
     let config = data_dance::config::load::read_config_from_env().unwrap();
 
-    let context = data_dance::context::DataDanceContext {
+    let context = Arc::new(data_dance::context::DataDanceContext {
         executor: JobExecutor::new(config.clone()),
+        scheduler: Scheduler::new(&config),
+        restore_drill_scheduler: Scheduler::for_restore_drills(&config),
         config,
-    };
+    });
+    tokio::spawn(run_scheduler(Arc::clone(&context)));
+    tokio::spawn(run_restore_drill_scheduler(Arc::clone(&context)));
 
     let exit_code = run_server(context).await;
     exit(exit_code);
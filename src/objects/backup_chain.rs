@@ -0,0 +1,318 @@
+use crate::config::ConsolidationPolicy;
+use crate::objects::{BackupEntry, BackupHistory, BackupType};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Backup entries needed to restore a backup, ordered from the full backup to the requested one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BackupChain {
+    pub entries: Vec<BackupEntry>,
+}
+
+impl BackupChain {
+    pub fn target(&self) -> &BackupEntry {
+        self.entries
+            .last()
+            .expect("a resolved backup chain is never empty")
+    }
+
+    /// The remote files to fetch, in the order they have to be applied.
+    pub fn remote_files(&self) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .map(|entry| entry.remote_filename.to_path_buf())
+            .collect()
+    }
+
+    /// Checks that every link of the chain is present in `remote_files`.
+    pub fn verify_remote_files(&self, remote_files: &[PathBuf]) -> Result<(), BackupChainError> {
+        for entry in &self.entries {
+            if !remote_files
+                .iter()
+                .any(|file| file.as_path() == &*entry.remote_filename)
+            {
+                return Err(BackupChainError::MissingRemoteFile {
+                    backup_id: entry.id,
+                    remote_filename: entry.remote_filename.to_string_lossy().to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every chunk the chunked links reference is present in `stored_ids`.
+    /// `indexed_ids` holds the chunk ids of their indexes by backup id.
+    pub fn verify_chunks(
+        &self,
+        indexed_ids: &HashMap<u32, Vec<String>>,
+        stored_ids: &HashSet<String>,
+    ) -> Result<(), BackupChainError> {
+        for entry in &self.entries {
+            let Some(chunk_ids) = indexed_ids.get(&entry.id) else {
+                continue;
+            };
+            if let Some(chunk_id) = chunk_ids.iter().find(|id| !stored_ids.contains(*id)) {
+                return Err(BackupChainError::MissingChunk {
+                    backup_id: entry.id,
+                    chunk_id: chunk_id.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BackupChain {
+    /// Whether another incremental on top of this chain would exceed `policy`.
+    pub fn exceeds(&self, policy: &ConsolidationPolicy, now: chrono::DateTime<chrono::Utc>) -> bool {
+        let full_backup = &self.entries[0];
+        let incrementals = &self.entries[1..];
+
+        let too_many_incrementals = policy
+            .max_incrementals
+            .is_some_and(|max_incrementals| incrementals.len() >= max_incrementals as usize);
+
+        let full_backup_age_millis = now.timestamp_millis() - full_backup.timestamp as i64;
+        let too_old = policy.max_age_days.is_some_and(|max_age_days| {
+            full_backup_age_millis >= (max_age_days * 24 * 60 * 60 * 1000) as i64
+        });
+
+        // Backups made before sizes were recorded never count towards the ratio
+        let incremental_size: Option<u64> = incrementals.iter().map(|entry| entry.size).sum();
+        let too_large = match (
+            policy.max_incremental_size_ratio,
+            full_backup.size,
+            incremental_size,
+        ) {
+            (Some(max_ratio), Some(full_size), Some(incremental_size)) if full_size > 0 => {
+                incremental_size as f64 / full_size as f64 > max_ratio
+            }
+            _ => false,
+        };
+
+        too_many_incrementals || too_old || too_large
+    }
+}
+
+impl BackupHistory {
+    /// Whether the next backup should start a new chain instead of building on `parent_id`.
+    /// A parent whose chain cannot be resolved is never built upon.
+    pub fn needs_new_chain(
+        &self,
+        parent_id: u32,
+        policy: &ConsolidationPolicy,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> bool {
+        match self.resolve_chain(parent_id) {
+            Ok(chain) => chain.exceeds(policy, now),
+            Err(_) => true,
+        }
+    }
+
+    pub fn find(&self, backup_id: u32) -> Option<&BackupEntry> {
+        self.entries.iter().find(|entry| entry.id == backup_id)
+    }
+
+    pub fn latest(&self) -> Option<&BackupEntry> {
+        self.entries.iter().max_by_key(|entry| entry.timestamp)
+    }
+
+    /// Walks the parent links of `backup_id` back to its full backup.
+    pub fn resolve_chain(&self, backup_id: u32) -> Result<BackupChain, BackupChainError> {
+        let target = self
+            .find(backup_id)
+            .ok_or(BackupChainError::BackupNotFound { backup_id })?;
+
+        let mut entries = vec![target.clone()];
+        loop {
+            let child = entries.last().unwrap();
+            let Some(parent_id) = child.parent else {
+                if child.backup_type != BackupType::Full {
+                    return Err(BackupChainError::RootNotFull { backup_id: child.id });
+                }
+                break;
+            };
+            if entries.iter().any(|entry| entry.id == parent_id) {
+                return Err(BackupChainError::Cycle { backup_id });
+            }
+            let parent = self
+                .find(parent_id)
+                .ok_or(BackupChainError::MissingParent {
+                    backup_id: child.id,
+                    parent_id,
+                })?;
+            entries.push(parent.clone());
+        }
+        entries.reverse();
+
+        Ok(BackupChain { entries })
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+pub enum BackupChainError {
+    #[error("backup {backup_id} does not exist in the backup history")]
+    BackupNotFound { backup_id: u32 },
+    #[error("backup {backup_id} references parent {parent_id}, which is missing from the backup history")]
+    MissingParent { backup_id: u32, parent_id: u32 },
+    #[error("backup {backup_id} has no parent but is not a full backup")]
+    RootNotFull { backup_id: u32 },
+    #[error("the parent links of backup {backup_id} form a cycle")]
+    Cycle { backup_id: u32 },
+    #[error("the file '{remote_filename}' of backup {backup_id} is missing on the remote")]
+    MissingRemoteFile {
+        backup_id: u32,
+        remote_filename: String,
+    },
+    #[error("the chunk '{chunk_id}' of backup {backup_id} is missing on the remote")]
+    MissingChunk { backup_id: u32, chunk_id: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u32, parent: Option<u32>) -> BackupEntry {
+        BackupEntry {
+            timestamp: id as u64,
+            remote_filename: format!("{id}.{}", if parent.is_some() { "dbin" } else { "bin" })
+                .into(),
+            local_snapshot: format!("{id}/").into(),
+            ..BackupEntry::test(id, parent)
+        }
+    }
+
+    #[test]
+    fn resolves_chain_oldest_first() {
+        let history = BackupHistory {
+            entries: vec![entry(1, None), entry(2, Some(1)), entry(3, Some(2)), entry(4, None)],
+        };
+
+        let chain = history.resolve_chain(3).unwrap();
+        assert_eq!(
+            chain.remote_files(),
+            vec![
+                PathBuf::from("1.bin"),
+                PathBuf::from("2.dbin"),
+                PathBuf::from("3.dbin")
+            ]
+        );
+        assert_eq!(chain.target().id, 3);
+
+        let chain = history.resolve_chain(4).unwrap();
+        assert_eq!(chain.remote_files(), vec![PathBuf::from("4.bin")]);
+    }
+
+    #[test]
+    fn reports_missing_parent_link() {
+        let history = BackupHistory {
+            entries: vec![entry(1, None), entry(3, Some(2)), entry(4, Some(3))],
+        };
+
+        assert_eq!(
+            history.resolve_chain(4),
+            Err(BackupChainError::MissingParent {
+                backup_id: 3,
+                parent_id: 2
+            })
+        );
+        assert_eq!(
+            history.resolve_chain(5),
+            Err(BackupChainError::BackupNotFound { backup_id: 5 })
+        );
+    }
+
+    #[test]
+    fn reports_invalid_roots_and_cycles() {
+        let mut orphan = entry(1, None);
+        orphan.backup_type = BackupType::Incremental;
+        let history = BackupHistory {
+            entries: vec![orphan, entry(2, Some(1)), entry(3, Some(4)), entry(4, Some(3))],
+        };
+
+        assert_eq!(
+            history.resolve_chain(2),
+            Err(BackupChainError::RootNotFull { backup_id: 1 })
+        );
+        assert_eq!(
+            history.resolve_chain(3),
+            Err(BackupChainError::Cycle { backup_id: 3 })
+        );
+    }
+
+    #[test]
+    fn reports_missing_remote_file() {
+        let history = BackupHistory {
+            entries: vec![entry(1, None), entry(2, Some(1)), entry(3, Some(2))],
+        };
+        let chain = history.resolve_chain(3).unwrap();
+
+        assert_eq!(
+            chain.verify_remote_files(&["1.bin".into(), "2.dbin".into(), "3.dbin".into()]),
+            Ok(())
+        );
+        assert_eq!(
+            chain.verify_remote_files(&["1.bin".into(), "3.dbin".into()]),
+            Err(BackupChainError::MissingRemoteFile {
+                backup_id: 2,
+                remote_filename: "2.dbin".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn reports_missing_chunk() {
+        let history = BackupHistory {
+            entries: vec![entry(1, None), entry(2, Some(1))],
+        };
+        let chain = history.resolve_chain(2).unwrap();
+        let indexed_ids = HashMap::from([(2, vec!["a".to_string(), "b".to_string()])]);
+
+        assert_eq!(
+            chain.verify_chunks(&indexed_ids, &HashSet::from(["a".to_string(), "b".to_string()])),
+            Ok(())
+        );
+        assert_eq!(
+            chain.verify_chunks(&indexed_ids, &HashSet::from(["a".to_string()])),
+            Err(BackupChainError::MissingChunk {
+                backup_id: 2,
+                chunk_id: "b".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn chain_exceeds_consolidation_policy() {
+        let day = 24 * 60 * 60 * 1000;
+        let mut full = entry(1, None);
+        full.size = Some(1000);
+        let mut first = entry(2, Some(1));
+        first.size = Some(300);
+        let mut second = entry(3, Some(2));
+        second.size = Some(300);
+        let history = BackupHistory {
+            entries: vec![full, first, second],
+        };
+        let chain = history.resolve_chain(3).unwrap();
+        let now = chrono::DateTime::from_timestamp_millis(10 * day).unwrap();
+
+        assert!(!chain.exceeds(&ConsolidationPolicy::default(), now));
+        let count_policy = ConsolidationPolicy {
+            max_incrementals: Some(2),
+            ..ConsolidationPolicy::default()
+        };
+        assert!(chain.exceeds(&count_policy, now));
+        let age_policy = ConsolidationPolicy {
+            max_age_days: Some(11),
+            ..ConsolidationPolicy::default()
+        };
+        assert!(!chain.exceeds(&age_policy, now));
+        let size_policy = ConsolidationPolicy {
+            max_incremental_size_ratio: Some(0.5),
+            ..ConsolidationPolicy::default()
+        };
+        assert!(chain.exceeds(&size_policy, now));
+        assert!(history.needs_new_chain(4, &ConsolidationPolicy::default(), now));
+    }
+}
@@ -0,0 +1,124 @@
+use crate::objects::BackupEntry;
+use std::fmt::{Display, Formatter};
+use thiserror::Error;
+
+impl BackupEntry {
+    /// Checks the remote file, as it was read back, against the size and hash recorded when it
+    /// was written. Values that were not recorded are not checked.
+    pub fn verify_stored(&self, size: u64, hash: &str) -> Result<(), ChecksumMismatch> {
+        let recorded = (self.size, self.stored_hash.as_deref());
+        self.verify(ChecksummedData::StoredFile, recorded, size, hash)
+    }
+
+    /// Checks the decoded content of the remote file against the backup that was uploaded.
+    pub fn verify_source(&self, size: u64, hash: &str) -> Result<(), ChecksumMismatch> {
+        let recorded = (self.source_size, self.source_hash.as_deref());
+        self.verify(ChecksummedData::Content, recorded, size, hash)
+    }
+
+    fn verify(
+        &self,
+        data: ChecksummedData,
+        (recorded_size, recorded_hash): (Option<u64>, Option<&str>),
+        size: u64,
+        hash: &str,
+    ) -> Result<(), ChecksumMismatch> {
+        if let Some(recorded_size) = recorded_size
+            && recorded_size != size
+        {
+            return Err(ChecksumMismatch::Size {
+                backup_id: self.id,
+                data,
+                size,
+                recorded_size,
+            });
+        }
+        if let Some(recorded_hash) = recorded_hash
+            && recorded_hash != hash
+        {
+            return Err(ChecksumMismatch::Hash {
+                backup_id: self.id,
+                data,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChecksummedData {
+    StoredFile,
+    Content,
+}
+
+impl Display for ChecksummedData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChecksummedData::StoredFile => write!(f, "remote file"),
+            ChecksummedData::Content => write!(f, "decoded content"),
+        }
+    }
+}
+
+/// A backup read back does not match what was recorded when it was uploaded.
+#[derive(Clone, Debug, Eq, PartialEq, Error)]
+pub enum ChecksumMismatch {
+    #[error(
+        "backup {backup_id} is corrupted, its {data} has {size} bytes instead of {recorded_size}"
+    )]
+    Size {
+        backup_id: u32,
+        data: ChecksummedData,
+        size: u64,
+        recorded_size: u64,
+    },
+    #[error("backup {backup_id} is corrupted, its {data} does not match the recorded hash")]
+    Hash {
+        backup_id: u32,
+        data: ChecksummedData,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> BackupEntry {
+        BackupEntry {
+            timestamp: 10,
+            remote_filename: "10.bin".into(),
+            local_snapshot: "10/".into(),
+            size: Some(4),
+            source_size: Some(8),
+            source_hash: Some("source".to_string()),
+            stored_hash: Some("stored".to_string()),
+            ..BackupEntry::test(10, None)
+        }
+    }
+
+    #[test]
+    fn matching_checksums_pass() {
+        assert_eq!(entry().verify_stored(4, "stored"), Ok(()));
+        assert_eq!(entry().verify_source(8, "source"), Ok(()));
+    }
+
+    #[test]
+    fn mismatches_name_the_backup() {
+        assert_eq!(
+            entry().verify_stored(4, "source").unwrap_err().to_string(),
+            "backup 10 is corrupted, its remote file does not match the recorded hash"
+        );
+        assert_eq!(
+            entry().verify_source(7, "source").unwrap_err().to_string(),
+            "backup 10 is corrupted, its decoded content has 7 bytes instead of 8"
+        );
+    }
+
+    #[test]
+    fn unrecorded_checksums_are_not_checked() {
+        let entry = BackupEntry::test(10, None);
+
+        assert_eq!(entry.verify_stored(1, "any"), Ok(()));
+        assert_eq!(entry.verify_source(1, "any"), Ok(()));
+    }
+}
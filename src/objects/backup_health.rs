@@ -0,0 +1,100 @@
+use crate::objects::JobHistory;
+use crate::objects::job_result::{JobResult, VerifyIssue, VerifyMode, VerifyResultState};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+/// Health of the remote backups as seen by the latest verify job.
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct BackupHealth {
+    pub verified_at: chrono::DateTime<chrono::Utc>,
+    pub mode: VerifyMode,
+    /// Whether the verification finished without finding any issue.
+    pub healthy: bool,
+    pub backups_checked: u32,
+    pub issues: Vec<VerifyIssue>,
+    /// Why the verification could not finish.
+    pub error: Option<String>,
+}
+
+impl JobHistory {
+    /// `None` if no verify job has finished yet.
+    pub fn backup_health(&self) -> Option<BackupHealth> {
+        let result = self.entries.iter().rev().find_map(|entry| match entry {
+            JobResult::Verify(result) => Some(result),
+            _ => None,
+        })?;
+        let health = match &result.state {
+            VerifyResultState::Success(success) => BackupHealth {
+                verified_at: result.finished_at,
+                mode: result.mode,
+                healthy: success.issues.is_empty(),
+                backups_checked: success.checked.len() as u32,
+                issues: success.issues.clone(),
+                error: None,
+            },
+            VerifyResultState::Error(err) => BackupHealth {
+                verified_at: result.finished_at,
+                mode: result.mode,
+                healthy: false,
+                backups_checked: 0,
+                issues: Vec::new(),
+                error: Some(err.clone()),
+            },
+        };
+        Some(health)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::job_result::{VerifyIssueKind, VerifyResult, VerifySuccess};
+    use chrono::TimeZone;
+
+    fn verify_result(hour: u32, issues: Vec<VerifyIssue>) -> JobResult {
+        let at = chrono::Utc
+            .with_ymd_and_hms(2024, 1, 1, hour, 0, 0)
+            .unwrap();
+        JobResult::Verify(VerifyResult {
+            started_at: at,
+            finished_at: at,
+            mode: VerifyMode::Quick,
+            state: VerifyResultState::Success(VerifySuccess {
+                checked: vec![1, 2],
+                issues,
+                bytes_read: 0,
+            }),
+        })
+    }
+
+    #[test]
+    fn backup_health_uses_the_latest_verification() {
+        let issue = VerifyIssue {
+            backup_id: None,
+            remote_filename: Some("3.bin".to_string()),
+            kind: VerifyIssueKind::ExtraFile,
+            details: "the file is not referenced by the backup history".to_string(),
+        };
+        let history = JobHistory {
+            entries: vec![
+                verify_result(1, Vec::new()),
+                verify_result(2, vec![issue.clone()]),
+            ],
+        };
+
+        let health = history.backup_health().unwrap();
+        assert_eq!(health.verified_at.to_rfc3339(), "2024-01-01T02:00:00+00:00");
+        assert!(!health.healthy);
+        assert_eq!(health.backups_checked, 2);
+        assert_eq!(health.issues, vec![issue]);
+    }
+
+    #[test]
+    fn backup_health_without_verification() {
+        let history = JobHistory {
+            entries: Vec::new(),
+        };
+
+        assert!(history.backup_health().is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::objects::{BackupSource, Compression, SnapshotManifest};
 use poem_openapi::{Enum, NewType, Object, types::Example};
 use serde::{Deserialize, Serialize};
 use std::{ops::Deref, path::PathBuf};
@@ -13,8 +14,72 @@ pub struct BackupEntry {
     pub parent: Option<u32>,
     pub timestamp: u64,
     pub remote_filename: Path,
+    /// The snapshot the backup was sent from, relative to the snapshots folder. For archives
+    /// the absolute filesystem root they were made of.
     pub local_snapshot: Path,
     pub backup_type: BackupType,
+    /// Bytes stored on the remote, unknown for backups made before sizes were recorded.
+    pub size: Option<u64>,
+    /// Id of the password the backup is encrypted with. `None` for backups that are not
+    /// encrypted with a password and for backups made before key ids were recorded.
+    pub key_id: Option<String>,
+    /// Unknown for backups made before it was recorded, those are compressed with zstd.
+    pub compression: Option<Compression>,
+    /// Bytes of the backup before it was compressed and encrypted. Like both hashes unknown
+    /// for backups made before checksums were recorded.
+    pub source_size: Option<u64>,
+    /// BLAKE2b-512 of the backup before it was compressed and encrypted, in lowercase hex.
+    pub source_hash: Option<String>,
+    /// BLAKE2b-512 of the remote file, whose size is `size`.
+    pub stored_hash: Option<String>,
+    /// The local snapshot as it was backed up. Only recorded for snapshot backups, and only
+    /// since restore drills compare against it.
+    pub manifest: Option<SnapshotManifest>,
+    /// Chunks of a backup stored in the chunk repository, whose remote file is then the index
+    /// of those chunks. `None` for backups stored as a single file.
+    pub chunk_count: Option<u64>,
+    /// What the backup holds, unknown for backups made before it was recorded. Those are
+    /// btrfs streams unless they were made by a full data backup.
+    pub source: Option<BackupSource>,
+    /// Set while the re-encrypted file of the backup, uploaded next to it with a `.rekey`
+    /// extension, replaces the old one. The other fields still describe the old file.
+    #[oai(default)]
+    pub rekey_pending: bool,
+}
+
+impl BackupEntry {
+    /// Archives of full data backups are no snapshots, they are never a parent and are
+    /// retained apart from the snapshot backups.
+    pub fn is_archive(&self) -> bool {
+        self.source == Some(BackupSource::Tar)
+    }
+
+    /// Backup `id` on top of `parent`, stored as `backup_<id>.bin` from `snapshot_<id>/`
+    /// without any checksums. Tests set whatever else they need on top of it.
+    #[cfg(test)]
+    pub fn test(id: u32, parent: Option<u32>) -> Self {
+        BackupEntry {
+            id,
+            parent,
+            timestamp: id as u64 * 100,
+            remote_filename: format!("backup_{id}.bin").into(),
+            local_snapshot: format!("snapshot_{id}/").into(),
+            backup_type: match parent {
+                None => BackupType::Full,
+                Some(_) => BackupType::Incremental,
+            },
+            size: None,
+            key_id: None,
+            compression: None,
+            source_size: None,
+            source_hash: None,
+            stored_hash: None,
+            manifest: None,
+            chunk_count: None,
+            source: None,
+            rekey_pending: false,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, NewType)]
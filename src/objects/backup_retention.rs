@@ -0,0 +1,191 @@
+use crate::config::RetentionPolicy;
+use crate::objects::{BackupEntry, BackupHistory};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+
+impl BackupHistory {
+    /// Ids of the snapshot backups kept by `policy`, including every parent they depend on.
+    pub fn retained_ids(&self, policy: &RetentionPolicy) -> HashSet<u32> {
+        self.retained_ids_where(policy, |entry| !entry.is_archive())
+    }
+
+    /// Drops every snapshot backup not retained by `policy` and returns the dropped entries.
+    /// Archives are kept.
+    pub fn prune(&mut self, policy: &RetentionPolicy) -> Vec<BackupEntry> {
+        self.prune_where(policy, |entry| !entry.is_archive())
+    }
+
+    /// Drops every archive not retained by `policy` and returns the dropped entries. Snapshot
+    /// backups are kept.
+    pub fn prune_archives(&mut self, policy: &RetentionPolicy) -> Vec<BackupEntry> {
+        self.prune_where(policy, BackupEntry::is_archive)
+    }
+
+    fn retained_ids_where(
+        &self,
+        policy: &RetentionPolicy,
+        applies: impl Fn(&BackupEntry) -> bool,
+    ) -> HashSet<u32> {
+        let mut newest_first: Vec<&BackupEntry> =
+            self.entries.iter().filter(|entry| applies(entry)).collect();
+        newest_first.sort_by_key(|entry| Reverse(entry.timestamp));
+
+        let mut retained: HashSet<u32> = newest_first
+            .iter()
+            .take(policy.keep_last.max(1) as usize)
+            .map(|entry| entry.id)
+            .collect();
+
+        let periods = [
+            (policy.hourly, "%Y-%m-%d %H"),
+            (policy.daily, "%Y-%m-%d"),
+            (policy.weekly, "%G-%V"),
+            (policy.monthly, "%Y-%m"),
+            (policy.yearly, "%Y"),
+        ];
+        for (count, period_format) in periods {
+            let mut kept_periods = 0;
+            let mut last_period = None;
+            for entry in &newest_first {
+                if kept_periods >= count {
+                    break;
+                }
+                let period = chrono::DateTime::from_timestamp_millis(entry.timestamp as i64)
+                    .map(|timestamp| timestamp.format(period_format).to_string());
+                if last_period != Some(period.clone()) {
+                    retained.insert(entry.id);
+                    kept_periods += 1;
+                    last_period = Some(period);
+                }
+            }
+        }
+
+        let mut pending: Vec<u32> = retained.iter().copied().collect();
+        while let Some(backup_id) = pending.pop() {
+            let parent = self.find(backup_id).and_then(|entry| entry.parent);
+            if let Some(parent_id) = parent
+                && retained.insert(parent_id)
+            {
+                pending.push(parent_id);
+            }
+        }
+
+        retained
+    }
+
+    fn prune_where(
+        &mut self,
+        policy: &RetentionPolicy,
+        applies: impl Fn(&BackupEntry) -> bool,
+    ) -> Vec<BackupEntry> {
+        let retained = self.retained_ids_where(policy, &applies);
+        let (kept, expired) = self
+            .entries
+            .drain(..)
+            .partition(|entry| !applies(entry) || retained.contains(&entry.id));
+        self.entries = kept;
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 60 * 60 * 1000;
+    const DAY: u64 = 24 * HOUR;
+
+    fn entry(id: u32, parent: Option<u32>, timestamp: u64) -> BackupEntry {
+        BackupEntry {
+            timestamp,
+            remote_filename: format!("{id}.bin").into(),
+            local_snapshot: format!("{id}/").into(),
+            ..BackupEntry::test(id, parent)
+        }
+    }
+
+    fn ids(entries: &[BackupEntry]) -> Vec<u32> {
+        entries.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn keeps_newest_backup_per_day() {
+        let mut history = BackupHistory {
+            entries: vec![
+                entry(1, None, DAY),
+                entry(2, None, DAY + HOUR),
+                entry(3, None, 2 * DAY),
+                entry(4, None, 2 * DAY + HOUR),
+                entry(5, None, 3 * DAY),
+            ],
+        };
+        let policy = RetentionPolicy {
+            daily: 2,
+            ..RetentionPolicy::default()
+        };
+
+        let expired = history.prune(&policy);
+
+        assert_eq!(ids(&expired), vec![1, 2, 3]);
+        assert_eq!(ids(&history.entries), vec![4, 5]);
+    }
+
+    #[test]
+    fn latest_backup_is_never_pruned() {
+        let mut history = BackupHistory {
+            entries: vec![entry(1, None, DAY), entry(2, None, 2 * DAY)],
+        };
+
+        let expired = history.prune(&RetentionPolicy::default());
+
+        assert_eq!(ids(&expired), vec![1]);
+        assert_eq!(ids(&history.entries), vec![2]);
+    }
+
+    #[test]
+    fn parents_of_retained_incrementals_are_kept() {
+        let mut history = BackupHistory {
+            entries: vec![
+                entry(1, None, 10 * DAY),
+                entry(2, Some(1), 40 * DAY),
+                entry(6, None, 45 * DAY),
+                entry(3, Some(2), 70 * DAY),
+                entry(4, None, 100 * DAY),
+                entry(5, Some(4), 101 * DAY),
+            ],
+        };
+        let policy = RetentionPolicy {
+            monthly: 2,
+            ..RetentionPolicy::default()
+        };
+
+        let expired = history.prune(&policy);
+
+        assert_eq!(ids(&expired), vec![6]);
+        assert_eq!(ids(&history.entries), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn archives_are_retained_apart_from_snapshots() {
+        let archive = |id, timestamp| BackupEntry {
+            source: Some(crate::objects::BackupSource::Tar),
+            ..entry(id, None, timestamp)
+        };
+        let mut history = BackupHistory {
+            entries: vec![
+                entry(1, None, DAY),
+                archive(2, 2 * DAY),
+                entry(3, None, 3 * DAY),
+                archive(4, 4 * DAY),
+            ],
+        };
+
+        let expired = history.prune(&RetentionPolicy::default());
+        assert_eq!(ids(&expired), vec![1]);
+        assert_eq!(ids(&history.entries), vec![2, 3, 4]);
+
+        let expired = history.prune_archives(&RetentionPolicy::default());
+        assert_eq!(ids(&expired), vec![2]);
+        assert_eq!(ids(&history.entries), vec![3, 4]);
+    }
+}
@@ -1,6 +1,8 @@
-use poem_openapi::Enum;
+use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
 
+/// Presets from before the algorithm and level could be chosen. They all use zstd, `None` its
+/// fastest level -5.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Enum)]
 pub enum CompressionLevel {
     None,
@@ -8,3 +10,105 @@ pub enum CompressionLevel {
     Balanced,
     Best,
 }
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum CompressionAlgorithm {
+    Zstd,
+    Lz4,
+    Gzip,
+    Xz,
+    /// Stores the data as is.
+    None,
+}
+
+/// Algorithm and its exact level. Presets and plain algorithm names are accepted as well.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[serde(from = "CompressionSetting")]
+pub struct Compression {
+    pub algorithm: CompressionAlgorithm,
+    pub level: i32,
+    /// Zstd only: threads compressing in the background, 0 compresses on the writing thread.
+    #[oai(default)]
+    pub workers: u32,
+    /// Zstd only: log2 of the match window, defaults to what the level picks.
+    pub window_log: Option<u32>,
+    /// Zstd only: finds repeats far back in the stream, best with a large `window_log`.
+    #[oai(default)]
+    pub long_distance_matching: bool,
+    /// Zstd only: steps down to a faster level or stored blocks while the data does not shrink.
+    pub adaptive: Option<AdaptiveCompression>,
+}
+
+/// The stream is compressed window by window, every window is its own zstd frame. Windows
+/// that barely shrink at `level` move to `fast_level`, windows that do not shrink at all to
+/// stored blocks, which are probed again at `fast_level` now and then.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[serde(default)]
+pub struct AdaptiveCompression {
+    pub fast_level: i32,
+    /// Bytes of input judged together.
+    pub window_bytes: u32,
+}
+
+impl Default for AdaptiveCompression {
+    fn default() -> Self {
+        AdaptiveCompression {
+            fast_level: 1,
+            window_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Input bytes of an adaptive upload by the way they were stored.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct CompressionMix {
+    pub high_level_bytes: u64,
+    pub fast_level_bytes: u64,
+    pub stored_bytes: u64,
+}
+
+/// Configs and job results written before algorithms were selectable name a preset, configs
+/// may name just the algorithm. Preset names win over algorithm names, so `None` keeps meaning
+/// zstd -5. Storing the data as is takes an explicit `algorithm = "None"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CompressionSetting {
+    Preset(CompressionLevel),
+    Algorithm(CompressionAlgorithm),
+    Explicit {
+        algorithm: CompressionAlgorithm,
+        level: i32,
+        #[serde(default)]
+        workers: u32,
+        #[serde(default)]
+        window_log: Option<u32>,
+        #[serde(default)]
+        long_distance_matching: bool,
+        #[serde(default)]
+        adaptive: Option<AdaptiveCompression>,
+    },
+}
+
+impl From<CompressionSetting> for Compression {
+    fn from(value: CompressionSetting) -> Self {
+        match value {
+            CompressionSetting::Preset(preset) => preset.into(),
+            CompressionSetting::Algorithm(algorithm) => algorithm.into(),
+            CompressionSetting::Explicit {
+                algorithm,
+                level,
+                workers,
+                window_log,
+                long_distance_matching,
+                adaptive,
+            } => Compression {
+                algorithm,
+                level,
+                workers,
+                window_log,
+                long_distance_matching,
+                adaptive,
+            },
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use crate::objects::Compression;
+use poem_openapi::Enum;
+use serde::{Deserialize, Serialize};
+
+/// Describes how an uploaded backup file was encoded. It is stored in front of the data so
+/// files can be decoded without knowing the configuration they were created with.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ContainerHeader {
+    pub compression: Compression,
+    pub encryption: EncryptionScheme,
+    pub source: BackupSource,
+    pub parent: Option<u32>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum EncryptionScheme {
+    None,
+    /// AES-256-GCM sealed chunks with a key derived from the configured password.
+    Aes256GcmChunked { kdf: KeyDerivation },
+    /// AES-256-GCM sealed chunks with a random key sealed to X25519 recipients.
+    X25519Recipients { recipients: u32 },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum KeyDerivation {
+    Scrypt { log_n: u8, r: u32, p: u32 },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum BackupSource {
+    /// Output of `btrfs send`, incremental if the header has a parent.
+    BtrfsStream,
+    Tar,
+}
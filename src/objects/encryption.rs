@@ -5,6 +5,12 @@ use std::fmt::Debug;
 pub enum EncryptionLevel {
     None,
     Symmetrical { password: SensitiveString },
+    /// Every backup key is sealed to all `recipients`. Decoding needs one matching identity,
+    /// which is only supplied at restore time.
+    Recipients {
+        recipients: Vec<String>,
+        identities: Vec<SensitiveString>,
+    },
 }
 
 impl Debug for EncryptionLevel {
@@ -12,6 +18,9 @@ impl Debug for EncryptionLevel {
         match self {
             EncryptionLevel::None => write!(f, "None"),
             EncryptionLevel::Symmetrical { password } => write!(f, "Symmetrical(?)"),
+            EncryptionLevel::Recipients { recipients, .. } => {
+                write!(f, "Recipients({recipients:?})")
+            }
         }
     }
 }
@@ -0,0 +1,31 @@
+use crate::objects::Compression;
+use crate::objects::job_result::ReplicaUploadResult;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FullDataBackupResult {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    pub state: FullDataBackupResultState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum FullDataBackupResultState {
+    Error(String),
+    Success(FullDataBackupUploadResult),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FullDataBackupUploadResult {
+    pub id: u32,
+    pub filesystem_root: String,
+    pub remote_filename: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Results recorded before algorithms were selectable name a preset.
+    #[serde(alias = "compression_level")]
+    pub compression: Compression,
+    pub encrypted: bool,
+    #[serde(default)]
+    pub replicas: Vec<ReplicaUploadResult>,
+}
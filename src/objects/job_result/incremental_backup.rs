@@ -1,4 +1,5 @@
-use crate::objects::CompressionLevel;
+use crate::config::ReplicationPolicy;
+use crate::objects::{Compression, CompressionMix};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -22,6 +23,47 @@ pub struct IncrementalBackupUploadResult {
     pub local_snapshot: String,
     pub bytes_read: u64,
     pub bytes_written: u64,
-    pub compression_level: CompressionLevel,
+    /// Results recorded before algorithms were selectable name a preset.
+    #[serde(alias = "compression_level")]
+    pub compression: Compression,
+    /// How the input was stored, only set for adaptive compression.
+    pub compression_mix: Option<CompressionMix>,
     pub encrypted: bool,
+    #[serde(default)]
+    pub replicas: Vec<ReplicaUploadResult>,
+    /// Only set when the backup was stored as chunks, in the repository or as the segments of
+    /// a resumable upload.
+    #[serde(default)]
+    pub repository: Option<RepositoryUploadResult>,
+    #[serde(default)]
+    pub resumable_upload: Option<ResumableUploadResult>,
+    /// Why no snapshot manifest was recorded, restore drills skip the backup then.
+    #[serde(default)]
+    pub manifest_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplicaUploadResult {
+    pub name: String,
+    pub policy: ReplicationPolicy,
+    /// Why the backup is missing on the replica, only best-effort replicas can fail.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepositoryUploadResult {
+    /// Chunks the backup is made of.
+    pub chunks: u64,
+    /// Chunks that were not stored yet, all others were shared with earlier backups.
+    pub new_chunks: u64,
+    /// Chunks no backup references any more after the retention was applied.
+    pub removed_chunks: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResumableUploadResult {
+    /// Segments an earlier run had stored before its upload failed, which were not sent again.
+    pub resumed_segments: u64,
+    /// Failed attempts of this run that were resumed.
+    pub retries: u32,
 }
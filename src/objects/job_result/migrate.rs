@@ -0,0 +1,26 @@
+use crate::config::MigrationMode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MigrateResult {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    pub state: MigrateResultState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum MigrateResultState {
+    Error(String),
+    Success(MigrateSuccess),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MigrateSuccess {
+    pub mode: MigrationMode,
+    /// Ids of the backup entries copied to the target.
+    pub migrated: Vec<u32>,
+    /// Ids of the backup entries the target already had.
+    pub skipped: Vec<u32>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
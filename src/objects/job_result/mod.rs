@@ -1,13 +1,28 @@
 use serde::{Deserialize, Serialize};
 
+mod full_backup;
 mod incremental_backup;
+mod migrate;
+mod rekey;
 mod restore;
+mod restore_drill;
+mod verify;
 
+pub use full_backup::*;
 pub use incremental_backup::*;
-pub use restore::RestoreResult;
+pub use migrate::*;
+pub use rekey::*;
+pub use restore::*;
+pub use restore_drill::*;
+pub use verify::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum JobResult {
     IncrementalBackup(IncrementalBackupResult),
+    FullDataBackup(FullDataBackupResult),
     Restore(RestoreResult),
+    Rekey(RekeyResult),
+    Migrate(MigrateResult),
+    Verify(VerifyResult),
+    RestoreDrill(RestoreDrillResult),
 }
@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RekeyResult {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    pub state: RekeyResultState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RekeyResultState {
+    Error(String),
+    Success(RekeySuccess),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RekeySuccess {
+    pub key_id: Option<String>,
+    /// Ids of the re-encrypted backup entries, empty if all of them already used the key.
+    pub rekeyed: Vec<u32>,
+    /// Ids of outdated backups in the chunk repository, they keep the key they were made with.
+    #[serde(default)]
+    pub skipped: Vec<u32>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
@@ -1,4 +1,4 @@
-use crate::objects::CompressionLevel;
+use crate::objects::Compression;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -11,17 +11,19 @@ pub struct RestoreResult {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum RestoreResultState {
     Error(String),
-    Success(crate::objects::job_result::IncrementalBackupUploadResult),
+    Success(RestoreSuccess),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RestoreSuccess {
     pub id: u32,
-    pub parent: Option<u32>,
-    pub remote_filename: String,
+    /// Ids of all restored backup entries, oldest first.
+    pub restored_chain: Vec<u32>,
     pub local_snapshot: String,
     pub bytes_read: u64,
     pub bytes_written: u64,
-    pub compression_level: CompressionLevel,
+    /// Compression of the restored entry, unknown for backups made before it was recorded.
+    #[serde(alias = "compression_level")]
+    pub compression: Option<Compression>,
     pub encrypted: bool,
 }
@@ -0,0 +1,29 @@
+use crate::config::DrillPick;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestoreDrillResult {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    /// An error means the drill failed, including restores that do not match their snapshot.
+    pub state: RestoreDrillResultState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RestoreDrillResultState {
+    Error(String),
+    Success(RestoreDrillSuccess),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RestoreDrillSuccess {
+    pub pick: DrillPick,
+    /// Id of the drilled backup entry.
+    pub id: u32,
+    /// Ids of all restored backup entries, oldest first.
+    pub restored_chain: Vec<u32>,
+    pub local_snapshot: String,
+    /// Files of the restored snapshot, equal to the recorded ones.
+    pub file_count: u64,
+    pub bytes_read: u64,
+}
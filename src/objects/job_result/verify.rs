@@ -0,0 +1,71 @@
+use crate::objects::BackupEntry;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerifyResult {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub finished_at: chrono::DateTime<chrono::Utc>,
+    pub mode: VerifyMode,
+    pub state: VerifyResultState,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum VerifyResultState {
+    Error(String),
+    Success(VerifySuccess),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerifySuccess {
+    /// Ids of the backup entries that were checked.
+    pub checked: Vec<u32>,
+    /// Everything that was found wrong, an empty list means the backups are healthy.
+    pub issues: Vec<VerifyIssue>,
+    pub bytes_read: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum VerifyMode {
+    /// Only checks that every file exists with the recorded size.
+    Quick,
+    /// Downloads and decodes every file and checks the recorded checksums.
+    #[default]
+    Deep,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct VerifyIssue {
+    /// `None` for files that do not belong to any backup entry.
+    pub backup_id: Option<u32>,
+    pub remote_filename: Option<String>,
+    pub kind: VerifyIssueKind,
+    pub details: String,
+}
+
+impl VerifyIssue {
+    pub fn of(entry: &BackupEntry, kind: VerifyIssueKind, details: impl Display) -> Self {
+        VerifyIssue {
+            backup_id: Some(entry.id),
+            remote_filename: Some(entry.remote_filename.to_string_lossy().to_string()),
+            kind,
+            details: details.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum VerifyIssueKind {
+    /// The file of a backup entry is not on the remote.
+    MissingFile,
+    /// A file on the remote does not belong to any backup entry.
+    ExtraFile,
+    /// The parent links of a backup entry do not lead to a full backup.
+    BrokenChain,
+    SizeMismatch,
+    /// The file could not be decrypted or decompressed.
+    Unreadable,
+    /// The file or its decoded content does not match the recorded hash.
+    Corrupted,
+}
@@ -0,0 +1,35 @@
+use crate::objects::job_state::FetchingMetadataState;
+use crate::objects::Compression;
+use poem_openapi::{Object, Union};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct FullDataBackupState {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub stage: FullDataBackupStage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "stage")]
+pub enum FullDataBackupStage {
+    FetchingMetadata(FetchingMetadataState),
+    Uploading(FullDataBackupUploadState),
+}
+
+impl From<FetchingMetadataState> for FullDataBackupStage {
+    fn from(state: FetchingMetadataState) -> Self {
+        FullDataBackupStage::FetchingMetadata(state)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct FullDataBackupUploadState {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub filesystem_root: String,
+    pub remote_filename: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub compression: Compression,
+    pub encrypted: bool,
+    pub finishing: bool,
+}
@@ -1,4 +1,4 @@
-use crate::objects::CompressionLevel;
+use crate::objects::Compression;
 use poem_openapi::{Object, Union};
 use serde::{Deserialize, Serialize};
 
@@ -32,7 +32,7 @@ pub struct IncrementalBackupUploadState {
     pub local_snapshot: String,
     pub bytes_read: u64,
     pub bytes_written: u64,
-    pub compression_level: CompressionLevel,
+    pub compression: Compression,
     pub encrypted: bool,
     pub finishing: bool,
 }
@@ -0,0 +1,54 @@
+use crate::config::MigrationMode;
+use crate::objects::job_state::FetchingMetadataState;
+use poem_openapi::{Enum, Object, Union};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct MigrateState {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub mode: MigrateMode,
+    pub stage: MigrateStage,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum MigrateMode {
+    Copy,
+    Reencode,
+}
+
+impl From<MigrationMode> for MigrateMode {
+    fn from(mode: MigrationMode) -> Self {
+        match mode {
+            MigrationMode::Copy => MigrateMode::Copy,
+            MigrationMode::Reencode => MigrateMode::Reencode,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "stage")]
+pub enum MigrateStage {
+    FetchingMetadata(FetchingMetadataState),
+    Migrating(MigrateProgressState),
+}
+
+impl From<FetchingMetadataState> for MigrateStage {
+    fn from(state: FetchingMetadataState) -> Self {
+        MigrateStage::FetchingMetadata(state)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct MigrateProgressState {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Id of the backup entry currently being migrated.
+    pub current: u32,
+    /// Position of the current entry among the ones the target is missing, starting at 0.
+    pub position: u32,
+    pub missing: u32,
+    pub remote_filename: String,
+    /// Whether the copy is being read back from the target.
+    pub verifying: bool,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
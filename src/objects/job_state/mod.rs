@@ -1,9 +1,21 @@
 use poem_openapi::{Object, Union};
 use serde::{Deserialize, Serialize};
 
+mod full_backup;
 mod incremental_backup;
+mod migrate;
+mod rekey;
+mod restore;
+mod restore_drill;
+mod verify;
 
+pub use full_backup::*;
 pub use incremental_backup::*;
+pub use migrate::*;
+pub use rekey::*;
+pub use restore::*;
+pub use restore_drill::*;
+pub use verify::*;
 
 #[derive(Clone, Debug, Serialize, Deserialize, Object)]
 pub struct JobStates {
@@ -18,7 +30,9 @@ pub struct JobStates {
 #[oai(discriminator_name = "type")]
 pub enum BackupJobState {
     Incremental(IncrementalBackupState),
+    Full(FullDataBackupState),
+    Rekey(RekeyState),
+    Migrate(MigrateState),
+    Verify(VerifyState),
+    RestoreDrill(RestoreDrillState),
 }
-
-#[derive(Clone, Debug, Serialize, Deserialize, Object)]
-pub struct RestoreJobState;
\ No newline at end of file
@@ -0,0 +1,37 @@
+use crate::objects::job_state::FetchingMetadataState;
+use poem_openapi::{Object, Union};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RekeyState {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Key the backups are re-encrypted with, `none` if they are stored unencrypted.
+    pub key_id: Option<String>,
+    pub stage: RekeyStage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "stage")]
+pub enum RekeyStage {
+    FetchingMetadata(FetchingMetadataState),
+    Rekeying(RekeyProgressState),
+}
+
+impl From<FetchingMetadataState> for RekeyStage {
+    fn from(state: FetchingMetadataState) -> Self {
+        RekeyStage::FetchingMetadata(state)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RekeyProgressState {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Id of the backup entry currently being re-encrypted.
+    pub current: u32,
+    /// Position of the current entry among the outdated ones, starting at 0.
+    pub position: u32,
+    pub outdated: u32,
+    pub remote_filename: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
@@ -0,0 +1,43 @@
+use crate::objects::Compression;
+use poem_openapi::{Object, Union};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreJobState {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub backup_id: Option<u32>,
+    pub stage: RestoreStage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "stage")]
+pub enum RestoreStage {
+    FetchingMetadata(RestoreFetchingMetadataState),
+    Downloading(RestoreDownloadState),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreFetchingMetadataState;
+
+impl From<RestoreFetchingMetadataState> for RestoreStage {
+    fn from(state: RestoreFetchingMetadataState) -> Self {
+        RestoreStage::FetchingMetadata(state)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreDownloadState {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Id of the backup entry currently being restored.
+    pub current: u32,
+    /// Position of the current entry in the restored chain, starting at 0.
+    pub chain_position: u32,
+    pub chain_length: u32,
+    pub remote_filename: String,
+    pub local_snapshot: String,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Compression of the current entry, unknown for backups made before it was recorded.
+    pub compression: Option<Compression>,
+    pub encrypted: bool,
+}
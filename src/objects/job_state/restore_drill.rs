@@ -0,0 +1,40 @@
+use crate::objects::job_state::{FetchingMetadataState, RestoreDownloadState};
+use poem_openapi::{Object, Union};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreDrillState {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// The drilled backup entry, once it was picked.
+    pub backup_id: Option<u32>,
+    pub stage: RestoreDrillStage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "stage")]
+pub enum RestoreDrillStage {
+    FetchingMetadata(FetchingMetadataState),
+    Restoring(RestoreDrillRestoringState),
+    Checking(RestoreDrillCheckingState),
+    CleaningUp(RestoreDrillCleaningUpState),
+}
+
+impl From<FetchingMetadataState> for RestoreDrillStage {
+    fn from(state: FetchingMetadataState) -> Self {
+        RestoreDrillStage::FetchingMetadata(state)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreDrillRestoringState {
+    /// `None` until the first backup of the chain is downloaded.
+    pub download: Option<RestoreDownloadState>,
+}
+
+/// The received snapshot is compared with its manifest.
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreDrillCheckingState;
+
+/// Everything the drill received is being deleted.
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct RestoreDrillCleaningUpState;
@@ -0,0 +1,39 @@
+use crate::objects::job_result::VerifyMode;
+use crate::objects::job_state::FetchingMetadataState;
+use poem_openapi::{Object, Union};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct VerifyState {
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub mode: VerifyMode,
+    pub stage: VerifyStage,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Union)]
+#[oai(discriminator_name = "stage")]
+pub enum VerifyStage {
+    FetchingMetadata(FetchingMetadataState),
+    Verifying(VerifyProgressState),
+}
+
+impl From<FetchingMetadataState> for VerifyStage {
+    fn from(state: FetchingMetadataState) -> Self {
+        VerifyStage::FetchingMetadata(state)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Object)]
+pub struct VerifyProgressState {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// Id of the backup entry currently being checked.
+    pub current: u32,
+    /// Position of the current entry in the backup history, starting at 0.
+    pub position: u32,
+    pub total: u32,
+    pub remote_filename: String,
+    /// Bytes downloaded so far, always 0 in quick mode.
+    pub bytes_read: u64,
+    /// Issues found so far.
+    pub issues: u32,
+}
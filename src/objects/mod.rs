@@ -1,13 +1,26 @@
+mod backup_chain;
+mod backup_health;
+mod backup_checksums;
 mod backup_history;
+mod backup_retention;
 mod compression;
+mod container;
 mod encryption;
 mod job_history;
 pub mod job_result;
 pub mod job_state;
+mod schedule;
 mod sensitive;
+mod snapshot_manifest;
 
+pub use backup_chain::*;
+pub use backup_health::*;
+pub use backup_checksums::*;
 pub use backup_history::*;
 pub use compression::*;
+pub use container::*;
 pub use encryption::*;
 pub use job_history::*;
+pub use schedule::*;
 pub use sensitive::*;
+pub use snapshot_manifest::*;
@@ -0,0 +1,21 @@
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Object)]
+pub struct ScheduleState {
+    /// Whether a `[schedule]` is configured.
+    pub enabled: bool,
+    /// The next planned run, including its random jitter.
+    pub next_run: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_outcome: Option<ScheduledRunOutcome>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum ScheduledRunOutcome {
+    Submitted,
+    /// Another backup was still running, so this run was skipped.
+    SkippedJobRunning,
+    /// A destination could not be set up, so this run was skipped.
+    SkippedSetupFailed,
+}
@@ -0,0 +1,11 @@
+use poem_openapi::Object;
+
+/// Describes the tree of a local snapshot, so that a restore of it can be compared.
+#[derive(Clone, Debug, Eq, PartialEq, Object)]
+pub struct SnapshotManifest {
+    /// Entries below the snapshot root that are not directories.
+    pub file_count: u64,
+    /// BLAKE2b-512 over the path, type and content of every entry in path order, in
+    /// lowercase hex. Permissions and timestamps are not part of it.
+    pub tree_hash: String,
+}
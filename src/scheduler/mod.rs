@@ -0,0 +1,232 @@
+use crate::config::{DataDanceConfiguration, ScheduleConfig, ScheduleRule};
+use crate::context::DataDanceContext;
+use crate::jobs::incremental_backup::IncrementalBackupJob;
+use crate::jobs::restore_drill::RestoreDrillJob;
+use crate::jobs::{ExecutorError, Job, JobVariant};
+use crate::objects::{ScheduleState, ScheduledRunOutcome};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+mod tests;
+
+impl ScheduleRule {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ScheduleRule::Cron { expression } => cron_schedule(expression)
+                .map(|_| ())
+                .map_err(|err| format!("invalid cron expression '{expression}': {err}")),
+            ScheduleRule::Interval { seconds: 0 } => {
+                Err("the schedule interval must be at least one second".to_string())
+            }
+            ScheduleRule::Interval { .. } => Ok(()),
+        }
+    }
+
+    /// The first run of this rule strictly after `after`, keeping to the slots of `previous`.
+    pub fn next_slot_after(
+        &self,
+        previous: DateTime<Utc>,
+        after: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleRule::Cron { .. } => self.next_after(after),
+            ScheduleRule::Interval { seconds } => {
+                let seconds = (*seconds).max(1) as i64;
+                let elapsed_slots = (after - previous).num_seconds().div_euclid(seconds);
+                Some(previous + chrono::Duration::seconds((elapsed_slots.max(0) + 1) * seconds))
+            }
+        }
+    }
+
+    /// The first run of this rule strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            ScheduleRule::Cron { expression } => cron_schedule(expression).ok()?.after(&after).next(),
+            ScheduleRule::Interval { seconds } => {
+                Some(after + chrono::Duration::seconds(*seconds as i64))
+            }
+        }
+    }
+}
+
+fn cron_schedule(expression: &str) -> Result<cron::Schedule, cron::error::Error> {
+    // The cron crate expects a seconds field, classic crontab lines do not have one
+    if expression.split_whitespace().count() == 5 {
+        cron::Schedule::from_str(&format!("0 {expression}"))
+    } else {
+        cron::Schedule::from_str(expression)
+    }
+}
+
+/// Plans the next run. A run missed since `last_run` is due immediately when catching up,
+/// otherwise the next slot after `last_run` is planned so intervals do not drift.
+pub fn plan_next_run(
+    schedule: &ScheduleConfig,
+    last_run: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    match last_run {
+        Some(last_run) if schedule.catch_up => {
+            let next_run = schedule.rule.next_after(last_run)?;
+            Some(next_run.max(now))
+        }
+        Some(last_run) => schedule.rule.next_slot_after(last_run, now),
+        None => schedule.rule.next_after(now),
+    }
+}
+
+pub struct Scheduler {
+    schedule: Option<ScheduleConfig>,
+    state_path: PathBuf,
+    state: Mutex<ScheduleState>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct PersistedSchedule {
+    last_run: Option<DateTime<Utc>>,
+}
+
+impl Scheduler {
+    pub fn new(config: &DataDanceConfiguration) -> Self {
+        let state_path = config
+            .local_storage
+            .jobs_folder
+            .clone()
+            .join("schedule.json");
+        Self::with_schedule(config.schedule.clone(), state_path)
+    }
+
+    /// Plans the restore drills of `[restore_drill.schedule]`.
+    pub fn for_restore_drills(config: &DataDanceConfiguration) -> Self {
+        let schedule = config
+            .restore_drill
+            .as_ref()
+            .and_then(|restore_drill| restore_drill.schedule.clone());
+        let state_path = config
+            .local_storage
+            .jobs_folder
+            .clone()
+            .join("restore_drill_schedule.json");
+        Self::with_schedule(schedule, state_path)
+    }
+
+    fn with_schedule(schedule: Option<ScheduleConfig>, state_path: PathBuf) -> Self {
+        let persisted: PersistedSchedule = File::open(&state_path)
+            .ok()
+            .and_then(|handle| serde_json::from_reader(BufReader::new(handle)).ok())
+            .unwrap_or_default();
+
+        Scheduler {
+            state: Mutex::new(ScheduleState {
+                enabled: schedule.is_some(),
+                next_run: None,
+                last_run: persisted.last_run,
+                last_outcome: None,
+            }),
+            schedule,
+            state_path,
+        }
+    }
+
+    pub fn state(&self) -> ScheduleState {
+        self.state.lock().unwrap().clone()
+    }
+
+    fn set_next_run(&self, next_run: Option<DateTime<Utc>>) {
+        let mut state_lock = self.state.lock().unwrap();
+        state_lock.next_run = next_run;
+    }
+
+    fn record_run(&self, planned_run: DateTime<Utc>, outcome: ScheduledRunOutcome) -> io::Result<()> {
+        {
+            let mut state_lock = self.state.lock().unwrap();
+            state_lock.last_run = Some(planned_run);
+            state_lock.last_outcome = Some(outcome);
+        }
+
+        let handle = File::create(&self.state_path)?;
+        serde_json::to_writer(
+            BufWriter::new(handle),
+            &PersistedSchedule {
+                last_run: Some(planned_run),
+            },
+        )?;
+        Ok(())
+    }
+}
+
+/// Submits incremental backups according to the configured schedule until the rule runs out.
+pub async fn run_scheduler(context: Arc<DataDanceContext>) {
+    run_schedule(
+        context,
+        |context| &context.scheduler,
+        "backup",
+        |config| IncrementalBackupJob::from_config(config.clone()).map(JobVariant::from),
+    )
+    .await
+}
+
+/// Submits restore drills according to `[restore_drill.schedule]` until the rule runs out.
+pub async fn run_restore_drill_scheduler(context: Arc<DataDanceContext>) {
+    run_schedule(
+        context,
+        |context| &context.restore_drill_scheduler,
+        "restore drill",
+        |config| RestoreDrillJob::from_config(config.clone()).map(JobVariant::from),
+    )
+    .await
+}
+
+async fn run_schedule(
+    context: Arc<DataDanceContext>,
+    scheduler: fn(&DataDanceContext) -> &Scheduler,
+    job_name: &str,
+    make_job: fn(&DataDanceConfiguration) -> io::Result<JobVariant>,
+) {
+    let Some(schedule) = scheduler(&context).schedule.clone() else {
+        return;
+    };
+
+    loop {
+        let last_run = scheduler(&context).state().last_run;
+        let Some(planned_run) = plan_next_run(&schedule, last_run, Utc::now()) else {
+            println!("The {job_name} schedule has no upcoming runs, stopping its scheduler");
+            scheduler(&context).set_next_run(None);
+            return;
+        };
+        let jitter = if schedule.jitter_seconds > 0 {
+            rand::random_range(0..=schedule.jitter_seconds)
+        } else {
+            0
+        };
+        let due_at = planned_run + chrono::Duration::seconds(jitter as i64);
+        scheduler(&context).set_next_run(Some(due_at));
+
+        let wait = (due_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let outcome = match make_job(&context.config) {
+            Ok(job) => match context.executor.submit_job(job) {
+                Ok(_) => ScheduledRunOutcome::Submitted,
+                Err(ExecutorError::JobAlreadyRunning { running }) => {
+                    println!("Skipping scheduled {job_name}, the {running} job is still running");
+                    ScheduledRunOutcome::SkippedJobRunning
+                }
+            },
+            Err(err) => {
+                eprintln!("Skipping scheduled {job_name}, it could not be set up: {err}");
+                ScheduledRunOutcome::SkippedSetupFailed
+            }
+        };
+        if let Err(err) = scheduler(&context).record_run(planned_run, outcome) {
+            eprintln!("Failed to persist the {job_name} schedule state: {err}");
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use crate::config::{ScheduleConfig, ScheduleRule};
+use crate::scheduler::plan_next_run;
+use chrono::{DateTime, TimeZone, Utc};
+
+fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, hour, minute, 0).unwrap()
+}
+
+fn cron_schedule(expression: &str, catch_up: bool) -> ScheduleConfig {
+    ScheduleConfig {
+        rule: ScheduleRule::Cron {
+            expression: expression.to_string(),
+        },
+        jitter_seconds: 0,
+        catch_up,
+    }
+}
+
+#[test]
+fn cron_accepts_five_and_six_fields() {
+    let five_fields = ScheduleRule::Cron {
+        expression: "30 3 * * *".to_string(),
+    };
+    let six_fields = ScheduleRule::Cron {
+        expression: "0 30 3 * * *".to_string(),
+    };
+
+    assert!(five_fields.validate().is_ok());
+    assert!(six_fields.validate().is_ok());
+    assert_eq!(five_fields.next_after(at(1, 0)), Some(at(3, 30)));
+    assert_eq!(six_fields.next_after(at(1, 0)), Some(at(3, 30)));
+}
+
+#[test]
+fn invalid_rules_are_rejected() {
+    assert!(ScheduleRule::Cron {
+        expression: "every day".to_string()
+    }
+    .validate()
+    .is_err());
+    assert!(ScheduleRule::Interval { seconds: 0 }.validate().is_err());
+}
+
+#[test]
+fn interval_runs_after_last_run() {
+    let schedule = ScheduleConfig {
+        rule: ScheduleRule::Interval { seconds: 3600 },
+        jitter_seconds: 0,
+        catch_up: true,
+    };
+
+    assert_eq!(
+        plan_next_run(&schedule, Some(at(10, 0)), at(10, 30)),
+        Some(at(11, 0))
+    );
+    assert_eq!(plan_next_run(&schedule, None, at(10, 30)), Some(at(11, 30)));
+}
+
+#[test]
+fn missed_run_is_caught_up_immediately() {
+    let schedule = cron_schedule("0 3 * * *", true);
+    let last_run = at(3, 0) - chrono::Duration::days(2);
+
+    assert_eq!(plan_next_run(&schedule, Some(last_run), at(12, 0)), Some(at(12, 0)));
+}
+
+#[test]
+fn missed_run_is_dropped_without_catch_up() {
+    let schedule = cron_schedule("0 3 * * *", false);
+    let last_run = at(3, 0) - chrono::Duration::days(2);
+
+    assert_eq!(
+        plan_next_run(&schedule, Some(last_run), at(12, 0)),
+        Some(at(3, 0) + chrono::Duration::days(1))
+    );
+}
+
+#[test]
+fn interval_keeps_its_slots_without_catch_up() {
+    let schedule = ScheduleConfig {
+        rule: ScheduleRule::Interval { seconds: 3600 },
+        jitter_seconds: 0,
+        catch_up: false,
+    };
+
+    assert_eq!(
+        plan_next_run(&schedule, Some(at(10, 0)), at(10, 0)),
+        Some(at(11, 0))
+    );
+    assert_eq!(
+        plan_next_run(&schedule, Some(at(10, 0)), at(12, 30)),
+        Some(at(13, 0))
+    );
+}
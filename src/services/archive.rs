@@ -0,0 +1,93 @@
+use std::io;
+use std::io::{PipeReader, Read};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+
+/// Streams a tar archive of a directory tree, built on a separate thread.
+///
+/// Errors of the archiving thread are reported by the read hitting the end of the stream,
+/// so a truncated archive never looks like a complete one.
+pub struct TarArchiveReader {
+    inner: PipeReader,
+    producer: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TarArchiveReader {
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("'{}' is not a directory", root.display()),
+            ));
+        }
+
+        let (reader, writer) = io::pipe()?;
+        let producer = std::thread::spawn(move || {
+            let mut builder = tar::Builder::new(writer);
+            builder.follow_symlinks(false);
+            builder.append_dir_all(".", &root)?;
+            builder.into_inner()?;
+            Ok(())
+        });
+
+        Ok(Self {
+            inner: reader,
+            producer: Some(producer),
+        })
+    }
+}
+
+impl Read for TarArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        if bytes_read == 0 && !buf.is_empty() {
+            if let Some(producer) = self.producer.take() {
+                producer
+                    .join()
+                    .map_err(|_| io::Error::other("tar archiving thread panicked"))??;
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn archives_directory_tree() {
+        let root = std::env::temp_dir().join(format!("data-dance-archive-{}", rand::random::<u64>()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("top.txt"), b"top").unwrap();
+        fs::write(root.join("nested/inner.txt"), b"inner").unwrap();
+
+        let mut archive = tar::Archive::new(TarArchiveReader::new(root.clone()).unwrap());
+        let mut files = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.header().entry_type().is_file() {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                files.push((entry.path().unwrap().to_path_buf(), content));
+            }
+        }
+        files.sort();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            files,
+            vec![
+                (PathBuf::from("nested/inner.txt"), "inner".to_string()),
+                (PathBuf::from("top.txt"), "top".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_root_fails() {
+        let root = std::env::temp_dir().join(format!("data-dance-missing-{}", rand::random::<u64>()));
+        assert!(TarArchiveReader::new(root).is_err());
+    }
+}
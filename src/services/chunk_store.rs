@@ -0,0 +1,251 @@
+use crate::config::RemoteStorageConfig;
+use crate::objects::{BackupEntry, BackupHistory, EncryptionLevel};
+use crate::services::data_dest::DestService;
+use crate::services::data_tunnel::{DecodingDataTunnel, EncodingDataTunnel};
+use crate::services::encryption::EncryptionSession;
+use crate::services::tracking::{BytesCountingReader, BytesCountingWriter};
+use blake2::digest::consts::U32;
+use blake2::{Blake2b, Blake2b512, Digest};
+use std::collections::HashSet;
+use std::io;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Content-addressed chunks of the backups stored in the repository. Every chunk is a container
+/// of its own, stored next to the backup files as `<id>.chunk`. The remote file of such a backup
+/// is the encoded `ChunkIndex` of its chunks.
+pub struct ChunkStore<'a> {
+    dest: &'a dyn DestService,
+    /// Keys the chunk ids, so they do not reveal the content to anyone without the password.
+    id_key: Vec<u8>,
+}
+
+impl<'a> ChunkStore<'a> {
+    /// Chunks are addressed by a hash keyed with the password or the recipients of
+    /// `encryption_level`, changing the recipients changes the ids. Without encryption the ids
+    /// are plain content hashes.
+    pub fn new(dest: &'a dyn DestService, encryption_level: &EncryptionLevel) -> Self {
+        let mut hasher = Blake2b512::new();
+        hasher.update(b"data-dance chunk id\0");
+        match encryption_level {
+            EncryptionLevel::Symmetrical { password } => {
+                hasher.update(password.insecure().as_bytes());
+            }
+            EncryptionLevel::Recipients { recipients, .. } => {
+                let mut recipients = recipients.clone();
+                recipients.sort();
+                for recipient in recipients {
+                    hasher.update(recipient.as_bytes());
+                    hasher.update(b"\0");
+                }
+            }
+            EncryptionLevel::None => return Self { dest, id_key: Vec::new() },
+        }
+        Self {
+            dest,
+            id_key: hasher.finalize().to_vec(),
+        }
+    }
+
+    /// Lowercase hex of the keyed BLAKE2b-256 of the plaintext of a chunk.
+    pub fn chunk_id(&self, data: &[u8]) -> String {
+        let mut hasher = Blake2b::<U32>::new();
+        hasher.update(&self.id_key);
+        hasher.update(data);
+        let hash = hasher.finalize();
+        hash.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    /// Ids of the chunks present on the remote.
+    pub fn stored_ids(&self) -> io::Result<HashSet<String>> {
+        Ok(self
+            .dest
+            .list_chunk_files()?
+            .iter()
+            .filter_map(|file| chunk_id_of(file))
+            .collect())
+    }
+
+    /// Encodes and uploads a chunk, encrypted in `session` as deriving a key for every chunk
+    /// would be slow. It is written under a temporary name first, so a chunk
+    /// that exists is always complete. Returns the bytes stored on the remote.
+    pub fn write_chunk(
+        &self,
+        id: &str,
+        data: Vec<u8>,
+        encoding_data_tunnel: &EncodingDataTunnel,
+        session: &EncryptionSession,
+    ) -> io::Result<u64> {
+        let chunk_path = chunk_path(id);
+        let part_path = chunk_path.with_added_extension("part");
+        // Left behind if a previous run was interrupted
+        let _ = self.dest.remove_backup_file(part_path.clone());
+        let writer = BytesCountingWriter::new(self.dest.get_backup_writer(part_path.clone())?);
+        let written_bytes = writer.counter();
+        let transferred = encoding_data_tunnel
+            .transfer_in_session(session, Cursor::new(data), writer)
+            .and_then(|_| {
+                self.dest
+                    .verify_backup_file_size(part_path.clone(), written_bytes.value())
+            })
+            .and_then(|_| self.dest.replace_backup_file(part_path.clone(), chunk_path));
+        if let Err(err) = transferred {
+            let _ = self.dest.remove_backup_file(part_path);
+            return Err(err);
+        }
+        Ok(written_bytes.value())
+    }
+
+    /// Downloads and decodes a chunk into `writer`, checking it against its id. Returns the
+    /// bytes read from the remote.
+    pub fn read_chunk(
+        &self,
+        chunk: &ChunkRef,
+        decoding_data_tunnel: &DecodingDataTunnel,
+        writer: &mut impl Write,
+    ) -> io::Result<u64> {
+        let with_id =
+            |err: io::Error| io::Error::new(err.kind(), format!("chunk {}: {err}", chunk.id));
+        let reader = BytesCountingReader::new(
+            self.dest
+                .get_backup_reader(chunk_path(&chunk.id))
+                .map_err(with_id)?,
+        );
+        let read_bytes = reader.counter();
+        let (_, mut decoder) = decoding_data_tunnel.decoder(reader).map_err(with_id)?;
+        let mut data = Vec::with_capacity(chunk.size as usize);
+        decoder.read_to_end(&mut data).map_err(with_id)?;
+        if data.len() as u64 != chunk.size || self.chunk_id(&data) != chunk.id {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("chunk {} is corrupted, it does not match its id", chunk.id),
+            ));
+        }
+        writer.write_all(&data)?;
+        Ok(read_bytes.value())
+    }
+}
+
+/// Removes the chunks that no backup in `history` references any more, which is the case once
+/// the retention pruned the last backup using them. Every index in the history is read to find
+/// the references, if one of them cannot be read, like after its key was removed from the
+/// keyring, no chunk is removed. The `.chunk.part` files of interrupted uploads are removed
+/// either way. Returns how many chunks were removed.
+pub fn collect_garbage(
+    dest: &dyn DestService,
+    history: &BackupHistory,
+    decoding_data_tunnel: &DecodingDataTunnel,
+    remote_storage: &RemoteStorageConfig,
+) -> io::Result<usize> {
+    let (part_files, chunk_files): (Vec<PathBuf>, Vec<PathBuf>) = dest
+        .list_chunk_files()?
+        .into_iter()
+        .partition(|file| file.to_string_lossy().ends_with(".chunk.part"));
+    // Jobs do not run concurrently, no upload is still writing them
+    for part_file in part_files {
+        let _ = dest.remove_backup_file(part_file);
+    }
+    if chunk_files.is_empty() {
+        return Ok(0);
+    }
+
+    let mut referenced = HashSet::new();
+    for entry in &history.entries {
+        if entry.chunk_count.is_none() {
+            continue;
+        }
+        let index = match read_index(dest, entry, decoding_data_tunnel, remote_storage) {
+            Ok(index) => index,
+            Err(err) => {
+                eprintln!("Keeping every chunk, those of backup {} are unknown: {err}", entry.id);
+                return Ok(0);
+            }
+        };
+        referenced.extend(index.chunks.into_iter().map(|chunk| chunk.id));
+    }
+
+    let mut removed_counter = 0;
+    for chunk_file in chunk_files {
+        let Some(id) = chunk_id_of(&chunk_file) else {
+            continue;
+        };
+        if !referenced.contains(&id) && dest.remove_backup_file(chunk_file).is_ok() {
+            removed_counter += 1;
+        }
+    }
+    Ok(removed_counter)
+}
+
+/// Downloads and decodes the `ChunkIndex` stored as the remote file of a chunked `entry`.
+pub fn read_index(
+    dest: &dyn DestService,
+    entry: &BackupEntry,
+    decoding_data_tunnel: &DecodingDataTunnel,
+    remote_storage: &RemoteStorageConfig,
+) -> io::Result<ChunkIndex> {
+    let decoding_data_tunnel = decoding_data_tunnel.for_entry(remote_storage, entry)?;
+    let reader = dest.get_backup_reader(entry.remote_filename.to_path_buf())?;
+    let (_, decoder) = decoding_data_tunnel.decoder(reader)?;
+    ChunkIndex::read_from(decoder)
+}
+
+/// Remote path of the chunk with `id`.
+pub fn chunk_path(id: &str) -> PathBuf {
+    PathBuf::from(format!("{id}.chunk"))
+}
+
+/// Id of a chunk file listed on the remote, `None` for the `.chunk.part` file of an interrupted
+/// upload.
+pub fn chunk_id_of(chunk_file: &Path) -> Option<String> {
+    if chunk_file.extension()? != "chunk" {
+        return None;
+    }
+    Some(chunk_file.file_stem()?.to_string_lossy().to_string())
+}
+
+/// The chunks of a backup in the order they make up its content.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChunkIndex {
+    pub chunks: Vec<ChunkRef>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkRef {
+    pub id: String,
+    /// Bytes of the plaintext of the chunk.
+    pub size: u64,
+}
+
+impl ChunkIndex {
+    /// One `<id> <size>` line per chunk.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for chunk in &self.chunks {
+            bytes.extend_from_slice(format!("{} {}\n", chunk.id, chunk.size).as_bytes());
+        }
+        bytes
+    }
+
+    pub fn read_from(reader: impl Read) -> io::Result<ChunkIndex> {
+        let mut chunks = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let chunk = line.split_once(' ').and_then(|(id, size)| {
+                Some(ChunkRef {
+                    id: id.to_string(),
+                    size: size.parse().ok()?,
+                })
+            });
+            match chunk {
+                Some(chunk) => chunks.push(chunk),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed chunk index line '{line}'"),
+                    ));
+                }
+            }
+        }
+        Ok(ChunkIndex { chunks })
+    }
+}
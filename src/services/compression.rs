@@ -1,24 +1,650 @@
-use crate::objects::CompressionLevel;
-use serde::{Deserialize, Serialize};
-use std::io::{BufRead, Read, Write};
+use crate::objects::{AdaptiveCompression, Compression, CompressionAlgorithm, CompressionLevel};
+use crate::services::encoder::{Encoder, Unencoded};
+use crate::services::tracking::CompressionMixCounter;
+use std::io;
+use std::io::{Read, Write};
+use std::ops::RangeInclusive;
 
-impl CompressionLevel {
-    fn to_zstd_level(&self) -> i32 {
-        match self.clone() {
+/// Window logs zstd accepts, the upper bound needs a 64 bit target.
+const ZSTD_WINDOW_LOGS: RangeInclusive<u32> =
+    10..=if cfg!(target_pointer_width = "64") { 31 } else { 30 };
+
+impl From<CompressionLevel> for Compression {
+    fn from(value: CompressionLevel) -> Self {
+        let level = match value {
             CompressionLevel::None => -5,
             CompressionLevel::Fast => 3,
             CompressionLevel::Balanced => 9,
             CompressionLevel::Best => 15,
+        };
+        Compression::new(CompressionAlgorithm::Zstd, level)
+    }
+}
+
+impl From<CompressionAlgorithm> for Compression {
+    fn from(algorithm: CompressionAlgorithm) -> Self {
+        Compression::new(algorithm, algorithm.default_level())
+    }
+}
+
+impl CompressionAlgorithm {
+    pub fn default_level(&self) -> i32 {
+        match self {
+            CompressionAlgorithm::Zstd => 3,
+            CompressionAlgorithm::Lz4 => 0,
+            CompressionAlgorithm::Gzip => 6,
+            CompressionAlgorithm::Xz => 6,
+            CompressionAlgorithm::None => 0,
+        }
+    }
+
+    /// Levels the algorithm accepts, higher is smaller and slower.
+    pub fn levels(&self) -> RangeInclusive<i32> {
+        match self {
+            CompressionAlgorithm::Zstd => zstd::compression_level_range(),
+            CompressionAlgorithm::Lz4 => 0..=12,
+            CompressionAlgorithm::Gzip => 0..=9,
+            CompressionAlgorithm::Xz => 0..=9,
+            CompressionAlgorithm::None => 0..=0,
+        }
+    }
+
+    pub fn to_decoder<R: Read + 'static>(&self, r: R) -> io::Result<Box<dyn Read>> {
+        Ok(match self {
+            CompressionAlgorithm::Zstd => {
+                let mut decoder = zstd::stream::read::Decoder::new(r)?;
+                // Streams written with a large `window_log` are refused by default
+                decoder.window_log_max(*ZSTD_WINDOW_LOGS.end())?;
+                Box::new(decoder)
+            }
+            CompressionAlgorithm::Lz4 => Box::new(lz4::Decoder::new(r)?),
+            CompressionAlgorithm::Gzip => Box::new(flate2::read::GzDecoder::new(r)),
+            CompressionAlgorithm::Xz => Box::new(xz2::read::XzDecoder::new(r)),
+            CompressionAlgorithm::None => Box::new(r),
+        })
+    }
+}
+
+impl Compression {
+    pub fn new(algorithm: CompressionAlgorithm, level: i32) -> Self {
+        Compression {
+            algorithm,
+            level,
+            workers: 0,
+            window_log: None,
+            long_distance_matching: false,
+            adaptive: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let levels = self.algorithm.levels();
+        if !levels.contains(&self.level) {
+            return Err(format!(
+                "{:?} compression levels range from {} to {}, got {}",
+                self.algorithm,
+                levels.start(),
+                levels.end(),
+                self.level
+            ));
+        }
+        let zstd_tuned = self.workers > 0
+            || self.window_log.is_some()
+            || self.long_distance_matching
+            || self.adaptive.is_some();
+        if zstd_tuned && self.algorithm != CompressionAlgorithm::Zstd {
+            return Err(format!(
+                "workers, window_log, long_distance_matching and adaptive only apply to Zstd, \
+                not {:?}",
+                self.algorithm
+            ));
+        }
+        if let Some(window_log) = self.window_log
+            && !ZSTD_WINDOW_LOGS.contains(&window_log)
+        {
+            return Err(format!(
+                "zstd window logs range from {} to {}, got {window_log}",
+                ZSTD_WINDOW_LOGS.start(),
+                ZSTD_WINDOW_LOGS.end()
+            ));
+        }
+        if let Some(adaptive) = self.adaptive {
+            let levels = self.algorithm.levels();
+            if !levels.contains(&adaptive.fast_level) {
+                return Err(format!(
+                    "the adaptive fast level must be within {} to {}, got {}",
+                    levels.start(),
+                    levels.end(),
+                    adaptive.fast_level
+                ));
+            }
+            if !ADAPTIVE_WINDOW_BYTES.contains(&adaptive.window_bytes) {
+                return Err(format!(
+                    "adaptive windows range from {} to {} bytes, got {}",
+                    ADAPTIVE_WINDOW_BYTES.start(),
+                    ADAPTIVE_WINDOW_BYTES.end(),
+                    adaptive.window_bytes
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The end of the compressed stream is written once the encoder is finished, on drop at the
+    /// latest.
+    pub fn to_encoder<W: Write + 'static>(&self, w: W) -> io::Result<Box<dyn Encoder>> {
+        self.to_tracked_encoder(Unencoded(w), CompressionMixCounter::default())
+    }
+
+    /// Like `to_encoder`, but finishing it finishes `w` too. Adaptive encoders count how they
+    /// stored the input in `mix`.
+    pub fn to_tracked_encoder<W: Encoder + 'static>(
+        &self,
+        w: W,
+        mix: CompressionMixCounter,
+    ) -> io::Result<Box<dyn Encoder>> {
+        // Levels are validated with the config, clamping keeps the unsigned casts sound
+        let levels = self.algorithm.levels();
+        let level = self.level.clamp(*levels.start(), *levels.end());
+        Ok(match self.algorithm {
+            CompressionAlgorithm::Zstd if let Some(adaptive) = self.adaptive => {
+                Box::new(AdaptiveZstdWriter::new(w, *self, adaptive, mix))
+            }
+            CompressionAlgorithm::Zstd => Box::new(ZstdWriter(self.zstd_encoder(w, level)?)),
+            CompressionAlgorithm::Lz4 => Box::new(Lz4Writer(Some(
+                lz4::EncoderBuilder::new().level(level as u32).build(w)?,
+            ))),
+            CompressionAlgorithm::Gzip => Box::new(flate2::write::GzEncoder::new(
+                w,
+                flate2::Compression::new(level as u32),
+            )),
+            CompressionAlgorithm::Xz => Box::new(xz2::write::XzEncoder::new(w, level as u32)),
+            CompressionAlgorithm::None => Box::new(w),
+        })
+    }
+
+    fn zstd_encoder<W: Write>(
+        &self,
+        w: W,
+        level: i32,
+    ) -> io::Result<zstd::stream::write::Encoder<'static, W>> {
+        let mut encoder = zstd::stream::write::Encoder::new(w, level)?;
+        if self.workers > 0 {
+            encoder.multithread(self.workers)?;
+        }
+        if let Some(window_log) = self.window_log {
+            encoder.window_log(window_log)?;
+        }
+        encoder.long_distance_matching(self.long_distance_matching)?;
+        Ok(encoder)
+    }
+}
+
+const ADAPTIVE_WINDOW_BYTES: RangeInclusive<u32> = 64 * 1024..=256 * 1024 * 1024;
+/// Windows shrinking less than this at the high level move to the fast level.
+const ADAPTIVE_HIGH_LEVEL_MIN_SAVING: f64 = 0.10;
+/// Windows shrinking less than this at the fast level are stored, more than the high level
+/// saving moves them back up.
+const ADAPTIVE_FAST_LEVEL_MIN_SAVING: f64 = 0.03;
+/// While storing, every this many windows is compressed at the fast level to notice when the
+/// data starts shrinking again.
+const ADAPTIVE_PROBE_INTERVAL: u32 = 8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AdaptiveMode {
+    HighLevel,
+    FastLevel,
+    Stored,
+}
+
+/// Writes one zstd frame per window, a decoder reads the concatenated frames as one stream.
+struct AdaptiveZstdWriter<W: Write> {
+    inner: W,
+    compression: Compression,
+    adaptive: AdaptiveCompression,
+    mix: CompressionMixCounter,
+    mode: AdaptiveMode,
+    stored_windows: u32,
+    window: Vec<u8>,
+    frames_written: u64,
+    finished: bool,
+}
+
+impl<W: Write> AdaptiveZstdWriter<W> {
+    fn new(
+        inner: W,
+        compression: Compression,
+        adaptive: AdaptiveCompression,
+        mix: CompressionMixCounter,
+    ) -> Self {
+        AdaptiveZstdWriter {
+            inner,
+            compression,
+            adaptive,
+            mix,
+            mode: AdaptiveMode::HighLevel,
+            stored_windows: 0,
+            window: Vec::with_capacity(adaptive.window_bytes as usize),
+            frames_written: 0,
+            finished: false,
+        }
+    }
+
+    /// Writes the last window. An empty stream still needs a frame to be a valid zstd stream.
+    fn write_last_window(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if !self.window.is_empty() || self.frames_written == 0 {
+            self.write_window()?;
+        }
+        Ok(())
+    }
+
+    fn write_window(&mut self) -> io::Result<()> {
+        let input_bytes = self.window.len() as u64;
+        let probing = self.mode == AdaptiveMode::Stored
+            && self.stored_windows % ADAPTIVE_PROBE_INTERVAL == ADAPTIVE_PROBE_INTERVAL - 1;
+        let level = match self.mode {
+            AdaptiveMode::HighLevel => Some(self.compression.level),
+            AdaptiveMode::FastLevel => Some(self.adaptive.fast_level),
+            AdaptiveMode::Stored if probing => Some(self.adaptive.fast_level),
+            AdaptiveMode::Stored => None,
+        };
+
+        match level {
+            Some(level) => {
+                let mut encoder = self
+                    .compression
+                    .zstd_encoder(Vec::with_capacity(self.window.len()), level)?;
+                encoder.write_all(&self.window)?;
+                let frame = encoder.finish()?;
+                let saving = 1.0 - frame.len() as f64 / self.window.len().max(1) as f64;
+                self.inner.write_all(&frame)?;
+
+                self.mode = match self.mode {
+                    AdaptiveMode::HighLevel => {
+                        self.mix.add_high_level(input_bytes);
+                        if saving < ADAPTIVE_HIGH_LEVEL_MIN_SAVING {
+                            AdaptiveMode::FastLevel
+                        } else {
+                            AdaptiveMode::HighLevel
+                        }
+                    }
+                    AdaptiveMode::FastLevel | AdaptiveMode::Stored => {
+                        self.mix.add_fast_level(input_bytes);
+                        if saving < ADAPTIVE_FAST_LEVEL_MIN_SAVING {
+                            AdaptiveMode::Stored
+                        } else if saving > ADAPTIVE_HIGH_LEVEL_MIN_SAVING {
+                            AdaptiveMode::HighLevel
+                        } else {
+                            AdaptiveMode::FastLevel
+                        }
+                    }
+                };
+            }
+            None => {
+                write_stored_frame(&mut self.inner, &self.window)?;
+                self.mix.add_stored(input_bytes);
+            }
+        }
+        self.stored_windows = match self.mode {
+            AdaptiveMode::Stored => self.stored_windows + 1,
+            _ => 0,
+        };
+
+        self.window.clear();
+        self.frames_written += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for AdaptiveZstdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("zstd stream already finished"));
+        }
+        let free = self.adaptive.window_bytes as usize - self.window.len();
+        let taken = buf.len().min(free);
+        self.window.extend_from_slice(&buf[..taken]);
+        if self.window.len() == self.adaptive.window_bytes as usize {
+            self.write_window()?;
+        }
+        Ok(taken)
+    }
+
+    /// Ends the current window early, frequent flushes make the windows small.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.window.is_empty() {
+            self.write_window()?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl<W: Encoder> Encoder for AdaptiveZstdWriter<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_last_window()?;
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Drop for AdaptiveZstdWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.write_last_window().and_then(|()| self.inner.flush());
+    }
+}
+
+/// Raw blocks are at most 128 KiB, the same as the window this frame declares.
+const STORED_BLOCK_BYTES: usize = 128 * 1024;
+
+/// A zstd frame of raw blocks, it costs no more CPU than copying the data.
+fn write_stored_frame<W: Write>(w: &mut W, data: &[u8]) -> io::Result<()> {
+    // Magic number, then a header without content size or checksum and a 2^17 byte window
+    w.write_all(&0xFD2FB528u32.to_le_bytes())?;
+    w.write_all(&[0x00, 7 << 3])?;
+
+    let mut blocks = data.chunks(STORED_BLOCK_BYTES).peekable();
+    if blocks.peek().is_none() {
+        // Last block flag set, raw block type, no content
+        return w.write_all(&[1, 0, 0]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none() as u32;
+        // Bit 0 marks the last block, bits 1-2 are the raw block type 0, the rest its size
+        let block_header = (block.len() as u32) << 3 | last;
+        w.write_all(&block_header.to_le_bytes()[..3])?;
+        w.write_all(block)?;
+    }
+    Ok(())
+}
+
+/// Finishes the zstd frame on drop like `auto_finish`, but reports errors in `finish`.
+struct ZstdWriter<W: Write>(zstd::stream::write::Encoder<'static, W>);
+
+impl<W: Write> Write for ZstdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Encoder> Encoder for ZstdWriter<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.do_finish()?;
+        self.0.get_mut().finish()
+    }
+}
+
+impl<W: Write> Drop for ZstdWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.0.do_finish();
+    }
+}
+
+/// The lz4 encoder only ends its stream in `finish`, unlike the other encoders it is not
+/// finished when dropped.
+struct Lz4Writer<W: Write>(Option<lz4::Encoder<W>>);
+
+impl<W: Write> Write for Lz4Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Some(encoder) => encoder.write(buf),
+            None => Err(io::Error::other("lz4 stream already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Some(encoder) => encoder.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Encoder> Encoder for Lz4Writer<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        match self.0.take() {
+            Some(encoder) => {
+                let (mut inner, finished) = encoder.finish();
+                finished?;
+                inner.finish()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for Lz4Writer<W> {
+    fn drop(&mut self) {
+        if let Some(encoder) = self.0.take() {
+            let (mut inner, _) = encoder.finish();
+            let _ = inner.flush();
+        }
+    }
+}
+
+impl<W: Encoder> Encoder for flate2::write::GzEncoder<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().finish()
+    }
+}
+
+impl<W: Encoder> Encoder for xz2::write::XzEncoder<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::channels::ChannelWriter;
+    use rand::RngCore;
+    use std::io::Cursor;
+    use std::sync::mpsc;
+
+    #[test]
+    fn every_algorithm_round_trips() {
+        let input = b"data-dance compresses backups, ".repeat(1000);
+
+        for algorithm in [
+            CompressionAlgorithm::Zstd,
+            CompressionAlgorithm::Lz4,
+            CompressionAlgorithm::Gzip,
+            CompressionAlgorithm::Xz,
+            CompressionAlgorithm::None,
+        ] {
+            let levels = algorithm.levels();
+            for level in [*levels.start(), algorithm.default_level(), *levels.end()] {
+                let compression = Compression::new(algorithm, level);
+                let (tx, rx) = mpsc::channel();
+                let mut encoder = compression.to_encoder(ChannelWriter::new(tx)).unwrap();
+                encoder.write_all(&input).unwrap();
+                drop(encoder);
+                let compressed: Vec<u8> = rx.iter().collect();
+                if algorithm != CompressionAlgorithm::None && level == algorithm.default_level() {
+                    assert!(compressed.len() < input.len(), "{compression:?} did not compress");
+                }
+
+                let mut output = Vec::new();
+                algorithm
+                    .to_decoder(Cursor::new(compressed))
+                    .unwrap()
+                    .read_to_end(&mut output)
+                    .unwrap();
+                assert_eq!(output, input, "{compression:?} changed the data");
+            }
         }
     }
 
-    pub fn to_zstd_encoder<W: Write + 'static>(&self, w: W) -> impl Write {
-        zstd::stream::write::Encoder::new(w, self.to_zstd_level())
+    fn encode(compression: Compression, input: &[u8]) -> (Vec<u8>, CompressionMixCounter) {
+        let mix = CompressionMixCounter::default();
+        let (tx, rx) = mpsc::channel();
+        let mut encoder = compression
+            .to_tracked_encoder(Unencoded(ChannelWriter::new(tx)), mix.clone())
+            .unwrap();
+        encoder.write_all(input).unwrap();
+        encoder.finish().unwrap();
+        drop(encoder);
+        (rx.iter().collect(), mix)
+    }
+
+    fn decode(compressed: Vec<u8>) -> Vec<u8> {
+        let mut output = Vec::new();
+        CompressionAlgorithm::Zstd
+            .to_decoder(Cursor::new(compressed))
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn adaptive_compression_backs_off_and_recovers() {
+        let window_bytes = 64 * 1024;
+        let compression = Compression {
+            adaptive: Some(AdaptiveCompression {
+                fast_level: 1,
+                window_bytes,
+            }),
+            ..CompressionLevel::Best.into()
+        };
+        assert!(compression.validate().is_ok());
+
+        // 16 windows of text around 20 windows of noise
+        let text = b"data-dance compresses backups adaptively, ".repeat(25_000);
+        let mut noise = vec![0u8; 20 * window_bytes as usize];
+        rand::rng().fill_bytes(&mut noise);
+        let input = [text.as_slice(), &noise, &text].concat();
+
+        let (compressed, mix) = encode(compression, &input);
+        assert_eq!(decode(compressed), input);
+
+        let mix = mix.value();
+        assert_eq!(
+            mix.high_level_bytes + mix.fast_level_bytes + mix.stored_bytes,
+            input.len() as u64
+        );
+        // Most of the noise is stored, the text after it is probed and compressed again
+        assert!(mix.stored_bytes > 10 * window_bytes as u64);
+        assert!(mix.high_level_bytes > text.len() as u64 + 4 * window_bytes as u64);
+    }
+
+    #[test]
+    fn adaptive_compression_of_empty_stream_decodes() {
+        let compression = Compression {
+            adaptive: Some(AdaptiveCompression::default()),
+            ..CompressionLevel::Fast.into()
+        };
+
+        let (compressed, mix) = encode(compression, b"");
+        assert!(!compressed.is_empty());
+        assert!(decode(compressed).is_empty());
+        assert_eq!(mix.value(), Default::default());
+    }
+
+    #[test]
+    fn stored_frames_decode_as_zstd() {
+        let mut input = vec![0u8; 3 * STORED_BLOCK_BYTES + 17];
+        rand::rng().fill_bytes(&mut input);
+        let mut frame = Vec::new();
+        write_stored_frame(&mut frame, &input).unwrap();
+
+        assert_eq!(frame.len(), input.len() + 6 + 4 * 3);
+        assert_eq!(decode(frame), input);
+    }
+
+    #[test]
+    fn presets_map_to_zstd_levels() {
+        let compression = Compression::from(CompressionLevel::Best);
+        assert_eq!(compression.algorithm, CompressionAlgorithm::Zstd);
+        assert_eq!(compression.level, 15);
+        assert!(compression.validate().is_ok());
+    }
+
+    #[test]
+    fn levels_are_validated() {
+        let compression = Compression::new(CompressionAlgorithm::Gzip, 10);
+        assert!(compression.validate().is_err());
+
+        let compression = Compression {
+            workers: 4,
+            ..Compression::new(CompressionAlgorithm::Xz, 6)
+        };
+        assert!(compression.validate().is_err());
+
+        let compression = Compression {
+            window_log: Some(40),
+            ..Compression::new(CompressionAlgorithm::Zstd, 3)
+        };
+        assert!(compression.validate().is_err());
+
+        let compression = Compression {
+            adaptive: Some(AdaptiveCompression {
+                fast_level: 1,
+                window_bytes: 1024,
+            }),
+            ..Compression::new(CompressionAlgorithm::Zstd, 3)
+        };
+        assert!(compression.validate().is_err());
+    }
+
+    #[test]
+    fn tuned_zstd_round_trips() {
+        let input = b"data-dance compresses backups with threads, ".repeat(100_000);
+        let compression = Compression {
+            workers: 2,
+            window_log: Some(27),
+            long_distance_matching: true,
+            ..Compression::new(CompressionAlgorithm::Zstd, 15)
+        };
+        assert!(compression.validate().is_ok());
+
+        let (tx, rx) = mpsc::channel();
+        let mut encoder = compression.to_encoder(ChannelWriter::new(tx)).unwrap();
+        encoder.write_all(&input).unwrap();
+        drop(encoder);
+        let compressed: Vec<u8> = rx.iter().collect();
+        assert!(compressed.len() < input.len());
+
+        let mut output = Vec::new();
+        CompressionAlgorithm::Zstd
+            .to_decoder(Cursor::new(compressed))
             .unwrap()
-            .auto_finish()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(output, input);
     }
 
-    pub fn to_zstd_decoder<R: Read + 'static>(&self, r: R) -> impl Read {
-        zstd::stream::read::Decoder::new(r).unwrap()
+    #[test]
+    fn settings_deserialize_from_presets_algorithms_and_levels() {
+        let parse = |json: &str| serde_json::from_str::<Compression>(json).unwrap();
+
+        assert_eq!(parse(r#""Best""#), CompressionLevel::Best.into());
+        assert_eq!(parse(r#""None""#), Compression::new(CompressionAlgorithm::Zstd, -5));
+        assert_eq!(parse(r#""Xz""#), CompressionAlgorithm::Xz.into());
+        assert_eq!(
+            parse(r#"{"algorithm": "None", "level": 0}"#),
+            CompressionAlgorithm::None.into()
+        );
+        assert_eq!(
+            parse(r#"{"algorithm": "Lz4", "level": 9}"#),
+            Compression::new(CompressionAlgorithm::Lz4, 9)
+        );
+        assert_eq!(
+            parse(r#"{"algorithm": "Zstd", "level": 19, "workers": 4, "window_log": 27}"#),
+            Compression {
+                workers: 4,
+                window_log: Some(27),
+                ..Compression::new(CompressionAlgorithm::Zstd, 19)
+            }
+        );
     }
 }
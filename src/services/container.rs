@@ -0,0 +1,166 @@
+use crate::objects::{
+    BackupSource, Compression, CompressionAlgorithm, CompressionLevel, ContainerHeader,
+    EncryptionScheme,
+};
+use serde::Deserialize;
+use std::io;
+use std::io::{Cursor, Read};
+
+/// Every file written since the container format starts with this magic.
+pub const MAGIC: &[u8; 8] = b"DDBACKUP";
+/// Version 2 records the exact compression, version 1 only a zstd preset.
+pub const VERSION: u8 = 2;
+
+/// Header length limit, guards against reading garbage as a huge header.
+const MAX_HEADER_LEN: u32 = 64 * 1024;
+
+#[derive(Deserialize)]
+struct ContainerHeaderV1 {
+    compression: CompressionAlgorithm,
+    compression_level: CompressionLevel,
+    encryption: EncryptionScheme,
+    source: BackupSource,
+    parent: Option<u32>,
+}
+
+impl From<ContainerHeaderV1> for ContainerHeader {
+    fn from(value: ContainerHeaderV1) -> Self {
+        ContainerHeader {
+            compression: Compression {
+                algorithm: value.compression,
+                ..value.compression_level.into()
+            },
+            encryption: value.encryption,
+            source: value.source,
+            parent: value.parent,
+        }
+    }
+}
+
+impl ContainerHeader {
+    /// Magic, version, header length and the JSON encoded header.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let json = serde_json::to_vec(self).expect("container headers always serialize");
+
+        let mut bytes = Vec::with_capacity(MAGIC.len() + 5 + json.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&json);
+        bytes
+    }
+
+    /// Reads the header at the start of `reader` together with its raw bytes.
+    /// Files from before the container format have no header and are returned unchanged.
+    pub fn read_from<R: Read + 'static>(
+        mut reader: R,
+    ) -> io::Result<(Option<(ContainerHeader, Vec<u8>)>, Box<dyn Read>)> {
+        let mut magic = Vec::with_capacity(MAGIC.len());
+        (&mut reader)
+            .take(MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        if magic != MAGIC {
+            return Ok((None, Box::new(Cursor::new(magic).chain(reader))));
+        }
+
+        let mut version_and_length = [0u8; 5];
+        reader.read_exact(&mut version_and_length)?;
+        let [version, length @ ..] = version_and_length;
+        if !(1..=VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported backup container version {version}"),
+            ));
+        }
+        let length = u32::from_be_bytes(length);
+        if length > MAX_HEADER_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("backup container header of {length} bytes is too large"),
+            ));
+        }
+        let mut json = vec![0u8; length as usize];
+        reader.read_exact(&mut json)?;
+        let header = match version {
+            1 => serde_json::from_slice::<ContainerHeaderV1>(&json).map(ContainerHeader::from),
+            _ => serde_json::from_slice::<ContainerHeader>(&json),
+        }
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut raw = magic;
+        raw.extend_from_slice(&version_and_length);
+        raw.extend_from_slice(&json);
+        Ok((Some((header, raw)), Box::new(reader)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> ContainerHeader {
+        ContainerHeader {
+            compression: Compression {
+                workers: 2,
+                window_log: Some(24),
+                ..Compression::new(CompressionAlgorithm::Zstd, 12)
+            },
+            encryption: EncryptionScheme::None,
+            source: BackupSource::Tar,
+            parent: Some(7),
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let mut bytes = header().to_bytes();
+        bytes.extend_from_slice(b"payload");
+
+        let (parsed, mut rest) = ContainerHeader::read_from(Cursor::new(bytes.clone())).unwrap();
+        let (parsed, raw) = parsed.unwrap();
+        let mut payload = Vec::new();
+        rest.read_to_end(&mut payload).unwrap();
+
+        assert_eq!(parsed, header());
+        assert_eq!(raw, header().to_bytes());
+        assert_eq!(payload, b"payload");
+    }
+
+    #[test]
+    fn files_without_header_are_passed_through() {
+        let (parsed, mut rest) =
+            ContainerHeader::read_from(Cursor::new(b"legacy data".to_vec())).unwrap();
+        let mut payload = Vec::new();
+        rest.read_to_end(&mut payload).unwrap();
+
+        assert!(parsed.is_none());
+        assert_eq!(payload, b"legacy data");
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let mut bytes = header().to_bytes();
+        bytes[MAGIC.len()] = VERSION + 1;
+
+        assert!(ContainerHeader::read_from(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn version_1_headers_are_read_with_their_preset() {
+        let json = concat!(
+            r#"{"compression":"Zstd","compression_level":"Fast","#,
+            r#""encryption":"None","source":"Tar","parent":null}"#
+        )
+        .as_bytes();
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(1);
+        bytes.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(json);
+
+        let (parsed, _) = ContainerHeader::read_from(Cursor::new(bytes.clone())).unwrap();
+        let (parsed, raw) = parsed.unwrap();
+        assert_eq!(parsed.compression, CompressionLevel::Fast.into());
+        // Authenticated as it was written
+        assert_eq!(raw, bytes);
+    }
+}
@@ -4,7 +4,7 @@ use serde_json::Value;
 use crate::objects::{BackupHistory, Path};
 use crate::services::data_dest::DestService;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::PathBuf;
 
 pub struct BareFsDestService {
@@ -17,6 +17,35 @@ impl BareFsDestService {
     }
 }
 
+impl BareFsDestService {
+    /// Names of the files in the folder that have one of `extensions`.
+    fn list_files_with_extensions(&self, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+        let mut file_names = vec![];
+
+        for entry in self.dest_folder.read_dir()? {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let file_path = entry.path();
+            let Some(file_name) = file_path.file_name() else {
+                continue;
+            };
+            let Some(file_extension) = file_path.extension() else {
+                continue;
+            };
+            if file_path.is_file()
+                && extensions
+                    .iter()
+                    .any(|extension| file_extension == *extension)
+            {
+                file_names.push(PathBuf::from(file_name));
+            }
+        }
+
+        Ok(file_names)
+    }
+}
+
 impl DestService for BareFsDestService {
     fn backup_history(&self) -> std::io::Result<BackupHistory> {
         let file = self.dest_folder.join("backup_history.json");
@@ -38,15 +67,31 @@ impl DestService for BareFsDestService {
         }
     }
 
-    fn get_backup_writer(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Write>> {
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
         let file = self.dest_folder.join(relative_file_path);
         if file.is_file() {
             return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists));
         }
+        if let Some(folder) = file.parent() {
+            std::fs::create_dir_all(folder)?;
+        }
         let handle = File::create(file)?;
         Ok(Box::new(BufWriter::new(handle)))
     }
 
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let file = self.dest_folder.join(relative_file_path);
+        let handle = File::open(file)?;
+        Ok(Box::new(BufReader::new(handle)))
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        Ok(std::fs::metadata(self.dest_folder.join(relative_file_path))?.len())
+    }
+
     fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
         let file = self.dest_folder.join("backup_history.json");
         let handle = File::create(file)?;
@@ -54,27 +99,18 @@ impl DestService for BareFsDestService {
         Ok(())
     }
 
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["bin", "dbin"])
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["chunk", "part"])
+    }
+
     fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
-        let mut all_backup_file_names = vec![];
         let mut deleted_counter = 0;
 
-        for entry in self.dest_folder.read_dir()? {
-            let Ok(entry) = entry else {
-                continue;
-            };
-            let file_path = entry.path();
-            let Some(file_name) = file_path.file_name() else {
-                continue;
-            };
-            let Some(file_extension) = file_path.extension() else {
-                continue;
-            };
-            if file_path.is_file() && file_extension == "bin" || file_extension == "dbin" {
-                all_backup_file_names.push(file_name.to_os_string());
-            }
-        }
-
-        for file_name in all_backup_file_names {
+        for file_name in self.list_backup_files()? {
             if history
                 .entries
                 .iter()
@@ -90,4 +126,12 @@ impl DestService for BareFsDestService {
 
         Ok(deleted_counter)
     }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        std::fs::rename(self.dest_folder.join(from), self.dest_folder.join(to))
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        std::fs::remove_file(self.dest_folder.join(relative_file_path))
+    }
 }
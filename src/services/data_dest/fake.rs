@@ -1,13 +1,15 @@
 use crate::objects::BackupHistory;
 use crate::services::data_dest::DestService;
 use std::cell::RefCell;
-use std::io::{Sink, Write};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Sink, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
 pub struct FakeDestService {
     backup_history: Arc<Mutex<BackupHistory>>,
+    backup_files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
 }
 
 impl FakeDestService {
@@ -18,12 +20,14 @@ impl FakeDestService {
     pub fn new(backup_history: BackupHistory) -> Self {
         Self {
             backup_history: Arc::new(Mutex::new(backup_history)),
+            backup_files: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
     pub fn live_debug_data(&self) -> FakeDestServiceDebugData {
         FakeDestServiceDebugData {
             backup_history: self.backup_history.clone(),
+            backup_files: self.backup_files.clone(),
         }
     }
 }
@@ -35,8 +39,32 @@ impl DestService for FakeDestService {
         Ok(history)
     }
 
-    fn get_backup_writer(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Write>> {
-        Ok(Box::new(Sink::default()) as Box<dyn Write>)
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let mut files_lock = self.backup_files.lock().unwrap();
+        files_lock.insert(relative_file_path.clone(), Vec::new());
+        Ok(Box::new(FakeFileWriter {
+            backup_files: Arc::clone(&self.backup_files),
+            relative_file_path,
+        }))
+    }
+
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let files_lock = self.backup_files.lock().unwrap();
+        match files_lock.get(&relative_file_path) {
+            Some(content) => Ok(Box::new(Cursor::new(content.clone()))),
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        let files_lock = self.backup_files.lock().unwrap();
+        match files_lock.get(&relative_file_path) {
+            Some(content) => Ok(content.len() as u64),
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        }
     }
 
     fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
@@ -46,17 +74,104 @@ impl DestService for FakeDestService {
         Ok(())
     }
 
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let files_lock = self.backup_files.lock().unwrap();
+        Ok(files_lock
+            .keys()
+            .filter(|file| is_top_level_file(file) && !is_chunk_file(file))
+            .cloned()
+            .collect())
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let files_lock = self.backup_files.lock().unwrap();
+        Ok(files_lock
+            .keys()
+            .filter(|file| is_chunk_file(file))
+            .cloned()
+            .collect())
+    }
+
     fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
-        Ok(0)
+        let mut files_lock = self.backup_files.lock().unwrap();
+        let files_before = files_lock.len();
+        files_lock.retain(|file, _| {
+            is_chunk_file(file)
+                || !is_top_level_file(file)
+                || history
+                    .entries
+                    .iter()
+                    .any(|entry| file.as_path() == &*entry.remote_filename)
+        });
+        Ok(files_before - files_lock.len())
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        let mut files_lock = self.backup_files.lock().unwrap();
+        let content = files_lock
+            .remove(&from)
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+        files_lock.insert(to, content);
+        Ok(())
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        let mut files_lock = self.backup_files.lock().unwrap();
+        match files_lock.remove(&relative_file_path) {
+            Some(_) => Ok(()),
+            None => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        }
+    }
+}
+
+/// Like the real destinations, files in folders are not listed as backups.
+fn is_top_level_file(file: &Path) -> bool {
+    file.parent()
+        .is_none_or(|folder| folder.as_os_str().is_empty())
+}
+
+fn is_chunk_file(file: &Path) -> bool {
+    match file.extension() {
+        Some(extension) if extension == "part" => {
+            is_chunk_file(Path::new(file.file_stem().unwrap_or_default()))
+        }
+        Some(extension) => extension == "chunk",
+        None => false,
+    }
+}
+
+struct FakeFileWriter {
+    backup_files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
+    relative_file_path: PathBuf,
+}
+
+impl Write for FakeFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut files_lock = self.backup_files.lock().unwrap();
+        let Some(content) = files_lock.get_mut(&self.relative_file_path) else {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+        };
+        content.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
 pub struct FakeDestServiceDebugData {
     backup_history: Arc<Mutex<BackupHistory>>,
+    backup_files: Arc<Mutex<HashMap<PathBuf, Vec<u8>>>>,
 }
 
 impl FakeDestServiceDebugData {
     pub fn history(&self) -> BackupHistory {
         self.backup_history.lock().unwrap().clone()
     }
+
+    pub fn file(&self, relative_file_path: impl Into<PathBuf>) -> Option<Vec<u8>> {
+        let files_lock = self.backup_files.lock().unwrap();
+        files_lock.get(&relative_file_path.into()).cloned()
+    }
 }
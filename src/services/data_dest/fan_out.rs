@@ -0,0 +1,248 @@
+use crate::config::ReplicationPolicy;
+use std::io::Write;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Buffers a writer may fall behind the others before the stream waits for it.
+const QUEUED_BUFFERS: usize = 16;
+
+/// Writes one stream to several destination writers at once, so it only has to be produced
+/// once. Every writer runs on a thread of its own, so a slow destination does not hold up the
+/// others beyond `QUEUED_BUFFERS`. A failing best-effort writer is dropped and the stream
+/// continues without it, a failing required writer fails the whole stream.
+pub struct FanOutWriter {
+    writers: Vec<FanOutSlot>,
+    failures: FanOutFailures,
+}
+
+struct FanOutSlot {
+    policy: ReplicationPolicy,
+    sender: Option<SyncSender<FanOutMessage>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+enum FanOutMessage {
+    Write(Arc<[u8]>),
+    Flush(SyncSender<()>),
+}
+
+/// The writers that failed, by their position in `FanOutWriter::new`.
+#[derive(Clone, Default)]
+pub struct FanOutFailures(Arc<Mutex<Vec<(usize, std::io::Error)>>>);
+
+impl FanOutFailures {
+    pub fn take(&self) -> Vec<(usize, std::io::Error)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl FanOutWriter {
+    pub fn new(writers: Vec<(ReplicationPolicy, Box<dyn Write + Send>)>) -> Self {
+        let failures = FanOutFailures::default();
+        FanOutWriter {
+            writers: writers
+                .into_iter()
+                .enumerate()
+                .map(|(index, (policy, writer))| {
+                    let (sender, receiver) = sync_channel(QUEUED_BUFFERS);
+                    let failures = failures.clone();
+                    let thread = std::thread::spawn(move || {
+                        if let Err(err) = forward(receiver, writer) {
+                            failures.0.lock().unwrap().push((index, err));
+                        }
+                    });
+                    FanOutSlot {
+                        policy,
+                        sender: Some(sender),
+                        thread: Some(thread),
+                    }
+                })
+                .collect(),
+            failures,
+        }
+    }
+
+    /// Stays readable after the writer was moved into a transfer.
+    pub fn failures(&self) -> FanOutFailures {
+        self.failures.clone()
+    }
+
+    /// Stops sending to the writers that failed, the error of a required one is returned.
+    fn check_failures(&mut self) -> std::io::Result<()> {
+        let failures = self.failures.0.lock().unwrap();
+        for (index, err) in failures.iter() {
+            let slot = &mut self.writers[*index];
+            slot.sender = None;
+            if slot.policy == ReplicationPolicy::Required {
+                return Err(std::io::Error::new(err.kind(), err.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    fn send_to_each_writer(&mut self, message: impl Fn() -> FanOutMessage) {
+        for slot in &mut self.writers {
+            let Some(sender) = &slot.sender else {
+                continue;
+            };
+            // The thread only hangs up after it recorded its failure
+            if sender.send(message()).is_err() {
+                slot.sender = None;
+            }
+        }
+    }
+}
+
+/// Runs on the thread of a writer. Dropping the writer on a failure gives up its upload.
+fn forward(
+    receiver: Receiver<FanOutMessage>,
+    mut writer: Box<dyn Write + Send>,
+) -> std::io::Result<()> {
+    for message in receiver {
+        match message {
+            FanOutMessage::Write(buf) => writer.write_all(&buf)?,
+            FanOutMessage::Flush(done) => {
+                writer.flush()?;
+                let _ = done.send(());
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Write for FanOutWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.check_failures()?;
+        let buf: Arc<[u8]> = Arc::from(buf);
+        self.send_to_each_writer(|| FanOutMessage::Write(Arc::clone(&buf)));
+        Ok(buf.len())
+    }
+
+    /// Waits until every writer wrote and flushed what was sent to it.
+    fn flush(&mut self) -> std::io::Result<()> {
+        let (done_sender, done_receiver) = sync_channel(self.writers.len());
+        self.send_to_each_writer(|| FanOutMessage::Flush(done_sender.clone()));
+        drop(done_sender);
+        // Ends once every thread answered or hung up
+        for () in done_receiver {}
+        self.check_failures()
+    }
+}
+
+impl Drop for FanOutWriter {
+    /// Some destinations only complete an upload once its writer is dropped, that has to have
+    /// happened when the stream is done.
+    fn drop(&mut self) {
+        for slot in &mut self.writers {
+            slot.sender = None;
+        }
+        for slot in &mut self.writers {
+            if let Some(thread) = slot.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct BrokenWriter;
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn best_effort_failures_do_not_stop_the_stream() {
+        let first = SharedWriter::default();
+        let second = SharedWriter::default();
+        let mut writer = FanOutWriter::new(vec![
+            (ReplicationPolicy::Required, Box::new(first.clone())),
+            (ReplicationPolicy::BestEffort, Box::new(BrokenWriter)),
+            (ReplicationPolicy::BestEffort, Box::new(second.clone())),
+        ]);
+        let failures = writer.failures();
+
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"def").unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(*first.0.lock().unwrap(), b"abcdef");
+        assert_eq!(*second.0.lock().unwrap(), b"abcdef");
+        let failures = failures.take();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, 1);
+        assert_eq!(failures[0].1.kind(), std::io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn required_failures_stop_the_stream() {
+        let mut writer = FanOutWriter::new(vec![
+            (ReplicationPolicy::Required, Box::new(SharedWriter::default())),
+            (ReplicationPolicy::Required, Box::new(BrokenWriter)),
+        ]);
+
+        writer.write_all(b"abc").unwrap();
+        let err = writer.flush().unwrap_err();
+
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(writer.failures().take()[0].0, 1);
+    }
+
+    /// Blocks until it is released.
+    struct StalledWriter(Mutex<std::sync::mpsc::Receiver<()>>);
+
+    impl Write for StalledWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().recv().unwrap();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writers_do_not_wait_for_each_other() {
+        let (release, stalled) = std::sync::mpsc::channel();
+        let first = SharedWriter::default();
+        let mut writer = FanOutWriter::new(vec![
+            (ReplicationPolicy::Required, Box::new(StalledWriter(Mutex::new(stalled)))),
+            (ReplicationPolicy::Required, Box::new(first.clone())),
+        ]);
+
+        writer.write_all(b"abc").unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while first.0.lock().unwrap().is_empty() {
+            assert!(std::time::Instant::now() < deadline, "the writers ran one after another");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        release.send(()).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(*first.0.lock().unwrap(), b"abc");
+    }
+}
@@ -1,17 +1,127 @@
 pub mod bare_fs;
 pub mod fake;
+pub mod fan_out;
+pub mod s3;
+pub mod sftp;
 pub mod ssh;
+pub mod webdav;
 
+use crate::config::{DataDanceConfiguration, RemoteDestination};
 use crate::objects;
+use crate::services::data_dest::bare_fs::BareFsDestService;
+use crate::services::data_dest::fake::FakeDestService;
+use crate::services::data_dest::s3::{
+    DEFAULT_PART_SIZE, MAX_COPY_OBJECT_BYTES, S3DestService, S3Options,
+};
+use crate::services::data_dest::sftp::{SftpDestService, SftpOptions};
+use crate::services::data_dest::ssh::SshDestService;
+use crate::services::data_dest::webdav::{WebDavDestService, WebDavOptions};
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 
 pub trait DestService {
     fn backup_history(&self) -> io::Result<objects::BackupHistory>;
 
-    fn get_backup_writer(&self, relative_file_path: PathBuf) -> io::Result<Box<dyn Write>>;
+    /// Missing folders of `relative_file_path` are created first.
+    fn get_backup_writer(&self, relative_file_path: PathBuf) -> io::Result<Box<dyn Write + Send>>;
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> io::Result<Box<dyn Read>>;
+    /// Size in bytes of a backup file, without downloading it.
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> io::Result<u64>;
+    /// Fails unless the backup file holds the `written_bytes` sent to its writer. S3 and WebDAV
+    /// complete an upload when its writer is dropped, failing to do so only shows up here.
+    fn verify_backup_file_size(
+        &self,
+        relative_file_path: PathBuf,
+        written_bytes: u64,
+    ) -> io::Result<()> {
+        let size = self.backup_file_size(relative_file_path.clone())?;
+        if size != written_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} holds {size} bytes, but {written_bytes} bytes were uploaded",
+                    relative_file_path.display()
+                ),
+            ));
+        }
+        Ok(())
+    }
     fn set_backup_history(&self, history: objects::BackupHistory) -> io::Result<()>;
 
+    /// Lists the `.bin` and `.dbin` backup files present on the remote.
+    fn list_backup_files(&self) -> io::Result<Vec<PathBuf>>;
+    /// Lists the `.chunk` files of the chunk repository, see `ChunkStore`, and the `.chunk.part`
+    /// files of interrupted chunk uploads.
+    fn list_chunk_files(&self) -> io::Result<Vec<PathBuf>>;
     fn clear_orphaned_backups(&self, history: &objects::BackupHistory) -> io::Result<usize>;
+
+    /// Atomically replaces the file at `to` with the one at `from`.
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> io::Result<()>;
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> io::Result<()>;
+}
+
+pub fn dest_service_from_config(
+    config: &DataDanceConfiguration,
+) -> io::Result<Box<dyn DestService + Send>> {
+    dest_service_from_destination(config.remote_storage.dest.clone())
+}
+
+/// Fails if an S3 or WebDAV client cannot be set up, their URLs are checked by `read_config`.
+pub fn dest_service_from_destination(
+    dest: RemoteDestination,
+) -> io::Result<Box<dyn DestService + Send>> {
+    Ok(match dest {
+        RemoteDestination::Local { folder } => Box::new(BareFsDestService::new(folder)),
+        RemoteDestination::Ssh {
+            hostname,
+            port,
+            username,
+            folder,
+        } => Box::new(SshDestService::new(port, hostname, username, folder)),
+        RemoteDestination::Sftp {
+            username,
+            hostname,
+            port,
+            folder,
+            identity_file,
+            identity_passphrase,
+            known_hosts,
+        } => Box::new(SftpDestService::new(SftpOptions {
+            username,
+            hostname,
+            port,
+            folder,
+            identity_file,
+            identity_passphrase,
+            known_hosts,
+        })),
+        RemoteDestination::S3 {
+            bucket,
+            prefix,
+            region,
+            endpoint,
+            access_key_id,
+            secret_access_key,
+        } => Box::new(S3DestService::new(S3Options {
+            bucket,
+            prefix,
+            region,
+            endpoint,
+            access_key_id,
+            secret_access_key,
+            part_size: DEFAULT_PART_SIZE,
+            max_copy_object_bytes: MAX_COPY_OBJECT_BYTES,
+        })?),
+        RemoteDestination::WebDav {
+            url,
+            username,
+            password,
+        } => Box::new(WebDavDestService::new(WebDavOptions {
+            url,
+            username,
+            password,
+        })?),
+        RemoteDestination::Fake => Box::new(FakeDestService::empty()),
+    })
 }
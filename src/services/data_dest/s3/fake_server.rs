@@ -0,0 +1,408 @@
+//! An in-memory S3 server with just the API `S3DestService` uses. Every request has to carry a
+//! valid signature for the fake credentials.
+use super::signing::{self, Credentials, SigningRequest, sha256_hex};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Request, Response, Server};
+
+pub const ACCESS_KEY_ID: &str = "fake-access-key";
+pub const SECRET_ACCESS_KEY: &str = "fake-secret-key";
+pub const REGION: &str = "fake-region-1";
+
+#[derive(Default)]
+struct FakeBucket {
+    objects: BTreeMap<String, Vec<u8>>,
+    uploads: HashMap<String, BTreeMap<u32, Vec<u8>>>,
+    next_upload_id: u32,
+    /// Method and query parameter names of every request, in order.
+    requests: Vec<String>,
+    /// Methods whose object responses carry no ETag.
+    omitted_etags: Vec<&'static str>,
+}
+
+pub struct FakeS3Server {
+    pub endpoint: String,
+    bucket_name: String,
+    bucket: Arc<Mutex<FakeBucket>>,
+    server: Arc<Server>,
+}
+
+impl FakeS3Server {
+    /// Listings return at most `page_size` keys per request.
+    pub fn start(bucket_name: &str, page_size: usize) -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let endpoint = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let bucket = Arc::new(Mutex::new(FakeBucket::default()));
+
+        let handler = FakeS3Handler {
+            bucket_name: bucket_name.to_string(),
+            bucket: Arc::clone(&bucket),
+            page_size,
+        };
+        let server_handle = Arc::clone(&server);
+        thread::spawn(move || {
+            for request in server_handle.incoming_requests() {
+                handler.handle(request);
+            }
+        });
+
+        FakeS3Server {
+            endpoint,
+            bucket_name: bucket_name.to_string(),
+            bucket,
+            server,
+        }
+    }
+
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.bucket.lock().unwrap().objects.get(key).cloned()
+    }
+
+    /// Writes like another host would.
+    pub fn put_object(&self, key: &str, data: &[u8]) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.objects.insert(key.to_string(), data.to_vec());
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.bucket
+            .lock()
+            .unwrap()
+            .objects
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.bucket.lock().unwrap().requests.clone()
+    }
+
+    /// Answers requests with `methods` without an ETag, like some S3 compatible servers do.
+    pub fn omit_etags(&self, methods: &[&'static str]) {
+        self.bucket.lock().unwrap().omitted_etags = methods.to_vec();
+    }
+
+    pub fn open_uploads(&self) -> usize {
+        self.bucket.lock().unwrap().uploads.len()
+    }
+
+    pub fn bucket_name(&self) -> &str {
+        &self.bucket_name
+    }
+}
+
+impl Drop for FakeS3Server {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+struct FakeS3Handler {
+    bucket_name: String,
+    bucket: Arc<Mutex<FakeBucket>>,
+    page_size: usize,
+}
+
+type FakeResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+impl FakeS3Handler {
+    fn handle(&self, mut request: Request) {
+        let mut body = vec![];
+        request.as_reader().read_to_end(&mut body).unwrap();
+        let response = self.route(&request, body);
+        let _ = request.respond(response);
+    }
+
+    fn route(&self, request: &Request, body: Vec<u8>) -> FakeResponse {
+        let method = request.method().to_string();
+        let (raw_path, raw_query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let query: Vec<(String, String)> = raw_query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (percent_decode(name), percent_decode(value))
+            })
+            .collect();
+        let header = |name: &str| {
+            request
+                .headers()
+                .iter()
+                .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+                .map(|header| header.value.as_str().to_string())
+        };
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        if let Err(message) = verify_signature(request, raw_path, &query, &body) {
+            return error(403, "SignatureDoesNotMatch", &message);
+        }
+        let mut bucket = self.bucket.lock().unwrap();
+        let query_names: Vec<&str> = query.iter().map(|(name, _)| name.as_str()).collect();
+        bucket
+            .requests
+            .push(format!("{method} {}", query_names.join("&")));
+
+        let path = percent_decode(raw_path);
+        let Some(rest) = path.strip_prefix(&format!("/{}", self.bucket_name)) else {
+            return error(404, "NoSuchBucket", "The specified bucket does not exist");
+        };
+        let key = rest.trim_start_matches('/').to_string();
+        let omit_etag = bucket.omitted_etags.contains(&method.as_str());
+        let etag_header = |data: &[u8]| (!omit_etag).then(|| etag_header(data));
+
+        match (method.as_str(), key.is_empty()) {
+            ("GET", true) => self.list(&bucket, &param("prefix").unwrap_or_default(), param),
+            ("GET", false) | ("HEAD", false) => match bucket.objects.get(&key) {
+                Some(data) => with_header(Response::from_data(data.clone()), etag_header(data)),
+                None => error(404, "NoSuchKey", "The specified key does not exist."),
+            },
+            ("PUT", false) => {
+                if let Some(source) = header("x-amz-copy-source") {
+                    let source = percent_decode(&source);
+                    let source_key = source
+                        .trim_start_matches('/')
+                        .strip_prefix(&format!("{}/", self.bucket_name))
+                        .unwrap_or_default();
+                    let Some(data) = bucket.objects.get(source_key).cloned() else {
+                        return error(404, "NoSuchKey", "The copy source does not exist.");
+                    };
+                    if let (Some(part), Some(upload_id)) = (param("partNumber"), param("uploadId"))
+                    {
+                        let range = header("x-amz-copy-source-range").unwrap_or_default();
+                        let (start, end) =
+                            range.trim_start_matches("bytes=").split_once('-').unwrap();
+                        let part_data =
+                            data[start.parse::<usize>().unwrap()..=end.parse().unwrap()].to_vec();
+                        let etag = etag(&part_data);
+                        let Some(upload) = bucket.uploads.get_mut(&upload_id) else {
+                            return error(404, "NoSuchUpload", "The upload does not exist.");
+                        };
+                        upload.insert(part.parse().unwrap(), part_data);
+                        return Response::from_string(format!(
+                            "<CopyPartResult><ETag>{etag}</ETag></CopyPartResult>"
+                        ));
+                    }
+                    let etag = etag(&data);
+                    bucket.objects.insert(key, data);
+                    return Response::from_string(format!(
+                        "<CopyObjectResult><ETag>{etag}</ETag></CopyObjectResult>"
+                    ));
+                }
+                if let (Some(part), Some(upload_id)) = (param("partNumber"), param("uploadId")) {
+                    let Some(upload) = bucket.uploads.get_mut(&upload_id) else {
+                        return error(404, "NoSuchUpload", "The upload does not exist.");
+                    };
+                    let header = etag_header(&body);
+                    upload.insert(part.parse().unwrap(), body);
+                    return with_header(Response::from_data(vec![]), header);
+                }
+
+                let existing = bucket.objects.get(&key);
+                let if_match_failed = header("if-match")
+                    .is_some_and(|expected| existing.map(|data| etag(data)) != Some(expected));
+                let if_none_match_failed = header("if-none-match").is_some() && existing.is_some();
+                if if_match_failed || if_none_match_failed {
+                    return error(
+                        412,
+                        "PreconditionFailed",
+                        "At least one of the pre-conditions you specified did not hold",
+                    );
+                }
+                let header = etag_header(&body);
+                bucket.objects.insert(key, body);
+                with_header(Response::from_data(vec![]), header)
+            }
+            ("POST", false) if param("uploads").is_some() => {
+                bucket.next_upload_id += 1;
+                let upload_id = format!("upload-{}", bucket.next_upload_id);
+                bucket.uploads.insert(upload_id.clone(), BTreeMap::new());
+                Response::from_string(format!(
+                    "<InitiateMultipartUploadResult><UploadId>{upload_id}</UploadId>\
+                    </InitiateMultipartUploadResult>"
+                ))
+            }
+            ("POST", false) if let Some(upload_id) = param("uploadId") => {
+                let Some(parts) = bucket.uploads.remove(&upload_id) else {
+                    return error(404, "NoSuchUpload", "The upload does not exist.");
+                };
+                let body = String::from_utf8(body).unwrap();
+                let document = roxmltree::Document::parse(&body).unwrap();
+                let listed: Vec<(u32, String)> = document
+                    .descendants()
+                    .filter(|node| node.tag_name().name() == "Part")
+                    .map(|part| {
+                        let text = |name: &str| {
+                            part.children()
+                                .find(|child| child.tag_name().name() == name)
+                                .and_then(|child| child.text())
+                                .unwrap_or_default()
+                                .to_string()
+                        };
+                        (
+                            text("PartNumber").parse().unwrap(),
+                            text("ETag").replace("&quot;", "\""),
+                        )
+                    })
+                    .collect();
+                let uploaded: Vec<(u32, String)> = parts
+                    .iter()
+                    .map(|(number, data)| (*number, etag(data)))
+                    .collect();
+                if listed != uploaded {
+                    return error(400, "InvalidPart", "The listed parts were not uploaded.");
+                }
+                let data: Vec<u8> = parts.into_values().flatten().collect();
+                let etag = etag(&data);
+                bucket.objects.insert(key, data);
+                Response::from_string(format!(
+                    "<CompleteMultipartUploadResult><ETag>{etag}</ETag>\
+                    </CompleteMultipartUploadResult>"
+                ))
+            }
+            ("DELETE", false) => {
+                match param("uploadId") {
+                    Some(upload_id) => bucket.uploads.remove(&upload_id),
+                    None => bucket.objects.remove(&key).map(|_| BTreeMap::new()),
+                };
+                Response::from_data(vec![]).with_status_code(204)
+            }
+            _ => error(405, "MethodNotAllowed", "Not supported by the fake server"),
+        }
+    }
+
+    fn list(
+        &self,
+        bucket: &FakeBucket,
+        prefix: &str,
+        param: impl Fn(&str) -> Option<String>,
+    ) -> FakeResponse {
+        let start_after = param("continuation-token").unwrap_or_default();
+        let delimited = param("delimiter").is_some();
+        let matching: Vec<&String> = bucket
+            .objects
+            .keys()
+            .filter(|key| key.starts_with(prefix) && key.as_str() > start_after.as_str())
+            .filter(|key| !delimited || !key[prefix.len()..].contains('/'))
+            .collect();
+
+        let page = &matching[..matching.len().min(self.page_size)];
+        let truncated = matching.len() > page.len();
+        let contents: String = page
+            .iter()
+            .map(|key| format!("<Contents><Key>{key}</Key></Contents>"))
+            .collect();
+        let token = match (truncated, page.last()) {
+            (true, Some(last)) => format!("<NextContinuationToken>{last}</NextContinuationToken>"),
+            _ => String::new(),
+        };
+        Response::from_string(format!(
+            "<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+            <IsTruncated>{truncated}</IsTruncated>{contents}{token}</ListBucketResult>"
+        ))
+    }
+}
+
+fn verify_signature(
+    request: &Request,
+    raw_path: &str,
+    query: &[(String, String)],
+    body: &[u8],
+) -> Result<(), String> {
+    let header = |name: &str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str().to_string())
+    };
+    let authorization = header("authorization").ok_or("no authorization header")?;
+    let amz_date = header("x-amz-date").ok_or("no x-amz-date header")?;
+    let payload_sha256 = header("x-amz-content-sha256").ok_or("no payload hash")?;
+    if payload_sha256 != sha256_hex(body) {
+        return Err("the payload hash does not match the body".to_string());
+    }
+
+    let signed_header_names = authorization
+        .split("SignedHeaders=")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .ok_or("no signed headers")?;
+    let signed_headers: Vec<(String, String)> = signed_header_names
+        .split(';')
+        .map(|name| (name.to_string(), header(name).unwrap_or_default()))
+        .collect();
+
+    let expected = signing::authorization(
+        &Credentials {
+            access_key_id: ACCESS_KEY_ID,
+            secret_access_key: SECRET_ACCESS_KEY,
+            region: REGION,
+        },
+        &SigningRequest {
+            method: &request.method().to_string(),
+            path: raw_path,
+            query,
+            headers: &signed_headers,
+            payload_sha256: &payload_sha256,
+        },
+        &amz_date,
+    );
+    if expected == authorization {
+        Ok(())
+    } else {
+        Err(
+            "The request signature we calculated does not match the signature you provided."
+                .to_string(),
+        )
+    }
+}
+
+fn etag(data: &[u8]) -> String {
+    format!("\"{}\"", &sha256_hex(data)[..32])
+}
+
+fn with_header(response: FakeResponse, header: Option<Header>) -> FakeResponse {
+    match header {
+        Some(header) => response.with_header(header),
+        None => response,
+    }
+}
+
+fn etag_header(data: &[u8]) -> Header {
+    Header::from_bytes("ETag", etag(data)).unwrap()
+}
+
+fn error(status: u16, code: &str, message: &str) -> FakeResponse {
+    Response::from_string(format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+        <Error><Code>{code}</Code><Message>{message}</Message></Error>"
+    ))
+    .with_status_code(status)
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%'
+            && let Some(hex) = value.get(index + 1..index + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            decoded.push(byte);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
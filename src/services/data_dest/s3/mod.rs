@@ -0,0 +1,666 @@
+mod signing;
+
+use poem_openapi::types::{ParseFromJSON, ToJSON};
+use serde_json::Value;
+
+use crate::objects::{BackupHistory, Path, SensitiveString};
+use crate::services::data_dest::DestService;
+use signing::{Credentials, EMPTY_PAYLOAD_SHA256, SigningRequest, sha256_hex, uri_encode};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+pub const DEFAULT_PART_SIZE: usize = 16 * 1024 * 1024;
+/// The largest object a single CopyObject request may copy.
+pub const MAX_COPY_OBJECT_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+/// Parts grow by this factor every so many parts to stay below the limit of 10000 parts.
+const PARTS_PER_SIZE: usize = 1000;
+const HISTORY_KEY: &str = "backup_history.json";
+
+#[derive(Clone)]
+pub struct S3Options {
+    pub bucket: String,
+    pub prefix: String,
+    pub region: String,
+    pub endpoint: String,
+    pub access_key_id: String,
+    pub secret_access_key: SensitiveString,
+    /// Size of the first multipart parts, it doubles every 1000 parts.
+    pub part_size: usize,
+    /// Larger objects are copied part by part, in parts of this size.
+    pub max_copy_object_bytes: u64,
+}
+
+/// What `backup_history.json` looked like when it was last read or written, the next write
+/// only succeeds if it still does. Only a history that was never read is written unconditionally.
+enum HistoryVersion {
+    Unread,
+    Missing,
+    ETag(String),
+}
+
+/// Objects are addressed path-style (`endpoint/bucket/key`), as MinIO and most S3 compatible
+/// storage expect.
+pub struct S3DestService {
+    client: Arc<S3Client>,
+    history_version: Mutex<HistoryVersion>,
+}
+
+impl S3DestService {
+    pub fn new(options: S3Options) -> std::io::Result<Self> {
+        let tls_connector = native_tls::TlsConnector::new().map_err(std::io::Error::other)?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(Arc::new(tls_connector))
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(300))
+            .timeout_write(Duration::from_secs(300))
+            .build();
+        let endpoint = url::Url::parse(&options.endpoint).map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("s3 endpoint {}: {err}", options.endpoint),
+            )
+        })?;
+
+        Ok(S3DestService {
+            client: Arc::new(S3Client {
+                options,
+                endpoint,
+                agent,
+            }),
+            history_version: Mutex::new(HistoryVersion::Unread),
+        })
+    }
+
+    fn list_keys(&self) -> std::io::Result<Vec<String>> {
+        let client = &self.client;
+        let mut keys = vec![];
+        let mut continuation_token = None;
+        loop {
+            let mut query = vec![
+                ("list-type".to_string(), "2".to_string()),
+                ("prefix".to_string(), client.key("".as_ref())),
+                ("delimiter".to_string(), "/".to_string()),
+            ];
+            if let Some(token) = continuation_token.take() {
+                query.push(("continuation-token".to_string(), token));
+            }
+            let response = client.request("GET", None, &query, &[], &[])?;
+            let body = response.into_string()?;
+            let document = parse_xml(&body)?;
+
+            keys.extend(xml_texts(&document, "Key"));
+            let truncated = xml_texts(&document, "IsTruncated").first().map(String::as_str)
+                == Some("true");
+            continuation_token = xml_texts(&document, "NextContinuationToken").pop();
+            if !truncated || continuation_token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+}
+
+struct S3Client {
+    options: S3Options,
+    endpoint: url::Url,
+    agent: ureq::Agent,
+}
+
+impl S3Client {
+    fn key(&self, relative_file_path: &std::path::Path) -> String {
+        let prefix = &self.options.prefix;
+        let relative = relative_file_path.to_string_lossy();
+        if prefix.is_empty() || prefix.ends_with('/') {
+            format!("{prefix}{relative}")
+        } else {
+            format!("{prefix}/{relative}")
+        }
+    }
+
+    /// Without a key the request goes to the bucket itself.
+    fn request(
+        &self,
+        method: &'static str,
+        key: Option<&str>,
+        query: &[(String, String)],
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<ureq::Response, S3Error> {
+        let resource = match key {
+            Some(key) => format!("{}/{key}", self.options.bucket),
+            None => self.options.bucket.clone(),
+        };
+        let path = format!(
+            "{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            uri_encode(&resource, false)
+        );
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{port}", self.endpoint.host_str().unwrap_or_default()),
+            None => self.endpoint.host_str().unwrap_or_default().to_string(),
+        };
+        let payload_sha256 = if body.is_empty() {
+            EMPTY_PAYLOAD_SHA256.to_string()
+        } else {
+            sha256_hex(body)
+        };
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+        let mut signed_headers: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.clone()))
+            .collect();
+        signed_headers.push(("host".to_string(), host.clone()));
+        signed_headers.push(("x-amz-content-sha256".to_string(), payload_sha256.clone()));
+        signed_headers.push(("x-amz-date".to_string(), amz_date.clone()));
+        let authorization = signing::authorization(
+            &Credentials {
+                access_key_id: &self.options.access_key_id,
+                secret_access_key: self.options.secret_access_key.insecure(),
+                region: &self.options.region,
+            },
+            &SigningRequest {
+                method,
+                path: &path,
+                query,
+                headers: &signed_headers,
+                payload_sha256: &payload_sha256,
+            },
+            &amz_date,
+        );
+
+        let mut url = format!("{}://{host}{path}", self.endpoint.scheme());
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&signing::canonical_query(query));
+        }
+        let mut request = self.agent.request(method, &url);
+        for (name, value) in &signed_headers {
+            request = request.set(name, value);
+        }
+        request = request.set("authorization", &authorization);
+
+        let result = if body.is_empty() && method != "PUT" && method != "POST" {
+            request.call()
+        } else {
+            request.send_bytes(body)
+        };
+        result.map_err(|err| match err {
+            ureq::Error::Status(status, response) => {
+                let body = response.into_string().unwrap_or_default();
+                let (code, message) = match parse_xml(&body) {
+                    Ok(document) => (
+                        xml_texts(&document, "Code").pop().unwrap_or_default(),
+                        xml_texts(&document, "Message").pop().unwrap_or_default(),
+                    ),
+                    Err(_) => (String::new(), body),
+                };
+                S3Error::Status {
+                    method,
+                    resource,
+                    status,
+                    code,
+                    message,
+                }
+            }
+            ureq::Error::Transport(transport) => S3Error::Transport {
+                method,
+                resource,
+                message: transport.to_string(),
+            },
+        })
+    }
+
+    fn object_size(&self, key: &str) -> std::io::Result<u64> {
+        let response = self.request("HEAD", Some(key), &[], &[], &[])?;
+        response
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| std::io::Error::other(format!("s3 HEAD {key}: no content length")))
+    }
+
+    fn initiate_multipart_upload(&self, key: &str) -> std::io::Result<String> {
+        let query = [("uploads".to_string(), String::new())];
+        let body = self.request("POST", Some(key), &query, &[], &[])?.into_string()?;
+        xml_texts(&parse_xml(&body)?, "UploadId")
+            .pop()
+            .ok_or_else(|| std::io::Error::other(format!("s3 POST {key}: no upload id")))
+    }
+
+    fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: usize,
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> std::io::Result<String> {
+        let query = [
+            ("partNumber".to_string(), part_number.to_string()),
+            ("uploadId".to_string(), upload_id.to_string()),
+        ];
+        let response = self.request("PUT", Some(key), &query, headers, body)?;
+        if let Some(etag) = response.header("etag") {
+            return Ok(etag.to_string());
+        }
+        // Copied parts return their ETag in the body
+        let body = response.into_string()?;
+        xml_texts(&parse_xml(&body)?, "ETag")
+            .pop()
+            .ok_or_else(|| std::io::Error::other(format!("s3 PUT {key}: no part etag")))
+    }
+
+    fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        etags: &[String],
+    ) -> std::io::Result<()> {
+        let parts: String = etags
+            .iter()
+            .enumerate()
+            .map(|(index, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    index + 1,
+                    xml_escape(etag)
+                )
+            })
+            .collect();
+        let body = format!("<CompleteMultipartUpload>{parts}</CompleteMultipartUpload>");
+        let query = [("uploadId".to_string(), upload_id.to_string())];
+        let response = self.request("POST", Some(key), &query, &[], body.as_bytes())?;
+        // Completion can fail after the status line was already sent as 200
+        check_body_for_error(key, &response.into_string()?)
+    }
+
+    fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> std::io::Result<()> {
+        let query = [("uploadId".to_string(), upload_id.to_string())];
+        self.request("DELETE", Some(key), &query, &[], &[])?;
+        Ok(())
+    }
+
+    fn copy_object(&self, from_key: &str, to_key: &str) -> std::io::Result<()> {
+        let source = format!(
+            "/{}",
+            uri_encode(&format!("{}/{from_key}", self.options.bucket), false)
+        );
+        let size = self.object_size(from_key)?;
+        let max_copy_bytes = self.options.max_copy_object_bytes;
+
+        if size <= max_copy_bytes {
+            let headers = [("x-amz-copy-source", source)];
+            let response = self.request("PUT", Some(to_key), &[], &headers, &[])?;
+            return check_body_for_error(to_key, &response.into_string()?);
+        }
+
+        let upload_id = self.initiate_multipart_upload(to_key)?;
+        let copied: std::io::Result<Vec<String>> = (0..size.div_ceil(max_copy_bytes))
+            .map(|index| {
+                let start = index * max_copy_bytes;
+                let end = (start + max_copy_bytes).min(size) - 1;
+                let headers = [
+                    ("x-amz-copy-source", source.clone()),
+                    ("x-amz-copy-source-range", format!("bytes={start}-{end}")),
+                ];
+                self.upload_part(to_key, &upload_id, index as usize + 1, &headers, &[])
+            })
+            .collect();
+        let result = copied
+            .and_then(|etags| self.complete_multipart_upload(to_key, &upload_id, &etags));
+        if result.is_err() {
+            let _ = self.abort_multipart_upload(to_key, &upload_id);
+        }
+        result
+    }
+}
+
+#[derive(Debug, Error)]
+enum S3Error {
+    #[error("s3 {method} {resource}: {status} {code}: {message}")]
+    Status {
+        method: &'static str,
+        resource: String,
+        status: u16,
+        code: String,
+        message: String,
+    },
+    #[error("s3 {method} {resource}: {message}")]
+    Transport {
+        method: &'static str,
+        resource: String,
+        message: String,
+    },
+}
+
+impl From<S3Error> for std::io::Error {
+    fn from(err: S3Error) -> Self {
+        let kind = match &err {
+            S3Error::Status { status: 404, .. } => std::io::ErrorKind::NotFound,
+            S3Error::Status { status: 403, .. } => std::io::ErrorKind::PermissionDenied,
+            S3Error::Status { .. } => std::io::ErrorKind::Other,
+            S3Error::Transport { .. } => std::io::ErrorKind::ConnectionAborted,
+        };
+        std::io::Error::new(kind, err.to_string())
+    }
+}
+
+fn parse_xml(body: &str) -> std::io::Result<roxmltree::Document<'_>> {
+    roxmltree::Document::parse(body).map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("s3 returned malformed XML: {err}"),
+        )
+    })
+}
+
+/// Texts of all elements with the name, namespaces ignored.
+fn xml_texts(document: &roxmltree::Document, name: &str) -> Vec<String> {
+    document
+        .descendants()
+        .filter(|node| node.tag_name().name() == name)
+        .map(|node| node.text().unwrap_or_default().to_string())
+        .collect()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn check_body_for_error(key: &str, body: &str) -> std::io::Result<()> {
+    if body.is_empty() {
+        return Ok(());
+    }
+    let document = parse_xml(body)?;
+    if document.root_element().tag_name().name() != "Error" {
+        return Ok(());
+    }
+    Err(std::io::Error::other(format!(
+        "s3 {key}: {}: {}",
+        xml_texts(&document, "Code").pop().unwrap_or_default(),
+        xml_texts(&document, "Message").pop().unwrap_or_default()
+    )))
+}
+
+/// Sends full parts as they fill up. The last part, or the whole object if it fits one part,
+/// is sent when the writer is dropped. Failing that is only logged, uploads are checked with
+/// `verify_backup_file_size`.
+struct S3Writer {
+    client: Arc<S3Client>,
+    key: String,
+    buffer: Vec<u8>,
+    upload_id: Option<String>,
+    etags: Vec<String>,
+    failed: bool,
+}
+
+impl S3Writer {
+    fn part_size(&self) -> usize {
+        let doublings = (self.etags.len() / PARTS_PER_SIZE).min(16) as u32;
+        self.client.options.part_size << doublings
+    }
+
+    fn upload_buffered_part(&mut self) -> std::io::Result<()> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let upload_id = self.client.initiate_multipart_upload(&self.key)?;
+                self.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+        let etag = self.client.upload_part(
+            &self.key,
+            &upload_id,
+            self.etags.len() + 1,
+            &[],
+            &self.buffer,
+        )?;
+        self.etags.push(etag);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.upload_id.is_none() {
+            self.client
+                .request("PUT", Some(&self.key), &[], &[], &self.buffer)?;
+            return Ok(());
+        }
+        if !self.buffer.is_empty() {
+            self.upload_buffered_part()?;
+        }
+        let upload_id = self.upload_id.clone().unwrap_or_default();
+        self.client
+            .complete_multipart_upload(&self.key, &upload_id, &self.etags)
+    }
+
+    fn abort(&mut self) {
+        if let Some(upload_id) = self.upload_id.take() {
+            let _ = self.client.abort_multipart_upload(&self.key, &upload_id);
+        }
+    }
+}
+
+impl Write for S3Writer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.failed {
+            return Err(std::io::Error::other(format!(
+                "s3 upload of {} already failed",
+                self.key
+            )));
+        }
+        let free = self.part_size() - self.buffer.len();
+        let taken = buf.len().min(free);
+        self.buffer.extend_from_slice(&buf[..taken]);
+        if self.buffer.len() == self.part_size()
+            && let Err(err) = self.upload_buffered_part()
+        {
+            self.failed = true;
+            self.abort();
+            return Err(err);
+        }
+        Ok(taken)
+    }
+
+    /// Parts below the minimum part size of S3 cannot be sent early.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for S3Writer {
+    fn drop(&mut self) {
+        if self.failed {
+            return;
+        }
+        let mut last_error = None;
+        for attempt in 0..3 {
+            match self.finish() {
+                Ok(()) => return,
+                Err(err) => last_error = Some(err),
+            }
+            thread::sleep(Duration::from_secs(2u64.pow(attempt)));
+        }
+        if let Some(err) = last_error {
+            eprintln!("Error finishing s3 upload of {}: {err}", self.key);
+        }
+        self.abort();
+    }
+}
+
+impl S3DestService {
+    /// Names of the files in the folder that have one of `extensions`.
+    fn list_files_with_extensions(&self, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+        let prefix = self.client.key("".as_ref());
+        let mut file_names = vec![];
+
+        for key in self.list_keys()? {
+            let Some(file_name) = key.strip_prefix(&prefix) else {
+                continue;
+            };
+            let file_path = PathBuf::from(file_name);
+            let Some(file_extension) = file_path.extension() else {
+                continue;
+            };
+            if extensions.iter().any(|extension| file_extension == *extension) {
+                file_names.push(file_path);
+            }
+        }
+
+        Ok(file_names)
+    }
+}
+
+impl DestService for S3DestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        let response = match self
+            .client
+            .request("GET", Some(&self.client.key(HISTORY_KEY.as_ref())), &[], &[], &[])
+        {
+            Ok(response) => response,
+            Err(S3Error::Status { status: 404, .. }) => {
+                *self.history_version.lock().unwrap() = HistoryVersion::Missing;
+                return Ok(BackupHistory { entries: vec![] });
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // Without a version the next write could overwrite a history changed by another host
+        let Some(etag) = response.header("etag") else {
+            return Err(std::io::Error::other(format!(
+                "s3 GET {HISTORY_KEY}: no ETag, changes by other hosts could not be detected"
+            )));
+        };
+        let version = HistoryVersion::ETag(etag.to_string());
+
+        let reader_content: Option<Value> = serde_json::from_reader(response.into_reader())?;
+        *self.history_version.lock().unwrap() = version;
+        match reader_content {
+            Some(value) => Ok(BackupHistory::parse_from_json(Some(value))
+                .map_err(|err| std::io::Error::other(err.message()))?),
+            None => Ok(BackupHistory { entries: vec![] }),
+        }
+    }
+
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let key = self.client.key(&relative_file_path);
+        match self.client.object_size(&key) {
+            Ok(_) => return Err(std::io::Error::from(std::io::ErrorKind::AlreadyExists)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(Box::new(S3Writer {
+            client: Arc::clone(&self.client),
+            key,
+            buffer: Vec::new(),
+            upload_id: None,
+            etags: vec![],
+            failed: false,
+        }))
+    }
+
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let key = self.client.key(&relative_file_path);
+        let response = self.client.request("GET", Some(&key), &[], &[], &[])?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        self.client.object_size(&self.client.key(&relative_file_path))
+    }
+
+    /// Fails instead of overwriting when another host changed the history since it was read.
+    /// Servers that return no ETag for the write are asked for it with a HEAD request.
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        let body = serde_json::to_vec(&history.to_json())?;
+        let mut version_lock = self.history_version.lock().unwrap();
+        let headers = match &*version_lock {
+            HistoryVersion::Unread => vec![],
+            HistoryVersion::Missing => vec![("if-none-match", "*".to_string())],
+            HistoryVersion::ETag(etag) => vec![("if-match", etag.clone())],
+        };
+
+        let key = self.client.key(HISTORY_KEY.as_ref());
+        let response = match self.client.request("PUT", Some(&key), &[], &headers, &body) {
+            Ok(response) => response,
+            Err(err @ S3Error::Status { status: 412, .. }) => {
+                return Err(std::io::Error::other(format!(
+                    "{err}, the backup history was changed by another host"
+                )));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let etag = match response.header("etag") {
+            Some(etag) => etag.to_string(),
+            None => self
+                .client
+                .request("HEAD", Some(&key), &[], &[], &[])?
+                .header("etag")
+                .map(str::to_string)
+                .ok_or_else(|| {
+                    std::io::Error::other(format!(
+                        "s3 HEAD {HISTORY_KEY}: no ETag, changes by other hosts could not be \
+                         detected"
+                    ))
+                })?,
+        };
+        *version_lock = HistoryVersion::ETag(etag);
+        Ok(())
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["bin", "dbin"])
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["chunk", "part"])
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        let mut deleted_counter = 0;
+
+        for file_name in self.list_backup_files()? {
+            if history
+                .entries
+                .iter()
+                .find(|entry| entry.remote_filename == Path::from(file_name.clone()))
+                .is_none()
+            {
+                if self.remove_backup_file(file_name).is_ok() {
+                    deleted_counter += 1;
+                }
+            }
+        }
+
+        Ok(deleted_counter)
+    }
+
+    /// S3 cannot rename, the object is copied and the original removed.
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        let from_key = self.client.key(&from);
+        self.client.copy_object(&from_key, &self.client.key(&to))?;
+        self.client.request("DELETE", Some(&from_key), &[], &[], &[])?;
+        Ok(())
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        let key = self.client.key(&relative_file_path);
+        self.client.request("DELETE", Some(&key), &[], &[], &[])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod fake_server;
+#[cfg(test)]
+mod tests;
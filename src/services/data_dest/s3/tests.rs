@@ -0,0 +1,260 @@
+use super::fake_server::{ACCESS_KEY_ID, FakeS3Server, REGION, SECRET_ACCESS_KEY};
+use super::*;
+use crate::objects::BackupEntry;
+use std::io::ErrorKind;
+
+fn options(server: &FakeS3Server, prefix: &str) -> S3Options {
+    S3Options {
+        bucket: server.bucket_name().to_string(),
+        prefix: prefix.to_string(),
+        region: REGION.to_string(),
+        endpoint: server.endpoint.clone(),
+        access_key_id: ACCESS_KEY_ID.to_string(),
+        secret_access_key: SECRET_ACCESS_KEY.into(),
+        part_size: 1024,
+        max_copy_object_bytes: 2048,
+    }
+}
+
+/// `DATA_DANCE_S3_TEST=http://host:port/bucket` runs the round trip against a real S3
+/// compatible server like MinIO, with `DATA_DANCE_S3_TEST_ACCESS_KEY_ID`,
+/// `DATA_DANCE_S3_TEST_SECRET_ACCESS_KEY` and optionally `DATA_DANCE_S3_TEST_REGION`.
+fn minio_options() -> Option<S3Options> {
+    let target = std::env::var("DATA_DANCE_S3_TEST").ok()?;
+    let (endpoint, bucket) = target.rsplit_once('/')?;
+
+    Some(S3Options {
+        bucket: bucket.to_string(),
+        prefix: "data-dance-test/".to_string(),
+        region: std::env::var("DATA_DANCE_S3_TEST_REGION").unwrap_or("us-east-1".to_string()),
+        endpoint: endpoint.to_string(),
+        access_key_id: std::env::var("DATA_DANCE_S3_TEST_ACCESS_KEY_ID").ok()?,
+        secret_access_key: std::env::var("DATA_DANCE_S3_TEST_SECRET_ACCESS_KEY")
+            .ok()?
+            .into(),
+        // The minimum part size of S3
+        part_size: 5 * 1024 * 1024,
+        max_copy_object_bytes: 5 * 1024 * 1024,
+    })
+}
+
+fn entry(id: u32, parent: Option<u32>, remote_filename: &str) -> BackupEntry {
+    BackupEntry {
+        timestamp: id as u64,
+        remote_filename: remote_filename.into(),
+        local_snapshot: format!("{id}/").into(),
+        ..BackupEntry::test(id, parent)
+    }
+}
+
+fn read_file(service: &S3DestService, file: &str) -> Vec<u8> {
+    let mut content = vec![];
+    service
+        .get_backup_reader(file.into())
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    content
+}
+
+/// `large` has to span several parts and be larger than `max_copy_object_bytes`.
+fn round_trip(service: &S3DestService, large: &[u8]) {
+    for file in ["10.bin", "20.dbin", "orphan.bin", "20.dbin.new", "30.dbin"] {
+        let _ = service.remove_backup_file(file.into());
+    }
+    let _ = service.remove_backup_file(HISTORY_KEY.into());
+    assert_eq!(service.backup_history().unwrap().entries, vec![]);
+
+    service
+        .get_backup_writer("10.bin".into())
+        .unwrap()
+        .write_all(large)
+        .unwrap();
+    for (file, content) in [("20.dbin", "old"), ("orphan.bin", "gone")] {
+        let mut writer = service.get_backup_writer(file.into()).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+    }
+    assert_eq!(read_file(service, "10.bin"), large);
+    let err = service.get_backup_writer("10.bin".into()).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    service
+        .get_backup_writer("20.dbin.new".into())
+        .unwrap()
+        .write_all(b"incremental")
+        .unwrap();
+    service
+        .replace_backup_file("20.dbin.new".into(), "20.dbin".into())
+        .unwrap();
+    assert_eq!(read_file(service, "20.dbin"), b"incremental");
+    // Copied part by part
+    service
+        .replace_backup_file("10.bin".into(), "30.dbin".into())
+        .unwrap();
+    assert_eq!(read_file(service, "30.dbin"), large);
+    service
+        .replace_backup_file("30.dbin".into(), "10.bin".into())
+        .unwrap();
+
+    let history = BackupHistory {
+        entries: vec![entry(10, None, "10.bin"), entry(20, Some(10), "20.dbin")],
+    };
+    service.set_backup_history(history.clone()).unwrap();
+    service.set_backup_history(history.clone()).unwrap();
+    assert_eq!(service.backup_history().unwrap(), history);
+
+    let mut files = service.list_backup_files().unwrap();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            PathBuf::from("10.bin"),
+            "20.dbin".into(),
+            "orphan.bin".into()
+        ]
+    );
+    assert_eq!(service.clear_orphaned_backups(&history).unwrap(), 1);
+
+    let err = service
+        .get_backup_reader("orphan.bin".into())
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err.to_string().contains("orphan.bin"), "{err}");
+}
+
+#[test]
+fn round_trip_against_fake_server() {
+    let server = FakeS3Server::start("backups", 2);
+    let service = S3DestService::new(options(&server, "host-a")).unwrap();
+    let large: Vec<u8> = (0..5000u32).map(|index| (index % 251) as u8).collect();
+
+    round_trip(&service, &large);
+
+    assert_eq!(
+        server.keys(),
+        vec![
+            "host-a/10.bin",
+            "host-a/20.dbin",
+            "host-a/backup_history.json"
+        ]
+    );
+    assert_eq!(server.open_uploads(), 0);
+}
+
+#[test]
+fn large_files_are_uploaded_in_parts() {
+    let server = FakeS3Server::start("backups", 1000);
+    let service = S3DestService::new(options(&server, "")).unwrap();
+    let large = vec![7u8; 2500];
+
+    let mut writer = service.get_backup_writer("10.bin".into()).unwrap();
+    for chunk in large.chunks(300) {
+        writer.write_all(chunk).unwrap();
+    }
+    drop(writer);
+
+    assert_eq!(server.object("10.bin"), Some(large));
+    let part_uploads = server
+        .requests()
+        .iter()
+        .filter(|request| request.as_str() == "PUT partNumber&uploadId")
+        .count();
+    assert_eq!(part_uploads, 3);
+}
+
+#[test]
+fn history_changed_by_another_host_is_not_overwritten() {
+    let server = FakeS3Server::start("backups", 1000);
+    let first = S3DestService::new(options(&server, "")).unwrap();
+    let second = S3DestService::new(options(&server, "")).unwrap();
+    let history = |ids: &[u32]| BackupHistory {
+        entries: ids
+            .iter()
+            .map(|id| entry(*id, None, &format!("{id}.bin")))
+            .collect(),
+    };
+
+    // Both saw no history, only the first write creates it
+    first.backup_history().unwrap();
+    second.backup_history().unwrap();
+    first.set_backup_history(history(&[10])).unwrap();
+    let err = second.set_backup_history(history(&[20])).unwrap_err();
+    assert!(
+        err.to_string()
+            .ends_with("the backup history was changed by another host"),
+        "{err}"
+    );
+
+    // Both read the same version, only the first write replaces it
+    assert_eq!(second.backup_history().unwrap(), history(&[10]));
+    second.set_backup_history(history(&[10, 20])).unwrap();
+    first.set_backup_history(history(&[10, 30])).unwrap_err();
+    assert_eq!(first.backup_history().unwrap(), history(&[10, 20]));
+    first.set_backup_history(history(&[10, 20, 30])).unwrap();
+
+    server.put_object(HISTORY_KEY, b"{\"entries\": []}");
+    first.set_backup_history(history(&[10])).unwrap_err();
+}
+
+#[test]
+fn history_without_etag_is_never_written_unconditionally() {
+    let server = FakeS3Server::start("backups", 1000);
+    let dest = S3DestService::new(options(&server, "")).unwrap();
+    let history = |ids: &[u32]| BackupHistory {
+        entries: ids
+            .iter()
+            .map(|id| entry(*id, None, &format!("{id}.bin")))
+            .collect(),
+    };
+
+    // The version of a write is read back when the PUT response lacks it
+    server.omit_etags(&["PUT"]);
+    dest.backup_history().unwrap();
+    dest.set_backup_history(history(&[10])).unwrap();
+    server.put_object(HISTORY_KEY, b"{\"entries\": []}");
+    dest.set_backup_history(history(&[10, 20])).unwrap_err();
+
+    // Without any version the write fails and the one after it is still checked
+    server.omit_etags(&["GET", "HEAD", "PUT"]);
+    let err = dest.backup_history().unwrap_err();
+    assert!(err.to_string().contains("no ETag"), "{err}");
+    server.omit_etags(&["HEAD", "PUT"]);
+    dest.backup_history().unwrap();
+    dest.set_backup_history(history(&[30])).unwrap_err();
+    server.put_object(HISTORY_KEY, b"{\"entries\": [ ]}");
+    dest.set_backup_history(history(&[30, 40])).unwrap_err();
+    assert_eq!(server.object(HISTORY_KEY).unwrap(), b"{\"entries\": [ ]}");
+}
+
+#[test]
+fn rejected_requests_carry_the_s3_error() {
+    let server = FakeS3Server::start("backups", 1000);
+    let service = S3DestService::new(S3Options {
+        secret_access_key: "wrong".into(),
+        ..options(&server, "")
+    })
+    .unwrap();
+
+    let err = service.list_backup_files().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(
+        err.to_string()
+            .starts_with("s3 GET backups: 403 SignatureDoesNotMatch: "),
+        "{err}"
+    );
+}
+
+#[test]
+fn round_trip_against_minio() {
+    let Some(options) = minio_options() else {
+        eprintln!("DATA_DANCE_S3_TEST not set, skipping");
+        return;
+    };
+    let service = S3DestService::new(options).unwrap();
+    let large: Vec<u8> = (0..11 * 1024 * 1024u32)
+        .map(|index| (index % 251) as u8)
+        .collect();
+
+    round_trip(&service, &large);
+}
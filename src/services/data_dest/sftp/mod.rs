@@ -0,0 +1,407 @@
+use poem_openapi::types::{ParseFromJSON, ToJSON};
+use serde_json::Value;
+
+use crate::objects::{BackupHistory, Path, SensitiveString};
+use crate::services::data_dest::DestService;
+use crate::services::data_dest::ssh::shell_quoted;
+use ssh2::{CheckResult, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session, Sftp};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Blocking calls on a stalled connection fail after this instead of hanging the job.
+const SESSION_TIMEOUT_MS: u32 = 5 * 60 * 1000;
+const WRITE_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct SftpOptions {
+    pub username: String,
+    pub hostname: String,
+    pub port: Option<u16>,
+    pub folder: PathBuf,
+    /// The ssh agent is asked when there is no identity file.
+    pub identity_file: Option<PathBuf>,
+    pub identity_passphrase: Option<SensitiveString>,
+    /// Defaults to `~/.ssh/known_hosts`.
+    pub known_hosts: Option<PathBuf>,
+}
+
+/// Connects on first use and keeps the session for the lifetime of the service, which is
+/// created once per job.
+pub struct SftpDestService {
+    options: SftpOptions,
+    sftp: Mutex<Option<(Session, Arc<Sftp>)>>,
+}
+
+impl SftpDestService {
+    pub fn new(options: SftpOptions) -> Self {
+        SftpDestService {
+            options,
+            sftp: Mutex::new(None),
+        }
+    }
+
+    fn sftp(&self) -> std::io::Result<Arc<Sftp>> {
+        Ok(self.connection()?.1)
+    }
+
+    fn connection(&self) -> std::io::Result<(Session, Arc<Sftp>)> {
+        let mut sftp_lock = self.sftp.lock().unwrap();
+        if let Some((session, sftp)) = sftp_lock.as_ref() {
+            return Ok((session.clone(), Arc::clone(sftp)));
+        }
+        let (session, sftp) = self.connect()?;
+        let sftp = Arc::new(sftp);
+        *sftp_lock = Some((session.clone(), Arc::clone(&sftp)));
+        Ok((session, sftp))
+    }
+
+    fn connect(&self) -> std::io::Result<(Session, Sftp)> {
+        let options = &self.options;
+        let port = options.port.unwrap_or(22);
+        let tcp = TcpStream::connect((options.hostname.as_str(), port)).map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!("sftp connecting to {}:{port}: {err}", options.hostname),
+            )
+        })?;
+
+        let mut session = Session::new().map_err(|err| sftp_error("creating session", err))?;
+        session.set_timeout(SESSION_TIMEOUT_MS);
+        session.set_tcp_stream(tcp);
+        session
+            .handshake()
+            .map_err(|err| sftp_error(format!("handshake with {}", options.hostname), err))?;
+
+        self.verify_host_key(&session, port)?;
+
+        let auth_result = match &options.identity_file {
+            Some(identity_file) => session.userauth_pubkey_file(
+                &options.username,
+                None,
+                identity_file,
+                options
+                    .identity_passphrase
+                    .as_ref()
+                    .map(|passphrase| passphrase.insecure()),
+            ),
+            None => session.userauth_agent(&options.username),
+        };
+        auth_result
+            .map_err(|err| sftp_error(format!("authenticating as {}", options.username), err))?;
+        if !session.authenticated() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("sftp authenticating as {}: rejected", options.username),
+            ));
+        }
+
+        let sftp = session
+            .sftp()
+            .map_err(|err| sftp_error("starting the sftp subsystem", err))?;
+        Ok((session, sftp))
+    }
+
+    fn verify_host_key(&self, session: &Session, port: u16) -> std::io::Result<()> {
+        let hostname = &self.options.hostname;
+        let known_hosts_file = match &self.options.known_hosts {
+            Some(known_hosts) => known_hosts.clone(),
+            None => {
+                PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".ssh/known_hosts")
+            }
+        };
+
+        let mut known_hosts = session
+            .known_hosts()
+            .map_err(|err| sftp_error("loading known hosts", err))?;
+        known_hosts
+            .read_file(&known_hosts_file, KnownHostFileKind::OpenSSH)
+            .map_err(|err| sftp_error(format!("reading {}", known_hosts_file.display()), err))?;
+        let Some((host_key, _)) = session.host_key() else {
+            return Err(std::io::Error::other(format!(
+                "sftp {hostname} did not send a host key"
+            )));
+        };
+
+        let problem = match known_hosts.check_port(hostname, port, host_key) {
+            CheckResult::Match => return Ok(()),
+            CheckResult::Mismatch => "does not match the key listed",
+            CheckResult::NotFound => "is not listed",
+            CheckResult::Failure => "could not be checked",
+        };
+        Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "sftp host key of {hostname} {problem} in {}",
+                known_hosts_file.display()
+            ),
+        ))
+    }
+
+    fn remote_path(&self, relative_file_path: impl Into<PathBuf>) -> PathBuf {
+        self.options.folder.join(relative_file_path.into())
+    }
+
+    fn open_writer(
+        &self,
+        relative_file_path: PathBuf,
+        flags: OpenFlags,
+    ) -> std::io::Result<SftpWriter> {
+        let sftp = self.sftp()?;
+        let mut folder = self.options.folder.clone();
+        for component in relative_file_path.parent().into_iter().flatten() {
+            folder.push(component);
+            if sftp.stat(&folder).is_err() {
+                sftp.mkdir(&folder, 0o755)
+                    .map_err(|err| sftp_error(format!("creating {}", folder.display()), err))?;
+            }
+        }
+        let path = self.remote_path(relative_file_path);
+        let file = sftp
+            .open_mode(&path, flags, 0o644, OpenType::File)
+            .map_err(|err| sftp_error(format!("creating {}", path.display()), err))?;
+        Ok(SftpWriter(Some(BufWriter::with_capacity(
+            WRITE_BUFFER_BYTES,
+            file,
+        ))))
+    }
+
+    fn open_reader(&self, relative_file_path: PathBuf) -> std::io::Result<ssh2::File> {
+        let path = self.remote_path(relative_file_path);
+        self.sftp()?
+            .open(&path)
+            .map_err(|err| sftp_error(format!("opening {}", path.display()), err))
+    }
+
+    /// SFTP servers speaking protocol version 3 (OpenSSH) refuse to rename onto an existing
+    /// file, `mv` replaces it atomically there. Only accounts without a shell remove the target
+    /// first, it is briefly missing in that case.
+    fn move_file(&self, relative_from: PathBuf, relative_to: PathBuf) -> std::io::Result<()> {
+        let sftp = self.sftp()?;
+        let from = self.remote_path(relative_from);
+        let to = self.remote_path(relative_to);
+        let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+
+        if let Err(err) = sftp.rename(&from, &to, Some(flags)) {
+            // A missing source must not cost the target
+            if sftp.stat(&from).is_err() || sftp.stat(&to).is_err() {
+                return Err(sftp_error(
+                    format!("moving {} to {}", from.display(), to.display()),
+                    err,
+                ));
+            }
+            let command = format!("mv -f {} {}", shell_quoted(&from), shell_quoted(&to));
+            if self.exec(&command).is_ok() {
+                return Ok(());
+            }
+            sftp.unlink(&to)
+                .map_err(|err| sftp_error(format!("removing {}", to.display()), err))?;
+            sftp.rename(&from, &to, Some(flags)).map_err(|err| {
+                sftp_error(
+                    format!("moving {} to {}", from.display(), to.display()),
+                    err,
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Runs `command` in the shell of the account, for what the SFTP protocol cannot do.
+    fn exec(&self, command: &str) -> std::io::Result<()> {
+        let (session, _) = self.connection()?;
+        let mut channel = session
+            .channel_session()
+            .map_err(|err| sftp_error("opening an exec channel", err))?;
+        channel
+            .exec(command)
+            .map_err(|err| sftp_error(format!("running `{command}`"), err))?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.stderr().read_to_string(&mut output)?;
+        channel
+            .wait_close()
+            .map_err(|err| sftp_error(format!("running `{command}`"), err))?;
+        match channel
+            .exit_status()
+            .map_err(|err| sftp_error(format!("running `{command}`"), err))?
+        {
+            0 => Ok(()),
+            status => Err(std::io::Error::other(format!(
+                "sftp running `{command}` exited with {status}: {}",
+                output.trim()
+            ))),
+        }
+    }
+
+    fn remove_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        let path = self.remote_path(relative_file_path);
+        self.sftp()?
+            .unlink(&path)
+            .map_err(|err| sftp_error(format!("removing {}", path.display()), err))
+    }
+}
+
+/// Keeps the error kind ssh2 derives from the status code, adds what was attempted.
+fn sftp_error(action: impl AsRef<str>, err: ssh2::Error) -> std::io::Error {
+    let message = format!("sftp {}: {err}", action.as_ref());
+    let kind = std::io::Error::from(err).kind();
+    std::io::Error::new(kind, message)
+}
+
+/// Flushes and syncs the file when dropped, like `dd conv=fsync` did for the ssh destination.
+struct SftpWriter(Option<BufWriter<ssh2::File>>);
+
+impl Write for SftpWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.0 {
+            Some(writer) => writer.write(buf),
+            None => Err(std::io::Error::other("sftp file already closed")),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.0 {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SftpWriter {
+    fn drop(&mut self) {
+        let Some(writer) = self.0.take() else {
+            return;
+        };
+        if let Ok(mut file) = writer.into_inner() {
+            // Not every server has the fsync extension
+            let _ = file.fsync();
+            let _ = file.close();
+        }
+    }
+}
+
+impl SftpDestService {
+    /// Names of the files in the folder that have one of `extensions`.
+    fn list_files_with_extensions(&self, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+        let folder = &self.options.folder;
+        let entries = self
+            .sftp()?
+            .readdir(folder)
+            .map_err(|err| sftp_error(format!("listing {}", folder.display()), err))?;
+
+        let mut file_names = vec![];
+        for (file_path, stat) in entries {
+            let Some(file_name) = file_path.file_name() else {
+                continue;
+            };
+            let Some(file_extension) = file_path.extension() else {
+                continue;
+            };
+            if stat.is_file() && extensions.iter().any(|extension| file_extension == *extension) {
+                file_names.push(PathBuf::from(file_name));
+            }
+        }
+
+        Ok(file_names)
+    }
+}
+
+impl DestService for SftpDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        let reader = match self.open_reader("backup_history.json".into()) {
+            Ok(reader) => reader,
+            // Left alone by a replacement interrupted after the old history was removed
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                match self.open_reader("bh_new.json".into()) {
+                    Ok(reader) => reader,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(BackupHistory { entries: vec![] });
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Err(err) => return Err(err),
+        };
+
+        let reader_content: Option<Value> = serde_json::from_reader(BufReader::new(reader))?;
+        match reader_content {
+            Some(value) => Ok(BackupHistory::parse_from_json(Some(value))
+                .map_err(|err| std::io::Error::other(err.message()))?),
+            None => Ok(BackupHistory { entries: vec![] }),
+        }
+    }
+
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let writer =
+            self.open_writer(relative_file_path, OpenFlags::WRITE | OpenFlags::EXCLUSIVE)?;
+        Ok(Box::new(writer))
+    }
+
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let reader = self.open_reader(relative_file_path)?;
+        Ok(Box::new(BufReader::new(reader)))
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        let path = self.remote_path(relative_file_path);
+        let stat = self
+            .sftp()?
+            .stat(&path)
+            .map_err(|err| sftp_error(format!("reading the size of {}", path.display()), err))?;
+        stat.size.ok_or_else(|| {
+            std::io::Error::other(format!("sftp reported no size for {}", path.display()))
+        })
+    }
+
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        {
+            let mut writer =
+                self.open_writer("bh_new.json".into(), OpenFlags::WRITE | OpenFlags::TRUNCATE)?;
+            serde_json::to_writer(&mut writer, &history.to_json())?;
+            writer.flush()?;
+        }
+        self.move_file("bh_new.json".into(), "backup_history.json".into())
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["bin", "dbin"])
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["chunk", "part"])
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        let mut deleted_counter = 0;
+
+        for file_name in self.list_backup_files()? {
+            if history
+                .entries
+                .iter()
+                .find(|entry| entry.remote_filename == Path::from(file_name.clone()))
+                .is_none()
+            {
+                if self.remove_file(file_name).is_ok() {
+                    deleted_counter += 1;
+                }
+            }
+        }
+
+        Ok(deleted_counter)
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.move_file(from, to)
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        self.remove_file(relative_file_path)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,170 @@
+use super::*;
+use crate::objects::BackupEntry;
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::thread;
+
+fn options(hostname: &str, port: u16, folder: &str) -> SftpOptions {
+    SftpOptions {
+        username: "backup".to_string(),
+        hostname: hostname.to_string(),
+        port: Some(port),
+        folder: folder.into(),
+        identity_file: None,
+        identity_passphrase: None,
+        known_hosts: None,
+    }
+}
+
+/// `DATA_DANCE_SFTP_TEST=user@host:port/absolute/folder` runs the round trip against a real
+/// sshd, with `DATA_DANCE_SFTP_TEST_IDENTITY` and `DATA_DANCE_SFTP_TEST_KNOWN_HOSTS` as the
+/// key file and known hosts file.
+fn sshd_options() -> Option<SftpOptions> {
+    let target = std::env::var("DATA_DANCE_SFTP_TEST").ok()?;
+    let (username, rest) = target.split_once('@')?;
+    let (hostname, rest) = rest.split_once(':')?;
+    let (port, folder) = rest.split_at(rest.find('/')?);
+
+    Some(SftpOptions {
+        username: username.to_string(),
+        identity_file: std::env::var_os("DATA_DANCE_SFTP_TEST_IDENTITY").map(PathBuf::from),
+        known_hosts: std::env::var_os("DATA_DANCE_SFTP_TEST_KNOWN_HOSTS").map(PathBuf::from),
+        ..options(hostname, port.parse().ok()?, folder)
+    })
+}
+
+#[test]
+fn refused_connections_name_the_host() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+
+    let service = SftpDestService::new(options("127.0.0.1", port, "/backups"));
+    let err = service.backup_history().unwrap_err();
+
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    assert!(
+        err.to_string()
+            .starts_with(&format!("sftp connecting to 127.0.0.1:{port}")),
+        "{err}"
+    );
+}
+
+#[test]
+fn protocol_errors_are_reported() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")
+            .unwrap();
+    });
+
+    let service = SftpDestService::new(options("127.0.0.1", port, "/backups"));
+    let err = service.list_backup_files().unwrap_err();
+    server.join().unwrap();
+
+    let message = err.to_string();
+    assert!(
+        message.starts_with("sftp handshake with 127.0.0.1: "),
+        "{message}"
+    );
+    assert!(message.len() > "sftp handshake with 127.0.0.1: ".len());
+}
+
+#[test]
+fn round_trip_against_sshd() {
+    let Some(options) = sshd_options() else {
+        eprintln!("DATA_DANCE_SFTP_TEST not set, skipping");
+        return;
+    };
+    let service = SftpDestService::new(options);
+
+    for file in [
+        "10.bin",
+        "20.dbin",
+        "orphan.bin",
+        "20.dbin.new",
+        "bh_new.json",
+    ] {
+        let _ = service.remove_backup_file(file.into());
+    }
+    let _ = service.remove_backup_file("backup_history.json".into());
+    assert_eq!(service.backup_history().unwrap().entries, vec![]);
+
+    for (file, content) in [
+        ("10.bin", "full"),
+        ("20.dbin", "old"),
+        ("orphan.bin", "gone"),
+    ] {
+        let mut writer = service.get_backup_writer(file.into()).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+    }
+    let err = service.get_backup_writer("10.bin".into()).err().unwrap();
+    assert!(err.to_string().contains("10.bin"), "{err}");
+
+    service
+        .get_backup_writer("20.dbin.new".into())
+        .unwrap()
+        .write_all(b"incremental")
+        .unwrap();
+    service
+        .replace_backup_file("20.dbin.new".into(), "20.dbin".into())
+        .unwrap();
+    let mut content = String::new();
+    service
+        .get_backup_reader("20.dbin".into())
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "incremental");
+    assert!(
+        service
+            .replace_backup_file("20.dbin.new".into(), "20.dbin".into())
+            .is_err()
+    );
+    assert!(service.get_backup_reader("20.dbin".into()).is_ok());
+
+    let entry = |id: u32, parent: Option<u32>, remote_filename: &str| BackupEntry {
+        timestamp: id as u64,
+        remote_filename: remote_filename.into(),
+        local_snapshot: format!("{id}/").into(),
+        ..BackupEntry::test(id, parent)
+    };
+    let history = BackupHistory {
+        entries: vec![entry(10, None, "10.bin"), entry(20, Some(10), "20.dbin")],
+    };
+    service.set_backup_history(history.clone()).unwrap();
+    service.set_backup_history(history.clone()).unwrap();
+    assert_eq!(service.backup_history().unwrap(), history);
+
+    // A replacement interrupted between removing the old history and moving the new one
+    let mut writer = service.get_backup_writer("bh_new.json".into()).unwrap();
+    serde_json::to_writer(&mut writer, &history.to_json()).unwrap();
+    drop(writer);
+    service
+        .remove_backup_file("backup_history.json".into())
+        .unwrap();
+    assert_eq!(service.backup_history().unwrap(), history);
+    service.set_backup_history(history.clone()).unwrap();
+
+    let mut files = service.list_backup_files().unwrap();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            PathBuf::from("10.bin"),
+            "20.dbin".into(),
+            "orphan.bin".into()
+        ]
+    );
+    assert_eq!(service.clear_orphaned_backups(&history).unwrap(), 1);
+
+    let err = service
+        .get_backup_reader("orphan.bin".into())
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err.to_string().contains("orphan.bin"), "{err}");
+}
@@ -4,10 +4,10 @@ use serde_json::Value;
 use crate::objects::{BackupHistory, SensitiveString, Path};
 use crate::services::data_dest::DestService;
 use crate::services::data_source::btrfs::BtrfsSourceService;
-use crate::services::processes::{AwaitedChild, AwaitedStdin};
+use crate::services::processes::{AwaitedChild, AwaitedStdin, AwaitedStdout};
 use std::cell::RefCell;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::path::PathBuf;
 use std::process::{Child, ChildStdin, ChildStdout, Stdio};
 use std::thread;
@@ -34,6 +34,11 @@ impl SshDestService {
         &self,
         relative_file_path: PathBuf,
     ) -> std::io::Result<(ChildStdin, AwaitedChild)> {
+        if let Some(relative_folder) = relative_file_path.parent()
+            && !relative_folder.as_os_str().is_empty()
+        {
+            self.create_folder(relative_folder)?;
+        }
         let mut command = std::process::Command::new("ssh");
         if let Some(port) = self.port {
             command.args(["-p", &port.to_string()]);
@@ -74,6 +79,7 @@ impl SshDestService {
             ))
             .arg("bs=4M")
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .stdin(Stdio::null());
 
         let mut process = command.spawn()?;
@@ -81,6 +87,32 @@ impl SshDestService {
         Ok((output, process.into()))
     }
 
+    /// Creates a folder and its missing parents.
+    pub fn create_folder(&self, relative_folder: &std::path::Path) -> std::io::Result<()> {
+        let folder = self.folder.join(relative_folder);
+        let mut command = std::process::Command::new("ssh");
+        if let Some(port) = self.port {
+            command.args(["-p", &port.to_string()]);
+        }
+        command
+            .args(["-o", "Compression no"])
+            .arg(format!("{}@{}", self.username, self.host))
+            .arg("mkdir -p")
+            .arg(shell_quoted(&folder))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::null());
+        let output = command.spawn()?.wait_with_output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "creating {} failed: {}",
+                folder.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(())
+    }
+
     pub fn remove_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
         let mut command = std::process::Command::new("ssh");
         if let Some(port) = self.port {
@@ -135,6 +167,36 @@ impl SshDestService {
             .flatten()
     }
 
+    pub fn file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        let path = self.folder.join(&relative_file_path);
+        let mut command = std::process::Command::new("ssh");
+        if let Some(port) = self.port {
+            command.args(["-p", &port.to_string()]);
+        }
+        command
+            .args(["-o", "Compression no"])
+            .arg(format!("{}@{}", self.username, self.host))
+            .arg("stat -c %s")
+            .arg(shell_quoted(&path))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .stdin(Stdio::null());
+        let output = command.spawn()?.wait_with_output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "reading the size of {} failed: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .map_err(|_| {
+                std::io::Error::other(format!("no size reported for {}", path.display()))
+            })
+    }
+
     pub fn list_files(&self) -> std::io::Result<Vec<PathBuf>> {
         let mut command = std::process::Command::new("ssh");
         if let Some(port) = self.port {
@@ -146,9 +208,17 @@ impl SshDestService {
             .arg("ls")
             .arg(format!("{}", self.folder.display()))
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .stdin(Stdio::null());
         let mut process = command.spawn()?;
         let output = process.wait_with_output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "listing {} failed: {}",
+                self.folder.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
         let reader = BufReader::new(output.stdout.as_slice());
         let mut files = vec![];
         for line in reader.lines() {
@@ -173,16 +243,46 @@ impl SshDestService {
     }
 }
 
+impl SshDestService {
+    /// Names of the files in the folder that have one of `extensions`.
+    fn list_files_with_extensions(&self, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+        let mut file_names = vec![];
+
+        for entry in self.list_files()? {
+            let Some(file_extension) = entry.extension() else {
+                continue;
+            };
+            if extensions.iter().any(|extension| file_extension == *extension) {
+                file_names.push(entry);
+            }
+        }
+
+        Ok(file_names)
+    }
+}
+
 impl DestService for SshDestService {
     fn backup_history(&self) -> std::io::Result<BackupHistory> {
         self.read_history_at("backup_history.json")
     }
 
-    fn get_backup_writer(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Write>> {
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
         let (writer, process) = self.open_writer(relative_file_path.clone())?;
         Ok(Box::new(AwaitedStdin::new(writer, process)))
     }
 
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let (reader, process) = self.open_reader(relative_file_path)?;
+        Ok(Box::new(AwaitedStdout::new(reader, process)))
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        self.file_size(relative_file_path)
+    }
+
     fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
         let try_setting_history = || -> std::io::Result<()> {
             let (writer, write_process) = self.open_writer("bh_new.json".into())?;
@@ -212,20 +312,18 @@ impl DestService for SshDestService {
         Err(last_error)
     }
 
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["bin", "dbin"])
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["chunk", "part"])
+    }
+
     fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
         let mut deleted_counter = 0;
-        let mut all_backup_file_names = vec![];
 
-        for entry in self.list_files()? {
-            let Some(file_extension) = entry.extension() else {
-                continue;
-            };
-            if file_extension == "bin" || file_extension == "dbin" {
-                all_backup_file_names.push(entry);
-            }
-        }
-
-        for file_name in all_backup_file_names {
+        for file_name in self.list_backup_files()? {
             if history
                 .entries
                 .iter()
@@ -240,4 +338,17 @@ impl DestService for SshDestService {
 
         Ok(deleted_counter)
     }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.move_file(from, to)
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        self.remove_file(relative_file_path)
+    }
+}
+
+/// Quotes a path for the remote shell ssh passes its command to.
+pub(crate) fn shell_quoted(path: &std::path::Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
 }
@@ -0,0 +1,206 @@
+//! An in-memory WebDAV server with just the methods `WebDavDestService` uses.
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode_str, utf8_percent_encode};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Request, Response, Server};
+
+pub const USERNAME: &str = "backup";
+pub const PASSWORD: &str = "fake password";
+
+#[derive(Default)]
+struct FakeFolder {
+    files: BTreeMap<String, Vec<u8>>,
+    collections: BTreeSet<String>,
+    /// Paths of the PUT requests that used chunked transfer encoding.
+    chunked_puts: Vec<String>,
+}
+
+pub struct FakeWebDavServer {
+    pub address: String,
+    folder: Arc<Mutex<FakeFolder>>,
+    server: Arc<Server>,
+}
+
+impl FakeWebDavServer {
+    /// Serves the collections given, like `/dav/backups/`.
+    pub fn start(collections: &[&str]) -> Self {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        let address = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let folder = Arc::new(Mutex::new(FakeFolder {
+            collections: collections.iter().map(|path| path.to_string()).collect(),
+            ..FakeFolder::default()
+        }));
+
+        let handler_folder = Arc::clone(&folder);
+        let server_handle = Arc::clone(&server);
+        thread::spawn(move || {
+            for mut request in server_handle.incoming_requests() {
+                let mut body = vec![];
+                request.as_reader().read_to_end(&mut body).unwrap();
+                let response = handle(&mut handler_folder.lock().unwrap(), &request, body);
+                let _ = request.respond(response);
+            }
+        });
+
+        FakeWebDavServer {
+            address,
+            folder,
+            server,
+        }
+    }
+
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.folder.lock().unwrap().files.get(path).cloned()
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.folder.lock().unwrap().files.keys().cloned().collect()
+    }
+
+    pub fn chunked_puts(&self) -> Vec<String> {
+        self.folder.lock().unwrap().chunked_puts.clone()
+    }
+}
+
+impl Drop for FakeWebDavServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+type FakeResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+fn handle(folder: &mut FakeFolder, request: &Request, body: Vec<u8>) -> FakeResponse {
+    let header = |name: &str| {
+        request
+            .headers()
+            .iter()
+            .find(|header| header.field.as_str().as_str().eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str().to_string())
+    };
+    let expected_authorization = format!(
+        "Basic {}",
+        openssl::base64::encode_block(format!("{USERNAME}:{PASSWORD}").as_bytes())
+    );
+    if header("authorization") != Some(expected_authorization) {
+        return Response::from_data(vec![]).with_status_code(401);
+    }
+
+    let path = percent_decode_str(request.url().split('?').next().unwrap_or_default())
+        .decode_utf8_lossy()
+        .to_string();
+    let parent = match path.trim_end_matches('/').rsplit_once('/') {
+        Some((parent, _)) => format!("{parent}/"),
+        None => "/".to_string(),
+    };
+
+    match request.method().as_str() {
+        "HEAD" | "GET" => match folder.files.get(&path) {
+            Some(data) => Response::from_data(data.clone()),
+            None => Response::from_data(vec![]).with_status_code(404),
+        },
+        "PUT" => {
+            if !folder.collections.contains(&parent) {
+                return Response::from_data(vec![]).with_status_code(409);
+            }
+            if header("if-none-match").is_some() && folder.files.contains_key(&path) {
+                return Response::from_data(vec![]).with_status_code(412);
+            }
+            if header("transfer-encoding").is_some_and(|encoding| encoding.contains("chunked")) {
+                folder.chunked_puts.push(path.clone());
+            }
+            let status = if folder.files.insert(path, body).is_some() {
+                204
+            } else {
+                201
+            };
+            Response::from_data(vec![]).with_status_code(status)
+        }
+        "MKCOL" => {
+            let path = format!("{}/", path.trim_end_matches('/'));
+            if folder.collections.contains(&path) || folder.files.contains_key(&path) {
+                return Response::from_data(vec![]).with_status_code(405);
+            }
+            if !folder.collections.contains(&parent) {
+                return Response::from_data(vec![]).with_status_code(409);
+            }
+            folder.collections.insert(path);
+            Response::from_data(vec![]).with_status_code(201)
+        }
+        "DELETE" => match folder.files.remove(&path) {
+            Some(_) => Response::from_data(vec![]).with_status_code(204),
+            None => Response::from_data(vec![]).with_status_code(404),
+        },
+        "MOVE" => {
+            let Some(destination) = header("destination")
+                .and_then(|destination| url::Url::parse(&destination).ok())
+                .map(|url| {
+                    percent_decode_str(url.path())
+                        .decode_utf8_lossy()
+                        .to_string()
+                })
+            else {
+                return Response::from_data(vec![]).with_status_code(400);
+            };
+            let overwrite = header("overwrite").is_none_or(|overwrite| overwrite == "T");
+            if !overwrite && folder.files.contains_key(&destination) {
+                return Response::from_data(vec![]).with_status_code(412);
+            }
+            let Some(data) = folder.files.remove(&path) else {
+                return Response::from_data(vec![]).with_status_code(404);
+            };
+            let status = if folder.files.insert(destination, data).is_some() {
+                204
+            } else {
+                201
+            };
+            Response::from_data(vec![]).with_status_code(status)
+        }
+        "PROPFIND" if folder.collections.contains(&path) => {
+            if header("depth").as_deref() != Some("1") {
+                return Response::from_data(vec![]).with_status_code(403);
+            }
+            let is_child = |child: &&String| {
+                child.len() > path.len()
+                    && child.starts_with(&path)
+                    && !child[path.len()..].trim_end_matches('/').contains('/')
+            };
+            let href = |child: &str| {
+                child
+                    .split('/')
+                    .map(|segment| utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string())
+                    .collect::<Vec<_>>()
+                    .join("/")
+            };
+            let mut responses = vec![propfind_response(&href(&path), true)];
+            for collection in folder.collections.iter().filter(is_child) {
+                responses.push(propfind_response(&href(collection), true));
+            }
+            for file in folder.files.keys().filter(is_child) {
+                responses.push(propfind_response(&href(file), false));
+            }
+            Response::from_string(format!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                <d:multistatus xmlns:d=\"DAV:\">{}</d:multistatus>",
+                responses.concat()
+            ))
+            .with_status_code(207)
+        }
+        "PROPFIND" => Response::from_data(vec![]).with_status_code(404),
+        _ => Response::from_data(vec![]).with_status_code(405),
+    }
+}
+
+fn propfind_response(href: &str, is_collection: bool) -> String {
+    let resource_type = if is_collection {
+        "<d:resourcetype><d:collection/></d:resourcetype>"
+    } else {
+        "<d:resourcetype/>"
+    };
+    format!(
+        "<d:response><d:href>{href}</d:href><d:propstat><d:prop>{resource_type}</d:prop>\
+        <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"
+    )
+}
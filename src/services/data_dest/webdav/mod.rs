@@ -0,0 +1,421 @@
+use poem_openapi::types::{ParseFromJSON, ToJSON};
+use serde_json::Value;
+
+use crate::objects::{BackupHistory, Path, SensitiveString};
+use crate::services::data_dest::DestService;
+use percent_encoding::percent_decode_str;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use url::Url;
+
+/// Uploads are streamed with chunked transfer encoding in chunks of this size.
+const CHUNK_BYTES: usize = 1024 * 1024;
+/// Chunks waiting for the upload thread, bounds the memory an upload takes.
+const QUEUED_CHUNKS: usize = 4;
+const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+    <d:propfind xmlns:d=\"DAV:\"><d:prop><d:resourcetype/></d:prop></d:propfind>";
+
+#[derive(Clone)]
+pub struct WebDavOptions {
+    /// The folder holding the backups, it has to exist.
+    pub url: String,
+    pub username: String,
+    pub password: SensitiveString,
+}
+
+pub struct WebDavDestService {
+    folder: Url,
+    authorization: SensitiveString,
+    agent: ureq::Agent,
+}
+
+impl WebDavDestService {
+    pub fn new(options: WebDavOptions) -> std::io::Result<Self> {
+        let mut folder = Url::parse(&options.url).map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("webdav url {}: {err}", options.url),
+            )
+        })?;
+        if !folder.path().ends_with('/') {
+            folder.set_path(&format!("{}/", folder.path()));
+        }
+        let credentials = format!("{}:{}", options.username, options.password.insecure());
+        let authorization = format!(
+            "Basic {}",
+            openssl::base64::encode_block(credentials.as_bytes())
+        );
+        let tls_connector = native_tls::TlsConnector::new().map_err(std::io::Error::other)?;
+        let agent = ureq::AgentBuilder::new()
+            .tls_connector(std::sync::Arc::new(tls_connector))
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(300))
+            .timeout_write(Duration::from_secs(300))
+            .build();
+
+        Ok(WebDavDestService {
+            folder,
+            authorization: authorization.into(),
+            agent,
+        })
+    }
+
+    fn file_url(&self, relative_file_path: &std::path::Path) -> Url {
+        let mut url = self.folder.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty();
+            for component in relative_file_path.iter() {
+                segments.push(&component.to_string_lossy());
+            }
+        }
+        url
+    }
+
+    fn request(&self, method: &str, url: &Url) -> ureq::Request {
+        self.agent
+            .request_url(method, url)
+            .set("authorization", self.authorization.insecure())
+    }
+
+    /// Replaces `to` in one step, the server does the move.
+    fn move_file(&self, from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+        let from = self.file_url(from);
+        self.request("MOVE", &from)
+            .set("destination", self.file_url(to).as_str())
+            .set("overwrite", "T")
+            .call()
+            .map_err(|err| webdav_error("MOVE", &from, err))?;
+        Ok(())
+    }
+
+    /// Creates the collections `relative_file_path` lies in, one level after the other.
+    fn create_folders(&self, relative_file_path: &std::path::Path) -> std::io::Result<()> {
+        let mut folder = PathBuf::new();
+        for component in relative_file_path.parent().into_iter().flatten() {
+            folder.push(component);
+            let mut url = self.file_url(&folder);
+            if let Ok(mut segments) = url.path_segments_mut() {
+                segments.push("");
+            }
+            match self.request("MKCOL", &url).call() {
+                // Method not allowed, the collection exists
+                Ok(_) | Err(ureq::Error::Status(405, _)) => {}
+                Err(err) => return Err(webdav_error("MKCOL", &url, err)),
+            }
+        }
+        Ok(())
+    }
+
+    fn exists(&self, url: &Url) -> std::io::Result<bool> {
+        match self.request("HEAD", url).call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(err) => Err(webdav_error("HEAD", url, err)),
+        }
+    }
+}
+
+/// Keeps the status code in the error kind where there is a matching one.
+fn webdav_error(method: &str, url: &Url, err: ureq::Error) -> std::io::Error {
+    match err {
+        ureq::Error::Status(status, response) => {
+            let kind = match status {
+                404 => std::io::ErrorKind::NotFound,
+                401 | 403 => std::io::ErrorKind::PermissionDenied,
+                412 => std::io::ErrorKind::AlreadyExists,
+                _ => std::io::ErrorKind::Other,
+            };
+            std::io::Error::new(
+                kind,
+                format!("webdav {method} {url}: {status} {}", response.status_text()),
+            )
+        }
+        ureq::Error::Transport(transport) => std::io::Error::new(
+            std::io::ErrorKind::ConnectionAborted,
+            format!("webdav {method} {url}: {transport}"),
+        ),
+    }
+}
+
+/// Feeds the chunks handed over by `WebDavWriter` to the request body.
+struct ChunkReader {
+    chunks: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                // The writer was dropped, the body is complete
+                Err(_) => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Streams one PUT request from a separate thread. The upload completes when the writer is
+/// dropped, a failure then is only logged and caught by `verify_backup_file_size`.
+struct WebDavWriter {
+    url: Url,
+    buffer: Vec<u8>,
+    chunks: Option<SyncSender<Vec<u8>>>,
+    upload: Option<JoinHandle<std::io::Result<()>>>,
+}
+
+impl WebDavWriter {
+    fn start(service: &WebDavDestService, url: Url) -> Self {
+        let (sender, receiver) = sync_channel(QUEUED_CHUNKS);
+        // Fails instead of overwriting a file created since the existence check
+        let request = service.request("PUT", &url).set("if-none-match", "*");
+        let upload_url = url.clone();
+        let upload = thread::spawn(move || {
+            let body = ChunkReader {
+                chunks: receiver,
+                chunk: vec![],
+                position: 0,
+            };
+            request
+                .send(body)
+                .map_err(|err| webdav_error("PUT", &upload_url, err))?;
+            Ok(())
+        });
+
+        WebDavWriter {
+            url,
+            buffer: Vec::with_capacity(CHUNK_BYTES),
+            chunks: Some(sender),
+            upload: Some(upload),
+        }
+    }
+
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_BYTES));
+        let sent = match &self.chunks {
+            Some(chunks) => chunks.send(chunk).is_ok(),
+            None => false,
+        };
+        if sent {
+            return Ok(());
+        }
+        // The upload thread only stops reading early when the request failed
+        match self.finish() {
+            Err(err) => Err(err),
+            Ok(()) => Err(std::io::Error::other(format!(
+                "webdav PUT {}: upload already finished",
+                self.url
+            ))),
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.chunks.take();
+        match self.upload.take() {
+            Some(upload) => upload.join().unwrap_or_else(|_| {
+                Err(std::io::Error::other(format!(
+                    "webdav PUT {}: upload thread panicked",
+                    self.url
+                )))
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for WebDavWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let taken = buf.len().min(CHUNK_BYTES - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        if self.buffer.len() == CHUNK_BYTES {
+            self.send_buffer()?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.send_buffer()
+    }
+}
+
+impl Drop for WebDavWriter {
+    fn drop(&mut self) {
+        if self.upload.is_none() {
+            return;
+        }
+        let result = self.flush().and_then(|_| self.finish());
+        if let Err(err) = result {
+            eprintln!("Error finishing webdav upload of {}: {err}", self.url);
+        }
+    }
+}
+
+impl WebDavDestService {
+    /// Names of the files in the folder that have one of `extensions`.
+    fn list_files_with_extensions(&self, extensions: &[&str]) -> std::io::Result<Vec<PathBuf>> {
+        let response = self
+            .request("PROPFIND", &self.folder)
+            .set("depth", "1")
+            .set("content-type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND_BODY)
+            .map_err(|err| webdav_error("PROPFIND", &self.folder, err))?;
+        let body = response.into_string()?;
+        let document = roxmltree::Document::parse(&body).map_err(|err| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("webdav PROPFIND {}: malformed XML: {err}", self.folder),
+            )
+        })?;
+
+        let mut file_names = vec![];
+        for entry in document
+            .descendants()
+            .filter(|node| node.tag_name().name() == "response")
+        {
+            let is_collection = entry
+                .descendants()
+                .any(|node| node.tag_name().name() == "collection");
+            let Some(href) = entry
+                .descendants()
+                .find(|node| node.tag_name().name() == "href")
+                .and_then(|node| node.text())
+            else {
+                continue;
+            };
+            let Some(file_name) = href.trim_end_matches('/').rsplit('/').next() else {
+                continue;
+            };
+            let file_path =
+                PathBuf::from(percent_decode_str(file_name).decode_utf8_lossy().as_ref());
+            let Some(file_extension) = file_path.extension() else {
+                continue;
+            };
+            if !is_collection && extensions.iter().any(|extension| file_extension == *extension) {
+                file_names.push(file_path);
+            }
+        }
+
+        Ok(file_names)
+    }
+}
+
+impl DestService for WebDavDestService {
+    fn backup_history(&self) -> std::io::Result<BackupHistory> {
+        let url = self.file_url("backup_history.json".as_ref());
+        let response = match self.request("GET", &url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(BackupHistory { entries: vec![] }),
+            Err(err) => return Err(webdav_error("GET", &url, err)),
+        };
+
+        let reader_content: Option<Value> = serde_json::from_reader(response.into_reader())?;
+        match reader_content {
+            Some(value) => Ok(BackupHistory::parse_from_json(Some(value))
+                .map_err(|err| std::io::Error::other(err.message()))?),
+            None => Ok(BackupHistory { entries: vec![] }),
+        }
+    }
+
+    fn get_backup_writer(
+        &self,
+        relative_file_path: PathBuf,
+    ) -> std::io::Result<Box<dyn Write + Send>> {
+        let url = self.file_url(&relative_file_path);
+        if self.exists(&url)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("webdav {url} already exists"),
+            ));
+        }
+        self.create_folders(&relative_file_path)?;
+        Ok(Box::new(WebDavWriter::start(self, url)))
+    }
+
+    fn get_backup_reader(&self, relative_file_path: PathBuf) -> std::io::Result<Box<dyn Read>> {
+        let url = self.file_url(&relative_file_path);
+        let response = self
+            .request("GET", &url)
+            .call()
+            .map_err(|err| webdav_error("GET", &url, err))?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    fn backup_file_size(&self, relative_file_path: PathBuf) -> std::io::Result<u64> {
+        let url = self.file_url(&relative_file_path);
+        let response = self
+            .request("HEAD", &url)
+            .call()
+            .map_err(|err| webdav_error("HEAD", &url, err))?;
+        response
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| std::io::Error::other(format!("webdav HEAD {url}: no content length")))
+    }
+
+    fn set_backup_history(&self, history: BackupHistory) -> std::io::Result<()> {
+        let body = serde_json::to_vec(&history.to_json())?;
+        let url = self.file_url("bh_new.json".as_ref());
+        self.request("PUT", &url)
+            .send_bytes(&body)
+            .map_err(|err| webdav_error("PUT", &url, err))?;
+        self.move_file("bh_new.json".as_ref(), "backup_history.json".as_ref())
+    }
+
+    fn list_backup_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["bin", "dbin"])
+    }
+
+    fn list_chunk_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        self.list_files_with_extensions(&["chunk", "part"])
+    }
+
+    fn clear_orphaned_backups(&self, history: &BackupHistory) -> std::io::Result<usize> {
+        let mut deleted_counter = 0;
+
+        for file_name in self.list_backup_files()? {
+            if history
+                .entries
+                .iter()
+                .find(|entry| entry.remote_filename == Path::from(file_name.clone()))
+                .is_none()
+            {
+                if self.remove_backup_file(file_name).is_ok() {
+                    deleted_counter += 1;
+                }
+            }
+        }
+
+        Ok(deleted_counter)
+    }
+
+    fn replace_backup_file(&self, from: PathBuf, to: PathBuf) -> std::io::Result<()> {
+        self.move_file(&from, &to)
+    }
+
+    fn remove_backup_file(&self, relative_file_path: PathBuf) -> std::io::Result<()> {
+        let url = self.file_url(&relative_file_path);
+        self.request("DELETE", &url)
+            .call()
+            .map_err(|err| webdav_error("DELETE", &url, err))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod fake_server;
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,182 @@
+use super::fake_server::{FakeWebDavServer, PASSWORD, USERNAME};
+use super::*;
+use crate::objects::BackupEntry;
+use std::io::ErrorKind;
+
+fn options(server: &FakeWebDavServer, folder: &str) -> WebDavOptions {
+    WebDavOptions {
+        url: format!("{}{folder}", server.address),
+        username: USERNAME.to_string(),
+        password: PASSWORD.into(),
+    }
+}
+
+/// `DATA_DANCE_WEBDAV_TEST=https://host/folder/` runs the round trip against a real WebDAV
+/// server, with `DATA_DANCE_WEBDAV_TEST_USERNAME` and `DATA_DANCE_WEBDAV_TEST_PASSWORD`.
+fn server_options() -> Option<WebDavOptions> {
+    Some(WebDavOptions {
+        url: std::env::var("DATA_DANCE_WEBDAV_TEST").ok()?,
+        username: std::env::var("DATA_DANCE_WEBDAV_TEST_USERNAME").ok()?,
+        password: std::env::var("DATA_DANCE_WEBDAV_TEST_PASSWORD")
+            .ok()?
+            .into(),
+    })
+}
+
+fn entry(id: u32, parent: Option<u32>, remote_filename: &str) -> BackupEntry {
+    BackupEntry {
+        timestamp: id as u64,
+        remote_filename: remote_filename.into(),
+        local_snapshot: format!("{id}/").into(),
+        ..BackupEntry::test(id, parent)
+    }
+}
+
+fn read_file(service: &WebDavDestService, file: &str) -> Vec<u8> {
+    let mut content = vec![];
+    service
+        .get_backup_reader(file.into())
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    content
+}
+
+fn round_trip(service: &WebDavDestService) {
+    for file in [
+        "10.bin",
+        "20.dbin",
+        "orphan.bin",
+        "20.dbin.new",
+        "bh_new.json",
+    ] {
+        let _ = service.remove_backup_file(file.into());
+    }
+    let _ = service.remove_backup_file("backup_history.json".into());
+    assert_eq!(service.backup_history().unwrap().entries, vec![]);
+
+    let large: Vec<u8> = (0..CHUNK_BYTES as u32 * 5 / 2)
+        .map(|index| (index % 251) as u8)
+        .collect();
+    service
+        .get_backup_writer("10.bin".into())
+        .unwrap()
+        .write_all(&large)
+        .unwrap();
+    for (file, content) in [("20.dbin", "old"), ("orphan.bin", "gone")] {
+        let mut writer = service.get_backup_writer(file.into()).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+    }
+    assert_eq!(read_file(service, "10.bin"), large);
+    let err = service.get_backup_writer("10.bin".into()).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    service
+        .get_backup_writer("20.dbin.new".into())
+        .unwrap()
+        .write_all(b"incremental")
+        .unwrap();
+    service
+        .replace_backup_file("20.dbin.new".into(), "20.dbin".into())
+        .unwrap();
+    assert_eq!(read_file(service, "20.dbin"), b"incremental");
+
+    let history = BackupHistory {
+        entries: vec![entry(10, None, "10.bin"), entry(20, Some(10), "20.dbin")],
+    };
+    service.set_backup_history(history.clone()).unwrap();
+    service.set_backup_history(history.clone()).unwrap();
+    assert_eq!(service.backup_history().unwrap(), history);
+
+    let mut files = service.list_backup_files().unwrap();
+    files.sort();
+    assert_eq!(
+        files,
+        vec![
+            PathBuf::from("10.bin"),
+            "20.dbin".into(),
+            "orphan.bin".into()
+        ]
+    );
+    assert_eq!(service.clear_orphaned_backups(&history).unwrap(), 1);
+
+    let err = service
+        .get_backup_reader("orphan.bin".into())
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err.to_string().contains("orphan.bin"), "{err}");
+}
+
+#[test]
+fn round_trip_against_fake_server() {
+    let server = FakeWebDavServer::start(&["/dav/", "/dav/backups/"]);
+    let service = WebDavDestService::new(options(&server, "/dav/backups")).unwrap();
+
+    round_trip(&service);
+
+    assert_eq!(
+        server.paths(),
+        vec![
+            "/dav/backups/10.bin",
+            "/dav/backups/20.dbin",
+            "/dav/backups/backup_history.json"
+        ]
+    );
+    assert!(
+        server
+            .chunked_puts()
+            .contains(&"/dav/backups/10.bin".to_string())
+    );
+}
+
+#[test]
+fn listing_skips_folders_and_other_files() {
+    let server = FakeWebDavServer::start(&["/dav/", "/dav/nested.bin/"]);
+    let service = WebDavDestService::new(options(&server, "/dav/")).unwrap();
+    for file in ["with space.bin", "notes.txt", "nested.bin/10.bin"] {
+        service
+            .get_backup_writer(file.into())
+            .unwrap()
+            .write_all(b"content")
+            .unwrap();
+    }
+
+    assert_eq!(
+        server.file("/dav/with space.bin"),
+        Some(b"content".to_vec())
+    );
+    assert_eq!(
+        service.list_backup_files().unwrap(),
+        vec![PathBuf::from("with space.bin")]
+    );
+}
+
+#[test]
+fn rejected_credentials_are_reported() {
+    let server = FakeWebDavServer::start(&["/dav/"]);
+    let service = WebDavDestService::new(WebDavOptions {
+        password: "wrong".into(),
+        ..options(&server, "/dav/")
+    })
+    .unwrap();
+
+    let err = service.list_backup_files().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(
+        err.to_string()
+            .starts_with(&format!("webdav PROPFIND {}/dav/: 401", server.address)),
+        "{err}"
+    );
+}
+
+#[test]
+fn round_trip_against_webdav_server() {
+    let Some(options) = server_options() else {
+        eprintln!("DATA_DANCE_WEBDAV_TEST not set, skipping");
+        return;
+    };
+    let service = WebDavDestService::new(options).unwrap();
+
+    round_trip(&service);
+}
@@ -1,9 +1,11 @@
-use crate::objects::BackupHistory;
-use crate::services::data_source::{SourceBackup, SourceService};
+use crate::config::{ConsolidationPolicy, SnapshotRetention};
+use crate::objects::{BackupEntry, BackupHistory, SnapshotManifest};
+use crate::services::data_source::{SourceBackup, SourceService, folder_manifest};
+use crate::services::processes::{CheckedStdin, ProcessOutcome};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io;
-use std::io::{Stdout, Write};
+use std::io::{Read, Stdout, Write};
 use std::path::PathBuf;
 use std::process::{Child, Stdio};
 use thiserror::__private::AsDisplay;
@@ -12,7 +14,12 @@ pub struct BtrfsSourceService {
     pub snapshot_folder: PathBuf,
     pub source_folder: PathBuf,
     pub compressed_send: bool,
+    pub restore_folder: Option<PathBuf>,
+    pub snapshot_retention: SnapshotRetention,
     send_process: RefCell<Option<Child>>,
+    receive_outcome: RefCell<Option<ProcessOutcome>>,
+    /// Subvolumes received by this service, oldest first.
+    received: RefCell<Vec<PathBuf>>,
 }
 
 impl BtrfsSourceService {
@@ -21,18 +28,123 @@ impl BtrfsSourceService {
             snapshot_folder,
             source_folder,
             compressed_send,
+            restore_folder: None,
+            snapshot_retention: SnapshotRetention::default(),
             send_process: RefCell::new(None),
+            receive_outcome: RefCell::new(None),
+            received: RefCell::new(Vec::new()),
         }
     }
+
+    pub fn with_restore_folder(mut self, restore_folder: Option<PathBuf>) -> Self {
+        self.restore_folder = restore_folder;
+        self
+    }
+
+    pub fn with_snapshot_retention(mut self, snapshot_retention: SnapshotRetention) -> Self {
+        self.snapshot_retention = snapshot_retention;
+        self
+    }
+}
+
+impl BtrfsSourceService {
+    /// Starts `btrfs send` of a snapshot, incrementally on top of the snapshot of `parent`.
+    fn send(
+        &self,
+        snapshot_relative: PathBuf,
+        parent: Option<&BackupEntry>,
+    ) -> io::Result<Box<dyn Read>> {
+        let mut send_command = std::process::Command::new("btrfs");
+        send_command.arg("send").stdout(Stdio::piped());
+        if self.compressed_send {
+            send_command.arg("--compressed-data");
+        }
+        if let Some(parent) = parent {
+            send_command
+                .arg("-p")
+                .arg(self.snapshot_folder.join(&parent.local_snapshot));
+        }
+        send_command.arg(self.snapshot_folder.join(snapshot_relative));
+        let mut send_process = send_command.spawn()?;
+        let output = send_process.stdout.take().unwrap();
+        let mut old_process = self.send_process.replace(Some(send_process));
+        if let Some(mut old_process) = old_process {
+            let _ = old_process.kill();
+        }
+        Ok(Box::new(output))
+    }
+}
+
+/// Every snapshot data-dance creates starts with this prefix.
+const SNAPSHOT_PREFIX: &str = "snapshot_";
+
+/// Picks the data-dance snapshots among `snapshot_names` that `retention` no longer keeps.
+fn expired_snapshots(
+    snapshot_names: &[String],
+    backup_history: &BackupHistory,
+    retention: &SnapshotRetention,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Vec<String> {
+    // File-level backups share the history but never have a local snapshot
+    let mut backup_entries: Vec<_> = backup_history
+        .entries
+        .iter()
+        .filter(|entry| !entry.is_archive())
+        .filter_map(|entry| {
+            let name = entry.local_snapshot.file_name()?.to_string_lossy().to_string();
+            snapshot_names.contains(&name).then_some((name, entry.timestamp))
+        })
+        .collect();
+    backup_entries.sort_by_key(|(_, timestamp)| *timestamp);
+    backup_entries.reverse();
+
+    let keep_within_millis = retention
+        .keep_within_hours
+        .map(|hours| now.timestamp_millis() - (hours * 60 * 60 * 1000) as i64);
+    let retained_names: Vec<&String> = backup_entries
+        .iter()
+        .enumerate()
+        .filter(|(index, (_, timestamp))| {
+            *index < retention.keep_last.max(1)
+                || keep_within_millis.is_some_and(|oldest| *timestamp as i64 >= oldest)
+        })
+        .map(|(_, (name, _))| name)
+        .collect();
+
+    snapshot_names
+        .iter()
+        .filter(|name| name.starts_with(SNAPSHOT_PREFIX))
+        .filter(|name| !retained_names.contains(name))
+        .filter(|name| {
+            !name
+                .split('@')
+                .skip(1)
+                .any(|tag| retention.keep_tags.iter().any(|keep_tag| keep_tag == tag))
+        })
+        .cloned()
+        .collect()
 }
 
 impl SourceService for BtrfsSourceService {
-    fn get_backup_source(&self, backup_history: &BackupHistory) -> io::Result<SourceBackup> {
-        let mut entries = backup_history.entries.clone();
+    fn get_backup_source(
+        &self,
+        backup_history: &BackupHistory,
+        consolidation: &ConsolidationPolicy,
+    ) -> io::Result<SourceBackup> {
+        // Archives record the filesystem root they were made of, which is no snapshot
+        let mut entries: Vec<_> = backup_history
+            .entries
+            .iter()
+            .filter(|entry| !entry.is_archive())
+            .cloned()
+            .collect();
         entries.sort_by_key(|entry| entry.timestamp);
 
         let now = chrono::Utc::now();
-        let new_snapshot_relative_folder = format!("snapshot_{}/", now.format("%Y-%m-%d-%H-%M-%S"));
+        let new_snapshot_relative_folder = format!(
+            "{SNAPSHOT_PREFIX}{}/",
+            now.format("%Y-%m-%d-%H-%M-%S")
+        );
 
         let mut snapshot_creation_command = std::process::Command::new("btrfs");
         snapshot_creation_command
@@ -53,63 +165,66 @@ impl SourceService for BtrfsSourceService {
                 break;
             }
         }
-
-        let mut send_command = std::process::Command::new("btrfs");
-        send_command.arg("send").stdout(Stdio::piped());
-        if self.compressed_send {
-            send_command.arg("--compressed-data");
-        }
-        if let Some(parent_entry) = &parent_entry {
-            send_command
-                .arg("-p")
-                .arg(self.snapshot_folder.join(&parent_entry.local_snapshot));
-        }
-        send_command.arg(self.snapshot_folder.join(&new_snapshot_relative_folder));
-        let mut send_process = send_command.spawn()?;
-        let output = send_process.stdout.take().unwrap();
-        let mut old_process = self.send_process.replace(Some(send_process));
-        if let Some(mut old_process) = old_process {
-            let _ = old_process.kill();
+        if let Some(entry) = &parent_entry
+            && backup_history.needs_new_chain(entry.id, consolidation, now)
+        {
+            parent_entry = None;
         }
 
+        let data_stream = self.send(
+            PathBuf::from(&new_snapshot_relative_folder),
+            parent_entry.as_ref(),
+        )?;
+
         Ok(SourceBackup {
             parent_backup_id: parent_entry.map(|e| e.id),
             local_snapshot_relative: new_snapshot_relative_folder.into(),
-            data_stream: Box::new(output),
+            data_stream,
+        })
+    }
+
+    fn resend_backup_source(
+        &self,
+        local_snapshot: PathBuf,
+        parent: Option<&BackupEntry>,
+    ) -> io::Result<SourceBackup> {
+        let snapshots = std::iter::once(local_snapshot.as_path())
+            .chain(parent.map(|parent| &*parent.local_snapshot));
+        for snapshot in snapshots {
+            if !self.snapshot_folder.join(snapshot).is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "the snapshot {} does not exist any more",
+                        snapshot.display()
+                    ),
+                ));
+            }
+        }
+
+        Ok(SourceBackup {
+            parent_backup_id: parent.map(|parent| parent.id),
+            data_stream: self.send(local_snapshot.clone(), parent)?,
+            local_snapshot_relative: local_snapshot,
         })
     }
 
     fn clear_local_snapshots(&self, backup_history: &BackupHistory) -> io::Result<()> {
-        let all_snapshots = self
+        let snapshot_names = self
             .snapshot_folder
             .read_dir()?
             .filter_map(|entry| entry.ok())
             .filter(|entry| entry.file_type().is_ok_and(|ft| ft.is_dir()))
-            .map(|entry| entry.path())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
             .collect::<Vec<_>>();
-        let mut expired_snapshots = Vec::new();
-
-        let mut backup_entries = backup_history.entries.clone();
-        backup_entries.sort_by_key(|entry| entry.timestamp);
-        backup_entries.reverse();
-        let latest_backup_entries: Vec<_> = backup_entries.into_iter().take(2).collect();
-
-        for snapshot in all_snapshots {
-            let mut retained = false;
-
-            for entry in latest_backup_entries.iter() {
-                if snapshot.ends_with(&entry.local_snapshot) {
-                    retained = true;
-
-                    break;
-                }
-            }
-
-            if retained {
-                continue;
-            }
-            expired_snapshots.push(snapshot);
-        }
+        let expired_snapshots = expired_snapshots(
+            &snapshot_names,
+            backup_history,
+            &self.snapshot_retention,
+            chrono::Utc::now(),
+        )
+        .into_iter()
+        .map(|name| self.snapshot_folder.join(name));
 
         for expired_snapshot in expired_snapshots {
             let mut remove_subv_command = std::process::Command::new("btrfs");
@@ -129,8 +244,126 @@ impl SourceService for BtrfsSourceService {
         Ok(())
     }
 
-    fn get_restore_writer(&self, restored_folder: PathBuf) -> io::Result<Box<dyn Write>> {
-        todo!()
+    fn is_restored(&self, restored_folder: PathBuf) -> io::Result<bool> {
+        let Some(restore_folder) = &self.restore_folder else {
+            return Ok(false);
+        };
+        let restored_path = restore_folder.join(restored_folder);
+        if !restored_path.is_dir() {
+            return Ok(false);
+        }
+
+        // btrfs receive makes a subvolume read-only once it received all of it
+        let mut property_command = std::process::Command::new("btrfs");
+        property_command
+            .args(["property", "get", "-ts"])
+            .arg(&restored_path)
+            .arg("ro")
+            .stderr(Stdio::null());
+        let property_output = property_command.output()?;
+        // Not a subvolume at all
+        if !property_output.status.success() {
+            return Ok(false);
+        }
+        Ok(String::from_utf8_lossy(&property_output.stdout).trim() == "ro=true")
+    }
+
+    fn get_restore_writer(
+        &self,
+        restored_folder: PathBuf,
+        parent_folder: Option<PathBuf>,
+    ) -> io::Result<Box<dyn Write>> {
+        let Some(restore_folder) = &self.restore_folder else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no restore_folder is configured for the btrfs source",
+            ));
+        };
+
+        let restored_path = restore_folder.join(&restored_folder);
+        if restored_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("'{}' was already restored", restored_path.display()),
+            ));
+        }
+        // Incremental streams reference their parent by its received UUID
+        if let Some(parent_folder) = parent_folder {
+            let parent_path = restore_folder.join(&parent_folder);
+            if !parent_path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "parent '{}' must be received before '{}'",
+                        parent_path.display(),
+                        restored_path.display()
+                    ),
+                ));
+            }
+        }
+        std::fs::create_dir_all(restore_folder)?;
+
+        let mut receive_command = std::process::Command::new("btrfs");
+        receive_command
+            .arg("receive")
+            .arg(restore_folder)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let receive_process = receive_command.spawn()?;
+
+        let writer = CheckedStdin::new(receive_process, "btrfs receive")?;
+        self.receive_outcome.replace(Some(writer.outcome()));
+        // A failed receive can leave a partial subvolume behind
+        self.received.borrow_mut().push(restored_path);
+        Ok(Box::new(writer))
+    }
+
+    fn finish_restore(&self) -> io::Result<()> {
+        match self.receive_outcome.take() {
+            Some(outcome) => outcome.take(),
+            None => Err(io::Error::other("no btrfs receive was started")),
+        }
+    }
+
+    fn snapshot_manifest(&self, local_snapshot: PathBuf) -> io::Result<SnapshotManifest> {
+        folder_manifest(&self.snapshot_folder.join(local_snapshot))
+    }
+
+    fn restored_manifest(&self, restored_folder: PathBuf) -> io::Result<SnapshotManifest> {
+        let Some(restore_folder) = &self.restore_folder else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no restore_folder is configured for the btrfs source",
+            ));
+        };
+        folder_manifest(&restore_folder.join(restored_folder))
+    }
+
+    fn clear_restored(&self) -> io::Result<()> {
+        let mut received = self.received.borrow_mut();
+        while let Some(restored_path) = received.pop() {
+            if !restored_path.exists() {
+                continue;
+            }
+            let mut remove_subv_command = std::process::Command::new("btrfs");
+            remove_subv_command
+                .args(["subvolume", "delete", "-c"])
+                .arg(&restored_path)
+                .stdout(Stdio::null());
+            let remove_subv_status = remove_subv_command.status()?;
+            if !remove_subv_status.success() {
+                return Err(io::Error::other(format!(
+                    "btrfs subvolume delete of '{}' failed with {remove_subv_status}",
+                    restored_path.display()
+                )));
+            }
+        }
+        // Only succeeds if nothing else is in there
+        if let Some(restore_folder) = &self.restore_folder {
+            let _ = std::fs::remove_dir(restore_folder);
+        }
+        Ok(())
     }
 }
 
@@ -141,3 +374,72 @@ impl Drop for BtrfsSourceService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::BackupEntry;
+
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    fn history(snapshots: &[(&str, u64)]) -> BackupHistory {
+        BackupHistory {
+            entries: snapshots
+                .iter()
+                .enumerate()
+                .map(|(index, (name, timestamp))| BackupEntry {
+                    timestamp: *timestamp,
+                    remote_filename: format!("{name}.bin").into(),
+                    local_snapshot: format!("{name}/").into(),
+                    ..BackupEntry::test(index as u32, None)
+                })
+                .collect(),
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_latest_and_foreign_snapshots() {
+        let snapshot_names = names(&[
+            "snapshot_1",
+            "snapshot_2",
+            "snapshot_3",
+            "manual",
+            "snapshot_orphan",
+        ]);
+        let history = history(&[
+            ("snapshot_1", HOUR),
+            ("snapshot_2", 2 * HOUR),
+            ("snapshot_3", 3 * HOUR),
+        ]);
+        let now = chrono::DateTime::from_timestamp_millis((100 * HOUR) as i64).unwrap();
+
+        let expired =
+            expired_snapshots(&snapshot_names, &history, &SnapshotRetention::default(), now);
+
+        assert_eq!(expired, names(&["snapshot_1", "snapshot_orphan"]));
+    }
+
+    #[test]
+    fn keeps_young_and_tagged_snapshots() {
+        let snapshot_names = names(&["snapshot_1@keep", "snapshot_2", "snapshot_3", "snapshot_4"]);
+        let history = history(&[
+            ("snapshot_2", HOUR),
+            ("snapshot_3", 10 * HOUR),
+            ("snapshot_4", 11 * HOUR),
+        ]);
+        let now = chrono::DateTime::from_timestamp_millis((12 * HOUR) as i64).unwrap();
+        let retention = SnapshotRetention {
+            keep_last: 1,
+            keep_within_hours: Some(3),
+            keep_tags: vec!["keep".to_string()],
+        };
+
+        let expired = expired_snapshots(&snapshot_names, &history, &retention, now);
+
+        assert_eq!(expired, names(&["snapshot_2"]));
+    }
+}
@@ -1,6 +1,9 @@
-use crate::objects::{BackupEntry, BackupHistory};
+use crate::config::ConsolidationPolicy;
+use crate::objects::{BackupEntry, BackupHistory, SnapshotManifest};
 use crate::services::data_source::{SourceBackup, SourceService};
-use rand::{thread_rng, Rng, RngCore};
+use crate::services::tracking::{ContentHash, HashingReader};
+use rand::rngs::StdRng;
+use rand::{random, thread_rng, Rng, RngCore, SeedableRng};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::io::{Read, Repeat, Write};
@@ -14,6 +17,12 @@ pub struct FakeSourceService {
     pub local_snapshot: PathBuf,
     pub backup_byte_size: usize,
     local_snapshots_cleared: Arc<Mutex<bool>>,
+    restored_snapshots: Arc<Mutex<Vec<(PathBuf, Vec<u8>)>>>,
+    /// Every snapshot is a single file holding the bytes of its stream.
+    snapshot_hashes: Mutex<HashMap<PathBuf, ContentHash>>,
+    /// Seeds the stream of every snapshot, random unless set by `with_seed`.
+    seed: Option<u64>,
+    snapshot_seeds: Mutex<HashMap<PathBuf, u64>>,
 }
 
 impl FakeSourceService {
@@ -22,22 +31,64 @@ impl FakeSourceService {
             local_snapshot,
             backup_byte_size,
             local_snapshots_cleared: Arc::new(Mutex::new(false)),
+            restored_snapshots: Arc::new(Mutex::new(Vec::new())),
+            snapshot_hashes: Mutex::new(HashMap::new()),
+            seed: None,
+            snapshot_seeds: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Makes the streams the same on every service with the same seed, so an upload can be
+    /// resumed by another one.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn stream(&self, local_snapshot: PathBuf, seed: u64) -> Box<dyn Read> {
+        let data_stream = HashingReader::new(RandomByteReader::new(
+            StdRng::seed_from_u64(seed),
+            self.backup_byte_size,
+        ));
+        self.snapshot_hashes
+            .lock()
+            .unwrap()
+            .insert(local_snapshot.clone(), data_stream.hash());
+        self.snapshot_seeds
+            .lock()
+            .unwrap()
+            .insert(local_snapshot, seed);
+        Box::new(data_stream)
+    }
+
+    /// Receives into the same restore folder as the service of `debug_data`, like a later job
+    /// on the same host.
+    pub fn with_restored_snapshots_of(mut self, debug_data: &FakeSourceServiceDebugData) -> Self {
+        self.restored_snapshots = Arc::clone(&debug_data.restored_snapshots);
+        self
+    }
+
     pub fn live_debug_data(&self) -> FakeSourceServiceDebugData {
         FakeSourceServiceDebugData {
             local_snapshots_cleared: Arc::clone(&self.local_snapshots_cleared),
+            restored_snapshots: Arc::clone(&self.restored_snapshots),
         }
     }
 }
 
 impl SourceService for FakeSourceService {
-    fn get_backup_source(&self, backup_history: &BackupHistory) -> io::Result<SourceBackup> {
+    fn get_backup_source(
+        &self,
+        backup_history: &BackupHistory,
+        consolidation: &ConsolidationPolicy,
+    ) -> io::Result<SourceBackup> {
         thread::sleep(Duration::from_secs(2));
 
         let mut latest_backup: Option<BackupEntry> = None;
         for backup in backup_history.entries.clone() {
+            if backup.is_archive() {
+                continue;
+            }
             if let Some(latest) = &latest_backup {
                 if backup.timestamp >= latest.timestamp {
                     latest_backup = Some(backup);
@@ -47,10 +98,41 @@ impl SourceService for FakeSourceService {
             }
         }
 
+        let now = chrono::Utc::now();
+        let parent_backup = latest_backup
+            .filter(|latest| !backup_history.needs_new_chain(latest.id, consolidation, now));
+
+        let seed = self.seed.unwrap_or_else(random);
+
         Ok(SourceBackup {
-            parent_backup_id: latest_backup.map(|b| b.id),
+            parent_backup_id: parent_backup.map(|b| b.id),
             local_snapshot_relative: self.local_snapshot.clone(),
-            data_stream: Box::new(RandomByteReader::new(thread_rng(), self.backup_byte_size)),
+            data_stream: self.stream(self.local_snapshot.clone(), seed),
+        })
+    }
+
+    fn resend_backup_source(
+        &self,
+        local_snapshot: PathBuf,
+        parent: Option<&BackupEntry>,
+    ) -> io::Result<SourceBackup> {
+        let seed = self
+            .snapshot_seeds
+            .lock()
+            .unwrap()
+            .get(&local_snapshot)
+            .copied();
+        let Some(seed) = seed.or(self.seed) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("the snapshot {} does not exist", local_snapshot.display()),
+            ));
+        };
+
+        Ok(SourceBackup {
+            parent_backup_id: parent.map(|parent| parent.id),
+            local_snapshot_relative: local_snapshot.clone(),
+            data_stream: self.stream(local_snapshot, seed),
         })
     }
 
@@ -63,19 +145,102 @@ impl SourceService for FakeSourceService {
         Ok(())
     }
 
-    fn get_restore_writer(&self, restored_folder: PathBuf) -> std::io::Result<Box<dyn Write>> {
-        todo!()
+    fn is_restored(&self, restored_folder: PathBuf) -> io::Result<bool> {
+        let restored_lock = self.restored_snapshots.lock().unwrap();
+        Ok(restored_lock.iter().any(|(folder, _)| *folder == restored_folder))
+    }
+
+    fn get_restore_writer(
+        &self,
+        restored_folder: PathBuf,
+        parent_folder: Option<PathBuf>,
+    ) -> std::io::Result<Box<dyn Write>> {
+        let mut restored_lock = self.restored_snapshots.lock().unwrap();
+        if restored_lock.iter().any(|(folder, _)| *folder == restored_folder) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} was already restored", restored_folder.display()),
+            ));
+        }
+        if let Some(parent_folder) = parent_folder {
+            if !restored_lock.iter().any(|(folder, _)| *folder == parent_folder) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("parent {} was not restored", parent_folder.display()),
+                ));
+            }
+        }
+        restored_lock.push((restored_folder, Vec::new()));
+        let index = restored_lock.len() - 1;
+        Ok(Box::new(FakeRestoreWriter {
+            restored_snapshots: Arc::clone(&self.restored_snapshots),
+            index,
+        }))
+    }
+
+    fn finish_restore(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot_manifest(&self, local_snapshot: PathBuf) -> io::Result<SnapshotManifest> {
+        let hashes_lock = self.snapshot_hashes.lock().unwrap();
+        match hashes_lock.get(&local_snapshot) {
+            Some(hash) => Ok(SnapshotManifest {
+                file_count: 1,
+                tree_hash: hash.value(),
+            }),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn restored_manifest(&self, restored_folder: PathBuf) -> io::Result<SnapshotManifest> {
+        let restored_lock = self.restored_snapshots.lock().unwrap();
+        match restored_lock.iter().find(|(folder, _)| *folder == restored_folder) {
+            Some((_, content)) => Ok(SnapshotManifest {
+                file_count: 1,
+                tree_hash: ContentHash::of_reader(content.as_slice())?,
+            }),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    fn clear_restored(&self) -> io::Result<()> {
+        self.restored_snapshots.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+struct FakeRestoreWriter {
+    restored_snapshots: Arc<Mutex<Vec<(PathBuf, Vec<u8>)>>>,
+    index: usize,
+}
+
+impl Write for FakeRestoreWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut restored_lock = self.restored_snapshots.lock().unwrap();
+        restored_lock[self.index].1.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
     }
 }
 
 pub struct FakeSourceServiceDebugData {
     local_snapshots_cleared: Arc<Mutex<bool>>,
+    restored_snapshots: Arc<Mutex<Vec<(PathBuf, Vec<u8>)>>>,
 }
 
 impl FakeSourceServiceDebugData {
     pub fn local_snapshots_cleared(&self) -> bool {
         *self.local_snapshots_cleared.lock().unwrap()
     }
+
+    /// Returns the restored snapshots in the order they were received.
+    pub fn restored_snapshots(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        self.restored_snapshots.lock().unwrap().clone()
+    }
 }
 
 pub struct RandomByteReader<R: RngCore> {
@@ -0,0 +1,95 @@
+use crate::objects::SnapshotManifest;
+use crate::services::tracking::HashingWriter;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+/// Walks the tree below `root` without following symlinks.
+pub fn folder_manifest(root: &Path) -> io::Result<SnapshotManifest> {
+    let mut hasher = HashingWriter::new(io::sink());
+    let mut file_count = 0;
+    hash_folder(root, Path::new(""), &mut hasher, &mut file_count)?;
+    Ok(SnapshotManifest {
+        file_count,
+        tree_hash: hasher.hash().value(),
+    })
+}
+
+fn hash_folder(
+    root: &Path,
+    relative: &Path,
+    hasher: &mut HashingWriter<io::Sink>,
+    file_count: &mut u64,
+) -> io::Result<()> {
+    let mut names = fs::read_dir(root.join(relative))?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<Vec<_>>>()?;
+    names.sort();
+
+    for name in names {
+        let relative = relative.join(name);
+        let path = root.join(&relative);
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        // Separators keep the concatenation of path, type and content unambiguous
+        hasher.write_all(relative.as_os_str().as_encoded_bytes())?;
+        hasher.write_all(b"\0")?;
+        if file_type.is_dir() {
+            hasher.write_all(b"d")?;
+            hash_folder(root, &relative, hasher, file_count)?;
+            continue;
+        }
+
+        *file_count += 1;
+        if file_type.is_symlink() {
+            let target = fs::read_link(&path)?;
+            hasher.write_all(b"l")?;
+            hasher.write_all(&(target.as_os_str().len() as u64).to_le_bytes())?;
+            hasher.write_all(target.as_os_str().as_encoded_bytes())?;
+        } else if file_type.is_file() {
+            hasher.write_all(b"f")?;
+            hasher.write_all(&fs::metadata(&path)?.len().to_le_bytes())?;
+            io::copy(&mut fs::File::open(&path)?, hasher)?;
+        } else {
+            // Devices, fifos and sockets have no content to compare
+            hasher.write_all(b"o")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tree(files: &[(&str, &[u8])]) -> std::path::PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("data-dance-manifest-{}", rand::random::<u64>()));
+        for (file, content) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn equal_trees_have_equal_manifests() {
+        let files: &[(&str, &[u8])] = &[("top.txt", b"top"), ("nested/inner.txt", b"inner")];
+        let first = make_tree(files);
+        let second = make_tree(files);
+        let changed = make_tree(&[("top.txt", b"top"), ("nested/inner.txt", b"other")]);
+
+        let first_manifest = folder_manifest(&first).unwrap();
+        let second_manifest = folder_manifest(&second).unwrap();
+        let changed_manifest = folder_manifest(&changed).unwrap();
+        for root in [first, second, changed] {
+            fs::remove_dir_all(root).unwrap();
+        }
+
+        assert_eq!(first_manifest.file_count, 2);
+        assert_eq!(first_manifest, second_manifest);
+        assert_eq!(changed_manifest.file_count, 2);
+        assert_ne!(changed_manifest.tree_hash, first_manifest.tree_hash);
+    }
+}
@@ -1,17 +1,60 @@
 pub mod btrfs;
 pub mod fake;
+mod manifest;
 
-use crate::objects::BackupHistory;
+use crate::config::{ConsolidationPolicy, DataDanceConfiguration, LocalSource};
+use crate::objects::{BackupEntry, BackupHistory, SnapshotManifest};
+use crate::services::data_source::btrfs::BtrfsSourceService;
+use crate::services::data_source::fake::FakeSourceService;
 use std::io;
 use std::io::{Read, Write};
 use std::path::PathBuf;
 
+pub use manifest::folder_manifest;
+
 pub trait SourceService {
-    fn get_backup_source(&self, backup_history: &BackupHistory) -> io::Result<SourceBackup>;
+    /// Snapshots the source, incrementally on top of the latest backup unless `consolidation`
+    /// asks for a new full backup.
+    fn get_backup_source(
+        &self,
+        backup_history: &BackupHistory,
+        consolidation: &ConsolidationPolicy,
+    ) -> io::Result<SourceBackup>;
+
+    /// Sends a snapshot made by `get_backup_source` again, on top of the snapshot of `parent`,
+    /// to resume its upload.
+    fn resend_backup_source(
+        &self,
+        local_snapshot: PathBuf,
+        parent: Option<&BackupEntry>,
+    ) -> io::Result<SourceBackup>;
 
     fn clear_local_snapshots(&self, backup_history: &BackupHistory) -> io::Result<()>;
 
-    fn get_restore_writer(&self, restored_folder: PathBuf) -> io::Result<Box<dyn Write>>;
+    /// Whether `restored_folder` was received completely before, so it can be the parent of the
+    /// next link of a chain without being received again.
+    fn is_restored(&self, restored_folder: PathBuf) -> io::Result<bool>;
+
+    /// Opens a writer receiving the backup stream of `restored_folder`, which must not exist yet.
+    /// The `parent_folder` must already be restored if the stream is incremental.
+    fn get_restore_writer(
+        &self,
+        restored_folder: PathBuf,
+        parent_folder: Option<PathBuf>,
+    ) -> io::Result<Box<dyn Write>>;
+
+    /// Waits for the last restore writer to be applied and reports its outcome.
+    fn finish_restore(&self) -> io::Result<()>;
+
+    /// Describes a local snapshot made by `get_backup_source`.
+    fn snapshot_manifest(&self, local_snapshot: PathBuf) -> io::Result<SnapshotManifest>;
+
+    /// Describes a folder received through `get_restore_writer`.
+    fn restored_manifest(&self, restored_folder: PathBuf) -> io::Result<SnapshotManifest>;
+
+    /// Deletes every folder this service received, newest first, and the restore folder if it
+    /// is empty then. Nothing else in the restore folder is touched.
+    fn clear_restored(&self) -> io::Result<()>;
 }
 
 pub struct SourceBackup {
@@ -19,3 +62,25 @@ pub struct SourceBackup {
     pub local_snapshot_relative: PathBuf,
     pub data_stream: Box<dyn Read>,
 }
+
+pub fn source_service_from_config(
+    config: &DataDanceConfiguration,
+) -> Box<dyn SourceService + Send> {
+    match config.local_storage.source.clone() {
+        LocalSource::Btrfs {
+            snapshots_folder,
+            source_folder,
+            send_compressed_data,
+            restore_folder,
+            snapshot_retention,
+        } => Box::new(
+            BtrfsSourceService::new(snapshots_folder, source_folder, send_compressed_data)
+                .with_restore_folder(restore_folder)
+                .with_snapshot_retention(snapshot_retention),
+        ),
+        LocalSource::Fake { backup_byte_size } => Box::new(FakeSourceService::new(
+            "fake_snapshot".into(),
+            backup_byte_size,
+        )),
+    }
+}
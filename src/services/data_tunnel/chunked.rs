@@ -0,0 +1,324 @@
+use crate::services::chunk_store::{ChunkIndex, ChunkRef, ChunkStore};
+use crate::services::data_tunnel::{DataTunnel, DecodingDataTunnel, EncodingDataTunnel};
+use crate::services::tracking::{BytesCounter, BytesCountingReader, BytesCountingWriter};
+use fastcdc::v2020::StreamCDC;
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Shared between a chunked transfer and whoever reports on it.
+#[derive(Clone, Default)]
+pub struct ChunkStats {
+    chunks: Arc<AtomicU64>,
+    new_chunks: Arc<AtomicU64>,
+    resumed_chunks: Arc<AtomicU64>,
+    /// Bytes of the chunks and the index moved to or from the remote.
+    stored_bytes: Arc<AtomicU64>,
+}
+
+impl ChunkStats {
+    pub fn chunks(&self) -> u64 {
+        self.chunks.load(Ordering::Relaxed)
+    }
+
+    /// Chunks that were not on the remote yet, only counted when uploading.
+    pub fn new_chunks(&self) -> u64 {
+        self.new_chunks.load(Ordering::Relaxed)
+    }
+
+    /// Chunks an interrupted upload had stored already, only counted when uploading.
+    pub fn resumed_chunks(&self) -> u64 {
+        self.resumed_chunks.load(Ordering::Relaxed)
+    }
+
+    pub fn stored_bytes_counter(&self) -> BytesCounter {
+        BytesCounter::new(&self.stored_bytes)
+    }
+
+    fn add_stored_bytes(&self, bytes: u64) {
+        self.stored_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+/// Where a `ChunkingDataTunnel` cuts the plaintext.
+#[derive(Clone, Copy, Debug)]
+pub enum ChunkCut {
+    /// Between a quarter and four times `average_size` bytes, at points that depend on the
+    /// content only, so unchanged data is cut the same in every backup.
+    ContentDefined { average_size: u32 },
+    /// Every `size` bytes.
+    FixedSize { size: u64 },
+}
+
+/// Splits the plaintext into chunks and uploads the ones the store does not have yet, encoded
+/// one by one. The writer receives the encoded index of the chunks.
+pub struct ChunkingDataTunnel<'a> {
+    pub encoding_data_tunnel: EncodingDataTunnel,
+    pub store: ChunkStore<'a>,
+    pub cut: ChunkCut,
+    pub stats: ChunkStats,
+    /// Called with the number of every chunk once it is on the remote.
+    pub on_stored: Option<&'a dyn Fn(usize, &ChunkRef) -> io::Result<()>>,
+    /// The first chunks of an interrupted upload of the same stream, which the remote confirmed.
+    /// They are not sent again as long as the stream cuts into the same chunks.
+    pub resumed: &'a [ChunkRef],
+}
+
+impl DataTunnel for ChunkingDataTunnel<'_> {
+    fn transfer<R: Read + 'static, W: Write + 'static>(
+        &self,
+        reader: R,
+        writer: W,
+    ) -> Result<(), io::Error> {
+        let mut stored_ids = self.store.stored_ids()?;
+        // Chunks never build on a parent, only the index records it
+        let chunk_data_tunnel = EncodingDataTunnel {
+            parent: None,
+            ..self.encoding_data_tunnel.clone()
+        };
+        let session = self.encoding_data_tunnel.encryption_level.session()?;
+
+        let mut index = ChunkIndex::default();
+        let mut resuming = true;
+        cut_chunks(reader, self.cut, |data| {
+            let id = self.store.chunk_id(&data);
+            let size = data.len() as u64;
+            // Once the stream differs from the interrupted one, its chunks say nothing
+            resuming &= self
+                .resumed
+                .get(index.chunks.len())
+                .is_some_and(|resumed| resumed.id == id && resumed.size == size);
+            if resuming {
+                self.stats.resumed_chunks.fetch_add(1, Ordering::Relaxed);
+            } else if !stored_ids.contains(&id) {
+                let written_bytes =
+                    self.store
+                        .write_chunk(&id, data, &chunk_data_tunnel, &session)?;
+                self.stats.add_stored_bytes(written_bytes);
+                self.stats.new_chunks.fetch_add(1, Ordering::Relaxed);
+                stored_ids.insert(id.clone());
+            }
+            self.stats.chunks.fetch_add(1, Ordering::Relaxed);
+            let chunk = ChunkRef { id, size };
+            if let Some(on_stored) = self.on_stored {
+                on_stored(index.chunks.len(), &chunk)?;
+            }
+            index.chunks.push(chunk);
+            Ok(())
+        })?;
+
+        let writer = BytesCountingWriter::new(writer);
+        let written_bytes = writer.counter();
+        self.encoding_data_tunnel
+            .transfer(Cursor::new(index.to_bytes()), writer)?;
+        self.stats.add_stored_bytes(written_bytes.value());
+        Ok(())
+    }
+}
+
+fn cut_chunks(
+    mut reader: impl Read,
+    cut: ChunkCut,
+    mut on_chunk: impl FnMut(Vec<u8>) -> io::Result<()>,
+) -> io::Result<()> {
+    match cut {
+        ChunkCut::ContentDefined { average_size } => {
+            let chunker = StreamCDC::new(reader, average_size / 4, average_size, average_size * 4);
+            for chunk in chunker {
+                on_chunk(chunk?.data)?;
+            }
+        }
+        ChunkCut::FixedSize { size } => loop {
+            let mut data = Vec::new();
+            (&mut reader).take(size).read_to_end(&mut data)?;
+            if data.is_empty() {
+                break;
+            }
+            on_chunk(data)?;
+        },
+    }
+    Ok(())
+}
+
+/// Reads the encoded index of a chunked backup and writes the plaintext of its chunks.
+pub struct UnchunkingDataTunnel<'a> {
+    pub decoding_data_tunnel: DecodingDataTunnel,
+    pub store: ChunkStore<'a>,
+    pub stats: ChunkStats,
+}
+
+impl DataTunnel for UnchunkingDataTunnel<'_> {
+    fn transfer<R: Read + 'static, W: Write + 'static>(
+        &self,
+        reader: R,
+        mut writer: W,
+    ) -> Result<(), io::Error> {
+        let reader = BytesCountingReader::new(reader);
+        let read_bytes = reader.counter();
+        let (_, decoder) = self.decoding_data_tunnel.decoder(reader)?;
+        let index = ChunkIndex::read_from(decoder)?;
+        self.stats.add_stored_bytes(read_bytes.value());
+
+        for chunk in &index.chunks {
+            let read_bytes =
+                self.store
+                    .read_chunk(chunk, &self.decoding_data_tunnel, &mut writer)?;
+            self.stats.add_stored_bytes(read_bytes);
+            self.stats.chunks.fetch_add(1, Ordering::Relaxed);
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::{BackupSource, CompressionAlgorithm, CompressionLevel, EncryptionLevel};
+    use crate::services::channels::ChannelWriter;
+    use crate::services::data_dest::DestService;
+    use crate::services::data_dest::fake::FakeDestService;
+    use std::sync::mpsc;
+
+    fn encryption_level() -> EncryptionLevel {
+        EncryptionLevel::Symmetrical {
+            password: "pwd123".into(),
+        }
+    }
+
+    /// Incompressible, but the same on every call.
+    fn content(len: usize) -> Vec<u8> {
+        let mut state = 0x2545f4914f6cdd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn upload(dest: &FakeDestService, file: &str, content: Vec<u8>) -> ChunkStats {
+        upload_resumed(dest, file, content, &[])
+    }
+
+    fn upload_resumed(
+        dest: &FakeDestService,
+        file: &str,
+        content: Vec<u8>,
+        resumed: &[ChunkRef],
+    ) -> ChunkStats {
+        let stats = ChunkStats::default();
+        let tunnel = ChunkingDataTunnel {
+            encoding_data_tunnel: EncodingDataTunnel {
+                compression: CompressionLevel::Fast.into(),
+                encryption_level: encryption_level(),
+                source: BackupSource::BtrfsStream,
+                parent: None,
+                compression_mix: Default::default(),
+            },
+            store: ChunkStore::new(dest, &encryption_level()),
+            cut: ChunkCut::ContentDefined { average_size: 4096 },
+            stats: stats.clone(),
+            on_stored: None,
+            resumed,
+        };
+        let writer = dest.get_backup_writer(file.into()).unwrap();
+        tunnel.transfer(Cursor::new(content), writer).unwrap();
+        stats
+    }
+
+    fn download(dest: &FakeDestService, file: &str) -> io::Result<Vec<u8>> {
+        let tunnel = UnchunkingDataTunnel {
+            decoding_data_tunnel: DecodingDataTunnel {
+                compression: CompressionAlgorithm::Zstd,
+                encryption_level: encryption_level(),
+            },
+            store: ChunkStore::new(dest, &encryption_level()),
+            stats: ChunkStats::default(),
+        };
+        let (tx, rx) = mpsc::channel();
+        tunnel.transfer(dest.get_backup_reader(file.into())?, ChannelWriter::new(tx))?;
+        Ok(rx.iter().collect())
+    }
+
+    #[test]
+    fn test_chunked_backups_share_unchanged_chunks() {
+        let dest = FakeDestService::empty();
+        let original = content(256 * 1024);
+        let mut changed = original.clone();
+        changed[100 * 1024..100 * 1024 + 16].fill(0);
+
+        let first = upload(&dest, "first.bin", original.clone());
+        let second = upload(&dest, "second.bin", changed.clone());
+
+        assert_eq!(first.new_chunks(), first.chunks());
+        assert!(second.chunks() > 10);
+        assert!((1..=2).contains(&second.new_chunks()));
+        let stored = dest.list_chunk_files().unwrap().len() as u64;
+        assert_eq!(stored, first.new_chunks() + second.new_chunks());
+        assert_eq!(download(&dest, "first.bin").unwrap(), original);
+        assert_eq!(download(&dest, "second.bin").unwrap(), changed);
+    }
+
+    #[test]
+    fn test_chunked_backup_detects_swapped_chunk() {
+        let dest = FakeDestService::empty();
+        upload(&dest, "backup.bin", content(64 * 1024));
+        let debug_data = dest.live_debug_data();
+        let mut chunk_files = dest.list_chunk_files().unwrap();
+        chunk_files.sort();
+        let other = debug_data.file(&chunk_files[1]).unwrap();
+        dest.remove_backup_file(chunk_files[0].clone()).unwrap();
+        dest.get_backup_writer(chunk_files[0].clone())
+            .unwrap()
+            .write_all(&other)
+            .unwrap();
+
+        let err = download(&dest, "backup.bin").unwrap_err();
+
+        let id = chunk_files[0].file_stem().unwrap().to_string_lossy();
+        assert_eq!(
+            err.to_string(),
+            format!("chunk {id} is corrupted, it does not match its id")
+        );
+    }
+
+    #[test]
+    fn test_chunked_upload_skips_the_resumed_chunks() {
+        let dest = FakeDestService::empty();
+        let original = content(64 * 1024);
+        let store = ChunkStore::new(&dest, &encryption_level());
+        let mut chunks = Vec::new();
+        let cut = ChunkCut::ContentDefined { average_size: 4096 };
+        cut_chunks(Cursor::new(original.clone()), cut, |data| {
+            let id = store.chunk_id(&data);
+            chunks.push(ChunkRef {
+                id,
+                size: data.len() as u64,
+            });
+            Ok(())
+        })
+        .unwrap();
+        // Only the first two chunks made it before the upload was interrupted
+        upload(&dest, "interrupted.bin", original.clone());
+        dest.remove_backup_file("interrupted.bin".into()).unwrap();
+        for chunk in &chunks[2..] {
+            dest.remove_backup_file(format!("{}.chunk", chunk.id).into())
+                .unwrap();
+        }
+        let mut diverged = chunks[..2].to_vec();
+        diverged[1].size += 1;
+
+        let resumed = upload_resumed(&dest, "resumed.bin", original.clone(), &chunks[..2]);
+        let stale = upload_resumed(&dest, "stale.bin", original.clone(), &diverged);
+
+        assert_eq!(resumed.resumed_chunks(), 2);
+        assert_eq!(resumed.new_chunks(), chunks.len() as u64 - 2);
+        assert_eq!(download(&dest, "resumed.bin").unwrap(), original);
+        assert_eq!(stale.resumed_chunks(), 1);
+        assert_eq!(stale.new_chunks(), 0);
+    }
+}
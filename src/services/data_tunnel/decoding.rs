@@ -1,23 +1,121 @@
-use crate::objects::{CompressionLevel, EncryptionLevel};
+use crate::config::{RECIPIENTS_KEY_ID, RemoteStorageConfig, UNENCRYPTED_KEY_ID};
+use crate::objects::{
+    BackupEntry, Compression, CompressionAlgorithm, ContainerHeader, EncryptionLevel,
+    EncryptionScheme,
+};
 use crate::services::data_tunnel::DataTunnel;
 use std::io;
 use std::io::{BufRead, Read, Write};
 
+/// Decodes files according to their container header. The configured settings are only used
+/// for files written before the container format, the password is needed either way.
 #[derive(Clone)]
 pub struct DecodingDataTunnel {
-    pub compression_level: CompressionLevel,
+    /// Algorithm of files without a container header.
+    pub compression: CompressionAlgorithm,
     pub encryption_level: EncryptionLevel,
 }
 
+impl DecodingDataTunnel {
+    fn configure(&self, header: &ContainerHeader) -> io::Result<EncryptionLevel> {
+        match (&header.encryption, &self.encryption_level) {
+            (EncryptionScheme::None, EncryptionLevel::None) => Ok(EncryptionLevel::None),
+            // Accepting unencrypted files would let anyone with access to the remote forge backups
+            (EncryptionScheme::None, _) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the backup is not encrypted but encryption is configured",
+            )),
+            (_, EncryptionLevel::None) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the backup is encrypted but no encryption is configured",
+            )),
+            (_, level) => Ok(level.clone()),
+        }
+    }
+
+    /// Reader of the decoded content of `reader`, together with its container header.
+    pub fn decoder<R: Read + 'static>(
+        &self,
+        reader: R,
+    ) -> io::Result<(Option<ContainerHeader>, Box<dyn Read>)> {
+        let (header, reader) = ContainerHeader::read_from(reader)?;
+        let (encryption_level, associated_data) = match &header {
+            Some((header, raw_header)) => (self.configure(header)?, raw_header.clone()),
+            None => (self.encryption_level.clone(), Vec::new()),
+        };
+
+        let compression = match &header {
+            Some((header, _)) => header.compression.algorithm,
+            None => self.compression,
+        };
+
+        let declared = header.as_ref().map(|(header, _)| &header.encryption);
+        let decryptor = encryption_level.to_decoder(reader, declared, &associated_data);
+        let decompressor = compression.to_decoder(decryptor)?;
+        Ok((header.map(|(header, _)| header), decompressor))
+    }
+
+    /// Tunnel for the file of `entry`, decrypted with the key and decompressed with the
+    /// algorithm it was recorded with. Backups without a recorded key id use the configured
+    /// encryption unless the keyring holds the default key, backups encrypted to recipients
+    /// always use the configured identities. Backups recorded as unencrypted are read as such.
+    pub fn for_entry(
+        &self,
+        remote_storage: &RemoteStorageConfig,
+        entry: &BackupEntry,
+    ) -> io::Result<DecodingDataTunnel> {
+        let key_id = entry.key_id.as_deref();
+        let encryption_level = match (remote_storage.password_for(key_id), key_id) {
+            (_, Some(RECIPIENTS_KEY_ID)) => match &self.encryption_level {
+                level @ EncryptionLevel::Recipients { .. } => level.clone(),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "the backup is encrypted to recipients but none are configured",
+                    ));
+                }
+            },
+            (_, Some(UNENCRYPTED_KEY_ID)) => EncryptionLevel::None,
+            (Some(password), _) => EncryptionLevel::Symmetrical {
+                password: password.clone(),
+            },
+            (None, None) => self.encryption_level.clone(),
+            (None, Some(key_id)) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("the key '{key_id}' is not in the keyring"),
+                ));
+            }
+        };
+
+        Ok(DecodingDataTunnel {
+            compression: match entry.compression {
+                Some(compression) => compression.algorithm,
+                None => self.compression,
+            },
+            encryption_level,
+        })
+    }
+
+    /// Whether the content can be decoded. The secret keys of recipients are only given for a
+    /// single restore, without them only the stored file can be checked.
+    pub fn can_decode(&self) -> bool {
+        match &self.encryption_level {
+            EncryptionLevel::Recipients { identities, .. } => !identities.is_empty(),
+            _ => true,
+        }
+    }
+}
+
 impl DataTunnel for DecodingDataTunnel {
     fn transfer<R: Read + 'static, W: Write + 'static>(
         &self,
         reader: R,
         mut writer: W,
     ) -> Result<(), io::Error> {
-        let decryptor = self.encryption_level.to_decoder(reader);
-        let mut decompressor = self.compression_level.to_zstd_decoder(decryptor);
-        io::copy(&mut decompressor, &mut writer)?;
+        let (_, mut decoder) = self.decoder(reader)?;
+        io::copy(&mut decoder, &mut writer)?;
+        writer.flush()?;
         Ok(())
     }
 }
@@ -25,7 +123,7 @@ impl DataTunnel for DecodingDataTunnel {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::objects::CompressionLevel;
+    use crate::objects::{BackupSource, CompressionLevel};
     use crate::services::channels::ChannelWriter;
     use crate::services::data_tunnel::encoding::EncodingDataTunnel;
     use std::io::Cursor;
@@ -48,12 +146,15 @@ mod tests {
         decoding_options: TunnelOptions,
     ) -> io::Result<(Vec<u8>, Vec<u8>)> {
         let mut encoder = EncodingDataTunnel {
-            compression_level: encoding_options.compression_level,
+            compression: encoding_options.compression_level.into(),
             encryption_level: encoding_options.encryption_level,
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         let mut decoder = DecodingDataTunnel {
-            compression_level: decoding_options.compression_level,
+            compression: Compression::from(decoding_options.compression_level).algorithm,
             encryption_level: decoding_options.encryption_level,
         };
 
@@ -192,4 +293,89 @@ mod tests {
         );
         assert_eq!(input, output);
     }
+
+    fn decode(decoder: DecodingDataTunnel, encoded: Vec<u8>) -> io::Result<Vec<u8>> {
+        let (tx, rx) = mpsc::channel();
+        decoder.transfer(Cursor::new(encoded), ChannelWriter::new(tx))?;
+        Ok(rx.iter().collect())
+    }
+
+    #[test]
+    fn files_without_container_use_configured_algorithm() {
+        let input = b"Hello, world!".to_vec();
+        let legacy_file = zstd::encode_all(Cursor::new(input.clone()), 3).unwrap();
+
+        let output = decode(
+            DecodingDataTunnel {
+                compression: CompressionAlgorithm::Zstd,
+                encryption_level: EncryptionLevel::None,
+            },
+            legacy_file,
+        )
+        .unwrap();
+
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn tampered_container_header_is_rejected() {
+        let password = EncryptionLevel::Symmetrical {
+            password: "pwd123".into(),
+        };
+        let encoder = EncodingDataTunnel {
+            compression: CompressionLevel::Fast.into(),
+            encryption_level: password.clone(),
+            source: BackupSource::BtrfsStream,
+            parent: Some(1),
+            compression_mix: Default::default(),
+        };
+        let (tx, rx) = mpsc::channel();
+        encoder
+            .transfer(Cursor::new(b"Hello, world!".to_vec()), ChannelWriter::new(tx))
+            .unwrap();
+        let encoded: Vec<u8> = rx.iter().collect();
+        let decoder = DecodingDataTunnel {
+            compression: CompressionAlgorithm::Zstd,
+            encryption_level: password,
+        };
+        assert!(decode(decoder.clone(), encoded.clone()).is_ok());
+
+        let original = br#""parent":1"#;
+        let position = encoded
+            .windows(original.len())
+            .position(|window| window == original)
+            .unwrap();
+        let mut tampered = encoded;
+        tampered[position + original.len() - 1] = b'2';
+
+        assert!(decode(decoder, tampered).is_err());
+    }
+
+    #[test]
+    fn container_header_selects_decompressor() {
+        for algorithm in [
+            CompressionAlgorithm::Lz4,
+            CompressionAlgorithm::Gzip,
+            CompressionAlgorithm::Xz,
+            CompressionAlgorithm::None,
+        ] {
+            let encoder = EncodingDataTunnel {
+                compression: algorithm.into(),
+                encryption_level: EncryptionLevel::None,
+                source: BackupSource::BtrfsStream,
+                parent: None,
+                compression_mix: Default::default(),
+            };
+            let (tx, rx) = mpsc::channel();
+            encoder
+                .transfer(Cursor::new(b"Hello, world!".to_vec()), ChannelWriter::new(tx))
+                .unwrap();
+
+            let decoder = DecodingDataTunnel {
+                compression: CompressionAlgorithm::Zstd,
+                encryption_level: EncryptionLevel::None,
+            };
+            assert_eq!(decode(decoder, rx.iter().collect()).unwrap(), b"Hello, world!");
+        }
+    }
 }
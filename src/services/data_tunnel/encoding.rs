@@ -1,31 +1,82 @@
-use crate::objects::{CompressionLevel, EncryptionLevel};
+use crate::objects::{
+    BackupSource, Compression, ContainerHeader, EncryptionLevel,
+};
 use crate::services::data_tunnel::DataTunnel;
+use crate::services::encoder::Encoder;
+use crate::services::encryption::EncryptionSession;
+use crate::services::tracking::CompressionMixCounter;
 use std::io;
 use std::io::{Read, Write};
 
 #[derive(Clone)]
 pub struct EncodingDataTunnel {
-    pub compression_level: CompressionLevel,
+    pub compression: Compression,
     pub encryption_level: EncryptionLevel,
+    /// Recorded in the container header together with the encoding settings.
+    pub source: BackupSource,
+    pub parent: Option<u32>,
+    /// Filled while transferring with adaptive compression, clones share it.
+    pub compression_mix: CompressionMixCounter,
+}
+
+impl EncodingDataTunnel {
+    pub fn container_header(&self) -> ContainerHeader {
+        ContainerHeader {
+            compression: self.compression,
+            encryption: self.encryption_level.scheme(),
+            source: self.source,
+            parent: self.parent,
+        }
+    }
+
+    /// Like `transfer`, but encrypts with a key of `session`, which is cheaper for many small
+    /// transfers.
+    pub fn transfer_in_session<R: Read, W: Write + 'static>(
+        &self,
+        session: &EncryptionSession,
+        reader: R,
+        writer: W,
+    ) -> Result<(), io::Error> {
+        self.encode(reader, writer, |writer, header| {
+            session.to_encoder(writer, header)
+        })
+    }
+
+    fn encode<R: Read, W: Write + 'static>(
+        &self,
+        mut reader: R,
+        mut writer: W,
+        to_encoder: impl FnOnce(W, &[u8]) -> io::Result<Box<dyn Encoder>>,
+    ) -> Result<(), io::Error> {
+        let header = self.container_header().to_bytes();
+        writer.write_all(&header)?;
+
+        let encryptor = to_encoder(writer, &header)?;
+        let mut compressor = self
+            .compression
+            .to_tracked_encoder(encryptor, self.compression_mix.clone())?;
+        io::copy(&mut reader, &mut compressor)?;
+        // Ends the compressed and the encrypted stream, a truncated file must not count as done
+        compressor.finish()
+    }
 }
 
 impl DataTunnel for EncodingDataTunnel {
     fn transfer<R: Read, W: Write + 'static>(
         &self,
-        mut reader: R,
+        reader: R,
         writer: W,
     ) -> Result<(), io::Error> {
-        let encryptor = self.encryption_level.to_encoder(writer);
-        let mut compressor = self.compression_level.to_zstd_encoder(encryptor);
-        io::copy(&mut reader, &mut compressor)?;
-        compressor.flush()?;
-        Ok(())
+        self.encode(reader, writer, |writer, header| {
+            self.encryption_level.to_encoder(writer, header)
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::objects::CompressionLevel;
     use crate::services::channels::ChannelWriter;
     use std::io::Cursor;
     use std::sync::mpsc;
@@ -33,8 +84,11 @@ mod tests {
     #[test]
     fn test_encoding_data_tunnel_tunnel() {
         let tunnel = EncodingDataTunnel {
-            compression_level: CompressionLevel::None,
+            compression: CompressionLevel::None.into(),
             encryption_level: EncryptionLevel::None,
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         let input = b"Hello, world!".repeat(10);
@@ -49,8 +103,11 @@ mod tests {
         assert_ne!(output_uncompressed.as_slice(), input);
 
         let tunnel = EncodingDataTunnel {
-            compression_level: CompressionLevel::Fast,
+            compression: CompressionLevel::Fast.into(),
             encryption_level: EncryptionLevel::None,
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         let (tx, rx) = mpsc::channel();
@@ -67,8 +124,11 @@ mod tests {
     #[test]
     fn test_encoding_data_tunnel_tunnel_with_compression() {
         let mut tunnel = EncodingDataTunnel {
-            compression_level: CompressionLevel::Best,
+            compression: CompressionLevel::Best.into(),
             encryption_level: EncryptionLevel::None,
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         let input = b"Hello, world!";
@@ -85,10 +145,13 @@ mod tests {
     #[test]
     fn test_encoding_data_tunnel_tunnel_with_encryption() {
         let mut tunnel = EncodingDataTunnel {
-            compression_level: CompressionLevel::None,
+            compression: CompressionLevel::None.into(),
             encryption_level: EncryptionLevel::Symmetrical {
                 password: "pwd123".into(),
             },
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         let input = b"Hello, world!";
@@ -101,4 +164,48 @@ mod tests {
         let output: Vec<u8> = rx.iter().collect();
         assert_ne!(output.as_slice(), input);
     }
+
+    /// Accepts `limit` bytes, fails every write after them.
+    struct LimitedWriter {
+        limit: usize,
+    }
+
+    impl Write for LimitedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.limit {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "no space left"));
+            }
+            self.limit -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_encoding_data_tunnel_reports_failed_end_of_stream() {
+        let encryption_levels = [
+            EncryptionLevel::None,
+            EncryptionLevel::Symmetrical {
+                password: "pwd123".into(),
+            },
+        ];
+        for encryption_level in encryption_levels {
+            let tunnel = EncodingDataTunnel {
+                compression: CompressionLevel::Fast.into(),
+                encryption_level,
+                source: BackupSource::BtrfsStream,
+                parent: None,
+                compression_mix: Default::default(),
+            };
+            // Only the header fits, the compressed data is all written once the stream ends
+            let limit = tunnel.container_header().to_bytes().len();
+
+            let writer = LimitedWriter { limit };
+            let transferred = tunnel.transfer(Cursor::new(b"Hello, world!"), writer);
+            assert_eq!(transferred.unwrap_err().kind(), io::ErrorKind::StorageFull);
+        }
+    }
 }
@@ -1,9 +1,11 @@
+mod chunked;
 mod decoding;
 mod encoding;
 mod mapped;
 mod pass_through;
 mod tracker;
 
+pub use chunked::*;
 pub use decoding::*;
 pub use encoding::*;
 pub use mapped::*;
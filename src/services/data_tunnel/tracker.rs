@@ -1,21 +1,32 @@
 use crate::services::data_tunnel::DataTunnel;
-use crate::services::tracking::{BytesCounter, BytesCountingReader, BytesCountingWriter};
+use crate::services::tracking::{
+    BytesCounter, BytesCountingReader, BytesCountingWriter, ContentHash, HashingReader,
+    HashingWriter,
+};
 use std::cell::{Cell, RefCell};
 use std::io::{Read, Write};
 use std::ops::DerefMut;
 
+/// Counts and hashes the bytes on both sides of a tunnel, so the plaintext and the stored
+/// bytes of a backup can be recorded or checked in one pass.
 pub struct TrackedTransfer<DT: DataTunnel, R: Read + 'static, W: Write + 'static> {
     inner_tunnel: DT,
-    reader: RefCell<Option<BytesCountingReader<R>>>,
-    writer: RefCell<Option<BytesCountingWriter<W>>>,
+    reader: RefCell<Option<BytesCountingReader<HashingReader<R>>>>,
+    writer: RefCell<Option<BytesCountingWriter<HashingWriter<W>>>>,
     reader_bytes_count: BytesCounter,
     writer_bytes_count: BytesCounter,
+    reader_hash: ContentHash,
+    writer_hash: ContentHash,
 }
 
 impl<DT: DataTunnel, R: Read + 'static, W: Write + 'static> TrackedTransfer<DT, R, W> {
     pub fn new(tunnel: DT, reader: R, writer: W) -> Self {
-        let counting_reader = BytesCountingReader::new(reader);
-        let counting_writer = BytesCountingWriter::new(writer);
+        let hashing_reader = HashingReader::new(reader);
+        let hashing_writer = HashingWriter::new(writer);
+        let reader_hash = hashing_reader.hash();
+        let writer_hash = hashing_writer.hash();
+        let counting_reader = BytesCountingReader::new(hashing_reader);
+        let counting_writer = BytesCountingWriter::new(hashing_writer);
 
         let reader_bytes_count = counting_reader.counter();
         let writer_bytes_count = counting_writer.counter();
@@ -26,9 +37,20 @@ impl<DT: DataTunnel, R: Read + 'static, W: Write + 'static> TrackedTransfer<DT,
             writer: RefCell::new(Some(counting_writer)),
             reader_bytes_count,
             writer_bytes_count,
+            reader_hash,
+            writer_hash,
         }
     }
 
+    /// Hash of the bytes read so far, see `ContentHash::value`.
+    pub fn reader_hash(&self) -> String {
+        self.reader_hash.value()
+    }
+
+    pub fn writer_hash(&self) -> String {
+        self.writer_hash.value()
+    }
+
     pub fn reader_bytes_count(&self) -> u64 {
         self.reader_bytes_count.value()
     }
@@ -45,6 +67,16 @@ impl<DT: DataTunnel, R: Read + 'static, W: Write + 'static> TrackedTransfer<DT,
         self.writer_bytes_count.clone()
     }
 
+    /// Bytes and hashes read and written, in that order.
+    pub fn checksums(&self) -> (u64, String, u64, String) {
+        (
+            self.reader_bytes_count(),
+            self.reader_hash(),
+            self.writer_bytes_count(),
+            self.writer_hash(),
+        )
+    }
+
     pub fn run(&self) -> std::io::Result<()> {
         let mut reader = self.reader.borrow_mut();
         let reader = reader.deref_mut();
@@ -69,7 +101,7 @@ impl<DT: DataTunnel, R: Read + 'static, W: Write + 'static> TrackedTransfer<DT,
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::objects::{CompressionLevel, EncryptionLevel};
+    use crate::objects::{BackupSource, CompressionLevel, EncryptionLevel};
     use crate::services::channels::ChannelWriter;
     use crate::services::data_tunnel::encoding::EncodingDataTunnel;
     use std::io::Cursor;
@@ -78,10 +110,13 @@ mod tests {
     #[test]
     fn test_tracking_data_tunnel() {
         let tunnel = EncodingDataTunnel {
-            compression_level: CompressionLevel::Best,
+            compression: CompressionLevel::Best.into(),
             encryption_level: EncryptionLevel::Symmetrical {
                 password: "pwd123".into(),
             },
+            source: BackupSource::BtrfsStream,
+            parent: None,
+            compression_mix: Default::default(),
         };
 
         let input = b"Hello, world!";
@@ -92,5 +127,13 @@ mod tests {
 
         assert_eq!(transfer.reader_bytes_count(), input.len() as u64);
         assert_eq!(transfer.writer_bytes_count(), output.len() as u64);
+        assert_eq!(
+            transfer.reader_hash(),
+            ContentHash::of_reader(Cursor::new(input)).unwrap()
+        );
+        assert_eq!(
+            transfer.writer_hash(),
+            ContentHash::of_reader(Cursor::new(output)).unwrap()
+        );
     }
 }
@@ -0,0 +1,35 @@
+use std::io;
+use std::io::Write;
+
+/// A writer whose output is only complete once it is finished, like a compressed stream with
+/// its end frame or an encrypted stream with its final chunk.
+pub trait Encoder: Write {
+    /// Completes the output, then finishes the writer it wraps. Encoders also finish when
+    /// dropped, but their errors are lost then.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl<E: Encoder + ?Sized> Encoder for Box<E> {
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Ends a chain of encoders at a plain writer, finishing it only flushes.
+pub struct Unencoded<W: Write>(pub W);
+
+impl<W: Write> Write for Unencoded<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Encoder for Unencoded<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
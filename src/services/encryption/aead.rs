@@ -0,0 +1,487 @@
+//! Authenticated encryption: the stream is split into AES-256-GCM chunks that are sealed one by
+//! one. The key is derived from a password with scrypt and a random salt, or is random and
+//! sealed to X25519 recipients (see `recipients`).
+//!
+//! Layout: header (`MAGIC`, kdf, scrypt `log_n`, `r`, `p`, salt, nonce prefix) followed by
+//! chunks of `[length u32][final u8][ciphertext][tag]`. Each chunk nonce is the nonce prefix
+//! plus the chunk counter, and the header and final flag are authenticated with every chunk,
+//! so reordered, modified or truncated streams fail to decode.
+use crate::objects::KeyDerivation;
+use crate::services::encoder::Encoder;
+use crate::services::encryption::recipients;
+use blake2::{Blake2b512, Digest};
+use openssl::pkey::{PKey, Private};
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use rand::RngCore;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub(super) const MAGIC: &[u8; 8] = b"DDAEAD\x00\x01";
+const KDF_SCRYPT: u8 = 1;
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+/// Upper bounds for parameters read from untrusted headers.
+const SCRYPT_MAX_MEMORY: u64 = 1024 * 1024 * 1024;
+const SCRYPT_MAX_R: u32 = 1024;
+const SCRYPT_MAX_P: u32 = 64;
+
+const SALT_LEN: usize = 16;
+pub(super) const NONCE_PREFIX_LEN: usize = 8;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 4 + 4 + SALT_LEN + NONCE_PREFIX_LEN;
+const TAG_LEN: usize = 16;
+pub(super) const CHUNK_SIZE: usize = 64 * 1024;
+/// How many keys derived while decoding are kept, see `Header::derive_cached_key`.
+const CACHED_KEYS: usize = 16;
+
+/// Keys derived while decoding, looked up by a hash of the password and the header parameters.
+static CACHED_KEYS_BY_LOOKUP: Mutex<Vec<([u8; 64], [u8; 32])>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Copy)]
+struct Header {
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: [u8; SALT_LEN],
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+impl Header {
+    fn random() -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        rand::rng().fill_bytes(&mut salt);
+        rand::rng().fill_bytes(&mut nonce_prefix);
+
+        Self {
+            log_n: SCRYPT_LOG_N,
+            r: SCRYPT_R,
+            p: SCRYPT_P,
+            salt,
+            nonce_prefix,
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(KDF_SCRYPT);
+        bytes.push(self.log_n);
+        bytes.extend_from_slice(&self.r.to_be_bytes());
+        bytes.extend_from_slice(&self.p.to_be_bytes());
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes
+    }
+
+    /// Parses the header following `MAGIC`.
+    fn from_bytes(bytes: &[u8; HEADER_LEN - MAGIC.len()]) -> io::Result<Self> {
+        let [kdf, log_n, rest @ ..] = bytes;
+        if *kdf != KDF_SCRYPT {
+            return Err(invalid_data(format!(
+                "unknown key derivation function {kdf}"
+            )));
+        }
+        let r = u32::from_be_bytes(rest[0..4].try_into().unwrap());
+        let p = u32::from_be_bytes(rest[4..8].try_into().unwrap());
+        let unsupported = || invalid_data("unsupported scrypt parameters".to_string());
+        if *log_n == 0 || *log_n > 30 || !(1..=SCRYPT_MAX_R).contains(&r) {
+            return Err(unsupported());
+        }
+        if !(1..=SCRYPT_MAX_P).contains(&p) {
+            return Err(unsupported());
+        }
+        let memory = (1u64 << log_n)
+            .checked_mul(128 * r as u64)
+            .ok_or_else(unsupported)?;
+        if memory > SCRYPT_MAX_MEMORY {
+            return Err(unsupported());
+        }
+
+        Ok(Self {
+            log_n: *log_n,
+            r,
+            p,
+            salt: rest[8..8 + SALT_LEN].try_into().unwrap(),
+            nonce_prefix: rest[8 + SALT_LEN..].try_into().unwrap(),
+        })
+    }
+
+    fn derive_key(&self, password: &str) -> io::Result<[u8; 32]> {
+        let mut key = [0u8; 32];
+        let n = 1u64 << self.log_n;
+        let max_memory = 128 * n * self.r as u64 * (self.p as u64 + 1) + 1024 * 1024;
+        openssl::pkcs5::scrypt(
+            password.as_bytes(),
+            &self.salt,
+            n,
+            self.r as u64,
+            self.p as u64,
+            max_memory,
+            &mut key,
+        )
+        .map_err(io::Error::other)?;
+        Ok(key)
+    }
+
+    /// Like `derive_key`, but remembers the latest keys. The streams of a `SessionKey` share
+    /// their salt, so decoding all of them derives the key once.
+    fn derive_cached_key(&self, password: &str) -> io::Result<[u8; 32]> {
+        let mut hasher = Blake2b512::new();
+        hasher.update(password.as_bytes());
+        hasher.update([0, self.log_n]);
+        hasher.update(self.r.to_be_bytes());
+        hasher.update(self.p.to_be_bytes());
+        hasher.update(self.salt);
+        let lookup: [u8; 64] = hasher.finalize().into();
+
+        let cached = CACHED_KEYS_BY_LOOKUP
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(cached_lookup, _)| *cached_lookup == lookup)
+            .map(|(_, key)| *key);
+        if let Some(key) = cached {
+            return Ok(key);
+        }
+
+        let key = self.derive_key(password)?;
+        let mut cached_keys = CACHED_KEYS_BY_LOOKUP.lock().unwrap();
+        if cached_keys.len() == CACHED_KEYS {
+            cached_keys.remove(0);
+        }
+        cached_keys.push((lookup, key));
+        Ok(key)
+    }
+}
+
+/// The key and header of one encrypted stream.
+pub(super) struct StreamKey {
+    /// Written in front of the chunks, including its magic, and authenticated with each of them.
+    pub(super) header_bytes: Vec<u8>,
+    pub(super) key: [u8; 32],
+    pub(super) nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+impl StreamKey {
+    /// A fresh salt and key derived from `password`.
+    pub(super) fn from_password(password: &str) -> io::Result<Self> {
+        let header = Header::random();
+        Ok(Self {
+            key: header.derive_key(password)?,
+            header_bytes: header.to_bytes(),
+            nonce_prefix: header.nonce_prefix,
+        })
+    }
+
+    /// Reads a password header whose `MAGIC` was already consumed.
+    fn read_password_header(reader: &mut impl Read, password: &str) -> io::Result<Self> {
+        let mut header_bytes = [0u8; HEADER_LEN - MAGIC.len()];
+        read_exact_or_truncated(reader, &mut header_bytes)?;
+        let header = Header::from_bytes(&header_bytes)?;
+
+        Ok(Self {
+            key: header.derive_cached_key(password)?,
+            header_bytes: header.to_bytes(),
+            nonce_prefix: header.nonce_prefix,
+        })
+    }
+
+    fn nonce(&self, counter: u32) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+}
+
+/// A salt and key derived once from a password and shared by many streams. The nonce prefix
+/// of each stream is its number in the session, so no two streams reuse a nonce.
+pub(super) struct SessionKey {
+    header: Header,
+    key: [u8; 32],
+    streams: AtomicU64,
+}
+
+impl SessionKey {
+    pub(super) fn from_password(password: &str) -> io::Result<Self> {
+        let header = Header::random();
+        Ok(Self {
+            key: header.derive_key(password)?,
+            header,
+            streams: AtomicU64::new(0),
+        })
+    }
+
+    pub(super) fn stream_key(&self) -> StreamKey {
+        let header = Header {
+            nonce_prefix: self.streams.fetch_add(1, Ordering::Relaxed).to_be_bytes(),
+            ..self.header
+        };
+        StreamKey {
+            key: self.key,
+            header_bytes: header.to_bytes(),
+            nonce_prefix: header.nonce_prefix,
+        }
+    }
+}
+
+/// How a decryptor obtains the key of a stream from its header.
+pub(super) enum KeySource {
+    Password(String),
+    Identities(Vec<PKey<Private>>),
+}
+
+/// The key derivation used for newly encrypted files.
+pub(super) fn key_derivation() -> KeyDerivation {
+    KeyDerivation::Scrypt {
+        log_n: SCRYPT_LOG_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+    }
+}
+
+pub(super) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn chunk_aad(associated_data: &[u8], header_bytes: &[u8], is_final: bool) -> Vec<u8> {
+    let mut aad = associated_data.to_vec();
+    aad.extend_from_slice(header_bytes);
+    aad.push(is_final as u8);
+    aad
+}
+
+/// Seals everything written to it. The stream is only complete once the encryptor is
+/// finished, which happens on drop at the latest.
+pub struct AeadEncryptor<W: Write> {
+    inner: W,
+    stream_key: StreamKey,
+    header_written: bool,
+    associated_data: Vec<u8>,
+    counter: u32,
+    buffer: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> AeadEncryptor<W> {
+    /// `associated_data` is authenticated with every chunk but not written.
+    pub(super) fn new(inner: W, stream_key: StreamKey, associated_data: &[u8]) -> Self {
+        Self {
+            inner,
+            stream_key,
+            header_written: false,
+            associated_data: associated_data.to_vec(),
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            finished: false,
+        }
+    }
+
+    fn write_chunk(&mut self, is_final: bool) -> io::Result<()> {
+        if !self.header_written {
+            self.inner.write_all(&self.stream_key.header_bytes)?;
+            self.header_written = true;
+        }
+
+        let mut tag = [0u8; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &self.stream_key.key,
+            Some(&self.stream_key.nonce(self.counter)),
+            &chunk_aad(&self.associated_data, &self.stream_key.header_bytes, is_final),
+            &self.buffer,
+            &mut tag,
+        )
+        .map_err(io::Error::other)?;
+
+        self.inner
+            .write_all(&(ciphertext.len() as u32).to_be_bytes())?;
+        self.inner.write_all(&[is_final as u8])?;
+        self.inner.write_all(&ciphertext)?;
+        self.inner.write_all(&tag)?;
+
+        self.buffer.clear();
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many chunks for one encrypted stream"))?;
+        Ok(())
+    }
+
+    /// Seals the last chunk. Nothing can be written afterwards.
+    fn seal(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_chunk(true)
+    }
+}
+
+impl<W: Write> Write for AeadEncryptor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other(
+                "the encrypted stream was already finished",
+            ));
+        }
+        // A full chunk is only sealed once more data arrives, the last one has to be final
+        if self.buffer.len() == CHUNK_SIZE && !buf.is_empty() {
+            self.write_chunk(false)?;
+        }
+        let length = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Encoder> Encoder for AeadEncryptor<W> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.seal()?;
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Drop for AeadEncryptor<W> {
+    fn drop(&mut self) {
+        // The inner writer finishes itself when it is dropped next
+        let _ = self.seal().and_then(|()| self.inner.flush());
+    }
+}
+
+/// Opens a stream sealed by [`AeadEncryptor`] whose magic was already consumed.
+pub struct AeadDecryptor<R: Read> {
+    inner: R,
+    key_source: KeySource,
+    associated_data: Vec<u8>,
+    state: Option<DecryptorState>,
+    plaintext: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+struct DecryptorState {
+    stream_key: StreamKey,
+    counter: u32,
+}
+
+impl<R: Read> AeadDecryptor<R> {
+    pub(super) fn new(inner: R, key_source: KeySource, associated_data: &[u8]) -> Self {
+        Self {
+            inner,
+            key_source,
+            associated_data: associated_data.to_vec(),
+            state: None,
+            plaintext: Vec::new(),
+            position: 0,
+            finished: false,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<DecryptorState> {
+        let stream_key = match &self.key_source {
+            KeySource::Password(password) => {
+                StreamKey::read_password_header(&mut self.inner, password)?
+            }
+            KeySource::Identities(identities) => {
+                recipients::read_recipients_header(&mut self.inner, identities)?
+            }
+        };
+
+        Ok(DecryptorState {
+            stream_key,
+            counter: 0,
+        })
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        if self.state.is_none() {
+            self.state = Some(self.read_header()?);
+        }
+        let state = self.state.as_mut().unwrap();
+
+        let mut length = [0u8; 4];
+        read_exact_or_truncated(&mut self.inner, &mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > CHUNK_SIZE {
+            return Err(invalid_data(format!(
+                "encrypted chunk of {length} bytes is too large"
+            )));
+        }
+        let mut is_final = [0u8; 1];
+        read_exact_or_truncated(&mut self.inner, &mut is_final)?;
+        let is_final = match is_final[0] {
+            0 => false,
+            1 => true,
+            flag => return Err(invalid_data(format!("invalid chunk flag {flag}"))),
+        };
+        let mut ciphertext = vec![0u8; length];
+        read_exact_or_truncated(&mut self.inner, &mut ciphertext)?;
+        let mut tag = [0u8; TAG_LEN];
+        read_exact_or_truncated(&mut self.inner, &mut tag)?;
+
+        self.plaintext = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &state.stream_key.key,
+            Some(&state.stream_key.nonce(state.counter)),
+            &chunk_aad(&self.associated_data, &state.stream_key.header_bytes, is_final),
+            &ciphertext,
+            &tag,
+        )
+        .map_err(|_| {
+            invalid_data(
+                "encrypted chunk failed authentication, wrong key or tampered data"
+                    .to_string(),
+            )
+        })?;
+        self.position = 0;
+        state.counter = state
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| invalid_data("too many encrypted chunks".to_string()))?;
+
+        if is_final {
+            self.finished = true;
+            let mut trailing = [0u8; 1];
+            if self.inner.read(&mut trailing)? != 0 {
+                return Err(invalid_data(
+                    "data after the final encrypted chunk".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(super) fn read_exact_or_truncated(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<()> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "encrypted stream is truncated",
+        ),
+        _ => err,
+    })
+}
+
+impl<R: Read> Read for AeadDecryptor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.finished || buf.is_empty() {
+                return Ok(0);
+            }
+            self.read_chunk()?;
+        }
+
+        let length = buf.len().min(self.plaintext.len() - self.position);
+        buf[..length].copy_from_slice(&self.plaintext[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
@@ -0,0 +1,83 @@
+//! The original AES-256-CBC format. Key and IV are derived from the password alone,
+//! so it is only kept to decode backups made before the authenticated format.
+use blake2::{Blake2b512, Digest};
+use openssl::symm::Cipher;
+use rand::{Rng, SeedableRng};
+use std::io;
+use std::io::{Read, Write};
+
+pub(super) fn key_iv_for_cipher(cipher: &Cipher, password: &str) -> (Vec<u8>, Vec<u8>) {
+    fn string_to_seed(seed: &str) -> [u8; 32] {
+        let mut hasher = Blake2b512::new();
+        Digest::update(&mut hasher, seed.as_bytes());
+        let hash_result = hasher.finalize();
+
+        let mut seed_bytes = [0u8; 32];
+        seed_bytes.copy_from_slice(&hash_result[..32]);
+        seed_bytes
+    }
+
+    fn generate_random_vec(seed: &str, length: usize) -> Vec<u8> {
+        let seed_hash = string_to_seed(seed); // Convert string to a reproducible seed
+        let mut rng = rand_hc::Hc128Rng::from_seed(seed_hash); // Seed the PRNG with the hashed seed
+
+        // Generate the random Vec<u8>
+        (0..length).map(|_| rng.random()).collect()
+    }
+
+    let key_len = cipher.key_len();
+    let iv_len = cipher.iv_len().unwrap_or(0);
+
+    let key = generate_random_vec(password, key_len);
+    let iv = generate_random_vec(password, iv_len);
+
+    (key, iv)
+}
+
+pub(super) fn legacy_decoder<R: Read>(
+    r: R,
+    password: &str,
+) -> cryptostream::read::Decryptor<FullReads<R>> {
+    let cipher = Cipher::aes_256_cbc();
+    let (key, iv) = key_iv_for_cipher(&cipher, password);
+    cryptostream::read::Decryptor::new(FullReads(r), cipher, &key, &iv).unwrap()
+}
+
+/// Only returns short reads at the end of the stream. The cryptostream decryptor mishandles
+/// reads shorter than a cipher block.
+pub(super) struct FullReads<R: Read>(R);
+
+impl<R: Read> Read for FullReads<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.0.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(filled)
+    }
+}
+
+#[cfg(test)]
+pub(super) fn legacy_encoder<W: Write>(w: W, password: &str) -> cryptostream::write::Encryptor<W> {
+    let cipher = Cipher::aes_256_cbc();
+    let (key, iv) = key_iv_for_cipher(&cipher, password);
+    cryptostream::write::Encryptor::new(w, cipher, &key, &iv).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cipher() {
+        let cipher = Cipher::aes_256_cbc();
+        let (key, iv) = key_iv_for_cipher(&cipher, "password");
+        assert_eq!(key.len(), 32);
+        assert_eq!(iv.len(), 16);
+    }
+}
@@ -0,0 +1,426 @@
+mod aead;
+mod legacy;
+pub mod recipients;
+
+pub use aead::{AeadDecryptor, AeadEncryptor};
+
+use crate::objects::{EncryptionLevel, EncryptionScheme};
+use crate::services::encoder::{Encoder, Unencoded};
+use crate::services::encryption::aead::{KeySource, SessionKey, StreamKey};
+use std::io;
+use std::io::{Cursor, Read, Write};
+
+impl EncryptionLevel {
+    /// The scheme newly encoded files use at this level.
+    pub fn scheme(&self) -> EncryptionScheme {
+        match self {
+            EncryptionLevel::None => EncryptionScheme::None,
+            EncryptionLevel::Symmetrical { .. } => EncryptionScheme::Aes256GcmChunked {
+                kdf: aead::key_derivation(),
+            },
+            EncryptionLevel::Recipients { recipients, .. } => EncryptionScheme::X25519Recipients {
+                recipients: recipients.len() as u32,
+            },
+        }
+    }
+
+    /// `associated_data` is not encrypted but tampering with it fails decryption.
+    pub fn to_encoder<'b, W: Write + 'b>(
+        &self,
+        w: W,
+        associated_data: &[u8],
+    ) -> io::Result<Box<dyn Encoder + 'b>> {
+        let stream_key = match self {
+            EncryptionLevel::None => return Ok(Box::new(Unencoded(w))),
+            EncryptionLevel::Symmetrical { password } => {
+                StreamKey::from_password(password.insecure())?
+            }
+            EncryptionLevel::Recipients { recipients, .. } => {
+                let recipients = recipients
+                    .iter()
+                    .map(|recipient| recipients::parse_recipient(recipient))
+                    .collect::<io::Result<Vec<_>>>()?;
+                recipients::recipients_stream_key(&recipients)?
+            }
+        };
+        Ok(Box::new(AeadEncryptor::new(
+            Unencoded(w),
+            stream_key,
+            associated_data,
+        )))
+    }
+
+    /// Encrypting many small streams at this level is cheaper in a session, see
+    /// `EncryptionSession`.
+    pub fn session(&self) -> io::Result<EncryptionSession> {
+        let session_key = match self {
+            EncryptionLevel::Symmetrical { password } => {
+                Some(SessionKey::from_password(password.insecure())?)
+            }
+            EncryptionLevel::None | EncryptionLevel::Recipients { .. } => None,
+        };
+        Ok(EncryptionSession {
+            level: self.clone(),
+            session_key,
+        })
+    }
+
+    /// `declared` is the scheme named by the container header. Only files without a header
+    /// may use the legacy format.
+    pub fn to_decoder<R: Read + 'static>(
+        &self,
+        r: R,
+        declared: Option<&EncryptionScheme>,
+        associated_data: &[u8],
+    ) -> Box<dyn Read> {
+        match self {
+            EncryptionLevel::None => Box::new(r),
+            level => Box::new(DetectingDecoder::Detecting {
+                reader: Some(r),
+                level: level.clone(),
+                declared: declared.cloned(),
+                associated_data: associated_data.to_vec(),
+            }),
+        }
+    }
+}
+
+/// Encrypts streams like `EncryptionLevel::to_encoder`, but derives a password key only once:
+/// the streams of a session share the salt of their header.
+pub struct EncryptionSession {
+    level: EncryptionLevel,
+    session_key: Option<SessionKey>,
+}
+
+impl EncryptionSession {
+    pub fn to_encoder<'b, W: Write + 'b>(
+        &self,
+        w: W,
+        associated_data: &[u8],
+    ) -> io::Result<Box<dyn Encoder + 'b>> {
+        match &self.session_key {
+            Some(session_key) => Ok(Box::new(AeadEncryptor::new(
+                Unencoded(w),
+                session_key.stream_key(),
+                associated_data,
+            ))),
+            None => self.level.to_encoder(w, associated_data),
+        }
+    }
+}
+
+/// Picks the format of an encrypted stream once its first bytes are read.
+enum DetectingDecoder<R: Read> {
+    Detecting {
+        reader: Option<R>,
+        level: EncryptionLevel,
+        declared: Option<EncryptionScheme>,
+        associated_data: Vec<u8>,
+    },
+    Aead(AeadDecryptor<R>),
+    Legacy(cryptostream::read::Decryptor<legacy::FullReads<io::Chain<Cursor<Vec<u8>>, R>>>),
+}
+
+impl<R: Read> DetectingDecoder<R> {
+    fn detect(
+        mut reader: R,
+        level: &EncryptionLevel,
+        declared: Option<&EncryptionScheme>,
+        associated_data: &[u8],
+    ) -> io::Result<DetectingDecoder<R>> {
+        let mut magic = Vec::with_capacity(aead::MAGIC.len());
+        (&mut reader)
+            .take(aead::MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+
+        let mismatch = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        // Otherwise a forged header could downgrade the file to unauthenticated legacy CBC
+        let declared_magic = match declared {
+            None | Some(EncryptionScheme::None) => None,
+            Some(EncryptionScheme::Aes256GcmChunked { .. }) => Some(aead::MAGIC),
+            Some(EncryptionScheme::X25519Recipients { .. }) => Some(recipients::MAGIC),
+        };
+        if declared_magic.is_some_and(|declared_magic| magic != declared_magic) {
+            return Err(mismatch(
+                "the data does not match the encryption named in the backup header",
+            ));
+        }
+        match level {
+            EncryptionLevel::Symmetrical { password } if magic == aead::MAGIC => {
+                let key_source = KeySource::Password(password.insecure().to_string());
+                Ok(DetectingDecoder::Aead(AeadDecryptor::new(
+                    reader,
+                    key_source,
+                    associated_data,
+                )))
+            }
+            EncryptionLevel::Symmetrical { .. } if magic == recipients::MAGIC => Err(mismatch(
+                "the backup is encrypted to recipients and cannot be opened with a password",
+            )),
+            EncryptionLevel::Symmetrical { password } => {
+                let reader = Cursor::new(magic).chain(reader);
+                Ok(DetectingDecoder::Legacy(legacy::legacy_decoder(
+                    reader,
+                    password.insecure(),
+                )))
+            }
+            EncryptionLevel::Recipients { identities, .. } if magic == recipients::MAGIC => {
+                if identities.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "decrypting the backup needs the identity of one of its recipients",
+                    ));
+                }
+                let identities = identities
+                    .iter()
+                    .map(|identity| recipients::parse_identity(identity.insecure()))
+                    .collect::<io::Result<Vec<_>>>()?;
+                Ok(DetectingDecoder::Aead(AeadDecryptor::new(
+                    reader,
+                    KeySource::Identities(identities),
+                    associated_data,
+                )))
+            }
+            EncryptionLevel::Recipients { .. } => {
+                Err(mismatch("the backup is not encrypted to recipients"))
+            }
+            EncryptionLevel::None => unreachable!("unencrypted streams are not detected"),
+        }
+    }
+}
+
+impl<R: Read> Read for DetectingDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DetectingDecoder::Detecting {
+                reader,
+                level,
+                declared,
+                associated_data,
+            } => {
+                let reader = reader
+                    .take()
+                    .ok_or_else(|| io::Error::other("encryption format detection failed before"))?;
+                *self =
+                    DetectingDecoder::detect(reader, level, declared.as_ref(), associated_data)?;
+                self.read(buf)
+            }
+            DetectingDecoder::Aead(decryptor) => decryptor.read(buf),
+            DetectingDecoder::Legacy(decryptor) => decryptor.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::channels::ChannelWriter;
+    use std::sync::mpsc;
+
+    fn encrypt(encryption: &EncryptionLevel, input: &[u8]) -> Vec<u8> {
+        let (tx, rx) = mpsc::channel();
+        let mut encoder = encryption.to_encoder(ChannelWriter::new(tx), b"").unwrap();
+        std::io::copy(&mut Cursor::new(input.to_vec()), &mut encoder).unwrap();
+        drop(encoder);
+        rx.iter().collect()
+    }
+
+    fn decrypt(encryption: &EncryptionLevel, encrypted: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        encryption
+            .to_decoder(Cursor::new(encrypted), None, b"")
+            .read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    fn password(password: &str) -> EncryptionLevel {
+        EncryptionLevel::Symmetrical {
+            password: password.into(),
+        }
+    }
+
+    #[test]
+    fn test_information_preserve() {
+        let encryption = password("password");
+
+        let input = [1, 2, 4, 8, 16, 32, 64, 128, 255];
+        let encrypted = encrypt(&encryption, &input);
+
+        assert_eq!(decrypt(&encryption, encrypted).unwrap().as_slice(), input);
+    }
+
+    #[test]
+    fn multiple_chunks_round_trip() {
+        let encryption = password("password");
+        let input: Vec<u8> = (0..aead::CHUNK_SIZE * 3).map(|i| (i % 251) as u8).collect();
+
+        let encrypted = encrypt(&encryption, &input);
+        assert_eq!(decrypt(&encryption, encrypted).unwrap(), input);
+
+        let exact_chunk = vec![7u8; aead::CHUNK_SIZE];
+        let encrypted = encrypt(&encryption, &exact_chunk);
+        assert_eq!(decrypt(&encryption, encrypted).unwrap(), exact_chunk);
+    }
+
+    #[test]
+    fn every_backup_uses_a_fresh_salt_and_nonce() {
+        let encryption = password("password");
+
+        assert_ne!(
+            encrypt(&encryption, b"same data"),
+            encrypt(&encryption, b"same data")
+        );
+    }
+
+    #[test]
+    fn session_streams_share_the_salt_but_not_the_nonce() {
+        let encryption = password("password");
+        let session = encryption.session().unwrap();
+        let encrypt_in_session = |input: &[u8]| {
+            let (tx, rx) = mpsc::channel();
+            let mut encoder = session.to_encoder(ChannelWriter::new(tx), b"").unwrap();
+            encoder.write_all(input).unwrap();
+            drop(encoder);
+            rx.iter().collect::<Vec<u8>>()
+        };
+
+        let first = encrypt_in_session(b"same data");
+        let second = encrypt_in_session(b"same data");
+
+        let salt_end = aead::MAGIC.len() + 10 + 16;
+        assert_eq!(first[..salt_end], second[..salt_end]);
+        assert_ne!(first[salt_end..], second[salt_end..]);
+        assert_eq!(decrypt(&encryption, first).unwrap(), b"same data");
+        assert_eq!(decrypt(&encryption, second).unwrap(), b"same data");
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let encryption = password("password");
+        let input = vec![3u8; aead::CHUNK_SIZE + 100];
+        let encrypted = encrypt(&encryption, &input);
+
+        let mut flipped = encrypted.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert!(decrypt(&encryption, flipped).is_err());
+
+        let mut modified_header = encrypted.clone();
+        modified_header[aead::MAGIC.len() + 10] ^= 1;
+        assert!(decrypt(&encryption, modified_header).is_err());
+
+        assert!(decrypt(&password("other"), encrypted).is_err());
+    }
+
+    #[test]
+    fn oversized_scrypt_parameters_are_rejected() {
+        let encryption = password("password");
+        let encrypted = encrypt(&encryption, b"secret");
+        let params = aead::MAGIC.len() + 1;
+
+        for (log_n, r, p) in [(30, u32::MAX, 1), (20, 1024, 1), (15, 8, u32::MAX), (0, 8, 1)] {
+            let mut modified = encrypted.clone();
+            modified[params] = log_n;
+            modified[params + 1..params + 5].copy_from_slice(&r.to_be_bytes());
+            modified[params + 5..params + 9].copy_from_slice(&p.to_be_bytes());
+            let err = decrypt(&encryption, modified).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn truncation_is_detected() {
+        let encryption = password("password");
+        let input = vec![5u8; aead::CHUNK_SIZE * 2 + 10];
+        let encrypted = encrypt(&encryption, &input);
+
+        // Cut right after the first complete chunk, which is not marked final
+        let header_len = encrypted.len() - (input.len() + 3 * (4 + 1 + 16));
+        let first_chunk_end = header_len + 4 + 1 + aead::CHUNK_SIZE + 16;
+        assert!(decrypt(&encryption, encrypted[..first_chunk_end].to_vec()).is_err());
+        assert!(decrypt(&encryption, encrypted[..encrypted.len() - 1].to_vec()).is_err());
+
+        let mut appended = encrypted.clone();
+        appended.push(0);
+        assert!(decrypt(&encryption, appended).is_err());
+    }
+
+    #[test]
+    fn legacy_backups_still_decode() {
+        let input = b"written before authenticated encryption".to_vec();
+        let mut encrypted = Vec::new();
+        {
+            let mut encoder = legacy::legacy_encoder(&mut encrypted, "password");
+            encoder.write_all(&input).unwrap();
+        }
+
+        assert_eq!(decrypt(&password("password"), encrypted).unwrap(), input);
+    }
+
+    #[test]
+    fn legacy_data_under_an_aead_header_is_rejected() {
+        let mut encrypted = Vec::new();
+        {
+            let mut encoder = legacy::legacy_encoder(&mut encrypted, "password");
+            encoder.write_all(b"downgraded").unwrap();
+        }
+
+        let declared_schemes = [
+            EncryptionScheme::Aes256GcmChunked {
+                kdf: aead::key_derivation(),
+            },
+            EncryptionScheme::X25519Recipients { recipients: 1 },
+        ];
+        for declared in &declared_schemes {
+            let mut output = Vec::new();
+            let decoded = password("password")
+                .to_decoder(Cursor::new(encrypted.clone()), Some(declared), b"")
+                .read_to_end(&mut output);
+            assert_eq!(decoded.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        assert_eq!(decrypt(&password("password"), encrypted).unwrap(), b"downgraded");
+    }
+
+    fn recipients(recipients: &[&str], identities: &[&str]) -> EncryptionLevel {
+        EncryptionLevel::Recipients {
+            recipients: recipients.iter().map(|r| r.to_string()).collect(),
+            identities: identities.iter().map(|&i| i.into()).collect(),
+        }
+    }
+
+    #[test]
+    fn any_recipient_can_decrypt() {
+        let (owner, owner_identity) = recipients::generate_identity().unwrap();
+        let (escrow, escrow_identity) = recipients::generate_identity().unwrap();
+        let input: Vec<u8> = (0..aead::CHUNK_SIZE + 10).map(|i| (i % 13) as u8).collect();
+
+        let encrypted = encrypt(&recipients(&[&owner, &escrow], &[]), &input);
+
+        for identity in [&owner_identity, &escrow_identity] {
+            let decrypted = decrypt(&recipients(&[], &[identity]), encrypted.clone()).unwrap();
+            assert_eq!(decrypted, input);
+        }
+    }
+
+    #[test]
+    fn decrypting_needs_a_matching_identity() {
+        let (recipient, _) = recipients::generate_identity().unwrap();
+        let (_, stranger_identity) = recipients::generate_identity().unwrap();
+        let encrypted = encrypt(&recipients(&[&recipient], &[]), b"secret");
+
+        assert!(decrypt(&recipients(&[], &[]), encrypted.clone()).is_err());
+        assert!(decrypt(&recipients(&[], &[&stranger_identity]), encrypted.clone()).is_err());
+        assert!(decrypt(&password("password"), encrypted).is_err());
+
+        let (_, identity) = recipients::generate_identity().unwrap();
+        let encrypted = encrypt(&password("password"), b"secret");
+        assert!(decrypt(&recipients(&[], &[&identity]), encrypted).is_err());
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        assert!(recipients::parse_recipient("x25519:not base64").is_err());
+        assert!(recipients::parse_recipient("ed25519:AAAA").is_err());
+        assert!(recipients::parse_identity("x25519:AAAA").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! Recipient encryption: every stream gets a random key that is sealed to the X25519 public
+//! key of each recipient, so the backing up host never needs a secret.
+//!
+//! Header: `MAGIC`, recipient count, per recipient an ephemeral public key and the sealed
+//! stream key (ciphertext and tag), then the nonce prefix. The chunks follow as in `aead`.
+use crate::services::encryption::aead::{
+    NONCE_PREFIX_LEN, StreamKey, invalid_data, read_exact_or_truncated,
+};
+use openssl::derive::Deriver;
+use openssl::md::Md;
+use openssl::pkey::{Id, PKey, Private, Public};
+use openssl::pkey_ctx::PkeyCtx;
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use rand::RngCore;
+use std::io;
+use std::io::Read;
+
+pub(super) const MAGIC: &[u8; 8] = b"DDX25519";
+const RECIPIENT_PREFIX: &str = "x25519:";
+const IDENTITY_PREFIX: &str = "x25519-secret:";
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const STANZA_LEN: usize = KEY_LEN + KEY_LEN + TAG_LEN;
+const WRAP_INFO: &[u8] = b"data-dance x25519 stream key";
+
+/// Parses a recipient public key as printed by [`generate_identity`].
+pub fn parse_recipient(recipient: &str) -> io::Result<PKey<Public>> {
+    let bytes = decode_key(recipient, RECIPIENT_PREFIX)?;
+    PKey::public_key_from_raw_bytes(&bytes, Id::X25519)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// Parses a secret identity as printed by [`generate_identity`].
+pub fn parse_identity(identity: &str) -> io::Result<PKey<Private>> {
+    let bytes = decode_key(identity, IDENTITY_PREFIX)?;
+    PKey::private_key_from_raw_bytes(&bytes, Id::X25519)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// A new key pair as `(recipient, identity)`.
+pub fn generate_identity() -> io::Result<(String, String)> {
+    let key = PKey::generate_x25519().map_err(io::Error::other)?;
+    let public = key.raw_public_key().map_err(io::Error::other)?;
+    let private = key.raw_private_key().map_err(io::Error::other)?;
+
+    Ok((
+        format!("{RECIPIENT_PREFIX}{}", openssl::base64::encode_block(&public)),
+        format!("{IDENTITY_PREFIX}{}", openssl::base64::encode_block(&private)),
+    ))
+}
+
+fn decode_key(key: &str, prefix: &str) -> io::Result<Vec<u8>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("expected a key of the form '{prefix}<base64>'"),
+        )
+    };
+    let encoded = key.trim().strip_prefix(prefix).ok_or_else(invalid)?;
+    let bytes = openssl::base64::decode_block(encoded).map_err(|_| invalid())?;
+    if bytes.len() != KEY_LEN {
+        return Err(invalid());
+    }
+    Ok(bytes)
+}
+
+/// Key sealing the stream key for one recipient, derived from the X25519 shared secret.
+fn wrapping_key(
+    secret: &PKey<Private>,
+    peer: &PKey<Public>,
+    ephemeral_public: &[u8],
+    recipient_public: &[u8],
+) -> io::Result<[u8; KEY_LEN]> {
+    let mut deriver = Deriver::new(secret).map_err(io::Error::other)?;
+    deriver.set_peer(peer).map_err(io::Error::other)?;
+    let shared_secret = deriver.derive_to_vec().map_err(io::Error::other)?;
+
+    let mut salt = ephemeral_public.to_vec();
+    salt.extend_from_slice(recipient_public);
+    let mut key = [0u8; KEY_LEN];
+    let hkdf: Result<(), openssl::error::ErrorStack> = try {
+        let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+        ctx.derive_init()?;
+        ctx.set_hkdf_md(Md::sha256())?;
+        ctx.set_hkdf_key(&shared_secret)?;
+        ctx.set_hkdf_salt(&salt)?;
+        ctx.add_hkdf_info(WRAP_INFO)?;
+        ctx.derive(Some(&mut key))?;
+    };
+    hkdf.map_err(io::Error::other)?;
+    Ok(key)
+}
+
+/// A random stream key sealed to every recipient.
+pub(super) fn recipients_stream_key(recipients: &[PKey<Public>]) -> io::Result<StreamKey> {
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "between 1 and 255 recipients are required",
+        ));
+    }
+
+    let mut key = [0u8; KEY_LEN];
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    rand::rng().fill_bytes(&mut key);
+    rand::rng().fill_bytes(&mut nonce_prefix);
+
+    let mut header_bytes = MAGIC.to_vec();
+    header_bytes.push(recipients.len() as u8);
+    for recipient in recipients {
+        let ephemeral = PKey::generate_x25519().map_err(io::Error::other)?;
+        let ephemeral_public = ephemeral.raw_public_key().map_err(io::Error::other)?;
+        let recipient_public = recipient.raw_public_key().map_err(io::Error::other)?;
+        let wrapping_key =
+            wrapping_key(&ephemeral, recipient, &ephemeral_public, &recipient_public)?;
+
+        let mut tag = [0u8; TAG_LEN];
+        // Every wrapping key is used exactly once, so a fixed nonce is fine
+        let sealed_key = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            &wrapping_key,
+            Some(&[0u8; 12]),
+            &[],
+            &key,
+            &mut tag,
+        )
+        .map_err(io::Error::other)?;
+
+        header_bytes.extend_from_slice(&ephemeral_public);
+        header_bytes.extend_from_slice(&sealed_key);
+        header_bytes.extend_from_slice(&tag);
+    }
+    header_bytes.extend_from_slice(&nonce_prefix);
+
+    Ok(StreamKey {
+        header_bytes,
+        key,
+        nonce_prefix,
+    })
+}
+
+/// Reads a recipients header whose `MAGIC` was already consumed and opens the stream key
+/// with the first matching identity.
+pub(super) fn read_recipients_header(
+    reader: &mut impl Read,
+    identities: &[PKey<Private>],
+) -> io::Result<StreamKey> {
+    let mut count = [0u8; 1];
+    read_exact_or_truncated(reader, &mut count)?;
+    let mut stanzas = vec![0u8; count[0] as usize * STANZA_LEN];
+    read_exact_or_truncated(reader, &mut stanzas)?;
+    let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+    read_exact_or_truncated(reader, &mut nonce_prefix)?;
+
+    let mut header_bytes = MAGIC.to_vec();
+    header_bytes.push(count[0]);
+    header_bytes.extend_from_slice(&stanzas);
+    header_bytes.extend_from_slice(&nonce_prefix);
+
+    for stanza in stanzas.chunks_exact(STANZA_LEN) {
+        let (ephemeral_public, sealed) = stanza.split_at(KEY_LEN);
+        let (sealed_key, tag) = sealed.split_at(KEY_LEN);
+        let Ok(ephemeral) = PKey::public_key_from_raw_bytes(ephemeral_public, Id::X25519) else {
+            continue;
+        };
+
+        for identity in identities {
+            let identity_public = identity.raw_public_key().map_err(io::Error::other)?;
+            let wrapping_key =
+                wrapping_key(identity, &ephemeral, ephemeral_public, &identity_public)?;
+            let opened = decrypt_aead(
+                Cipher::aes_256_gcm(),
+                &wrapping_key,
+                Some(&[0u8; 12]),
+                &[],
+                sealed_key,
+                tag,
+            );
+            if let Ok(key) = opened {
+                return Ok(StreamKey {
+                    header_bytes,
+                    key: key.try_into().unwrap(),
+                    nonce_prefix,
+                });
+            }
+        }
+    }
+
+    Err(invalid_data(
+        "none of the supplied identities is a recipient of this backup".to_string(),
+    ))
+}
@@ -1,8 +1,12 @@
-mod channels;
+pub mod archive;
+pub(crate) mod channels;
+pub mod chunk_store;
 pub mod compression;
+pub mod container;
 pub mod data_dest;
 pub mod data_source;
 pub mod data_tunnel;
+pub mod encoder;
 pub mod encryption;
 mod processes;
 pub mod tracking;
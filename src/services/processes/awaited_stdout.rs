@@ -0,0 +1,122 @@
+use crate::services::processes::AwaitedChild;
+use std::io::Read;
+use std::ops::{Deref, DerefMut};
+
+/// Reads the stdout of a child process. Once it ends, the exit status of the child is checked,
+/// so a process that failed does not look like one that had nothing more to send.
+pub struct AwaitedStdout {
+    inner: std::process::ChildStdout,
+    process: AwaitedChild,
+    exited: bool,
+}
+
+impl AwaitedStdout {
+    pub fn new(inner: std::process::ChildStdout, process: AwaitedChild) -> Self {
+        Self {
+            inner,
+            process,
+            exited: false,
+        }
+    }
+
+    fn wait(&mut self) -> std::io::Result<()> {
+        if self.exited {
+            return Ok(());
+        }
+        self.exited = true;
+
+        let mut stderr = String::new();
+        if let Some(mut child_stderr) = self.process.stderr.take() {
+            let _ = child_stderr.read_to_string(&mut stderr);
+        }
+        let status = self.process.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "the process exited with {}: {}",
+                status,
+                stderr.trim()
+            )))
+        }
+    }
+}
+
+impl From<(std::process::ChildStdout, std::process::Child)> for AwaitedStdout {
+    fn from((inner, child): (std::process::ChildStdout, std::process::Child)) -> Self {
+        Self::new(inner, child.into())
+    }
+}
+
+impl From<(std::process::ChildStdout, AwaitedChild)> for AwaitedStdout {
+    fn from((inner, process): (std::process::ChildStdout, AwaitedChild)) -> Self {
+        Self::new(inner, process)
+    }
+}
+
+impl Deref for AwaitedStdout {
+    type Target = std::process::ChildStdout;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl DerefMut for AwaitedStdout {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl Read for AwaitedStdout {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.wait()?;
+        }
+        Ok(read)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+        let read = self.inner.read_to_end(buf)?;
+        self.wait()?;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    fn spawn_shell(script: &str) -> AwaitedStdout {
+        let mut child = Command::new("sh")
+            .args(["-c", script])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        (stdout, child).into()
+    }
+
+    #[test]
+    fn successful_process_reads_to_the_end() {
+        let mut stdout = spawn_shell("printf 'Hello, world!'");
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).unwrap();
+
+        assert_eq!(output, "Hello, world!");
+    }
+
+    #[test]
+    fn failing_process_is_a_read_error() {
+        let mut stdout = spawn_shell("printf 'Hello'; echo no such file >&2; exit 1");
+        let mut output = Vec::new();
+        let err = std::io::copy(&mut stdout, &mut output).unwrap_err();
+
+        assert_eq!(output, b"Hello");
+        assert!(err.to_string().contains("no such file"), "{err}");
+    }
+}
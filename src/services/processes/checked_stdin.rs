@@ -0,0 +1,148 @@
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin};
+use std::sync::{Arc, Mutex};
+
+/// Outcome of a child process fed through a [`CheckedStdin`], available once the writer is dropped.
+#[derive(Clone, Default)]
+pub struct ProcessOutcome {
+    result: Arc<Mutex<Option<std::io::Result<()>>>>,
+}
+
+impl ProcessOutcome {
+    /// Takes the recorded outcome. Returns an error if the process has not finished yet.
+    pub fn take(&self) -> std::io::Result<()> {
+        let mut result_lock = self.result.lock().unwrap();
+        match result_lock.take() {
+            Some(result) => result,
+            None => Err(std::io::Error::other("process outcome is not available yet")),
+        }
+    }
+
+    fn set(&self, result: std::io::Result<()>) {
+        let mut result_lock = self.result.lock().unwrap();
+        *result_lock = Some(result);
+    }
+}
+
+/// Like `AwaitedStdin`, but the exit status and stderr of the child are checked
+/// instead of being discarded.
+pub struct CheckedStdin {
+    inner: Option<ChildStdin>,
+    process: Child,
+    name: String,
+    outcome: ProcessOutcome,
+}
+
+impl CheckedStdin {
+    pub fn new(mut process: Child, name: impl Into<String>) -> std::io::Result<Self> {
+        let inner = process
+            .stdin
+            .take()
+            .ok_or_else(|| std::io::Error::other("child stdin is not piped"))?;
+        Ok(Self {
+            inner: Some(inner),
+            process,
+            name: name.into(),
+            outcome: ProcessOutcome::default(),
+        })
+    }
+
+    pub fn outcome(&self) -> ProcessOutcome {
+        self.outcome.clone()
+    }
+
+    fn wait(&mut self) -> std::io::Result<()> {
+        drop(self.inner.take());
+
+        let mut stderr = String::new();
+        if let Some(mut child_stderr) = self.process.stderr.take() {
+            let _ = child_stderr.read_to_string(&mut stderr);
+        }
+        let status = self.process.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "{} exited with {}: {}",
+                self.name,
+                status,
+                stderr.trim()
+            )))
+        }
+    }
+
+    fn map_write_error(&mut self, err: std::io::Error) -> std::io::Error {
+        if err.kind() != std::io::ErrorKind::BrokenPipe {
+            return err;
+        }
+        match self.wait() {
+            Ok(()) => err,
+            Err(process_err) => process_err,
+        }
+    }
+}
+
+impl Write for CheckedStdin {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Err(std::io::Error::from(std::io::ErrorKind::BrokenPipe));
+        };
+        inner.write(buf).map_err(|err| self.map_write_error(err))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let Some(inner) = self.inner.as_mut() else {
+            return Ok(());
+        };
+        inner.flush().map_err(|err| self.map_write_error(err))
+    }
+}
+
+impl Drop for CheckedStdin {
+    fn drop(&mut self) {
+        let result = self.wait();
+        self.outcome.set(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    fn spawn_shell(script: &str) -> Child {
+        Command::new("sh")
+            .args(["-c", script])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap()
+    }
+
+    #[test]
+    fn successful_process_reports_ok() {
+        let mut stdin = CheckedStdin::new(spawn_shell("cat > /dev/null"), "cat").unwrap();
+        let outcome = stdin.outcome();
+        stdin.write_all(b"Hello, world!").unwrap();
+        drop(stdin);
+
+        assert!(outcome.take().is_ok());
+    }
+
+    #[test]
+    fn failing_process_reports_status_and_stderr() {
+        let mut stdin = CheckedStdin::new(
+            spawn_shell("cat > /dev/null; echo broken stream >&2; exit 3"),
+            "receiver",
+        )
+        .unwrap();
+        let outcome = stdin.outcome();
+        stdin.write_all(b"Hello, world!").unwrap();
+        drop(stdin);
+
+        let err = outcome.take().unwrap_err();
+        assert!(err.to_string().contains("receiver"));
+        assert!(err.to_string().contains("broken stream"));
+    }
+}
@@ -1,5 +1,9 @@
 mod awaited_child;
 mod awaited_stdin;
+mod awaited_stdout;
+mod checked_stdin;
 
 pub use awaited_child::*;
 pub use awaited_stdin::*;
+pub use awaited_stdout::*;
+pub use checked_stdin::*;
@@ -1,8 +1,10 @@
+use crate::objects::CompressionMix;
+use blake2::{Blake2b512, Digest};
 use std::io::{BufRead, Read, Write};
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct BytesCounter {
     bytes_amount: Arc<AtomicU64>,
 }
@@ -19,6 +21,36 @@ impl BytesCounter {
     }
 }
 
+/// Shared between an adaptive encoder and whoever reports on the upload.
+#[derive(Clone, Default)]
+pub struct CompressionMixCounter {
+    high_level_bytes: Arc<AtomicU64>,
+    fast_level_bytes: Arc<AtomicU64>,
+    stored_bytes: Arc<AtomicU64>,
+}
+
+impl CompressionMixCounter {
+    pub fn add_high_level(&self, bytes: u64) {
+        self.high_level_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_fast_level(&self, bytes: u64) {
+        self.fast_level_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_stored(&self, bytes: u64) {
+        self.stored_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn value(&self) -> CompressionMix {
+        CompressionMix {
+            high_level_bytes: self.high_level_bytes.load(Ordering::Relaxed),
+            fast_level_bytes: self.fast_level_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
 pub struct BytesCountingReader<R: Read> {
     inner_reader: R,
     byte_count: Arc<AtomicU64>,
@@ -100,3 +132,87 @@ impl<W: Write> Write for BytesCountingWriter<W> {
         self.inner.flush()
     }
 }
+
+/// The BLAKE2b-512 hash of everything passed through a `HashingReader` or `HashingWriter` so
+/// far.
+#[derive(Clone, Default)]
+pub struct ContentHash {
+    hasher: Arc<Mutex<Blake2b512>>,
+}
+
+impl ContentHash {
+    /// Lowercase hex, the way hashes are recorded in the backup history.
+    pub fn value(&self) -> String {
+        let hash = self.hasher.lock().unwrap().clone().finalize();
+        hash.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn update(&self, bytes: &[u8]) {
+        Digest::update(&mut *self.hasher.lock().unwrap(), bytes);
+    }
+
+    /// Hashes the rest of `reader`.
+    pub fn of_reader(reader: impl Read) -> std::io::Result<String> {
+        let mut reader = HashingReader::new(reader);
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+        Ok(reader.hash().value())
+    }
+}
+
+pub struct HashingReader<R: Read> {
+    inner_reader: R,
+    hash: ContentHash,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        HashingReader {
+            inner_reader: inner,
+            hash: ContentHash::default(),
+        }
+    }
+
+    /// Stays readable after the reader was moved into a transfer.
+    pub fn hash(&self) -> ContentHash {
+        self.hash.clone()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let bytes_read = self.inner_reader.read(buf)?;
+        self.hash.update(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+pub struct HashingWriter<W: Write> {
+    inner: W,
+    hash: ContentHash,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            hash: ContentHash::default(),
+        }
+    }
+
+    /// Stays readable after the writer was moved into a transfer.
+    pub fn hash(&self) -> ContentHash {
+        self.hash.clone()
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let bytes_written = self.inner.write(buf)?;
+        self.hash.update(&buf[..bytes_written]);
+        Ok(bytes_written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,15 +1,73 @@
 //pub mod jobs;
 
+use crate::jobs::full_backup::FullDataBackupJob;
+use crate::jobs::incremental_backup::IncrementalBackupJob;
+use crate::jobs::migrate::MigrateJob;
+use crate::jobs::rekey::RekeyJob;
+use crate::jobs::restore::RestoreBackupJob;
+use crate::jobs::restore_drill::RestoreDrillJob;
+use crate::jobs::verify::VerifyJob;
+use crate::jobs::{Job, JobVariant};
+use crate::objects::job_result::VerifyMode;
+use crate::objects::{BackupHealth, ScheduleState, SensitiveString};
 use crate::{context::DataDanceContext, objects::job_state::JobStates};
 use poem::Endpoint;
 use poem::web::Data;
-use poem_openapi::payload::Response;
-use poem_openapi::{OpenApi, OpenApiService, payload::Json};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::{PlainText, Response};
+use poem_openapi::{ApiResponse, Object, OpenApi, OpenApiService, payload::Json};
 use std::sync::Arc;
 use poem::Result;
 
 pub struct DataDanceApi;
 
+#[derive(ApiResponse)]
+pub enum SubmitJobResponse {
+    /// The job was accepted and started in the background.
+    #[oai(status = 202)]
+    Accepted,
+    /// Another job of the same kind is already running.
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+    /// The configuration has no section for the job.
+    #[oai(status = 422)]
+    NotConfigured(PlainText<String>),
+    /// A destination of the configuration could not be set up.
+    #[oai(status = 500)]
+    SetupFailed(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+pub enum BackupHealthResponse {
+    /// The outcome of the latest verification.
+    #[oai(status = 200)]
+    Verified(Json<BackupHealth>),
+    /// No verification has finished yet.
+    #[oai(status = 404)]
+    NotVerified,
+}
+
+/// Secret keys for backups encrypted to recipients, used for this restore only.
+#[derive(Object)]
+pub struct RestoreIdentities {
+    pub identities: Vec<String>,
+}
+
+fn submit_job(
+    context: &DataDanceContext,
+    job: std::io::Result<impl Into<JobVariant>>,
+) -> SubmitJobResponse {
+    let job = match job {
+        Ok(job) => job.into(),
+        Err(err) => return SubmitJobResponse::SetupFailed(PlainText(err.to_string())),
+    };
+
+    match context.executor.submit_job(job) {
+        Ok(_) => SubmitJobResponse::Accepted,
+        Err(err) => SubmitJobResponse::Conflict(PlainText(err.to_string())),
+    }
+}
+
 #[OpenApi]
 impl DataDanceApi {
     #[oai(path = "/jobs", method = "get")]
@@ -25,6 +83,122 @@ impl DataDanceApi {
             }
         }
     }
+
+    #[oai(path = "/jobs/incremental_backup", method = "post")]
+    async fn start_incremental_backup(&self, context: Data<&Arc<DataDanceContext>>) -> SubmitJobResponse {
+        let job = IncrementalBackupJob::from_config(context.config.clone());
+
+        submit_job(&context, job)
+    }
+
+    #[oai(path = "/jobs/full_backup", method = "post")]
+    async fn start_full_backup(&self, context: Data<&Arc<DataDanceContext>>) -> SubmitJobResponse {
+        if context.config.full_backup.is_none() {
+            return SubmitJobResponse::NotConfigured(PlainText(
+                "no full backup is configured".to_string(),
+            ));
+        }
+        let job = FullDataBackupJob::from_config(context.config.clone());
+
+        submit_job(&context, job)
+    }
+
+    /// Re-encrypts all remote backups that are not encrypted with the current key.
+    #[oai(path = "/jobs/rekey", method = "post")]
+    async fn start_rekey(&self, context: Data<&Arc<DataDanceContext>>) -> SubmitJobResponse {
+        let job = RekeyJob::from_config(context.config.clone());
+
+        submit_job(&context, job)
+    }
+
+    /// Copies the backups of the configured migration source into the remote destination.
+    #[oai(path = "/jobs/migrate", method = "post")]
+    async fn start_migrate(&self, context: Data<&Arc<DataDanceContext>>) -> SubmitJobResponse {
+        let job = MigrateJob::from_config(context.config.clone());
+
+        submit_job(&context, job)
+    }
+
+    /// Checks the remote backups against their history, `deep` unless another mode is given.
+    #[oai(path = "/jobs/verify", method = "post")]
+    async fn start_verify(
+        &self,
+        context: Data<&Arc<DataDanceContext>>,
+        mode: Query<Option<VerifyMode>>,
+    ) -> SubmitJobResponse {
+        let job = VerifyJob::from_config(context.config.clone())
+            .map(|job| job.with_mode(mode.0.unwrap_or_default()));
+
+        submit_job(&context, job)
+    }
+
+    /// Restores a backup into the configured scratch folder and compares it with its snapshot.
+    #[oai(path = "/jobs/restore_drill", method = "post")]
+    async fn start_restore_drill(
+        &self,
+        context: Data<&Arc<DataDanceContext>>,
+    ) -> SubmitJobResponse {
+        let job = RestoreDrillJob::from_config(context.config.clone());
+
+        submit_job(&context, job)
+    }
+
+    #[oai(path = "/health", method = "get")]
+    async fn get_health(
+        &self,
+        context: Data<&Arc<DataDanceContext>>,
+    ) -> Result<BackupHealthResponse> {
+        let health = match context.executor.history() {
+            Ok(history) => history.backup_health(),
+            // Nothing has run yet
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => return Err(poem::error::InternalServerError(err)),
+        };
+
+        Ok(match health {
+            Some(health) => BackupHealthResponse::Verified(Json(health)),
+            None => BackupHealthResponse::NotVerified,
+        })
+    }
+
+    #[oai(path = "/schedule", method = "get")]
+    async fn get_schedule(&self, context: Data<&Arc<DataDanceContext>>) -> Json<ScheduleState> {
+        Json(context.scheduler.state())
+    }
+
+    #[oai(path = "/schedule/restore_drill", method = "get")]
+    async fn get_restore_drill_schedule(
+        &self,
+        context: Data<&Arc<DataDanceContext>>,
+    ) -> Json<ScheduleState> {
+        Json(context.restore_drill_scheduler.state())
+    }
+
+    #[oai(path = "/jobs/restore/:backup_id", method = "post")]
+    async fn start_restore(
+        &self,
+        context: Data<&Arc<DataDanceContext>>,
+        backup_id: Path<u32>,
+    ) -> SubmitJobResponse {
+        let job = RestoreBackupJob::from_config(context.config.clone())
+            .map(|job| job.with_backup_id(backup_id.0));
+
+        submit_job(&context, job)
+    }
+
+    #[oai(path = "/jobs/restore/:backup_id/decrypt", method = "post")]
+    async fn start_restore_with_identities(
+        &self,
+        context: Data<&Arc<DataDanceContext>>,
+        backup_id: Path<u32>,
+        body: Json<RestoreIdentities>,
+    ) -> SubmitJobResponse {
+        let identities = body.0.identities.into_iter().map(SensitiveString::from).collect();
+        let job = RestoreBackupJob::from_config(context.config.clone())
+            .map(|job| job.with_backup_id(backup_id.0).with_identities(identities));
+
+        submit_job(&context, job)
+    }
 }
 
 pub fn api_service() -> OpenApiService<impl OpenApi + use<>, ()> {
@@ -11,8 +11,7 @@ use tokio::net::ToSocketAddrs;
 use std::str::FromStr;
 use std::sync::Arc;
 
-pub async fn run_server(context: DataDanceContext) -> i32 {
-    let context = Arc::new(context);
+pub async fn run_server(context: Arc<DataDanceContext>) -> i32 {
     let socket = context.bound_socket_addr();
     let listener = TcpListener::bind(socket);
 